@@ -2,17 +2,42 @@ use leptos::prelude::*;
 use leptos_meta::{Stylesheet, Title, provide_meta_context};
 use leptos_router::{
     StaticSegment, WildcardSegment,
-    components::{Route, Router, Routes},
+    components::{ProtectedRoute, Route, Router, Routes},
     path,
 };
 
-use crate::pages::{add_mosques_of_region::AddMosquesOfRegion, auth::{Login, Register}};
+use crate::models::user::UserRole;
+use crate::pages::{
+    account_settings::AccountSettings,
+    api_tokens::ApiTokens,
+    auth_events::AuthEvents,
+    devices::Devices,
+    add_mosques_of_region::AddMosquesOfRegion,
+    export_mosques::ExportMosques,
+    auth::{Login, Register},
+    locked_logins::LockedLogins,
+    password_reset::{ForgotPassword, ResetPassword},
+    two_factor::TwoFactorSettings,
+    verification::VerifyIdentifier,
+};
+use crate::server_functions::auth::current_user;
 
 #[component]
 pub fn App() -> impl IntoView {
     // Provides context that manages stylesheets, titles, meta tags, etc.
     provide_meta_context();
 
+    let current_user = Resource::new(|| (), |_| current_user());
+    // `None` while the current user is still loading, so protected routes wait for it
+    let has_role = move |role: UserRole| {
+        current_user.get().map(|response| {
+            response
+                .ok()
+                .and_then(|response| response.data)
+                .is_some_and(|user| user.role.satisfies(role))
+        })
+    };
+
     view! {
         // injects a stylesheet into the document <head>
         // id=leptos means cargo-leptos will hot-reload this stylesheet
@@ -28,7 +53,57 @@ pub fn App() -> impl IntoView {
                     <Route path=StaticSegment("") view=HomePage/>
                     <Route path=path!("/register") view=Register/>
                     <Route path=path!("/login") view=Login/>
-                    <Route path=path!("/add-mosques") view=AddMosquesOfRegion/>
+                    <Route path=path!("/forgot-password") view=ForgotPassword/>
+                    <Route path=path!("/reset-password") view=ResetPassword/>
+                    <Route path=path!("/verify") view=VerifyIdentifier/>
+                    <ProtectedRoute
+                        path=path!("/add-mosques")
+                        view=AddMosquesOfRegion
+                        condition=move || has_role(UserRole::AppAdmin)
+                        redirect_path=|| "/login"
+                    />
+                    <ProtectedRoute
+                        path=path!("/mosques/export")
+                        view=ExportMosques
+                        condition=move || has_role(UserRole::Regular)
+                        redirect_path=|| "/login"
+                    />
+                    <ProtectedRoute
+                        path=path!("/settings")
+                        view=AccountSettings
+                        condition=move || has_role(UserRole::Regular)
+                        redirect_path=|| "/login"
+                    />
+                    <ProtectedRoute
+                        path=path!("/settings/devices")
+                        view=Devices
+                        condition=move || has_role(UserRole::Regular)
+                        redirect_path=|| "/login"
+                    />
+                    <ProtectedRoute
+                        path=path!("/settings/two-factor")
+                        view=TwoFactorSettings
+                        condition=move || has_role(UserRole::Regular)
+                        redirect_path=|| "/login"
+                    />
+                    <ProtectedRoute
+                        path=path!("/settings/api-tokens")
+                        view=ApiTokens
+                        condition=move || has_role(UserRole::Regular)
+                        redirect_path=|| "/login"
+                    />
+                    <ProtectedRoute
+                        path=path!("/admin/locked-logins")
+                        view=LockedLogins
+                        condition=move || has_role(UserRole::AppAdmin)
+                        redirect_path=|| "/login"
+                    />
+                    <ProtectedRoute
+                        path=path!("/admin/auth-events")
+                        view=AuthEvents
+                        condition=move || has_role(UserRole::AppAdmin)
+                        redirect_path=|| "/login"
+                    />
                     <Route path=WildcardSegment("any") view=NotFound/>
                 </Routes>
             </main>
@@ -0,0 +1,219 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use surrealdb::RecordId;
+use surrealdb::sql::Datetime;
+
+use crate::{
+    database::connection::get_db,
+    errors::{account::AccountError, auth::AuthError},
+    models::user::User,
+};
+
+/// How long a user can still change their mind after asking for their account
+/// to be deleted
+static ACCOUNT_DELETION_GRACE_PERIOD_IN_DAYS: i64 = 30;
+
+/// Schedules the account of the user for deletion once the grace period is over.
+/// The user is logged out of every other session and their API tokens stop
+/// working right away, the session they asked with stays so they can cancel.
+pub async fn request_account_deletion(user_id: RecordId, kept_session: RecordId) -> Result<DateTime<Utc>> {
+    let surql = r#"
+            BEGIN TRANSACTION;
+
+            UPDATE $user_id SET deletion_requested_at = time::now();
+            DELETE sessions WHERE user_id = $user_id AND id != $kept_session;
+            DELETE api_tokens WHERE user_id = $user_id;
+
+            COMMIT TRANSACTION;
+        "#;
+
+    let db = get_db();
+
+    let user: Option<User> = db
+        .select(user_id.clone())
+        .await
+        .map_err(|e| AccountError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to get user from user_id")?;
+    let user = user.ok_or(AuthError::UserNotFound)?;
+
+    if user.deletion_requested_at.is_some() {
+        Err(AccountError::DeletionAlreadyRequested)?
+    }
+
+    db.query(surql)
+        .bind(("user_id", user_id.clone()))
+        .bind(("kept_session", kept_session))
+        .await
+        .map_err(|e| AccountError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to schedule the account for deletion")?
+        .check()
+        .map_err(|e| AccountError::DatabaseError(Box::new(e)))?;
+
+    Ok(Utc::now() + Duration::days(ACCOUNT_DELETION_GRACE_PERIOD_IN_DAYS))
+}
+
+/// Keeps the account of the user after all
+pub async fn cancel_account_deletion(user_id: RecordId) -> Result<()> {
+    let cancelled: Vec<User> = get_db()
+        .query("UPDATE $user_id SET deletion_requested_at = NONE WHERE deletion_requested_at != NONE")
+        .bind(("user_id", user_id))
+        .await
+        .map_err(|e| AccountError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to cancel the deletion of the account")?
+        .take(0)
+        .map_err(|e| AccountError::DatabaseError(Box::new(e)))?;
+
+    if cancelled.is_empty() {
+        Err(AccountError::DeletionNotRequested)?
+    }
+
+    Ok(())
+}
+
+/// When the account of the user is deleted, if it is scheduled to be
+pub fn account_deleted_at(user: &User) -> Option<DateTime<Utc>> {
+    let deletion_requested_at: DateTime<Utc> = user.deletion_requested_at.clone()?.into();
+
+    Some(deletion_requested_at + Duration::days(ACCOUNT_DELETION_GRACE_PERIOD_IN_DAYS))
+}
+
+/// Deletes the accounts whose grace period is over, returning how many there were
+pub async fn purge_deleted_accounts() -> Result<usize> {
+    let cutoff = Datetime::from(Utc::now() - Duration::days(ACCOUNT_DELETION_GRACE_PERIOD_IN_DAYS));
+
+    let user_ids: Vec<RecordId> = get_db()
+        .query("SELECT VALUE id FROM users WHERE deletion_requested_at != NONE AND deletion_requested_at <= $cutoff")
+        .bind(("cutoff", cutoff))
+        .await
+        .map_err(|e| AccountError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the accounts due for deletion")?
+        .take(0)
+        .map_err(|e| AccountError::DatabaseError(Box::new(e)))?;
+
+    for user_id in &user_ids {
+        delete_account(user_id.clone()).await?;
+    }
+
+    Ok(user_ids.len())
+}
+
+/// Deletes the user along with everything that only exists for them. Their
+/// entries in the audit log are kept for the other users they concern, but no
+/// longer say who they were.
+async fn delete_account(user_id: RecordId) -> Result<()> {
+    let surql = r#"
+            BEGIN TRANSACTION;
+
+            LET $identifier_values = (SELECT VALUE identifier_value FROM user_identifier WHERE user_id = $user_id);
+            DELETE login_attempts WHERE kind = 'identifier' AND value IN $identifier_values;
+            DELETE identifier_verifications WHERE user_identifier.user_id = $user_id;
+            DELETE user_identifier WHERE user_id = $user_id;
+            DELETE sessions WHERE user_id = $user_id;
+            DELETE api_tokens WHERE user_id = $user_id;
+            DELETE two_factor WHERE user_id = $user_id;
+            DELETE two_factor_challenges WHERE user_id = $user_id;
+            DELETE password_reset_tokens WHERE user_id = $user_id;
+            DELETE oidc_states WHERE link_user = $user_id;
+
+            UPDATE mosque_details SET admins -= $user_id WHERE $user_id IN admins;
+            UPDATE auth_events SET user_id = NONE, identifier = NONE, ip_address = NONE, user_agent = NONE
+                WHERE user_id = $user_id OR identifier IN $identifier_values;
+            UPDATE auth_events SET actor_id = NONE WHERE actor_id = $user_id;
+
+            DELETE $user_id;
+
+            COMMIT TRANSACTION;
+        "#;
+
+    get_db()
+        .query(surql)
+        .bind(("user_id", user_id))
+        .await
+        .map_err(|e| AccountError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to delete the account")?
+        .check()
+        .map_err(|e| AccountError::DatabaseError(Box::new(e)))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::{
+            api_tokens::{create_api_token, list_api_tokens},
+            session::{create_session, get_session_by_token, list_sessions_for_user},
+        },
+        models::{api_token::ApiTokenScope, session::DeviceInfo},
+        test_support::{register_test_user, run},
+    };
+
+    #[test]
+    fn deletion_can_be_cancelled_during_the_grace_period() {
+        run(async {
+            let user_id = register_test_user("account-deletion-cancel@example.com").await;
+            let kept_token = create_session(user_id.clone(), DeviceInfo::default()).await.unwrap();
+            create_session(user_id.clone(), DeviceInfo::default()).await.unwrap();
+            create_api_token(user_id.clone(), "Display".to_string(), vec![ApiTokenScope::Read], None)
+                .await
+                .unwrap();
+            let kept_session = get_session_by_token(&kept_token).await.unwrap().id;
+
+            let deleted_at = request_account_deletion(user_id.clone(), kept_session.clone()).await.unwrap();
+            assert!(deleted_at > Utc::now() + Duration::days(ACCOUNT_DELETION_GRACE_PERIOD_IN_DAYS - 1));
+            let sessions = list_sessions_for_user(user_id.clone()).await.unwrap();
+            assert_eq!(sessions.len(), 1);
+            assert_eq!(sessions[0].id, kept_session);
+            assert!(list_api_tokens(user_id.clone()).await.unwrap().is_empty());
+
+            let error = request_account_deletion(user_id.clone(), kept_session).await.unwrap_err();
+            assert!(matches!(error.downcast_ref::<AccountError>(), Some(AccountError::DeletionAlreadyRequested)));
+
+            // Still within the grace period, so nothing is purged
+            purge_deleted_accounts().await.unwrap();
+            cancel_account_deletion(user_id.clone()).await.unwrap();
+            let user: Option<User> = get_db().select(user_id.clone()).await.unwrap();
+            assert!(user.unwrap().deletion_requested_at.is_none());
+
+            let error = cancel_account_deletion(user_id).await.unwrap_err();
+            assert!(matches!(error.downcast_ref::<AccountError>(), Some(AccountError::DeletionNotRequested)));
+        });
+    }
+
+    #[test]
+    fn accounts_are_purged_after_the_grace_period() {
+        run(async {
+            let user_id = register_test_user("account-deletion-purge@example.com").await;
+            let other_user_id = register_test_user("account-deletion-other@example.com").await;
+            create_session(user_id.clone(), DeviceInfo::default()).await.unwrap();
+
+            let long_ago = Datetime::from(Utc::now() - Duration::days(ACCOUNT_DELETION_GRACE_PERIOD_IN_DAYS + 1));
+            get_db()
+                .query("UPDATE $user_id SET deletion_requested_at = $long_ago")
+                .bind(("user_id", user_id.clone()))
+                .bind(("long_ago", long_ago))
+                .await
+                .unwrap()
+                .check()
+                .unwrap();
+
+            assert!(purge_deleted_accounts().await.unwrap() >= 1);
+
+            let user: Option<User> = get_db().select(user_id.clone()).await.unwrap();
+            assert!(user.is_none());
+            assert!(list_sessions_for_user(user_id.clone()).await.unwrap().is_empty());
+            let identifiers: Vec<RecordId> = get_db()
+                .query("SELECT VALUE id FROM user_identifier WHERE user_id = $user_id")
+                .bind(("user_id", user_id))
+                .await
+                .unwrap()
+                .take(0)
+                .unwrap();
+            assert!(identifiers.is_empty());
+
+            let other_user: Option<User> = get_db().select(other_user_id).await.unwrap();
+            assert!(other_user.is_some());
+        });
+    }
+}
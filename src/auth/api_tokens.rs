@@ -0,0 +1,255 @@
+use actix_web::{HttpMessage, HttpRequest};
+use actix_web::http::header::AUTHORIZATION;
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Duration, Utc};
+use leptos_actix::extract;
+use surrealdb::RecordId;
+use surrealdb::sql::Datetime;
+
+use crate::{
+    auth::session::{hash_session_token, validate_session_token},
+    database::connection::get_db,
+    errors::api_token::ApiTokenError,
+    models::{
+        api_token::{ApiToken, ApiTokenScope, CreateApiToken},
+        user::User,
+    },
+    utils::token_generator::generate_token,
+};
+
+/// Tells API tokens apart from other secrets, e.g. for secret scanners
+pub const API_TOKEN_PREFIX: &str = "mzh_";
+/// `last_used_at` is only written when it is at least this old, so busy clients
+/// don't cause a write on every request
+const LAST_USED_PRECISION_IN_SECONDS: i64 = 300;
+
+/// The scopes of the API token the current request was authenticated with, kept
+/// in the extensions of the request. Requests with a session cookie have none.
+#[derive(Debug, Clone)]
+pub struct ApiTokenGrant {
+    pub scopes: Vec<ApiTokenScope>,
+}
+
+/// Creates an API token for the user, returning the token along with what was
+/// stored for it. Only the hash of the token is kept.
+pub async fn create_api_token(
+    user_id: RecordId,
+    name: String,
+    scopes: Vec<ApiTokenScope>,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<(String, ApiToken)> {
+    let token = generate_token();
+
+    let api_token = CreateApiToken {
+        user_id,
+        name,
+        token_hash: hash_session_token(&token),
+        scopes,
+        expires_at: expires_at.map(Datetime::from),
+    };
+
+    let created: Option<ApiToken> = get_db()
+        .create("api_tokens")
+        .content(api_token)
+        .await
+        .map_err(|e| ApiTokenError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to create the API token")?;
+    let created = created.ok_or_else(|| anyhow!("API token creation returned no data"))?;
+
+    Ok((format!("{}{}", API_TOKEN_PREFIX, token), created))
+}
+
+/// The API tokens of the user, the most recently created first, expired ones included
+pub async fn list_api_tokens(user_id: RecordId) -> Result<Vec<ApiToken>> {
+    let api_tokens: Vec<ApiToken> = get_db()
+        .query("SELECT * FROM api_tokens WHERE user_id = $user_id ORDER BY created_at DESC")
+        .bind(("user_id", user_id))
+        .await
+        .map_err(|e| ApiTokenError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the API tokens of the user")?
+        .take(0)
+        .map_err(|e| ApiTokenError::DatabaseError(Box::new(e)))?;
+
+    Ok(api_tokens)
+}
+
+/// Deletes one API token of the user. Fails with `ApiTokenError::TokenNotFound`
+/// when the token doesn't exist or belongs to someone else.
+pub async fn revoke_api_token(user_id: RecordId, token_id: RecordId) -> Result<()> {
+    let revoked: Vec<ApiToken> = get_db()
+        .query("DELETE api_tokens WHERE id = $token_id AND user_id = $user_id RETURN BEFORE")
+        .bind(("token_id", token_id))
+        .bind(("user_id", user_id))
+        .await
+        .map_err(|e| ApiTokenError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to revoke the API token")?
+        .take(0)
+        .map_err(|e| ApiTokenError::DatabaseError(Box::new(e)))?;
+
+    if revoked.is_empty() {
+        Err(ApiTokenError::TokenNotFound)?
+    }
+
+    Ok(())
+}
+
+/// Resolves the user of an API token and notes that the token was used. Unknown,
+/// malformed and expired tokens all fail with `ApiTokenError::InvalidToken`.
+pub async fn authenticate_api_token(token: &str) -> Result<(User, ApiToken)> {
+    let token = token
+        .strip_prefix(API_TOKEN_PREFIX)
+        .ok_or(ApiTokenError::InvalidToken)?;
+    validate_session_token(token).map_err(|_| ApiTokenError::InvalidToken)?;
+
+    let db = get_db();
+    let api_token: Option<ApiToken> = db
+        .query("SELECT * FROM api_tokens WHERE token_hash = $token_hash")
+        .bind(("token_hash", hash_session_token(token)))
+        .await
+        .map_err(|e| ApiTokenError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the API token")?
+        .take(0)
+        .map_err(|e| ApiTokenError::DatabaseError(Box::new(e)))?;
+
+    let api_token = api_token
+        .filter(|api_token| {
+            api_token
+                .expires_at
+                .as_ref()
+                .is_none_or(|expires_at| *expires_at > Datetime::from(Utc::now()))
+        })
+        .ok_or(ApiTokenError::InvalidToken)?;
+
+    let user: Option<User> = db
+        .select(api_token.user_id.clone())
+        .await
+        .map_err(|e| ApiTokenError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the user of the API token")?;
+    let user = user.ok_or(ApiTokenError::InvalidToken)?;
+
+    touch_api_token(&api_token).await?;
+
+    Ok((user, api_token))
+}
+
+/// The token of an `Authorization: Bearer` header of the request, if it has one
+pub fn bearer_token(request: &HttpRequest) -> Option<String> {
+    let header = request.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = header.split_once(' ')?;
+
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then(|| token.trim().to_string())
+}
+
+/// Makes sure the API token the current request was made with has `scope`.
+/// Requests made with a session cookie may do everything the user may.
+pub async fn ensure_api_token_scope(scope: ApiTokenScope) -> Result<()> {
+    let request = extract::<HttpRequest>()
+        .await
+        .map_err(|e| anyhow!("Failed to extract the request: {}", e))?;
+
+    if let Some(grant) = request.extensions().get::<ApiTokenGrant>()
+        && !grant.scopes.contains(&scope)
+    {
+        Err(ApiTokenError::MissingScope)?
+    }
+
+    Ok(())
+}
+
+/// Moves `last_used_at` of the token to now, unless it was moved recently
+async fn touch_api_token(api_token: &ApiToken) -> Result<()> {
+    let last_used_at: Option<DateTime<Utc>> = api_token.last_used_at.clone().map(Into::into);
+    let is_recent = last_used_at
+        .is_some_and(|last_used_at| Utc::now() - last_used_at < Duration::seconds(LAST_USED_PRECISION_IN_SECONDS));
+
+    if is_recent {
+        return Ok(());
+    }
+
+    get_db()
+        .query("UPDATE $token_id SET last_used_at = time::now()")
+        .bind(("token_id", api_token.id.clone()))
+        .await
+        .map_err(|e| ApiTokenError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to update when the API token was last used")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{register_test_user, run};
+
+    #[test]
+    fn tokens_authenticate_their_user_until_revoked() {
+        run(async {
+            let user_id = register_test_user("api-token@example.com").await;
+            let other_user_id = register_test_user("api-token-other@example.com").await;
+
+            let (token, api_token) =
+                create_api_token(user_id.clone(), "Display".to_string(), vec![ApiTokenScope::Read], None)
+                    .await
+                    .unwrap();
+            assert!(token.starts_with(API_TOKEN_PREFIX));
+            assert_ne!(api_token.token_hash, token);
+            assert!(api_token.last_used_at.is_none());
+
+            let (user, _) = authenticate_api_token(&token).await.unwrap();
+            assert_eq!(user.id, user_id);
+            let listed = list_api_tokens(user_id.clone()).await.unwrap();
+            assert_eq!(listed.len(), 1);
+            assert!(listed[0].last_used_at.is_some());
+
+            // Only the prefixed token is accepted, not its hash or the bare token
+            for wrong_token in [api_token.token_hash.as_str(), &token[API_TOKEN_PREFIX.len()..]] {
+                let error = authenticate_api_token(wrong_token).await.unwrap_err();
+                assert!(matches!(error.downcast_ref::<ApiTokenError>(), Some(ApiTokenError::InvalidToken)));
+            }
+
+            let error = revoke_api_token(other_user_id, api_token.id.clone()).await.unwrap_err();
+            assert!(matches!(error.downcast_ref::<ApiTokenError>(), Some(ApiTokenError::TokenNotFound)));
+            assert!(authenticate_api_token(&token).await.is_ok());
+
+            revoke_api_token(user_id.clone(), api_token.id).await.unwrap();
+            let error = authenticate_api_token(&token).await.unwrap_err();
+            assert!(matches!(error.downcast_ref::<ApiTokenError>(), Some(ApiTokenError::InvalidToken)));
+            assert!(list_api_tokens(user_id).await.unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn bearer_tokens_are_read_from_the_authorization_header() {
+        let request_with = |header: &str| {
+            actix_web::test::TestRequest::default()
+                .insert_header((AUTHORIZATION, header))
+                .to_http_request()
+        };
+
+        assert_eq!(bearer_token(&request_with("Bearer mzh_abc")).as_deref(), Some("mzh_abc"));
+        assert_eq!(bearer_token(&request_with("bearer mzh_abc")).as_deref(), Some("mzh_abc"));
+        assert_eq!(bearer_token(&request_with("Basic dXNlcjpwYXNz")), None);
+        assert_eq!(bearer_token(&actix_web::test::TestRequest::default().to_http_request()), None);
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected() {
+        run(async {
+            let user_id = register_test_user("api-token-expired@example.com").await;
+
+            let (token, _) = create_api_token(
+                user_id,
+                "Old integration".to_string(),
+                vec![ApiTokenScope::Read, ApiTokenScope::Admin],
+                Some(Utc::now() - Duration::minutes(1)),
+            )
+            .await
+            .unwrap();
+
+            let error = authenticate_api_token(&token).await.unwrap_err();
+            assert!(matches!(error.downcast_ref::<ApiTokenError>(), Some(ApiTokenError::InvalidToken)));
+        });
+    }
+}
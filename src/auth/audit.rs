@@ -0,0 +1,150 @@
+use anyhow::{Context, Result};
+use surrealdb::RecordId;
+use tracing::error;
+
+use crate::{
+    auth::session::get_device_info,
+    database::connection::get_db,
+    errors::auth::AuthError,
+    models::auth_event::{AUTH_EVENTS_PAGE_SIZE, AuthEvent, AuthEventFilter, CreateAuthEvent},
+};
+
+pub async fn record_auth_event(event: CreateAuthEvent) -> Result<()> {
+    let _: Option<AuthEvent> = get_db()
+        .create("auth_events")
+        .content(event)
+        .await
+        .map_err(|e| AuthError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to record the auth event")?;
+
+    Ok(())
+}
+
+/// Records the event along with the device of the current request. A failure is
+/// only logged, the action that is audited goes through either way.
+pub async fn audit(event: CreateAuthEvent) {
+    let event = match get_device_info().await {
+        Ok(device) => CreateAuthEvent {
+            ip_address: device.ip_address,
+            user_agent: device.user_agent,
+            ..event
+        },
+        Err(error) => {
+            error!(?error, "Failed to read the device of the audited request");
+            event
+        },
+    };
+
+    if let Err(error) = record_auth_event(event).await {
+        error!(?error, "Failed to record the auth event");
+    }
+}
+
+/// A page of the events matching the filter, the newest first
+pub async fn list_auth_events(filter: &AuthEventFilter) -> Result<Vec<AuthEvent>> {
+    let user_id = match filter.user_id.as_deref().filter(|user_id| !user_id.is_empty()) {
+        Some(user_id) => match user_id.parse::<RecordId>() {
+            Ok(user_id) => Some(user_id),
+            // No event can belong to a user id that isn't even valid
+            Err(_) => return Ok(Vec::new()),
+        },
+        None => None,
+    };
+    let non_empty = |value: &Option<String>| value.clone().filter(|value| !value.is_empty());
+
+    let events: Vec<AuthEvent> = get_db()
+        .query(
+            "SELECT * FROM auth_events
+            WHERE ($kind = NONE OR kind = $kind)
+                AND ($outcome = NONE OR outcome = $outcome)
+                AND ($user_id = NONE OR user_id = $user_id OR actor_id = $user_id)
+                AND ($identifier = NONE OR identifier = $identifier)
+                AND ($ip_address = NONE OR ip_address = $ip_address)
+            ORDER BY created_at DESC
+            LIMIT $limit START $start",
+        )
+        .bind(("kind", filter.kind))
+        .bind(("outcome", filter.outcome))
+        .bind(("user_id", user_id))
+        .bind(("identifier", non_empty(&filter.identifier)))
+        .bind(("ip_address", non_empty(&filter.ip_address)))
+        .bind(("limit", AUTH_EVENTS_PAGE_SIZE))
+        .bind(("start", filter.page * AUTH_EVENTS_PAGE_SIZE))
+        .await
+        .map_err(|e| AuthError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the auth events")?
+        .take(0)
+        .map_err(|e| AuthError::DatabaseError(Box::new(e)))?;
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        auth_event::{AuthEventKind, AuthEventOutcome},
+        user::Identifier,
+    };
+    use crate::test_support::{register_test_user, run};
+
+    #[test]
+    fn events_are_filtered_and_listed_newest_first() {
+        run(async {
+            let user_id = register_test_user("audit@example.com").await;
+            let admin_id = register_test_user("audit-admin@example.com").await;
+            let identifier = Identifier::Email("audit@example.com".to_string());
+
+            for reason in ["wrong password", "too many attempts"] {
+                let failure = CreateAuthEvent::new(AuthEventKind::Login, AuthEventOutcome::Failure)
+                    .identifier(&identifier)
+                    .reason(reason);
+                record_auth_event(failure).await.unwrap();
+            }
+            let success = CreateAuthEvent::new(AuthEventKind::Login, AuthEventOutcome::Success)
+                .user(user_id.clone())
+                .identifier(&identifier);
+            record_auth_event(success).await.unwrap();
+            let role_change = CreateAuthEvent::new(AuthEventKind::RoleChanged, AuthEventOutcome::Success)
+                .user(user_id.clone())
+                .actor(admin_id.clone());
+            record_auth_event(role_change).await.unwrap();
+
+            let failures = list_auth_events(&AuthEventFilter {
+                outcome: Some(AuthEventOutcome::Failure),
+                identifier: Some("audit@example.com".to_string()),
+                ..AuthEventFilter::default()
+            })
+            .await
+            .unwrap();
+            assert_eq!(failures.len(), 2);
+            assert_eq!(failures[0].reason.as_deref(), Some("too many attempts"));
+
+            let of_user = list_auth_events(&AuthEventFilter {
+                user_id: Some(user_id.to_string()),
+                ..AuthEventFilter::default()
+            })
+            .await
+            .unwrap();
+            assert_eq!(of_user.len(), 2);
+            assert_eq!(of_user[0].kind, AuthEventKind::RoleChanged);
+
+            // What admins did to others shows up under them as well
+            let by_admin = list_auth_events(&AuthEventFilter {
+                user_id: Some(admin_id.to_string()),
+                ..AuthEventFilter::default()
+            })
+            .await
+            .unwrap();
+            assert_eq!(by_admin.len(), 1);
+
+            let invalid_user = list_auth_events(&AuthEventFilter {
+                user_id: Some("not a user".to_string()),
+                ..AuthEventFilter::default()
+            })
+            .await
+            .unwrap();
+            assert!(invalid_user.is_empty());
+        });
+    }
+}
@@ -1,19 +1,25 @@
+use crate::auth::login_throttle::{
+    LoginAttemptKeys, clear_failed_logins, ensure_login_allowed, record_failed_login,
+};
+use crate::auth::identifiers::list_identifiers;
+use crate::auth::password_config::get_password_config;
 use crate::database::connection::get_db;
 use crate::errors::auth::AuthError;
 use crate::models::auth::LoginFormData;
-use crate::models::user::{Identifier, User, UserIdentifier};
+use crate::models::user::{Identifier, User, UserIdentifier, UserRole};
 use crate::models::{
     auth::RegistrationFormData,
     user::CreateUser
 };
 use anyhow::{anyhow, Context, Result};
 use argon2::{
-    Argon2,
-    password_hash::{PasswordHasher, SaltString, PasswordVerifier},
+    Algorithm, Argon2, Params, Version,
+    password_hash::{PasswordHash, PasswordHasher, SaltString, PasswordVerifier},
 };
 use garde::Validate;
 use rand::rngs::OsRng;
 use surrealdb::RecordId;
+use tracing::error;
 
 pub async fn register_user(form: RegistrationFormData) -> Result<RecordId> {
     let db = get_db();
@@ -23,14 +29,7 @@ pub async fn register_user(form: RegistrationFormData) -> Result<RecordId> {
         .with_context(|| "The form validation for registration failed")?;
     form.validate_uniqueness().await?;
 
-    let password_bytes = form.password.as_bytes();
-    let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
-
-    let password_hash = argon2
-        .hash_password(password_bytes, &salt)
-        .map_err(AuthError::PasswordHashError)?;
-    let password_hash_str = password_hash.to_string();
+    let password_hash_str = hash_password(&form.password)?;
 
     let user = CreateUser {
         display_name: form.name,
@@ -47,7 +46,8 @@ pub async fn register_user(form: RegistrationFormData) -> Result<RecordId> {
             CREATE user_identifier CONTENT {
                 user_id: $created_user.id,
                 identifier_type: $identifier_data.identifier_type,
-                identifier_value: $identifier_data.identifier_value
+                identifier_value: $identifier_data.identifier_value,
+                is_primary: true
             };
 
             RETURN $created_user;
@@ -69,46 +69,311 @@ pub async fn register_user(form: RegistrationFormData) -> Result<RecordId> {
     Ok(user_id)
 }
 
-pub async fn authenticate(form: LoginFormData) -> Result<RecordId> {
+/// Checks the credentials of a login, throttled per identifier and per
+/// `client_ip`.
+///
+/// Fails with `AuthError::TooManyAttempts` while either of them is locked out.
+/// Unknown identifiers count as failures too so they can't be told apart from
+/// locked accounts.
+pub async fn authenticate(form: LoginFormData, client_ip: Option<String>) -> Result<RecordId> {
+    let attempt_keys = LoginAttemptKeys::new(&form.identifier, client_ip);
+    ensure_login_allowed(&attempt_keys).await?;
+
+    match verify_credentials(form).await {
+        Ok(user_id) => {
+            clear_failed_logins(&attempt_keys).await?;
+            Ok(user_id)
+        },
+        Err(error) => {
+            if let Some(AuthError::UserNotFound | AuthError::PasswordVerificationError(_)) =
+                error.downcast_ref::<AuthError>()
+            {
+                record_failed_login(&attempt_keys).await?;
+            }
+            Err(error)
+        },
+    }
+}
+
+async fn verify_credentials(form: LoginFormData) -> Result<RecordId> {
     let db = get_db();
 
-    let (identifier_type, identifier_value) = match form.identifier {
-        Identifier::Email(email) => ("email", email),
-        Identifier::Mobile(mobile) => ("mobile", mobile),
-    };
+    let user_identifier: UserIdentifier = find_user_identifier(&form.identifier)
+        .await?
+        .ok_or(AuthError::UserNotFound)?;
 
-    let mut result = db.query("SELECT * FROM user_identifier WHERE identifier_type = $identifier_type AND identifier_value = $identifier_value")
-        .bind(("identifier_type", identifier_type))
-        .bind(("identifier_value", identifier_value))
+    let requested_user_option: Option<User> = db
+        .select(user_identifier.user_id)
         .await
         .map_err(|e| AuthError::DatabaseError(Box::new(e)))
-        .with_context(|| "Failed to get search for the identifier for authentication")?;
+        .with_context(|| "Failed to get user from user_id")?;
+    let requested_user: User = requested_user_option.ok_or(AuthError::UserNotFound)?;
 
-    let user_identifier_option: Option<crate::models::user::UserIdentifier> = result.take(0)
-        .map_err(|e| AuthError::DatabaseError(Box::new(e)))
-        .with_context(|| "failed to get the result for the request user identifier")?;
-    let user_identifier: UserIdentifier =
-        user_identifier_option.ok_or(AuthError::UserNotFound)?;
+    verify_password(&form.password, &requested_user.password_hash)?;
+
+    if password_hash_needs_upgrade(&requested_user.password_hash) {
+        // The login goes through either way, the upgrade is retried on the next one
+        if let Err(error) = update_password_hash(requested_user.id.clone(), &form.password).await {
+            error!(?error, "Failed to upgrade the password hash");
+        }
+    }
 
-    let mut user_result = db
-        .query("SELECT * FROM user WHERE id = $user_id")
-        .bind(("user_id", user_identifier.user_id))
+    Ok(requested_user.id)
+}
+
+/// Replaces the password of the user after checking their current one, and logs
+/// them out of every session except `kept_session`.
+///
+/// The check is throttled like a login, per primary identifier of the user and
+/// per `client_ip`, so a stolen session can't be used to guess the password.
+/// Fails with `AuthError::TooManyAttempts` while either of them is locked out.
+pub async fn change_password(
+    user_id: RecordId,
+    kept_session: RecordId,
+    client_ip: Option<String>,
+    current_password: &str,
+    new_password: &str,
+) -> Result<()> {
+    let db = get_db();
+
+    let user: Option<User> = db
+        .select(user_id.clone())
         .await
         .map_err(|e| AuthError::DatabaseError(Box::new(e)))
         .with_context(|| "Failed to get user from user_id")?;
+    let user = user.ok_or(AuthError::UserNotFound)?;
+
+    let primary_identifier = list_identifiers(user_id.clone())
+        .await?
+        .into_iter()
+        .next()
+        .ok_or(AuthError::UserNotFound)?;
+    let attempt_keys = LoginAttemptKeys::new(&primary_identifier.identifier, client_ip);
+    ensure_login_allowed(&attempt_keys).await?;
 
-    let requested_user_option: Option<User> = user_result.take(0)
+    if let Err(error) = verify_password(current_password, &user.password_hash) {
+        if let Some(AuthError::PasswordVerificationError(_)) = error.downcast_ref::<AuthError>() {
+            record_failed_login(&attempt_keys).await?;
+        }
+        return Err(error);
+    }
+    clear_failed_logins(&attempt_keys).await?;
+    let password_hash = hash_password(new_password)?;
+
+    let surql = r#"
+            BEGIN TRANSACTION;
+
+            UPDATE $user_id SET password_hash = $password_hash, updated_at = time::now();
+            DELETE sessions WHERE user_id = $user_id AND id != $kept_session;
+
+            COMMIT TRANSACTION;
+        "#;
+
+    db.query(surql)
+        .bind(("user_id", user_id))
+        .bind(("password_hash", password_hash))
+        .bind(("kept_session", kept_session))
+        .await
         .map_err(|e| AuthError::DatabaseError(Box::new(e)))
-        .with_context(|| "failed to get the result for the request user")?;
-    let requested_user: User = requested_user_option.ok_or(AuthError::UserNotFound)?;
+        .with_context(|| "Failed to change the password and invalidate the other sessions")?
+        .check()
+        .map_err(|e| AuthError::DatabaseError(Box::new(e)))?;
 
-    let parsed_hash = argon2::password_hash::PasswordHash::new(&requested_user.password_hash)
+    Ok(())
+}
+
+/// Gives the user another role, returning the role they had before. Fails with
+/// `AuthError::UserNotFound` when there is no such user.
+pub async fn change_user_role(user_id: RecordId, role: UserRole) -> Result<UserRole> {
+    let previous: Option<User> = get_db()
+        .query("UPDATE $user_id SET role = $role, updated_at = time::now() RETURN BEFORE")
+        .bind(("user_id", user_id))
+        .bind(("role", role))
+        .await
+        .map_err(|e| AuthError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to change the role of the user")?
+        .take(0)
+        .map_err(|e| AuthError::DatabaseError(Box::new(e)))?;
+
+    let previous = previous.ok_or(AuthError::UserNotFound)?;
+
+    Ok(previous.role)
+}
+
+fn verify_password(password: &str, password_hash: &str) -> Result<()> {
+    let parsed_hash = PasswordHash::new(password_hash)
         .map_err(AuthError::PasswordHashError)?;
 
+    // The parameters come from the stored hash, so hashes made with older
+    // configurations keep working
     let argon2 = Argon2::default();
-    argon2.verify_password(form.password.as_bytes(), &parsed_hash)
+    argon2.verify_password(password.as_bytes(), &parsed_hash)
         .map_err(AuthError::PasswordVerificationError)
         .with_context(|| "Password verification failed")?;
 
-    Ok(requested_user.id)
+    Ok(())
+}
+
+pub fn hash_password(password: &str) -> Result<String, AuthError> {
+    let salt = SaltString::generate(&mut OsRng);
+    let argon2 = get_password_config().argon2();
+
+    let password_hash = argon2
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(AuthError::PasswordHashError)?;
+
+    Ok(password_hash.to_string())
+}
+
+/// Whether the hash was made with another algorithm or version, or with a lower
+/// cost than the current password config asks for.
+pub fn password_hash_needs_upgrade(password_hash: &str) -> bool {
+    let Ok(parsed_hash) = PasswordHash::new(password_hash) else {
+        return false;
+    };
+
+    if parsed_hash.algorithm != Algorithm::Argon2id.ident()
+        || parsed_hash.version != Some(Version::V0x13.into())
+    {
+        return true;
+    }
+
+    let Ok(params) = Params::try_from(&parsed_hash) else {
+        return false;
+    };
+    let config = get_password_config();
+
+    params.m_cost() < config.memory_cost
+        || params.t_cost() < config.iterations
+        || params.p_cost() < config.parallelism
+}
+
+async fn update_password_hash(user_id: RecordId, password: &str) -> Result<()> {
+    let db = get_db();
+    let password_hash = hash_password(password)?;
+
+    db.query("UPDATE $user_id SET password_hash = $password_hash, updated_at = time::now()")
+        .bind(("user_id", user_id))
+        .bind(("password_hash", password_hash))
+        .await
+        .map_err(|e| AuthError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to update the password hash")?
+        .check()
+        .map_err(|e| AuthError::DatabaseError(Box::new(e)))?;
+
+    Ok(())
+}
+
+pub async fn find_user_identifier(identifier: &Identifier) -> Result<Option<UserIdentifier>> {
+    let db = get_db();
+
+    let identifier_type = identifier.identifier_type();
+    let identifier_value = identifier.value().to_string();
+
+    let mut result = db.query("SELECT * FROM user_identifier WHERE identifier_type = $identifier_type AND identifier_value = $identifier_value")
+        .bind(("identifier_type", identifier_type))
+        .bind(("identifier_value", identifier_value))
+        .await
+        .map_err(|e| AuthError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to get search for the identifier")?;
+
+    let user_identifier_option: Option<UserIdentifier> = result.take(0)
+        .map_err(|e| AuthError::DatabaseError(Box::new(e)))
+        .with_context(|| "failed to get the result for the request user identifier")?;
+
+    Ok(user_identifier_option)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::session::{create_session, get_session_by_token};
+    use crate::models::session::DeviceInfo;
+    use crate::test_support::{TEST_PASSWORD, register_test_user, run};
+
+    fn login_form(email: &str, password: &str) -> LoginFormData {
+        LoginFormData {
+            identifier: Identifier::Email(email.to_string()),
+            password: password.to_string(),
+        }
+    }
+
+    async fn stored_password_hash(user_id: RecordId) -> String {
+        let user: Option<User> = get_db().select(user_id).await.unwrap();
+
+        user.unwrap().password_hash
+    }
+
+    #[test]
+    fn weaker_hashes_are_upgraded_on_login() {
+        run(async {
+            let email = "rehash@example.com";
+            let user_id = register_test_user(email).await;
+            assert!(!password_hash_needs_upgrade(&stored_password_hash(user_id.clone()).await));
+
+            let weak_params = Params::new(Params::MIN_M_COST, 1, 1, None).unwrap();
+            let weak_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, weak_params)
+                .hash_password(TEST_PASSWORD.as_bytes(), &SaltString::generate(&mut OsRng))
+                .unwrap()
+                .to_string();
+            assert!(password_hash_needs_upgrade(&weak_hash));
+
+            get_db()
+                .query("UPDATE $user_id SET password_hash = $password_hash")
+                .bind(("user_id", user_id.clone()))
+                .bind(("password_hash", weak_hash.clone()))
+                .await
+                .unwrap();
+
+            authenticate(login_form(email, TEST_PASSWORD), None).await.unwrap();
+
+            let upgraded_hash = stored_password_hash(user_id).await;
+            assert_ne!(upgraded_hash, weak_hash);
+            assert!(!password_hash_needs_upgrade(&upgraded_hash));
+            authenticate(login_form(email, TEST_PASSWORD), None).await.unwrap();
+        });
+    }
+
+    #[test]
+    fn changing_the_password_needs_the_current_one_and_ends_other_sessions() {
+        run(async {
+            let email = "change-password@example.com";
+            let user_id = register_test_user(email).await;
+            let kept_token = create_session(user_id.clone(), DeviceInfo::default()).await.unwrap();
+            let other_token = create_session(user_id.clone(), DeviceInfo::default()).await.unwrap();
+            let kept_session = get_session_by_token(&kept_token).await.unwrap();
+
+            let error = change_password(user_id.clone(), kept_session.id.clone(), None, "not the password", "a brand new password")
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<AuthError>(),
+                Some(AuthError::PasswordVerificationError(_))
+            ));
+
+            change_password(user_id, kept_session.id, None, TEST_PASSWORD, "a brand new password").await.unwrap();
+
+            assert!(get_session_by_token(&kept_token).await.is_ok());
+            assert!(get_session_by_token(&other_token).await.is_err());
+            authenticate(login_form(email, TEST_PASSWORD), None).await.unwrap_err();
+            authenticate(login_form(email, "a brand new password"), None).await.unwrap();
+        });
+    }
+
+    #[test]
+    fn roles_are_changed_for_existing_users_only() {
+        run(async {
+            let user_id = register_test_user("change-role@example.com").await;
+
+            let previous = change_user_role(user_id.clone(), UserRole::MosqueAdmin).await.unwrap();
+            assert_eq!(previous, UserRole::Regular);
+            let user: Option<User> = get_db().select(user_id).await.unwrap();
+            assert_eq!(user.unwrap().role, UserRole::MosqueAdmin);
+
+            let error = change_user_role(RecordId::from(("users", "missing")), UserRole::AppAdmin)
+                .await
+                .unwrap_err();
+            assert!(matches!(error.downcast_ref::<AuthError>(), Some(AuthError::UserNotFound)));
+        });
+    }
 }
@@ -0,0 +1,68 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use surrealdb::RecordId;
+
+use crate::{
+    auth::{
+        api_tokens::list_api_tokens, identifiers::list_identifiers, session::list_sessions_for_user,
+        two_factor::two_factor_status,
+    },
+    database::connection::get_db,
+    errors::account::AccountError,
+    models::{
+        account::AccountExport,
+        api_token::ApiTokenSummary,
+        auth_event::{AuthEvent, AuthEventSummary},
+        session::DeviceSession,
+        user::{IdentifierSummary, User, UserProfile},
+    },
+};
+
+/// Gathers everything stored about the user. `current_session_id` is the session
+/// the export was requested with, which is marked as such among the sessions.
+pub async fn export_account(user: &User, current_session_id: &RecordId) -> Result<AccountExport> {
+    let identifiers = list_identifiers(user.id.clone()).await?;
+    let sessions = list_sessions_for_user(user.id.clone()).await?;
+    let api_tokens = list_api_tokens(user.id.clone()).await?;
+    let two_factor = two_factor_status(user).await?;
+
+    let db = get_db();
+
+    let administered_mosques: Vec<RecordId> = db
+        .query("SELECT VALUE mosque FROM mosque_details WHERE $user_id IN admins")
+        .bind(("user_id", user.id.clone()))
+        .await
+        .map_err(|e| AccountError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the mosques the user is an admin of")?
+        .take(0)
+        .map_err(|e| AccountError::DatabaseError(Box::new(e)))?;
+
+    let auth_events: Vec<AuthEvent> = db
+        .query("SELECT * FROM auth_events WHERE user_id = $user_id OR actor_id = $user_id ORDER BY created_at DESC")
+        .bind(("user_id", user.id.clone()))
+        .await
+        .map_err(|e| AccountError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the auth events of the user")?
+        .take(0)
+        .map_err(|e| AccountError::DatabaseError(Box::new(e)))?;
+
+    Ok(AccountExport {
+        exported_at: Utc::now().to_rfc3339(),
+        profile: UserProfile {
+            id: user.id.to_string(),
+            display_name: user.display_name.clone(),
+            role: user.role,
+        },
+        created_at: user.created_at.to_string(),
+        deletion_requested_at: user.deletion_requested_at.as_ref().map(ToString::to_string),
+        identifiers: identifiers.into_iter().map(IdentifierSummary::from).collect(),
+        sessions: sessions
+            .into_iter()
+            .map(|session| DeviceSession::from_session(session, current_session_id))
+            .collect(),
+        api_tokens: api_tokens.into_iter().map(ApiTokenSummary::from).collect(),
+        two_factor,
+        administered_mosques: administered_mosques.iter().map(ToString::to_string).collect(),
+        auth_events: auth_events.into_iter().map(AuthEventSummary::from).collect(),
+    })
+}
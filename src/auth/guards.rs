@@ -0,0 +1,201 @@
+use actix_web::http::StatusCode;
+use anyhow::{Context, Result};
+use leptos::prelude::expect_context;
+use leptos_actix::ResponseOptions;
+use surrealdb::RecordId;
+use tracing::error;
+
+use crate::{
+    auth::{
+        api_tokens::ensure_api_token_scope, session::get_current_user,
+        two_factor::ensure_two_factor_if_required, verification::has_verified_identifier,
+    },
+    database::connection::get_db,
+    errors::{
+        api_token::ApiTokenError, auth::AuthError, session::SessionError,
+        two_factor::TwoFactorError, verification::VerificationError,
+    },
+    models::{
+        api_responses::ApiResponse,
+        api_token::ApiTokenScope,
+        user::{User, UserRole},
+    },
+};
+
+/// Resolves the current user and makes sure their role satisfies `role`.
+///
+/// Fails with `SessionError::Unauthenticated` when nobody is logged in, with
+/// `AuthError::Forbidden` when the user's role is insufficient, with
+/// `TwoFactorError::Required` when their role needs two-factor authentication
+/// they haven't set up and with `ApiTokenError::MissingScope` when the request
+/// was made with an API token lacking the admin scope.
+pub async fn require_role(role: UserRole) -> Result<User> {
+    let user = get_current_user().await?;
+
+    if !user.role.satisfies(role) {
+        Err(AuthError::Forbidden)?
+    }
+
+    // What every user may do stays open, setting two-factor authentication up included
+    if role != UserRole::Regular {
+        ensure_two_factor_if_required(&user).await?;
+        ensure_api_token_scope(ApiTokenScope::Admin).await?;
+    }
+
+    Ok(user)
+}
+
+/// Resolves the current user and makes sure at least one of their identifiers is
+/// verified, failing with `VerificationError::Unverified` otherwise.
+pub async fn require_verified_user() -> Result<User> {
+    let user = get_current_user().await?;
+
+    if !has_verified_identifier(user.id.clone()).await? {
+        Err(VerificationError::Unverified)?
+    }
+
+    Ok(user)
+}
+
+/// Resolves the current user and makes sure they are listed in the `admins` of the
+/// mosque's `mosque_details`. App admins may administer every mosque, everyone
+/// else also needs a verified identifier. Two-factor authentication and the admin
+/// scope of API tokens are required the same way as for `require_role`.
+pub async fn require_mosque_admin(mosque_id: RecordId) -> Result<User> {
+    let user = require_verified_user().await?;
+    ensure_two_factor_if_required(&user).await?;
+    ensure_api_token_scope(ApiTokenScope::Admin).await?;
+
+    if user.role == UserRole::AppAdmin {
+        return Ok(user);
+    }
+
+    let db = get_db();
+    let administered: Vec<RecordId> = db
+        .query("SELECT VALUE id FROM mosque_details WHERE mosque = $mosque AND admins CONTAINS $user")
+        .bind(("mosque", mosque_id))
+        .bind(("user", user.id.clone()))
+        .await
+        .map_err(|e| AuthError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the admins of the mosque")?
+        .take(0)
+        .map_err(|e| AuthError::DatabaseError(Box::new(e)))?;
+
+    if administered.is_empty() {
+        Err(AuthError::Forbidden)?
+    }
+
+    Ok(user)
+}
+
+/// Sets the status code matching a failed guard on the current response and
+/// builds the error response a server function should return.
+pub fn guard_error_response<T>(error: &anyhow::Error) -> ApiResponse<T> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    if let Some(SessionError::Unauthenticated) = error.downcast_ref::<SessionError>() {
+        response_option.set_status(StatusCode::UNAUTHORIZED);
+        return ApiResponse { data: None, error: Some("You are not logged in.".to_string()) };
+    }
+
+    if let Some(AuthError::Forbidden) = error.downcast_ref::<AuthError>() {
+        response_option.set_status(StatusCode::FORBIDDEN);
+        return ApiResponse { data: None, error: Some("You are not allowed to do this.".to_string()) };
+    }
+
+    if let Some(VerificationError::Unverified) = error.downcast_ref::<VerificationError>() {
+        response_option.set_status(StatusCode::FORBIDDEN);
+        return ApiResponse { data: None, error: Some("Please verify your email or mobile first.".to_string()) };
+    }
+
+    if let Some(TwoFactorError::Required) = error.downcast_ref::<TwoFactorError>() {
+        response_option.set_status(StatusCode::FORBIDDEN);
+        return ApiResponse { data: None, error: Some("Please set up two-factor authentication first.".to_string()) };
+    }
+
+    if let Some(ApiTokenError::MissingScope) = error.downcast_ref::<ApiTokenError>() {
+        response_option.set_status(StatusCode::FORBIDDEN);
+        return ApiResponse { data: None, error: Some("This API token is not allowed to do this.".to_string()) };
+    }
+
+    error!(?error, "Failed to authorize the request.");
+    response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+    ApiResponse { data: None, error: Some("An internal error occurred.".to_string()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{handle_request, run, session_cookie, user_with_role};
+    use actix_web::test::TestRequest;
+
+    const ROLES: [UserRole; 4] = [UserRole::AppAdmin, UserRole::MosqueAdmin, UserRole::Educator, UserRole::Regular];
+
+    async fn administers(session_token: &str, mosque_id: RecordId) -> Result<User> {
+        let request = TestRequest::post().cookie(session_cookie(session_token));
+        handle_request(request, || require_mosque_admin(mosque_id)).await.0
+    }
+
+    fn is_forbidden(result: Result<User>) -> bool {
+        matches!(result.unwrap_err().downcast_ref::<AuthError>(), Some(AuthError::Forbidden))
+    }
+
+    #[test]
+    fn app_admins_satisfy_every_role_and_everyone_regular() {
+        for role in ROLES {
+            for required in ROLES {
+                let expected = role == UserRole::AppAdmin || required == UserRole::Regular || role == required;
+                assert_eq!(role.satisfies(required), expected, "{:?} satisfying {:?}", role, required);
+            }
+        }
+        assert!(!UserRole::MosqueAdmin.satisfies(UserRole::Educator));
+        assert!(!UserRole::Regular.satisfies(UserRole::AppAdmin));
+    }
+
+    #[test]
+    fn roles_are_required_of_the_current_user() {
+        run(async {
+            let (_, session_token) = user_with_role("guard-educator@example.com", UserRole::Educator).await;
+            let request = || TestRequest::post().cookie(session_cookie(&session_token));
+
+            assert!(handle_request(request(), || require_role(UserRole::Educator)).await.0.is_ok());
+            assert!(handle_request(request(), || require_role(UserRole::Regular)).await.0.is_ok());
+            assert!(is_forbidden(handle_request(request(), || require_role(UserRole::AppAdmin)).await.0));
+
+            let error = handle_request(TestRequest::post(), || require_role(UserRole::Regular)).await.0.unwrap_err();
+            assert!(matches!(error.downcast_ref::<SessionError>(), Some(SessionError::Unauthenticated)));
+        });
+    }
+
+    #[test]
+    fn mosque_admins_only_administer_their_own_mosques() {
+        run(async {
+            let (mosque_admin_id, mosque_admin_token) =
+                user_with_role("guard-mosque-admin@example.com", UserRole::MosqueAdmin).await;
+            let (_, app_admin_token) = user_with_role("guard-app-admin@example.com", UserRole::AppAdmin).await;
+
+            let own_mosque = RecordId::from(("mosques", "guard_own"));
+            let other_mosque = RecordId::from(("mosques", "guard_other"));
+            get_db()
+                .query(
+                    "CREATE $own_mosque SET name = 'Own', location = (1.0, 1.0);
+                    CREATE $other_mosque SET name = 'Other', location = (2.0, 2.0);
+                    LET $times = (CREATE prayer_times SET fajr = '05:00:00', dhuhr = '13:00:00', asr = '16:30:00',
+                        maghrib = '19:45:00', isha = '21:15:00', jummah = '13:30:00')[0].id;
+                    CREATE mosque_details SET mosque = $own_mosque, admins = [$admin], jamat_times = $times, adhan_times = $times;
+                    CREATE mosque_details SET mosque = $other_mosque, admins = [], jamat_times = $times, adhan_times = $times;",
+                )
+                .bind(("own_mosque", own_mosque.clone()))
+                .bind(("other_mosque", other_mosque.clone()))
+                .bind(("admin", mosque_admin_id.clone()))
+                .await
+                .unwrap()
+                .check()
+                .unwrap();
+
+            assert_eq!(administers(&mosque_admin_token, own_mosque).await.unwrap().id, mosque_admin_id);
+            assert!(is_forbidden(administers(&mosque_admin_token, other_mosque.clone()).await));
+            assert!(administers(&app_admin_token, other_mosque).await.is_ok());
+        });
+    }
+}
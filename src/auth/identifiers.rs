@@ -0,0 +1,312 @@
+use anyhow::{Context, Result, anyhow};
+use surrealdb::RecordId;
+use tracing::error;
+
+use crate::{
+    auth::{custom_auth::find_user_identifier, verification::send_verification},
+    database::connection::get_db,
+    errors::identifier::IdentifierError,
+    models::user::{CreateUserIdentifier, Identifier, UserIdentifier},
+};
+
+/// Every identifier of the user, the primary one first.
+pub async fn list_identifiers(user_id: RecordId) -> Result<Vec<UserIdentifier>> {
+    let db = get_db();
+
+    let identifiers: Vec<UserIdentifier> = db
+        .query("SELECT * FROM user_identifier WHERE user_id = $user_id ORDER BY is_primary DESC, created_at ASC")
+        .bind(("user_id", user_id))
+        .await
+        .map_err(|e| IdentifierError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the identifiers of the user")?
+        .take(0)
+        .map_err(|e| IdentifierError::DatabaseError(Box::new(e)))?;
+
+    Ok(identifiers)
+}
+
+/// Links another, unverified identifier to the user and sends it a verification.
+///
+/// A user has at most one identifier of each type, so this is how an account
+/// registered with an email gets a mobile and the other way round.
+pub async fn add_identifier(user_id: RecordId, identifier: Identifier) -> Result<UserIdentifier> {
+    ensure_identifier_is_free(&identifier).await?;
+
+    let identifiers = list_identifiers(user_id.clone()).await?;
+    if identifiers
+        .iter()
+        .any(|linked| linked.identifier.identifier_type() == identifier.identifier_type())
+    {
+        Err(IdentifierError::TypeAlreadyLinked)?
+    }
+
+    let db = get_db();
+    let created: Option<UserIdentifier> = db
+        .create("user_identifier")
+        .content(CreateUserIdentifier { identifier, user_id })
+        .await
+        .map_err(|e| IdentifierError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to add the identifier")?;
+    let created = created.ok_or_else(|| anyhow!("Adding the identifier returned no data"))?;
+
+    send_verification_logging_failures(&created).await;
+
+    Ok(created)
+}
+
+/// Replaces the value of one of the user's identifiers with a new one of the
+/// same type. The new value starts out unverified and gets a verification sent.
+pub async fn change_identifier(
+    user_id: RecordId,
+    current: &Identifier,
+    new_identifier: Identifier,
+) -> Result<UserIdentifier> {
+    if current.identifier_type() != new_identifier.identifier_type() {
+        Err(IdentifierError::TypeMismatch)?
+    }
+
+    let user_identifier = find_identifier_of(&user_id, current).await?;
+    ensure_identifier_is_free(&new_identifier).await?;
+
+    let new_value = new_identifier.value().to_string();
+
+    // Codes sent to the old value mustn't verify the new one
+    let surql = r#"
+            BEGIN TRANSACTION;
+
+            DELETE identifier_verifications WHERE user_identifier = $user_identifier;
+            UPDATE $user_identifier SET identifier_value = $identifier_value, verified_at = NONE, updated_at = time::now();
+
+            COMMIT TRANSACTION;
+        "#;
+
+    let db = get_db();
+    db.query(surql)
+        .bind(("user_identifier", user_identifier.id.clone()))
+        .bind(("identifier_value", new_value))
+        .await
+        .map_err(|e| IdentifierError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to change the identifier")?
+        .check()
+        .map_err(|e| IdentifierError::DatabaseError(Box::new(e)))?;
+
+    let changed: Option<UserIdentifier> = db
+        .select(user_identifier.id)
+        .await
+        .map_err(|e| IdentifierError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the changed identifier")?;
+    let changed = changed.ok_or(IdentifierError::NotFound)?;
+
+    send_verification_logging_failures(&changed).await;
+
+    Ok(changed)
+}
+
+/// Unlinks an identifier from the user. The last identifier can't be removed,
+/// and when the primary one goes an email or mobile that remains becomes primary.
+pub async fn remove_identifier(user_id: RecordId, identifier: &Identifier) -> Result<()> {
+    let user_identifier = find_identifier_of(&user_id, identifier).await?;
+
+    let identifiers = list_identifiers(user_id).await?;
+    if identifiers.len() <= 1 {
+        Err(IdentifierError::LastIdentifier)?
+    }
+
+    let promoted = identifiers
+        .into_iter()
+        .find(|linked| linked.id != user_identifier.id && linked.identifier.is_contactable())
+        .filter(|_| user_identifier.is_primary)
+        .map(|linked| linked.id);
+
+    let surql = r#"
+            BEGIN TRANSACTION;
+
+            DELETE identifier_verifications WHERE user_identifier = $user_identifier;
+            DELETE $user_identifier;
+            IF $promoted != NONE {
+                UPDATE $promoted SET is_primary = true, updated_at = time::now();
+            };
+
+            COMMIT TRANSACTION;
+        "#;
+
+    let db = get_db();
+    db.query(surql)
+        .bind(("user_identifier", user_identifier.id))
+        .bind(("promoted", promoted))
+        .await
+        .map_err(|e| IdentifierError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to remove the identifier")?
+        .check()
+        .map_err(|e| IdentifierError::DatabaseError(Box::new(e)))?;
+
+    Ok(())
+}
+
+/// Makes a verified identifier of the user their primary one.
+pub async fn set_primary_identifier(user_id: RecordId, identifier: &Identifier) -> Result<()> {
+    let user_identifier = find_identifier_of(&user_id, identifier).await?;
+
+    if !identifier.is_contactable() {
+        Err(IdentifierError::NotContactable)?
+    }
+
+    if user_identifier.verified_at.is_none() {
+        Err(IdentifierError::Unverified)?
+    }
+
+    let surql = r#"
+            BEGIN TRANSACTION;
+
+            UPDATE user_identifier SET is_primary = false WHERE user_id = $user_id AND is_primary = true;
+            UPDATE $user_identifier SET is_primary = true, updated_at = time::now();
+
+            COMMIT TRANSACTION;
+        "#;
+
+    let db = get_db();
+    db.query(surql)
+        .bind(("user_id", user_id))
+        .bind(("user_identifier", user_identifier.id))
+        .await
+        .map_err(|e| IdentifierError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to set the primary identifier")?
+        .check()
+        .map_err(|e| IdentifierError::DatabaseError(Box::new(e)))?;
+
+    Ok(())
+}
+
+async fn find_identifier_of(user_id: &RecordId, identifier: &Identifier) -> Result<UserIdentifier> {
+    let user_identifier = find_user_identifier(identifier)
+        .await?
+        .filter(|user_identifier| &user_identifier.user_id == user_id)
+        .ok_or(IdentifierError::NotFound)?;
+
+    Ok(user_identifier)
+}
+
+async fn ensure_identifier_is_free(identifier: &Identifier) -> Result<()> {
+    if find_user_identifier(identifier).await?.is_some() {
+        Err(IdentifierError::AlreadyTaken)?
+    }
+
+    Ok(())
+}
+
+/// The identifier is linked either way and a new code can be requested from the
+/// verify page, so a failed delivery only gets logged
+async fn send_verification_logging_failures(user_identifier: &UserIdentifier) {
+    if let Err(error) = send_verification(user_identifier).await {
+        error!(?error, "Failed to send the verification for the identifier");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{register_test_user, run};
+
+    fn email(value: &str) -> Identifier {
+        Identifier::Email(value.to_string())
+    }
+
+    fn mobile(value: &str) -> Identifier {
+        Identifier::Mobile(value.to_string())
+    }
+
+    fn identifier_error(error: &anyhow::Error) -> Option<&IdentifierError> {
+        error.downcast_ref::<IdentifierError>()
+    }
+
+    async fn mark_verified(identifier: &Identifier) {
+        let user_identifier = find_user_identifier(identifier).await.unwrap().unwrap();
+
+        get_db()
+            .query("UPDATE $user_identifier SET verified_at = time::now()")
+            .bind(("user_identifier", user_identifier.id))
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn one_identifier_of_each_type_can_be_added() {
+        run(async {
+            let user_id = register_test_user("identifiers-add@example.com").await;
+
+            let added = add_identifier(user_id.clone(), mobile("+911234500001")).await.unwrap();
+            assert!(!added.is_primary);
+            assert!(added.verified_at.is_none());
+
+            let error = add_identifier(user_id.clone(), email("identifiers-add-2@example.com")).await.unwrap_err();
+            assert!(matches!(identifier_error(&error), Some(IdentifierError::TypeAlreadyLinked)));
+
+            let other_user_id = register_test_user("identifiers-add-other@example.com").await;
+            let error = add_identifier(other_user_id, mobile("+911234500001")).await.unwrap_err();
+            assert!(matches!(identifier_error(&error), Some(IdentifierError::AlreadyTaken)));
+
+            let identifiers = list_identifiers(user_id).await.unwrap();
+            assert_eq!(identifiers.len(), 2);
+            assert!(identifiers[0].is_primary);
+        });
+    }
+
+    #[test]
+    fn changing_an_identifier_needs_a_new_verification() {
+        run(async {
+            let old_email = email("identifiers-change@example.com");
+            let new_email = email("identifiers-changed@example.com");
+            let user_id = register_test_user("identifiers-change@example.com").await;
+            mark_verified(&old_email).await;
+
+            let error = change_identifier(user_id.clone(), &old_email, mobile("+911234500002")).await.unwrap_err();
+            assert!(matches!(identifier_error(&error), Some(IdentifierError::TypeMismatch)));
+
+            let changed = change_identifier(user_id, &old_email, new_email.clone()).await.unwrap();
+            assert_eq!(changed.identifier, new_email);
+            assert!(changed.verified_at.is_none());
+            assert!(changed.is_primary);
+            assert!(find_user_identifier(&old_email).await.unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn the_last_identifier_stays_and_removing_the_primary_promotes_the_other() {
+        run(async {
+            let primary = email("identifiers-remove@example.com");
+            let secondary = mobile("+911234500003");
+            let user_id = register_test_user("identifiers-remove@example.com").await;
+
+            let error = remove_identifier(user_id.clone(), &primary).await.unwrap_err();
+            assert!(matches!(identifier_error(&error), Some(IdentifierError::LastIdentifier)));
+
+            add_identifier(user_id.clone(), secondary.clone()).await.unwrap();
+            remove_identifier(user_id.clone(), &primary).await.unwrap();
+
+            let identifiers = list_identifiers(user_id).await.unwrap();
+            assert_eq!(identifiers.len(), 1);
+            assert_eq!(identifiers[0].identifier, secondary);
+            assert!(identifiers[0].is_primary);
+        });
+    }
+
+    #[test]
+    fn only_a_verified_identifier_becomes_primary() {
+        run(async {
+            let secondary = mobile("+911234500004");
+            let user_id = register_test_user("identifiers-primary@example.com").await;
+            add_identifier(user_id.clone(), secondary.clone()).await.unwrap();
+
+            let error = set_primary_identifier(user_id.clone(), &secondary).await.unwrap_err();
+            assert!(matches!(identifier_error(&error), Some(IdentifierError::Unverified)));
+
+            mark_verified(&secondary).await;
+            set_primary_identifier(user_id.clone(), &secondary).await.unwrap();
+
+            let identifiers = list_identifiers(user_id).await.unwrap();
+            assert_eq!(identifiers[0].identifier, secondary);
+            assert!(identifiers[0].is_primary);
+            assert!(!identifiers[1].is_primary);
+        });
+    }
+}
@@ -0,0 +1,267 @@
+use actix_web::HttpRequest;
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Duration, Utc};
+use leptos_actix::extract;
+use surrealdb::sql::Datetime;
+
+use crate::{
+    database::connection::get_db,
+    errors::auth::AuthError,
+    models::{
+        login_attempt::{LoginAttempt, LoginAttemptKind},
+        user::Identifier,
+    },
+};
+
+pub(crate) static MAX_FAILED_LOGINS_PER_IDENTIFIER: i64 = 5;
+/// Higher than per identifier since many users can share an address behind a NAT
+static MAX_FAILED_LOGINS_PER_IP: i64 = 20;
+/// Failures older than this are forgotten
+static FAILED_LOGIN_WINDOW_IN_HOURS: i64 = 24;
+static BASE_LOCKOUT_IN_SECONDS: i64 = 60;
+static MAX_LOCKOUT_IN_MINUTES: i64 = 60;
+
+/// What failed logins are counted for: the identifier that was tried and, when
+/// known, the address the request came from
+pub struct LoginAttemptKeys {
+    identifier: String,
+    client_ip: Option<String>,
+}
+
+impl LoginAttemptKeys {
+    pub fn new(identifier: &Identifier, client_ip: Option<String>) -> Self {
+        let identifier = match identifier {
+            Identifier::Email(email) => email.to_lowercase(),
+            Identifier::Mobile(mobile) | Identifier::Oidc(mobile) => mobile.clone(),
+        };
+
+        Self { identifier, client_ip }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (LoginAttemptKind, &str)> {
+        std::iter::once((LoginAttemptKind::Identifier, self.identifier.as_str()))
+            .chain(self.client_ip.as_deref().map(|ip| (LoginAttemptKind::Ip, ip)))
+    }
+}
+
+/// The address of the peer that sent the current request.
+///
+/// Forwarded headers are ignored on purpose, anyone could set them to spread
+/// their attempts over made up addresses.
+pub async fn get_client_ip() -> Result<Option<String>> {
+    let request = extract::<HttpRequest>()
+        .await
+        .map_err(|e| anyhow!("Failed to extract the request: {}", e))?;
+
+    Ok(request.peer_addr().map(|address| address.ip().to_string()))
+}
+
+/// Fails with `AuthError::TooManyAttempts` while the identifier or the address
+/// is locked, without looking at the password at all.
+pub async fn ensure_login_allowed(keys: &LoginAttemptKeys) -> Result<()> {
+    let db = get_db();
+
+    let locked_until: Vec<Datetime> = db
+        .query(
+            "SELECT VALUE locked_until FROM login_attempts
+            WHERE locked_until > time::now()
+                AND ((kind = 'identifier' AND value = $identifier) OR (kind = 'ip' AND value = $client_ip))",
+        )
+        .bind(("identifier", keys.identifier.clone()))
+        .bind(("client_ip", keys.client_ip.clone()))
+        .await
+        .map_err(|e| AuthError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the login lockouts")?
+        .take(0)
+        .map_err(|e| AuthError::DatabaseError(Box::new(e)))?;
+
+    if let Some(locked_until) = locked_until.into_iter().max() {
+        let locked_until: DateTime<Utc> = locked_until.into();
+        let retry_after = locked_until - Utc::now();
+        Err(AuthError::TooManyAttempts(retry_after.num_seconds().max(1)))?
+    }
+
+    Ok(())
+}
+
+/// Counts a failed login for every key and locks the ones that went over their
+/// limit. Every failure past the limit doubles the lockout, up to
+/// `MAX_LOCKOUT_IN_MINUTES`.
+pub async fn record_failed_login(keys: &LoginAttemptKeys) -> Result<()> {
+    let db = get_db();
+    let window_start = Datetime::from(Utc::now() - Duration::hours(FAILED_LOGIN_WINDOW_IN_HOURS));
+
+    for (kind, value) in keys.iter() {
+        // `failures` is set before `last_failed_at` so it still sees the previous failure
+        let attempt: Option<LoginAttempt> = db
+            .query(
+                "UPSERT login_attempts
+                SET kind = $kind, value = $value,
+                    failures = IF last_failed_at > $window_start THEN failures + 1 ELSE 1 END,
+                    last_failed_at = time::now()
+                WHERE kind = $kind AND value = $value
+                RETURN AFTER",
+            )
+            .bind(("kind", kind))
+            .bind(("value", value.to_string()))
+            .bind(("window_start", window_start.clone()))
+            .await
+            .map_err(|e| AuthError::DatabaseError(Box::new(e)))
+            .with_context(|| "Failed to count the failed login")?
+            .take(0)
+            .map_err(|e| AuthError::DatabaseError(Box::new(e)))?;
+
+        let attempt = attempt.ok_or_else(|| anyhow!("Counting the failed login returned no data"))?;
+        let max_failures = match kind {
+            LoginAttemptKind::Identifier => MAX_FAILED_LOGINS_PER_IDENTIFIER,
+            LoginAttemptKind::Ip => MAX_FAILED_LOGINS_PER_IP,
+        };
+
+        if attempt.failures < max_failures {
+            continue;
+        }
+
+        let locked_until = Datetime::from(Utc::now() + lockout_duration(attempt.failures - max_failures));
+        db.query("UPDATE $attempt SET locked_until = $locked_until")
+            .bind(("attempt", attempt.id))
+            .bind(("locked_until", locked_until))
+            .await
+            .map_err(|e| AuthError::DatabaseError(Box::new(e)))
+            .with_context(|| "Failed to lock the login")?
+            .check()
+            .map_err(|e| AuthError::DatabaseError(Box::new(e)))?;
+    }
+
+    Ok(())
+}
+
+/// Forgets the failures of the identifier after it logged in successfully. The
+/// failures of the address are kept, one good login shouldn't hide guessing at
+/// other accounts.
+pub async fn clear_failed_logins(keys: &LoginAttemptKeys) -> Result<()> {
+    unlock_login(LoginAttemptKind::Identifier, &keys.identifier).await
+}
+
+/// Lifts the lockout and forgets the failures of an identifier or address.
+pub async fn unlock_login(kind: LoginAttemptKind, value: &str) -> Result<()> {
+    let db = get_db();
+
+    db.query("DELETE login_attempts WHERE kind = $kind AND value = $value")
+        .bind(("kind", kind))
+        .bind(("value", value.to_string()))
+        .await
+        .map_err(|e| AuthError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to delete the failed logins")?
+        .check()
+        .map_err(|e| AuthError::DatabaseError(Box::new(e)))?;
+
+    Ok(())
+}
+
+/// The identifiers and addresses that are locked right now, the longest
+/// lockouts first.
+pub async fn list_locked_logins() -> Result<Vec<LoginAttempt>> {
+    let db = get_db();
+
+    let locked: Vec<LoginAttempt> = db
+        .query("SELECT * FROM login_attempts WHERE locked_until > time::now() ORDER BY locked_until DESC")
+        .await
+        .map_err(|e| AuthError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the locked logins")?
+        .take(0)
+        .map_err(|e| AuthError::DatabaseError(Box::new(e)))?;
+
+    Ok(locked)
+}
+
+/// How long a key stays locked once it has failed `failures_over_limit` more
+/// times than its limit
+fn lockout_duration(failures_over_limit: i64) -> Duration {
+    // Past 2^16 the cap has long been reached, this only keeps the shift in range
+    let multiplier = 1i64 << failures_over_limit.clamp(0, 16);
+
+    Duration::seconds(BASE_LOCKOUT_IN_SECONDS * multiplier).min(Duration::minutes(MAX_LOCKOUT_IN_MINUTES))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::custom_auth::authenticate;
+    use crate::models::auth::LoginFormData;
+    use crate::test_support::{TEST_PASSWORD, register_test_user, run};
+
+    fn login_form(email: &str, password: &str) -> LoginFormData {
+        LoginFormData {
+            identifier: Identifier::Email(email.to_string()),
+            password: password.to_string(),
+        }
+    }
+
+    fn is_too_many_attempts(error: &anyhow::Error) -> bool {
+        matches!(error.downcast_ref::<AuthError>(), Some(AuthError::TooManyAttempts(_)))
+    }
+
+    #[test]
+    fn lockouts_double_up_to_the_cap() {
+        assert_eq!(lockout_duration(0), Duration::seconds(BASE_LOCKOUT_IN_SECONDS));
+        assert_eq!(lockout_duration(1), Duration::seconds(2 * BASE_LOCKOUT_IN_SECONDS));
+        assert_eq!(lockout_duration(1000), Duration::minutes(MAX_LOCKOUT_IN_MINUTES));
+    }
+
+    #[test]
+    fn wrong_passwords_lock_the_identifier_until_unlocked() {
+        run(async {
+            let email = "locked-login@example.com";
+            register_test_user(email).await;
+
+            for _ in 0..MAX_FAILED_LOGINS_PER_IDENTIFIER {
+                let error = authenticate(login_form(email, "not the password"), None).await.unwrap_err();
+                assert!(!is_too_many_attempts(&error));
+            }
+
+            let error = authenticate(login_form(email, TEST_PASSWORD), None).await.unwrap_err();
+            assert!(is_too_many_attempts(&error));
+
+            let locked = list_locked_logins().await.unwrap();
+            assert!(locked.iter().any(|attempt| attempt.value == email));
+
+            unlock_login(LoginAttemptKind::Identifier, email).await.unwrap();
+            authenticate(login_form(email, TEST_PASSWORD), None).await.unwrap();
+        });
+    }
+
+    #[test]
+    fn a_successful_login_forgets_the_failures_of_the_identifier() {
+        run(async {
+            let email = "forgiven-login@example.com";
+            register_test_user(email).await;
+
+            for _ in 1..MAX_FAILED_LOGINS_PER_IDENTIFIER {
+                authenticate(login_form(email, "not the password"), None).await.unwrap_err();
+            }
+            authenticate(login_form(email, TEST_PASSWORD), None).await.unwrap();
+
+            for _ in 1..MAX_FAILED_LOGINS_PER_IDENTIFIER {
+                authenticate(login_form(email, "not the password"), None).await.unwrap_err();
+            }
+            authenticate(login_form(email, TEST_PASSWORD), None).await.unwrap();
+        });
+    }
+
+    #[test]
+    fn guessing_across_identifiers_locks_the_address() {
+        run(async {
+            let client_ip = Some("203.0.113.7".to_string());
+
+            for attempt in 0..MAX_FAILED_LOGINS_PER_IP {
+                let email = format!("unknown-{}@example.com", attempt);
+                authenticate(login_form(&email, "not the password"), client_ip.clone()).await.unwrap_err();
+            }
+
+            let error = authenticate(login_form("someone-else@example.com", "not the password"), client_ip)
+                .await
+                .unwrap_err();
+            assert!(is_too_many_attempts(&error));
+        });
+    }
+}
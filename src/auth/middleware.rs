@@ -0,0 +1,222 @@
+use actix_web::{
+    Error, HttpRequest, HttpResponse,
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        Method,
+        header::{HeaderValue, ORIGIN, SET_COOKIE},
+    },
+    middleware::Next,
+};
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use tracing::{error, warn};
+
+use crate::{
+    auth::{session::renew_session, session_config::get_session_config},
+    errors::session::SessionError,
+    notifications::app_base_url,
+};
+
+/// Paths that never need the session to be renewed, e.g. static files
+const SKIPPED_PATH_PREFIXES: [&str; 3] = ["/pkg", "/assets", "/favicon.ico"];
+
+/// Extends sessions that are close to expiring and rotates their token once the
+/// rotation interval has passed, re-issuing the session cookie when either happens.
+///
+/// Renewal runs after the request has been handled so the handler still sees the
+/// token the client sent.
+pub async fn session_renewal(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let skip = SKIPPED_PATH_PREFIXES
+        .iter()
+        .any(|prefix| req.path().starts_with(prefix));
+    let cookie_config = &get_session_config().cookie;
+    let session_token = req
+        .cookie(&cookie_config.name)
+        .map(|cookie| cookie.value().to_string());
+
+    let mut response = next.call(req).await?;
+
+    let Some(session_token) = session_token.filter(|_| !skip) else {
+        return Ok(response);
+    };
+
+    // The handler already issued or cleared the session cookie (login, logout, ...)
+    if sets_session_cookie(&response, &cookie_config.name) {
+        return Ok(response);
+    }
+
+    match renew_session(&session_token).await {
+        Ok(Some(renewed_session)) => {
+            let expires_at: DateTime<Utc> = renewed_session.expires_at.into();
+            let max_age = (expires_at - Utc::now()).num_seconds();
+            let cookie = cookie_config.session_cookie(&renewed_session.session_token, max_age);
+
+            match HeaderValue::from_str(&cookie) {
+                Ok(value) => {
+                    response.headers_mut().append(SET_COOKIE, value);
+                },
+                Err(error) => error!(?error, "Failed to build the renewed session cookie"),
+            }
+        },
+        Ok(None) => {},
+        Err(error) => match error.downcast_ref::<SessionError>() {
+            Some(SessionError::DatabaseError(_)) | None => {
+                error!(?error, "Failed to renew the session");
+            },
+            // Invalid or expired sessions are rejected by whoever needs the user
+            Some(_) => {},
+        },
+    }
+
+    Ok(response)
+}
+
+/// Rejects state-changing requests that carry the session cookie but were sent
+/// from another site, e.g. a form on a malicious page posting to a server function.
+///
+/// The browser's `Sec-Fetch-Site` header is trusted when it is sent, otherwise
+/// the `Origin` has to be the one of `APP_BASE_URL`. Requests without the session
+/// cookie, e.g. those authenticated with an API token, can't be forged this way
+/// and pass, as does everything Leptos sends from the app's own pages.
+pub async fn csrf_protection(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    if is_cross_site_request(req.request(), &app_origin(), &get_session_config().cookie.name) {
+        warn!(path = req.path(), "Rejected a cross-site request");
+        let response = HttpResponse::Forbidden().body("Cross-site requests are not allowed");
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+/// The origin pages of the app are served from, e.g. `https://merzah.app`
+fn app_origin() -> String {
+    Url::parse(app_base_url())
+        .map(|url| url.origin().ascii_serialization())
+        .unwrap_or_default()
+}
+
+fn is_cross_site_request(request: &HttpRequest, app_origin: &str, cookie_name: &str) -> bool {
+    let is_safe = [Method::GET, Method::HEAD, Method::OPTIONS].contains(request.method());
+    if is_safe || request.cookie(cookie_name).is_none() {
+        return false;
+    }
+
+    let header = |name| request.headers().get(name).and_then(|value| value.to_str().ok());
+
+    match (header("sec-fetch-site"), header(ORIGIN.as_str())) {
+        // `none` is a request the user started themselves, e.g. from a bookmark
+        (Some(fetch_site), _) => !matches!(fetch_site, "same-origin" | "none"),
+        (None, Some(origin)) => origin != app_origin,
+        // Every browser sends one of them with a POST
+        (None, None) => true,
+    }
+}
+
+fn sets_session_cookie<B>(response: &ServiceResponse<B>, cookie_name: &str) -> bool {
+    let cookie_prefix = format!("{}=", cookie_name);
+
+    response
+        .headers()
+        .get_all(SET_COOKIE)
+        .any(|value| {
+            value
+                .to_str()
+                .map(|value| value.starts_with(&cookie_prefix))
+                .unwrap_or(false)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::session::{create_session, get_session_by_token},
+        database::connection::get_db,
+        models::session::DeviceInfo,
+        test_support::{register_test_user, run, session_cookie},
+    };
+    use actix_web::{App, cookie::Cookie, middleware::from_fn, test::{self, TestRequest}, web};
+
+    const APP_ORIGIN: &str = "https://merzah.example";
+    const COOKIE_NAME: &str = "__Host-session";
+
+    fn post_with_session() -> TestRequest {
+        TestRequest::post()
+            .uri("/api/change-password")
+            .cookie(Cookie::new(COOKIE_NAME, "token"))
+    }
+
+    #[test]
+    fn posts_from_other_sites_are_rejected() {
+        let cross_site = post_with_session()
+            .insert_header(("sec-fetch-site", "cross-site"))
+            .insert_header((ORIGIN, APP_ORIGIN))
+            .to_http_request();
+        assert!(is_cross_site_request(&cross_site, APP_ORIGIN, COOKIE_NAME));
+
+        let same_site = post_with_session().insert_header(("sec-fetch-site", "same-site")).to_http_request();
+        assert!(is_cross_site_request(&same_site, APP_ORIGIN, COOKIE_NAME));
+
+        let other_origin = post_with_session().insert_header((ORIGIN, "https://evil.example")).to_http_request();
+        assert!(is_cross_site_request(&other_origin, APP_ORIGIN, COOKIE_NAME));
+
+        assert!(is_cross_site_request(&post_with_session().to_http_request(), APP_ORIGIN, COOKIE_NAME));
+    }
+
+    #[test]
+    fn posts_from_the_app_and_requests_without_a_session_pass() {
+        let same_origin = post_with_session().insert_header(("sec-fetch-site", "same-origin")).to_http_request();
+        assert!(!is_cross_site_request(&same_origin, APP_ORIGIN, COOKIE_NAME));
+
+        let app_origin = post_with_session().insert_header((ORIGIN, APP_ORIGIN)).to_http_request();
+        assert!(!is_cross_site_request(&app_origin, APP_ORIGIN, COOKIE_NAME));
+
+        let get = TestRequest::get()
+            .cookie(Cookie::new(COOKIE_NAME, "token"))
+            .insert_header(("sec-fetch-site", "cross-site"))
+            .to_http_request();
+        assert!(!is_cross_site_request(&get, APP_ORIGIN, COOKIE_NAME));
+
+        let api_client = TestRequest::post()
+            .insert_header(("authorization", "Bearer mzh_token"))
+            .to_http_request();
+        assert!(!is_cross_site_request(&api_client, APP_ORIGIN, COOKIE_NAME));
+    }
+
+    #[test]
+    fn rotated_sessions_get_a_new_cookie() {
+        run(async {
+            let user_id = register_test_user("session-cookie-rotation@example.com").await;
+            let session_token = create_session(user_id.clone(), DeviceInfo::default()).await.unwrap();
+            get_db()
+                .query("UPDATE sessions SET token_rotated_at = time::now() - 1h WHERE user_id = $user_id")
+                .bind(("user_id", user_id))
+                .await
+                .unwrap()
+                .check()
+                .unwrap();
+
+            let app = test::init_service(
+                App::new().wrap(from_fn(session_renewal)).route("/", web::get().to(HttpResponse::Ok)),
+            )
+            .await;
+            let request = TestRequest::get().uri("/").cookie(session_cookie(&session_token)).to_request();
+            let response = test::call_service(&app, request).await;
+
+            let renewed_cookie = response
+                .response()
+                .cookies()
+                .find(|cookie| cookie.name() == get_session_config().cookie.name)
+                .unwrap();
+            assert_ne!(renewed_cookie.value(), session_token);
+            assert!(get_session_by_token(renewed_cookie.value()).await.is_ok());
+        });
+    }
+}
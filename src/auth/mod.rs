@@ -1,2 +1,18 @@
+pub mod account_deletion;
+pub mod api_tokens;
+pub mod audit;
 pub mod custom_auth;
+pub mod data_export;
+pub mod guards;
+pub mod identifiers;
+pub mod login_throttle;
+pub mod middleware;
+pub mod oidc;
+pub mod oidc_config;
+pub mod password_config;
+pub mod password_reset;
 pub mod session;
+pub mod session_config;
+pub mod two_factor;
+pub mod two_factor_config;
+pub mod verification;
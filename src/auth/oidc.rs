@@ -0,0 +1,639 @@
+use actix_web::HttpRequest;
+use actix_web::http::header::{HeaderValue, SET_COOKIE};
+use anyhow::{Context, Result, anyhow};
+use chrono::{Duration, Utc};
+use leptos::prelude::expect_context;
+use leptos_actix::{ResponseOptions, extract};
+use openidconnect::{
+    AuthorizationCode, ClientId, ClientSecret, CsrfToken, EndpointMaybeSet, EndpointNotSet,
+    EndpointSet, IssuerUrl, Nonce, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope,
+    TokenResponse,
+    core::{CoreAuthenticationFlow, CoreClient, CoreProviderMetadata},
+    reqwest,
+};
+use surrealdb::RecordId;
+use surrealdb::sql::Datetime;
+
+use crate::{
+    auth::{
+        custom_auth::{find_user_identifier, hash_password},
+        identifiers::list_identifiers,
+        oidc_config::{OidcProviderConfig, get_oidc_config},
+        session::{hash_session_token, session_token_matches, validate_session_token},
+        session_config::get_session_config,
+    },
+    database::connection::get_db,
+    errors::oidc::OidcError,
+    models::{
+        oidc::{CreateOidcState, OidcState},
+        user::{CreateUser, Identifier, User},
+    },
+    notifications::app_base_url,
+    utils::token_generator::generate_token,
+};
+
+static OIDC_STATE_DURATION_IN_MINUTES: i64 = 10;
+
+/// A client for a provider as set up from its discovery document
+type OidcClient = CoreClient<
+    EndpointSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointMaybeSet,
+    EndpointMaybeSet,
+>;
+
+/// What a sign in that came back from the provider led to
+#[derive(Debug, PartialEq)]
+pub enum OidcOutcome {
+    /// The user to create a session for, who was created first if the account
+    /// wasn't linked to anyone yet
+    SignedIn(RecordId),
+    /// The account got linked to the user who started the sign in
+    Linked,
+}
+
+/// A sign in waiting for the user at the provider
+#[derive(Debug)]
+pub struct Authorization {
+    /// Where to send the user to sign in
+    pub url: String,
+    /// What the provider sends back along with the code, which the browser also
+    /// keeps in a cookie
+    pub state: String,
+}
+
+/// The verified claims of the provider account signed in with
+struct ProviderAccount {
+    subject: String,
+    email: Option<String>,
+    name: Option<String>,
+}
+
+/// Where providers send users back to after they signed in
+pub fn oidc_redirect_url() -> String {
+    format!("{}/auth/oidc-callback", app_base_url())
+}
+
+/// Starts an authorization code flow with PKCE at the provider. With
+/// `link_user` set the provider account gets linked to that user instead of
+/// being signed in with.
+pub async fn begin_authorization(provider_name: &str, link_user: Option<RecordId>) -> Result<Authorization> {
+    let provider = find_provider(provider_name)?;
+    let http_client = http_client()?;
+    let client = discover_client(provider, &http_client).await?;
+
+    let state = generate_token();
+    let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+
+    let mut authorization_request = client
+        .authorize_url(
+            CoreAuthenticationFlow::AuthorizationCode,
+            {
+                let state = state.clone();
+                move || CsrfToken::new(state)
+            },
+            Nonce::new_random,
+        )
+        .set_pkce_challenge(pkce_challenge);
+    for scope in &provider.scopes {
+        authorization_request = authorization_request.add_scope(Scope::new(scope.clone()));
+    }
+    let (authorization_url, _, nonce) = authorization_request.url();
+
+    let oidc_state = CreateOidcState {
+        state_hash: hash_session_token(&state),
+        provider: provider.name.clone(),
+        nonce: nonce.secret().clone(),
+        pkce_verifier: pkce_verifier.secret().clone(),
+        link_user,
+        expires_at: Datetime::from(Utc::now() + Duration::minutes(OIDC_STATE_DURATION_IN_MINUTES)),
+    };
+
+    let surql = r#"
+            BEGIN TRANSACTION;
+
+            DELETE oidc_states WHERE expires_at <= time::now();
+            CREATE oidc_states CONTENT $oidc_state;
+
+            COMMIT TRANSACTION;
+        "#;
+
+    get_db()
+        .query(surql)
+        .bind(("oidc_state", oidc_state))
+        .await
+        .map_err(|e| OidcError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to store the OpenID Connect state")?
+        .check()
+        .map_err(|e| OidcError::DatabaseError(Box::new(e)))?;
+
+    Ok(Authorization { url: authorization_url.to_string(), state })
+}
+
+/// Finishes a sign in the provider sent back with `code`. It has to come back
+/// to the browser that started it, which kept the state in a cookie, so nobody
+/// can get their own sign in completed in someone else's browser. The state is
+/// used up either way, so every sign in can only come back once.
+pub async fn complete_authorization(state: &str, browser_state: Option<&str>, code: &str) -> Result<OidcOutcome> {
+    // Compared through their keyed hashes, in constant time
+    if !browser_state.is_some_and(|browser_state| session_token_matches(browser_state, &hash_session_token(state))) {
+        Err(OidcError::InvalidState)?
+    }
+
+    let oidc_state = take_state(state).await?;
+    let provider = find_provider(&oidc_state.provider)?;
+    let http_client = http_client()?;
+    let client = discover_client(provider, &http_client).await?;
+
+    let token_response = client
+        .exchange_code(AuthorizationCode::new(code.to_string()))
+        .map_err(|e| OidcError::Provider(e.to_string()))?
+        .set_pkce_verifier(PkceCodeVerifier::new(oidc_state.pkce_verifier))
+        .request_async(&http_client)
+        .await
+        .map_err(|e| OidcError::Provider(e.to_string()))?;
+
+    let id_token = token_response
+        .id_token()
+        .ok_or_else(|| OidcError::InvalidIdToken("the token response has no ID token".to_string()))?;
+    let claims = id_token
+        .claims(&client.id_token_verifier(), &Nonce::new(oidc_state.nonce))
+        .map_err(|e| OidcError::InvalidIdToken(e.to_string()))?;
+
+    let account = ProviderAccount {
+        subject: claims.subject().to_string(),
+        // An email the provider hasn't verified could belong to anyone
+        email: claims
+            .email()
+            .filter(|_| claims.email_verified() == Some(true))
+            .map(|email| email.to_string()),
+        name: claims.name().and_then(|name| name.get(None)).map(|name| name.to_string()),
+    };
+
+    match oidc_state.link_user {
+        Some(user_id) => {
+            link_account(user_id, &provider.name, &account.subject).await?;
+            Ok(OidcOutcome::Linked)
+        },
+        None => Ok(OidcOutcome::SignedIn(sign_in(&provider.name, account).await?)),
+    }
+}
+
+/// The user the provider account is linked to, who is created along with the
+/// link the first time they sign in
+async fn sign_in(provider_name: &str, account: ProviderAccount) -> Result<RecordId> {
+    let identifier = Identifier::oidc(provider_name, &account.subject);
+
+    if let Some(user_identifier) = find_user_identifier(&identifier).await? {
+        return Ok(user_identifier.user_id);
+    }
+
+    // Taking over an existing account needs its owner to link it while logged in
+    if let Some(email) = &account.email
+        && find_user_identifier(&Identifier::Email(email.clone())).await?.is_some()
+    {
+        Err(OidcError::EmailTaken)?
+    }
+
+    let user = CreateUser {
+        display_name: account
+            .name
+            .or_else(|| account.email.clone())
+            .unwrap_or_else(|| format!("{} user", provider_name)),
+        // Nobody knows this password, a real one can be set through a password reset
+        password_hash: hash_password(&generate_token())?,
+    };
+
+    let surql = r#"
+            BEGIN TRANSACTION;
+
+            LET $created_user = CREATE ONLY users CONTENT $user_data;
+
+            CREATE user_identifier CONTENT {
+                user_id: $created_user.id,
+                identifier_type: 'oidc',
+                identifier_value: $identifier_value,
+                oidc_provider: $provider,
+                verified_at: time::now(),
+                is_primary: $email = NONE
+            };
+
+            IF $email != NONE {
+                CREATE user_identifier CONTENT {
+                    user_id: $created_user.id,
+                    identifier_type: 'email',
+                    identifier_value: $email,
+                    verified_at: time::now(),
+                    is_primary: true
+                };
+            };
+
+            RETURN $created_user;
+            COMMIT TRANSACTION;
+        "#;
+
+    let created_user: Option<User> = get_db()
+        .query(surql)
+        .bind(("user_data", user))
+        .bind(("identifier_value", identifier.value().to_string()))
+        .bind(("provider", provider_name.to_string()))
+        .bind(("email", account.email))
+        .await
+        .map_err(|e| OidcError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to create the user of the provider account")?
+        .take(0)
+        .map_err(|e| OidcError::DatabaseError(Box::new(e)))?;
+    let created_user = created_user.ok_or_else(|| anyhow!("User creation returned no data"))?;
+
+    Ok(created_user.id)
+}
+
+/// Links the provider account to the user, who can sign in with it from then on
+async fn link_account(user_id: RecordId, provider_name: &str, subject: &str) -> Result<()> {
+    let identifier = Identifier::oidc(provider_name, subject);
+
+    if let Some(user_identifier) = find_user_identifier(&identifier).await? {
+        if user_identifier.user_id == user_id {
+            return Ok(());
+        }
+        Err(OidcError::AlreadyLinked)?
+    }
+
+    if list_identifiers(user_id.clone())
+        .await?
+        .iter()
+        .any(|linked| linked.identifier.oidc_provider() == Some(provider_name))
+    {
+        Err(OidcError::ProviderAlreadyLinked)?
+    }
+
+    get_db()
+        .query(
+            "CREATE user_identifier CONTENT { \
+                user_id: $user_id, identifier_type: 'oidc', identifier_value: $identifier_value, \
+                oidc_provider: $provider, verified_at: time::now() \
+            }",
+        )
+        .bind(("user_id", user_id))
+        .bind(("identifier_value", identifier.value().to_string()))
+        .bind(("provider", provider_name.to_string()))
+        .await
+        .map_err(|e| OidcError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to link the provider account")?
+        .check()
+        .map_err(|e| OidcError::DatabaseError(Box::new(e)))?;
+
+    Ok(())
+}
+
+/// Deletes the pending sign in behind `state` and returns it, unless it expired
+async fn take_state(state: &str) -> Result<OidcState> {
+    validate_session_token(state).map_err(|_| OidcError::InvalidState)?;
+
+    let oidc_state: Option<OidcState> = get_db()
+        .query("DELETE oidc_states WHERE state_hash = $state_hash RETURN BEFORE")
+        .bind(("state_hash", hash_session_token(state)))
+        .await
+        .map_err(|e| OidcError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to take the OpenID Connect state")?
+        .take(0)
+        .map_err(|e| OidcError::DatabaseError(Box::new(e)))?;
+
+    let oidc_state = oidc_state
+        .filter(|oidc_state| oidc_state.expires_at > Datetime::from(Utc::now()))
+        .ok_or(OidcError::InvalidState)?;
+
+    Ok(oidc_state)
+}
+
+/// Keeps the state of a sign in in the browser until the provider sends it back
+pub fn set_state_cookie(state: &str) -> Result<()> {
+    let cookie = get_session_config()
+        .cookie
+        .oidc_state_cookie(state, OIDC_STATE_DURATION_IN_MINUTES * 60);
+
+    // Appended, as the callback may set the session cookie as well
+    expect_context::<ResponseOptions>().append_header(
+        SET_COOKIE,
+        HeaderValue::from_str(&cookie).with_context(|| "Failed to set the OpenID Connect state cookie")?,
+    );
+
+    Ok(())
+}
+
+/// The state of the sign in the browser started, if any
+pub async fn get_state_from_request() -> Result<Option<String>> {
+    let request = extract::<HttpRequest>()
+        .await
+        .map_err(|e| anyhow!("Failed to extract the request: {}", e))?;
+
+    let cookie = request.cookie(&get_session_config().cookie.oidc_state_cookie_name());
+
+    Ok(cookie.map(|cookie| cookie.value().to_string()))
+}
+
+pub fn clear_state_cookie() -> Result<()> {
+    let cookie = get_session_config().cookie.cleared_oidc_state_cookie();
+
+    expect_context::<ResponseOptions>().append_header(
+        SET_COOKIE,
+        HeaderValue::from_str(&cookie).with_context(|| "Failed to clear the OpenID Connect state cookie")?,
+    );
+
+    Ok(())
+}
+
+fn find_provider(provider_name: &str) -> Result<&'static OidcProviderConfig> {
+    let provider = get_oidc_config()
+        .provider(provider_name)
+        .ok_or_else(|| OidcError::UnknownProvider(provider_name.to_string()))?;
+
+    Ok(provider)
+}
+
+/// Following redirects would let a provider point the token request elsewhere
+fn http_client() -> Result<reqwest::Client> {
+    let http_client = reqwest::ClientBuilder::new()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .with_context(|| "Failed to build the OpenID Connect http client")?;
+
+    Ok(http_client)
+}
+
+async fn discover_client(provider: &OidcProviderConfig, http_client: &reqwest::Client) -> Result<OidcClient> {
+    let issuer_url = IssuerUrl::new(provider.issuer_url.clone())
+        .with_context(|| format!("The issuer url of {} is invalid", provider.name))?;
+    let provider_metadata = CoreProviderMetadata::discover_async(issuer_url, http_client)
+        .await
+        .map_err(|e| OidcError::Provider(e.to_string()))?;
+
+    let client = CoreClient::from_provider_metadata(
+        provider_metadata,
+        ClientId::new(provider.client_id.clone()),
+        provider.client_secret.clone().map(ClientSecret::new),
+    )
+    .set_redirect_uri(RedirectUrl::new(oidc_redirect_url()).with_context(|| "APP_BASE_URL is not a valid url")?);
+
+    Ok(client)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::auth::oidc_config::{OidcConfig, init_oidc_config};
+    use crate::test_support::{register_test_user, run};
+    use base64::{Engine as _, engine::general_purpose};
+    use openidconnect::{
+        Audience, EmptyAdditionalClaims, EndUserEmail, EndUserName, IssuerUrl, JsonWebKeyId,
+        LocalizedClaim, PrivateSigningKey, StandardClaims, SubjectIdentifier,
+        core::{
+            CoreIdToken, CoreIdTokenClaims, CoreJsonWebKeySet, CoreJwsSigningAlgorithm,
+            CoreResponseType, CoreRsaPrivateSigningKey, CoreSubjectIdentifierType,
+        },
+        ResponseTypes, JsonWebKeySetUrl, AuthUrl, TokenUrl,
+    };
+    use reqwest::Url;
+    use sha2::{Digest, Sha256};
+    use tokio::sync::OnceCell;
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate, matchers::{method, path}};
+
+    const CLIENT_ID: &str = "merzah-test-client";
+    const SIGNING_KEY: &str = include_str!("testdata/oidc_signing_key.pem");
+
+    static MOCK_ISSUER: OnceCell<MockServer> = OnceCell::const_new();
+
+    fn signing_key() -> CoreRsaPrivateSigningKey {
+        CoreRsaPrivateSigningKey::from_pem(SIGNING_KEY, Some(JsonWebKeyId::new("test-key".to_string()))).unwrap()
+    }
+
+    /// What the mock issuer puts into the ID token, carried in the authorization
+    /// code since the mock doesn't remember anything between requests
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct MockAuthorization {
+        subject: String,
+        email: Option<String>,
+        email_verified: bool,
+        nonce: String,
+        code_challenge: String,
+    }
+
+    /// Answers token requests with an ID token for the authorization in the
+    /// code, if the PKCE verifier matches the challenge it was started with
+    struct MockTokenEndpoint {
+        issuer: String,
+    }
+
+    impl wiremock::Respond for MockTokenEndpoint {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            let form = Url::parse(&format!("http://form/?{}", String::from_utf8_lossy(&request.body))).unwrap();
+            let param = |name: &str| {
+                form.query_pairs()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.to_string())
+                    .unwrap_or_default()
+            };
+
+            let authorization: MockAuthorization = serde_json::from_str(&param("code")).unwrap();
+            let verifier_challenge = general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(param("code_verifier")));
+            if verifier_challenge != authorization.code_challenge {
+                return ResponseTemplate::new(400).set_body_json(serde_json::json!({ "error": "invalid_grant" }));
+            }
+
+            let standard_claims = StandardClaims::new(SubjectIdentifier::new(authorization.subject))
+                .set_email(authorization.email.map(EndUserEmail::new))
+                .set_email_verified(Some(authorization.email_verified))
+                .set_name(Some(LocalizedClaim::from(EndUserName::new("Provider User".to_string()))));
+            let claims = CoreIdTokenClaims::new(
+                IssuerUrl::new(self.issuer.clone()).unwrap(),
+                vec![Audience::new(CLIENT_ID.to_string())],
+                Utc::now() + Duration::minutes(5),
+                Utc::now(),
+                standard_claims,
+                EmptyAdditionalClaims {},
+            )
+            .set_nonce(Some(Nonce::new(authorization.nonce)));
+            let id_token = CoreIdToken::new(claims, &signing_key(), CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256, None, None)
+                .unwrap();
+
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "mock-access-token",
+                "token_type": "Bearer",
+                "expires_in": 300,
+                "id_token": id_token.to_string(),
+            }))
+        }
+    }
+
+    /// Starts the mock issuer the first time and configures it as the `mock`
+    /// provider
+    async fn mock_issuer() -> &'static MockServer {
+        MOCK_ISSUER
+            .get_or_init(|| async {
+                let server = MockServer::start().await;
+                let issuer = server.uri();
+
+                let provider_metadata = CoreProviderMetadata::new(
+                    IssuerUrl::new(issuer.clone()).unwrap(),
+                    AuthUrl::new(format!("{}/authorize", issuer)).unwrap(),
+                    JsonWebKeySetUrl::new(format!("{}/jwks", issuer)).unwrap(),
+                    vec![ResponseTypes::new(vec![CoreResponseType::Code])],
+                    vec![CoreSubjectIdentifierType::Public],
+                    vec![CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256],
+                    Default::default(),
+                )
+                .set_token_endpoint(Some(TokenUrl::new(format!("{}/token", issuer)).unwrap()));
+
+                Mock::given(method("GET"))
+                    .and(path("/.well-known/openid-configuration"))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(&provider_metadata))
+                    .mount(&server)
+                    .await;
+                Mock::given(method("GET"))
+                    .and(path("/jwks"))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(CoreJsonWebKeySet::new(vec![
+                        signing_key().as_verification_key(),
+                    ])))
+                    .mount(&server)
+                    .await;
+                Mock::given(method("POST"))
+                    .and(path("/token"))
+                    .respond_with(MockTokenEndpoint { issuer: issuer.clone() })
+                    .mount(&server)
+                    .await;
+
+                init_oidc_config(OidcConfig {
+                    providers: vec![OidcProviderConfig {
+                        name: "mock".to_string(),
+                        display_name: "Mock".to_string(),
+                        issuer_url: issuer,
+                        client_id: CLIENT_ID.to_string(),
+                        client_secret: None,
+                        scopes: vec!["email".to_string(), "profile".to_string()],
+                    }],
+                });
+
+                server
+            })
+            .await
+    }
+
+    /// Goes through the provider's side of a sign in, returning the state and
+    /// code it would send the user back with
+    pub(crate) async fn authorize_at_provider(link_user: Option<RecordId>, subject: &str, email: Option<&str>) -> (String, String) {
+        mock_issuer().await;
+        let authorization = begin_authorization("mock", link_user).await.unwrap();
+        let authorization_url = Url::parse(&authorization.url).unwrap();
+        let param = |name: &str| {
+            authorization_url
+                .query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.to_string())
+                .unwrap()
+        };
+        assert_eq!(param("code_challenge_method"), "S256");
+        assert_eq!(param("redirect_uri"), oidc_redirect_url());
+
+        let code = serde_json::to_string(&MockAuthorization {
+            subject: subject.to_string(),
+            email: email.map(str::to_string),
+            email_verified: true,
+            nonce: param("nonce"),
+            code_challenge: param("code_challenge"),
+        })
+        .unwrap();
+
+        assert_eq!(param("state"), authorization.state);
+        (authorization.state, code)
+    }
+
+    /// Completes the sign in in the browser that started it
+    async fn complete(state: &str, code: &str) -> Result<OidcOutcome> {
+        complete_authorization(state, Some(state), code).await
+    }
+
+    fn oidc_error(error: &anyhow::Error) -> Option<&OidcError> {
+        error.downcast_ref::<OidcError>()
+    }
+
+    #[test]
+    fn the_first_sign_in_creates_the_user_and_later_ones_find_them() {
+        run(async {
+            let (state, code) = authorize_at_provider(None, "subject-new", Some("oidc-new@example.com")).await;
+            let OidcOutcome::SignedIn(user_id) = complete(&state, &code).await.unwrap() else {
+                panic!("The sign in should not link");
+            };
+
+            let identifiers = list_identifiers(user_id.clone()).await.unwrap();
+            assert_eq!(identifiers.len(), 2);
+            assert_eq!(identifiers[0].identifier, Identifier::Email("oidc-new@example.com".to_string()));
+            assert!(identifiers[0].is_primary && identifiers[0].verified_at.is_some());
+            assert_eq!(identifiers[1].identifier, Identifier::oidc("mock", "subject-new"));
+
+            // The state only works once
+            let error = complete(&state, &code).await.unwrap_err();
+            assert!(matches!(oidc_error(&error), Some(OidcError::InvalidState)));
+
+            let (state, code) = authorize_at_provider(None, "subject-new", Some("oidc-new@example.com")).await;
+            assert_eq!(complete(&state, &code).await.unwrap(), OidcOutcome::SignedIn(user_id));
+        });
+    }
+
+    #[test]
+    fn a_wrong_pkce_verifier_fails_the_exchange() {
+        run(async {
+            let (state, code) = authorize_at_provider(None, "subject-pkce", None).await;
+            let mut authorization: MockAuthorization = serde_json::from_str(&code).unwrap();
+            authorization.code_challenge = "not-the-challenge".to_string();
+
+            let error = complete(&state, &serde_json::to_string(&authorization).unwrap())
+                .await
+                .unwrap_err();
+            assert!(matches!(oidc_error(&error), Some(OidcError::Provider(_))));
+        });
+    }
+
+    #[test]
+    fn existing_users_link_their_account_instead_of_being_taken_over() {
+        run(async {
+            let user_id = register_test_user("oidc-existing@example.com").await;
+
+            let (state, code) = authorize_at_provider(None, "subject-existing", Some("oidc-existing@example.com")).await;
+            let error = complete(&state, &code).await.unwrap_err();
+            assert!(matches!(oidc_error(&error), Some(OidcError::EmailTaken)));
+
+            let (state, code) = authorize_at_provider(Some(user_id.clone()), "subject-existing", Some("oidc-existing@example.com")).await;
+            assert_eq!(complete(&state, &code).await.unwrap(), OidcOutcome::Linked);
+
+            let (state, code) = authorize_at_provider(None, "subject-existing", Some("oidc-existing@example.com")).await;
+            assert_eq!(complete(&state, &code).await.unwrap(), OidcOutcome::SignedIn(user_id.clone()));
+
+            let other_user_id = register_test_user("oidc-other@example.com").await;
+            let (state, code) = authorize_at_provider(Some(other_user_id), "subject-existing", None).await;
+            let error = complete(&state, &code).await.unwrap_err();
+            assert!(matches!(oidc_error(&error), Some(OidcError::AlreadyLinked)));
+
+            let (state, code) = authorize_at_provider(Some(user_id), "subject-second", None).await;
+            let error = complete(&state, &code).await.unwrap_err();
+            assert!(matches!(oidc_error(&error), Some(OidcError::ProviderAlreadyLinked)));
+        });
+    }
+
+    #[test]
+    fn sign_ins_only_complete_in_the_browser_that_started_them() {
+        run(async {
+            let (state, code) = authorize_at_provider(None, "subject-browser", None).await;
+            let (other_state, _) = authorize_at_provider(None, "subject-browser", None).await;
+
+            for browser_state in [None, Some(other_state.as_str())] {
+                let error = complete_authorization(&state, browser_state, &code).await.unwrap_err();
+                assert!(matches!(oidc_error(&error), Some(OidcError::InvalidState)));
+            }
+
+            // The sign in is still there for the browser that started it
+            assert!(matches!(complete(&state, &code).await.unwrap(), OidcOutcome::SignedIn(_)));
+        });
+    }
+}
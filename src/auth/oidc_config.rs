@@ -0,0 +1,41 @@
+use once_cell::sync::OnceCell;
+
+static OIDC_CONFIG: OnceCell<OidcConfig> = OnceCell::new();
+
+/// An OpenID Connect provider users can sign in with, e.g. Google
+#[derive(Debug, Clone)]
+pub struct OidcProviderConfig {
+    /// Short name used in urls and stored with the linked accounts, e.g. `google`
+    pub name: String,
+    /// Shown on the sign in button
+    pub display_name: String,
+    /// Where the provider's `/.well-known/openid-configuration` is found
+    pub issuer_url: String,
+    pub client_id: String,
+    /// Left out for public clients, which rely on PKCE alone
+    pub client_secret: Option<String>,
+    /// Asked for on top of `openid`
+    pub scopes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct OidcConfig {
+    pub providers: Vec<OidcProviderConfig>,
+}
+
+impl OidcConfig {
+    pub fn provider(&self, name: &str) -> Option<&OidcProviderConfig> {
+        self.providers.iter().find(|provider| provider.name == name)
+    }
+}
+
+/// Sets the OpenID Connect config, as loaded with the `AppConfig`
+pub fn init_oidc_config(config: OidcConfig) {
+    OIDC_CONFIG
+        .set(config)
+        .unwrap_or_else(|_| panic!("OpenID Connect config already initialized"));
+}
+
+pub fn get_oidc_config() -> &'static OidcConfig {
+    OIDC_CONFIG.get().expect("OpenID Connect config not initialized")
+}
@@ -0,0 +1,48 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use once_cell::sync::OnceCell;
+
+static PASSWORD_CONFIG: OnceCell<PasswordConfig> = OnceCell::new();
+
+/// The Argon2id cost new password hashes are made with. Hashes made with lower
+/// costs are upgraded the next time their user logs in.
+#[derive(Debug, Clone)]
+pub struct PasswordConfig {
+    /// Memory per hash, in KiB
+    pub memory_cost: u32,
+    /// Number of passes over the memory
+    pub iterations: u32,
+    /// Number of lanes hashed in parallel
+    pub parallelism: u32,
+}
+
+impl Default for PasswordConfig {
+    fn default() -> Self {
+        Self {
+            memory_cost: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl PasswordConfig {
+    pub fn params(&self) -> Params {
+        Params::new(self.memory_cost, self.iterations, self.parallelism, None)
+            .expect("The password config was validated when it was loaded")
+    }
+
+    pub fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params())
+    }
+}
+
+/// Sets the password config, as loaded with the `AppConfig`
+pub fn init_password_config(config: PasswordConfig) {
+    PASSWORD_CONFIG
+        .set(config)
+        .unwrap_or_else(|_| panic!("Password config already initialized"));
+}
+
+pub fn get_password_config() -> &'static PasswordConfig {
+    PASSWORD_CONFIG.get().expect("Password config not initialized")
+}
@@ -0,0 +1,178 @@
+use anyhow::{Context, Result};
+use chrono::{Duration, Utc};
+use surrealdb::RecordId;
+use surrealdb::sql::Datetime;
+
+use crate::{
+    auth::{
+        custom_auth::{find_user_identifier, hash_password},
+        session::{hash_session_token, validate_session_token},
+    },
+    database::connection::get_db,
+    errors::password_reset::PasswordResetError,
+    models::{
+        password_reset::{CreatePasswordResetToken, PasswordResetToken},
+        user::Identifier,
+    },
+    notifications::{OutboundMessage, app_base_url, get_delivery_channel},
+    utils::token_generator::generate_token,
+};
+
+static PASSWORD_RESET_TOKEN_DURATION_IN_MINUTES: i64 = 30;
+
+/// Issues a single-use reset token for the account behind `identifier` and sends
+/// the reset link to that identifier.
+///
+/// Unknown identifiers are silently ignored so callers can't probe for accounts.
+pub async fn request_password_reset(identifier: Identifier) -> Result<()> {
+    let Some(user_identifier) = find_user_identifier(&identifier).await? else {
+        return Ok(());
+    };
+
+    let db = get_db();
+
+    // Only the most recently requested link should work
+    db.query("DELETE password_reset_tokens WHERE user_id = $user_id")
+        .bind(("user_id", user_identifier.user_id.clone()))
+        .await
+        .map_err(|e| PasswordResetError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to delete the previous password reset tokens")?;
+
+    let token = generate_token();
+    let reset_token = CreatePasswordResetToken {
+        user_id: user_identifier.user_id,
+        token_hash: hash_session_token(&token),
+        expires_at: Datetime::from(
+            Utc::now() + Duration::minutes(PASSWORD_RESET_TOKEN_DURATION_IN_MINUTES),
+        ),
+    };
+
+    let _: Option<PasswordResetToken> = db
+        .create("password_reset_tokens")
+        .content(reset_token)
+        .await
+        .map_err(|e| PasswordResetError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to create a password reset token")?;
+
+    let message = OutboundMessage {
+        recipient: identifier,
+        subject: "Reset your Merzah password".to_string(),
+        body: format!(
+            "Use the following link to reset your password, it expires in {} minutes:\n{}/reset-password?token={}",
+            PASSWORD_RESET_TOKEN_DURATION_IN_MINUTES,
+            app_base_url(),
+            token
+        ),
+    };
+
+    get_delivery_channel()
+        .deliver(message)
+        .await
+        .map_err(PasswordResetError::DeliveryError)?;
+
+    Ok(())
+}
+
+/// Consumes the reset token, replaces the user's password and logs the user out
+/// of every session, returning who the user was.
+pub async fn reset_password(token: &str, new_password: &str) -> Result<RecordId> {
+    validate_session_token(token).map_err(|_| PasswordResetError::InvalidToken)?;
+
+    let password_hash = hash_password(new_password)?;
+
+    let db = get_db();
+
+    // Marking the token as used in the same statement that checks it keeps it single-use
+    let consumed_tokens: Vec<PasswordResetToken> = db
+        .query(
+            "UPDATE password_reset_tokens SET used_at = time::now()
+            WHERE token_hash = $token_hash AND used_at = NONE AND expires_at > time::now()",
+        )
+        .bind(("token_hash", hash_session_token(token)))
+        .await
+        .map_err(|e| PasswordResetError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to consume the password reset token")?
+        .take(0)
+        .map_err(|e| PasswordResetError::DatabaseError(Box::new(e)))?;
+
+    let reset_token = consumed_tokens
+        .into_iter()
+        .next()
+        .ok_or(PasswordResetError::InvalidToken)?;
+
+    let surql = r#"
+            BEGIN TRANSACTION;
+
+            UPDATE $user_id SET password_hash = $password_hash, updated_at = time::now();
+            DELETE sessions WHERE user_id = $user_id;
+
+            COMMIT TRANSACTION;
+        "#;
+
+    db.query(surql)
+        .bind(("user_id", reset_token.user_id.clone()))
+        .bind(("password_hash", password_hash))
+        .await
+        .map_err(|e| PasswordResetError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to update the password and invalidate the sessions")?
+        .check()
+        .map_err(|e| PasswordResetError::DatabaseError(Box::new(e)))?;
+
+    Ok(reset_token.user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{messages_to, register_test_user, run};
+
+    /// The token of the last reset link sent to `email`
+    fn sent_reset_token(email: &str) -> String {
+        let message = messages_to(email).pop().expect("No reset link was sent");
+        let (_, token) = message.split_once("token=").expect("The message has no reset link");
+
+        token.split_whitespace().next().unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn reset_links_are_delivered_and_work_once() {
+        run(async {
+            let email = "reset-once@example.com";
+            let user_id = register_test_user(email).await;
+
+            request_password_reset(Identifier::Email(email.to_string())).await.unwrap();
+            let token = sent_reset_token(email);
+
+            assert_eq!(reset_password(&token, "a brand new password").await.unwrap(), user_id);
+
+            let error = reset_password(&token, "another new password").await.unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<PasswordResetError>(),
+                Some(PasswordResetError::InvalidToken)
+            ));
+        });
+    }
+
+    #[test]
+    fn only_the_hash_of_a_reset_token_is_stored() {
+        run(async {
+            let email = "reset-hash@example.com";
+            let user_id = register_test_user(email).await;
+
+            request_password_reset(Identifier::Email(email.to_string())).await.unwrap();
+            let token = sent_reset_token(email);
+
+            let stored: Vec<PasswordResetToken> = get_db()
+                .query("SELECT * FROM password_reset_tokens WHERE user_id = $user_id")
+                .bind(("user_id", user_id))
+                .await
+                .unwrap()
+                .take(0)
+                .unwrap();
+
+            assert_eq!(stored.len(), 1);
+            assert_eq!(stored[0].token_hash, hash_session_token(&token));
+            assert_ne!(stored[0].token_hash, token);
+        });
+    }
+}
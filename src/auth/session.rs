@@ -1,33 +1,52 @@
-use actix_web::http::header::{HeaderValue, SET_COOKIE};
-use anyhow::{Context, Result};
-use chrono::{Duration, Utc};
+use actix_web::{HttpMessage, HttpRequest};
+use actix_web::http::header::{HeaderValue, SET_COOKIE, USER_AGENT};
+use anyhow::{Context, Result, anyhow};
+use base64::{Engine as _, engine::general_purpose};
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
 use leptos::prelude::expect_context;
-use leptos_actix::ResponseOptions;
+use leptos_actix::{ResponseOptions, extract};
+use sha2::Sha256;
 use surrealdb::RecordId;
 use surrealdb::sql::Datetime;
 
 use crate::{
+    auth::{
+        api_tokens::{ApiTokenGrant, authenticate_api_token, bearer_token},
+        session_config::get_session_config,
+    },
     database::connection::get_db,
-    errors::session::SessionError,
+    errors::{api_token::ApiTokenError, session::SessionError},
     models::{
-        session::{CreateSession, Session, UpdateSession},
+        session::{CreateSession, DeviceInfo, RenewedSession, Session, UpdateSession},
         user::User,
     },
     utils::token_generator::generate_token,
 };
 
-static SESSION_DURATION_IN_HOURS: i64 = 1;
+type HmacSha256 = Hmac<Sha256>;
+
+/// How long the token a session had before its rotation keeps working, for
+/// requests the browser sent before it got the new cookie, e.g. the parallel
+/// fetches of a page load
+const ROTATION_GRACE_PERIOD_IN_SECONDS: i64 = 30;
+/// `last_seen_at` is only written when it is at least this old, so busy sessions
+/// don't cause a write on every request
+const LAST_SEEN_PRECISION_IN_SECONDS: i64 = 300;
+const MAX_USER_AGENT_LENGTH: usize = 512;
 
-pub async fn create_session(user_id: RecordId) -> Result<String> {
+pub async fn create_session(user_id: RecordId, device: DeviceInfo) -> Result<String> {
     let db = get_db();
 
     let session_token = generate_token();
-    let expires_at = Datetime::from(Utc::now() + Duration::hours(SESSION_DURATION_IN_HOURS));
+    let expires_at = Datetime::from(Utc::now() + get_session_config().idle_timeout);
 
     let session = CreateSession {
         user_id,
-        session_token: session_token.clone(),
+        session_token_hash: hash_session_token(&session_token),
         expires_at,
+        user_agent: device.user_agent,
+        ip_address: device.ip_address,
     };
 
     let _: Option<CreateSession> = db
@@ -41,42 +60,100 @@ pub async fn create_session(user_id: RecordId) -> Result<String> {
 }
 
 pub async fn get_user_by_session(session_token: &str) -> Result<User> {
-    validate_session_token(session_token)?;
+    let session = get_session_by_token(session_token).await?;
 
     let db = get_db();
-
-    let result_from_sessions_table: Option<Session> = db
-        .query("SELECT * FROM sessions WHERE session_token = $token")
-        .bind(("token", session_token.to_string()))
+    let result_from_user_table = db
+        .select(session.user_id)
         .await
         .map_err(|e| SessionError::DatabaseError(Box::new(e)))
-        .with_context(|| "Failed to fetch the session details")?
-        .take(0)?;
-
-    if let Some(session) = result_from_sessions_table {
-        if session.expires_at >= Datetime::from(Utc::now()) {
-            Err(SessionError::SessionExpired(session.expires_at))?;
-        }
+        .with_context(|| "Failed to fetch the user of the session")?;
 
-        let result_from_user_table = db.select(session.user_id).await?;
-
-        if let Some(user) = result_from_user_table {
-            Ok(user)
-        } else {
-            Err(SessionError::UserNotFound)?
-        }
+    if let Some(user) = result_from_user_table {
+        Ok(user)
     } else {
-        Err(SessionError::SessionNotFound)?
+        Err(SessionError::UserNotFound)?
     }
 }
 
+/// Resolves the user making the current request from the `Authorization: Bearer`
+/// API token it was sent with, or else from the session cookie. The scopes of an
+/// API token are kept with the request for `ensure_api_token_scope`.
+///
+/// Works inside `#[server]` functions and during SSR. Any missing, malformed,
+/// unknown or expired token or session is reported as
+/// `SessionError::Unauthenticated` so callers only have to check for a single variant.
+pub async fn get_current_user() -> Result<User> {
+    let request = extract::<HttpRequest>()
+        .await
+        .map_err(|e| anyhow!("Failed to extract the request: {}", e))?;
+
+    let Some(api_token) = bearer_token(&request) else {
+        return get_session_user().await;
+    };
+
+    let (user, api_token) = authenticate_api_token(&api_token)
+        .await
+        .map_err(|error| match error.downcast_ref::<ApiTokenError>() {
+            Some(ApiTokenError::DatabaseError(_)) | None => error,
+            Some(_) => SessionError::Unauthenticated.into(),
+        })?;
+    request.extensions_mut().insert(ApiTokenGrant { scopes: api_token.scopes });
+
+    Ok(user)
+}
+
+/// Resolves the user making the current request from the session cookie alone,
+/// for what API tokens mustn't be used for, e.g. changing the password.
+pub async fn get_session_user() -> Result<User> {
+    let session_token = get_session_token_from_request().await?;
+
+    get_user_by_session(&session_token)
+        .await
+        .map_err(|error| match error.downcast_ref::<SessionError>() {
+            Some(SessionError::DatabaseError(_)) | None => error,
+            Some(_) => SessionError::Unauthenticated.into(),
+        })
+}
+
+pub async fn get_session_token_from_request() -> Result<String> {
+    let request = extract::<HttpRequest>()
+        .await
+        .map_err(|e| anyhow!("Failed to extract the request: {}", e))?;
+
+    let cookie = request
+        .cookie(&get_session_config().cookie.name)
+        .ok_or(SessionError::Unauthenticated)?;
+
+    Ok(cookie.value().to_string())
+}
+
+/// The user agent and address of the current request, to remember which device
+/// a session belongs to.
+pub async fn get_device_info() -> Result<DeviceInfo> {
+    let request = extract::<HttpRequest>()
+        .await
+        .map_err(|e| anyhow!("Failed to extract the request: {}", e))?;
+
+    let user_agent = request
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.chars().take(MAX_USER_AGENT_LENGTH).collect());
+
+    Ok(DeviceInfo {
+        user_agent,
+        ip_address: request.peer_addr().map(|address| address.ip().to_string()),
+    })
+}
+
 pub async fn delete_session(session_token: &str) -> Result<()> {
     validate_session_token(session_token)?;
 
     let db = get_db();
 
-    db.query("DELETE sessions WHERE session_token = $token")
-        .bind(("token", session_token.to_string()))
+    db.query("DELETE sessions WHERE session_token_hash = $token_hash OR previous_session_token_hash = $token_hash")
+        .bind(("token_hash", hash_session_token(session_token)))
         .await
         .map_err(|e| SessionError::DatabaseError(Box::new(e)))
         .with_context(|| "Failed to delete the session ")?;
@@ -84,91 +161,230 @@ pub async fn delete_session(session_token: &str) -> Result<()> {
     Ok(())
 }
 
-pub async fn update_session_token(user_id: RecordId) -> Result<String> {
+pub async fn delete_all_sessions_for_user(user_id: RecordId) -> Result<()> {
     let db = get_db();
-    let new_session_token = generate_token();
 
-    let updated_session = UpdateSession {
-        session_token: Some(new_session_token.clone()),
-        expires_at: None,
-    };
+    db.query("DELETE sessions WHERE user_id = $user_id")
+        .bind(("user_id", user_id))
+        .await
+        .map_err(|e| SessionError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to delete the sessions of the user")?;
 
-    let _: Option<Session> = db
-        .update(user_id.clone())
-        .merge(updated_session)
+    Ok(())
+}
+
+/// The sessions of the user that haven't expired, the most recently used first.
+pub async fn list_sessions_for_user(user_id: RecordId) -> Result<Vec<Session>> {
+    let db = get_db();
+
+    let sessions: Vec<Session> = db
+        .query("SELECT * FROM sessions WHERE user_id = $user_id AND expires_at > time::now() ORDER BY last_seen_at DESC")
+        .bind(("user_id", user_id))
+        .await
+        .map_err(|e| SessionError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the sessions of the user")?
+        .take(0)
+        .map_err(|e| SessionError::DatabaseError(Box::new(e)))?;
+
+    Ok(sessions)
+}
+
+/// Ends one session of the user. Fails with `SessionError::SessionNotFound` when
+/// the session doesn't exist or belongs to someone else.
+pub async fn revoke_session_for_user(user_id: RecordId, session_id: RecordId) -> Result<()> {
+    let db = get_db();
+
+    let revoked: Vec<Session> = db
+        .query("DELETE sessions WHERE id = $session_id AND user_id = $user_id RETURN BEFORE")
+        .bind(("session_id", session_id))
+        .bind(("user_id", user_id))
         .await
         .map_err(|e| SessionError::DatabaseError(Box::new(e)))
-        .with_context(|| "Failed to update the token for a user")?;
+        .with_context(|| "Failed to revoke the session")?
+        .take(0)
+        .map_err(|e| SessionError::DatabaseError(Box::new(e)))?;
 
-    Ok(new_session_token)
+    if revoked.is_empty() {
+        Err(SessionError::SessionNotFound)?
+    }
+
+    Ok(())
 }
 
-pub async fn update_session_expiry(user_id: RecordId) -> Result<()> {
+pub async fn get_session_by_token(session_token: &str) -> Result<Session> {
+    validate_session_token(session_token)?;
+
     let db = get_db();
 
     let session: Option<Session> = db
-        .select(user_id.clone())
+        .query(
+            "SELECT * FROM sessions
+            WHERE session_token_hash = $token_hash
+                OR (previous_session_token_hash = $token_hash AND previous_token_valid_until > time::now())",
+        )
+        .bind(("token_hash", hash_session_token(session_token)))
+        .await
+        .map_err(|e| SessionError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the session details")?
+        .take(0)?;
+
+    let session = session.ok_or(SessionError::SessionNotFound)?;
+
+    if session.expires_at <= Datetime::from(Utc::now()) {
+        return Err(SessionError::SessionExpired(session.expires_at).into());
+    }
+
+    Ok(session)
+}
+
+pub async fn update_session_expiry(session_id: RecordId) -> Result<Datetime> {
+    let db = get_db();
+
+    let session: Option<Session> = db
+        .select(session_id.clone())
         .await
         .map_err(|e| SessionError::DatabaseError(Box::new(e)))
         .with_context(|| "Failed to fetch session for it to update")?;
 
     let session = session.ok_or(SessionError::SessionNotFound)?;
-    let old_expired_at: chrono::DateTime<Utc> = session.expires_at.into();
-    let new_expired_at =
-        Datetime::from(old_expired_at + Duration::hours(SESSION_DURATION_IN_HOURS));
+    let new_expires_at = renewed_expiry(&session);
 
     let updated_session = UpdateSession {
-        session_token: None,
-        expires_at: Some(new_expired_at),
+        session_token_hash: None,
+        previous_session_token_hash: None,
+        previous_token_valid_until: None,
+        expires_at: Some(new_expires_at.clone()),
+        token_rotated_at: None,
     };
 
     let _: Option<Session> = db
-        .update(user_id)
+        .update(session_id)
         .merge(updated_session)
         .await
         .map_err(|e| SessionError::DatabaseError(Box::new(e)))
         .with_context(|| "Failed to fetch session record to update the expiry time")?;
 
-    Ok(())
+    Ok(new_expires_at)
 }
 
-pub async fn update_session_expiry_and_token(user_id: RecordId) -> Result<String> {
+/// Replaces the token of the session and sets its expiry, unless another
+/// request rotated the token since `session` was read. Returns `None` then, so
+/// the cookie that request issued isn't replaced by one the session doesn't have.
+async fn rotate_session_token(session: &Session, expires_at: Datetime) -> Result<Option<String>> {
     let db = get_db();
 
-    let session: Option<Session> = db
-        .select(user_id.clone())
+    let new_session_token = generate_token();
+
+    let updated_session = UpdateSession {
+        session_token_hash: Some(hash_session_token(&new_session_token)),
+        previous_session_token_hash: Some(session.session_token_hash.clone()),
+        previous_token_valid_until: Some(rotation_grace_period_end()),
+        expires_at: Some(expires_at),
+        token_rotated_at: Some(Datetime::from(Utc::now())),
+    };
+
+    let rotated: Vec<Session> = db
+        .query("UPDATE $session_id MERGE $updated_session WHERE session_token_hash = $token_hash")
+        .bind(("session_id", session.id.clone()))
+        .bind(("updated_session", updated_session))
+        .bind(("token_hash", session.session_token_hash.clone()))
         .await
         .map_err(|e| SessionError::DatabaseError(Box::new(e)))
-        .with_context(
-            || "Failed to fetch the session to update its session token and expiry time",
-        )?;
+        .with_context(|| "Failed to rotate the token of the session")?
+        .take(0)
+        .map_err(|e| SessionError::DatabaseError(Box::new(e)))?;
 
-    let session = session.ok_or(SessionError::SessionNotFound)?;
+    Ok((!rotated.is_empty()).then_some(new_session_token))
+}
 
-    let old_expired_at: chrono::DateTime<Utc> = session.expires_at.into();
-    let new_expired_at =
-        Datetime::from(old_expired_at + Duration::hours(SESSION_DURATION_IN_HOURS));
-    let new_session_token = generate_token();
+/// Extends and/or rotates the session behind `session_token` according to the
+/// session config.
+///
+/// Returns `None` when the session is left untouched or a parallel request
+/// rotated it first, otherwise the token and expiry that the session cookie has
+/// to be re-issued with.
+pub async fn renew_session(session_token: &str) -> Result<Option<RenewedSession>> {
+    let config = get_session_config();
+    let session = get_session_by_token(session_token).await?;
+    let now = Utc::now();
+
+    // A request still carrying the token from before the last rotation. The
+    // browser already got the new cookie, which mustn't be replaced by the old one.
+    if !session_token_matches(session_token, &session.session_token_hash) {
+        return Ok(None);
+    }
 
-    let updated_session = UpdateSession {
-        session_token: Some(new_session_token.clone()),
-        expires_at: Some(new_expired_at),
+    touch_session(&session).await?;
+
+    let expires_at: DateTime<Utc> = session.expires_at.clone().into();
+    let token_rotated_at: DateTime<Utc> = session
+        .token_rotated_at
+        .clone()
+        .unwrap_or_else(|| session.created_at.clone())
+        .into();
+
+    let needs_extension = expires_at - now < config.renewal_window
+        && renewed_expiry(&session) > session.expires_at;
+    let needs_rotation = now - token_rotated_at >= config.rotation_interval;
+
+    let renewed_session = match (needs_extension, needs_rotation) {
+        (needs_extension, true) => {
+            let expires_at = if needs_extension { renewed_expiry(&session) } else { session.expires_at.clone() };
+            rotate_session_token(&session, expires_at.clone())
+                .await?
+                .map(|session_token| RenewedSession { session_token, expires_at })
+        },
+        (true, false) => {
+            let expires_at = update_session_expiry(session.id).await?;
+            Some(RenewedSession { session_token: session_token.to_string(), expires_at })
+        },
+        (false, false) => None,
     };
 
-    let _: Option<Session> = db
-        .update(user_id)
-        .merge(updated_session)
+    Ok(renewed_session)
+}
+
+/// Moves `last_seen_at` of the session to now, unless it was moved recently
+async fn touch_session(session: &Session) -> Result<()> {
+    let last_seen_at: Option<DateTime<Utc>> = session.last_seen_at.clone().map(Into::into);
+    let is_recent = last_seen_at
+        .is_some_and(|last_seen_at| Utc::now() - last_seen_at < Duration::seconds(LAST_SEEN_PRECISION_IN_SECONDS));
+
+    if is_recent {
+        return Ok(());
+    }
+
+    let db = get_db();
+    db.query("UPDATE $session_id SET last_seen_at = time::now()")
+        .bind(("session_id", session.id.clone()))
         .await
         .map_err(|e| SessionError::DatabaseError(Box::new(e)))
-        .with_context(|| "Failed to update session's token and expiry time")?;
+        .with_context(|| "Failed to update when the session was last seen")?;
 
-    Ok(new_session_token)
+    Ok(())
+}
+
+/// Until when the token a session is rotated away from keeps working
+fn rotation_grace_period_end() -> Datetime {
+    Datetime::from(Utc::now() + Duration::seconds(ROTATION_GRACE_PERIOD_IN_SECONDS))
+}
+
+/// The expiry a session gets when it is extended: one idle timeout from now,
+/// capped by the session's maximum lifetime.
+fn renewed_expiry(session: &Session) -> Datetime {
+    let config = get_session_config();
+
+    let created_at: DateTime<Utc> = session.created_at.clone().into();
+    let absolute_expiry = created_at + config.max_lifetime;
+    let sliding_expiry = Utc::now() + config.idle_timeout;
+
+    Datetime::from(sliding_expiry.min(absolute_expiry))
 }
 
 pub async fn cleanup_expired_sessions() -> Result<()> {
     let db = get_db();
 
-    db.query("DELETE sessions WHERE expired_at <= time::now()")
+    db.query("DELETE sessions WHERE expires_at <= time::now()")
         .await
         .map_err(|e| SessionError::DatabaseError(Box::new(e)))
         .with_context(|| "Failed to deleted expired sessions")?;
@@ -182,13 +398,12 @@ pub fn set_session_cookie(
     
     let response = expect_context::<ResponseOptions>();
 
-    let cookie = format!(
-        "__Host-session={}; Path=/; Secure; HttpOnly; SameSite=Lax; Max-Age={}",
-        session_token,
-        SESSION_DURATION_IN_HOURS * 60 * 60
-    );
+    let config = get_session_config();
+    let cookie = config.cookie.session_cookie(session_token, config.idle_timeout.num_seconds());
 
-    response.insert_header(
+    // Appended, so the cookies others set on the response, like the cleared
+    // OpenID Connect state, are kept
+    response.append_header(
         SET_COOKIE,
         HeaderValue::from_str(&cookie)
             .with_context(|| "Failed to set sesion headers")?
@@ -197,6 +412,44 @@ pub fn set_session_cookie(
     Ok(())
 }
 
+pub fn clear_session_cookie() -> Result<()> {
+    let response = expect_context::<ResponseOptions>();
+
+    let cookie = get_session_config().cookie.cleared_session_cookie();
+
+    response.append_header(
+        SET_COOKIE,
+        HeaderValue::from_str(&cookie)
+            .with_context(|| "Failed to clear the session cookie")?
+    );
+
+    Ok(())
+}
+
+/// The keyed hash a token handed to a user is stored and looked up by, so the
+/// tokens in the database can't be replayed.
+pub fn hash_session_token(session_token: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(get_session_config().token_secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(session_token.as_bytes());
+
+    general_purpose::URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+}
+
+/// Whether `token` hashes to `token_hash`, compared in constant time so the
+/// hash can't be guessed byte by byte from response times.
+pub fn session_token_matches(token: &str, token_hash: &str) -> bool {
+    let Ok(expected) = general_purpose::URL_SAFE_NO_PAD.decode(token_hash) else {
+        return false;
+    };
+
+    let mut mac = HmacSha256::new_from_slice(get_session_config().token_secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(token.as_bytes());
+
+    mac.verify_slice(&expected).is_ok()
+}
+
 pub fn validate_session_token(token: &str) -> Result<(), SessionError> {
     if token.is_empty() {
         Err(SessionError::InvalidToken)?
@@ -215,3 +468,219 @@ pub fn validate_session_token(token: &str) -> Result<(), SessionError> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::api_tokens::create_api_token,
+        models::api_token::ApiTokenScope,
+        test_support::{handle_request, register_test_user, run, session_cookie},
+    };
+    use actix_web::{http::header::AUTHORIZATION, test::TestRequest};
+
+    async fn current_user_of(request: TestRequest) -> Result<User> {
+        handle_request(request, get_current_user).await.0
+    }
+
+    fn is_unauthenticated(result: Result<User>) -> bool {
+        matches!(result.unwrap_err().downcast_ref::<SessionError>(), Some(SessionError::Unauthenticated))
+    }
+
+    #[test]
+    fn the_current_user_comes_from_a_bearer_token_before_the_session_cookie() {
+        run(async {
+            let user_id = register_test_user("current-user@example.com").await;
+            let other_user_id = register_test_user("current-user-token@example.com").await;
+            let session_token = create_session(user_id.clone(), DeviceInfo::default()).await.unwrap();
+            let (api_token, _) = create_api_token(other_user_id.clone(), "Script".to_string(), vec![ApiTokenScope::Read], None)
+                .await
+                .unwrap();
+
+            let user = current_user_of(TestRequest::get().cookie(session_cookie(&session_token))).await.unwrap();
+            assert_eq!(user.id, user_id);
+
+            let with_both = TestRequest::get()
+                .cookie(session_cookie(&session_token))
+                .insert_header((AUTHORIZATION, format!("Bearer {}", api_token)));
+            assert_eq!(current_user_of(with_both).await.unwrap().id, other_user_id);
+
+            // A bad bearer token isn't made up for by the cookie
+            let with_bad_token = TestRequest::get()
+                .cookie(session_cookie(&session_token))
+                .insert_header((AUTHORIZATION, "Bearer mzh_unknown"));
+            assert!(is_unauthenticated(current_user_of(with_bad_token).await));
+
+            assert!(is_unauthenticated(current_user_of(TestRequest::get()).await));
+        });
+    }
+
+    #[test]
+    fn expired_and_deleted_sessions_have_no_user() {
+        run(async {
+            let user_id = register_test_user("current-user-gone@example.com").await;
+            let expired_token = create_session(user_id.clone(), DeviceInfo::default()).await.unwrap();
+            let deleted_token = create_session(user_id, DeviceInfo::default()).await.unwrap();
+
+            get_db()
+                .query("UPDATE sessions SET expires_at = time::now() - 1m WHERE session_token_hash = $token_hash")
+                .bind(("token_hash", hash_session_token(&expired_token)))
+                .await
+                .unwrap()
+                .check()
+                .unwrap();
+            delete_session(&deleted_token).await.unwrap();
+
+            for session_token in [expired_token, deleted_token, "not a token".to_string()] {
+                let request = TestRequest::get().cookie(session_cookie(&session_token));
+                assert!(is_unauthenticated(current_user_of(request).await));
+            }
+        });
+    }
+
+    /// Sets the session up as if it was created, last rotated and due to
+    /// expire that long from now
+    async fn age_session(session_token: &str, created_in: Duration, rotated_in: Duration, expires_in: Duration) {
+        get_db()
+            .query(
+                "UPDATE sessions SET created_at = $created_at, token_rotated_at = $token_rotated_at, expires_at = $expires_at
+                WHERE session_token_hash = $token_hash",
+            )
+            .bind(("created_at", Datetime::from(Utc::now() + created_in)))
+            .bind(("token_rotated_at", Datetime::from(Utc::now() + rotated_in)))
+            .bind(("expires_at", Datetime::from(Utc::now() + expires_in)))
+            .bind(("token_hash", hash_session_token(session_token)))
+            .await
+            .unwrap()
+            .check()
+            .unwrap();
+    }
+
+    fn minutes_from_now(datetime: &Datetime) -> i64 {
+        (DateTime::<Utc>::from(datetime.clone()) - Utc::now()).num_minutes()
+    }
+
+    #[test]
+    fn sessions_close_to_expiring_slide_up_to_their_max_lifetime() {
+        run(async {
+            let user_id = register_test_user("session-sliding@example.com").await;
+            let session_token = create_session(user_id, DeviceInfo::default()).await.unwrap();
+
+            age_session(&session_token, -Duration::hours(2), -Duration::minutes(1), Duration::minutes(5)).await;
+            let renewed = renew_session(&session_token).await.unwrap().unwrap();
+            assert_eq!(renewed.session_token, session_token);
+            assert_eq!(minutes_from_now(&renewed.expires_at), 59);
+            assert_eq!(get_session_by_token(&session_token).await.unwrap().expires_at, renewed.expires_at);
+
+            // Far from expiring, the session is left alone
+            assert!(renew_session(&session_token).await.unwrap().is_none());
+
+            let created_in = Duration::minutes(10) - get_session_config().max_lifetime;
+            age_session(&session_token, created_in, -Duration::minutes(1), Duration::minutes(5)).await;
+            let renewed = renew_session(&session_token).await.unwrap().unwrap();
+            assert_eq!(minutes_from_now(&renewed.expires_at), 9);
+        });
+    }
+
+    #[test]
+    fn rotation_issues_a_new_token_and_retires_the_old_one() {
+        run(async {
+            let user_id = register_test_user("session-rotation@example.com").await;
+            let session_token = create_session(user_id, DeviceInfo::default()).await.unwrap();
+            age_session(&session_token, -Duration::hours(1), -Duration::minutes(31), Duration::minutes(50)).await;
+
+            let renewed = renew_session(&session_token).await.unwrap().unwrap();
+            assert_ne!(renewed.session_token, session_token);
+            assert_eq!(minutes_from_now(&renewed.expires_at), 49);
+            let session = get_session_by_token(&renewed.session_token).await.unwrap();
+            assert_eq!(session.session_token_hash, hash_session_token(&renewed.session_token));
+
+            // Requests already under way with the old token still work, without
+            // replacing the new cookie
+            assert_eq!(get_session_by_token(&session_token).await.unwrap().id, session.id);
+            assert!(renew_session(&session_token).await.unwrap().is_none());
+
+            get_db()
+                .query("UPDATE $session_id SET previous_token_valid_until = time::now() - 1s")
+                .bind(("session_id", session.id))
+                .await
+                .unwrap()
+                .check()
+                .unwrap();
+            let error = get_session_by_token(&session_token).await.unwrap_err();
+            assert!(matches!(error.downcast_ref::<SessionError>(), Some(SessionError::SessionNotFound)));
+        });
+    }
+
+    #[test]
+    fn a_session_is_rotated_once_by_parallel_requests() {
+        run(async {
+            let user_id = register_test_user("session-parallel-rotation@example.com").await;
+            let session_token = create_session(user_id, DeviceInfo::default()).await.unwrap();
+            let session = get_session_by_token(&session_token).await.unwrap();
+
+            let first = rotate_session_token(&session, session.expires_at.clone()).await.unwrap().unwrap();
+            let second = rotate_session_token(&session, session.expires_at.clone()).await.unwrap();
+
+            assert!(second.is_none());
+            let rotated = get_session_by_token(&first).await.unwrap();
+            assert_eq!(rotated.previous_session_token_hash, Some(session.session_token_hash));
+        });
+    }
+
+    #[test]
+    fn sessions_are_listed_and_revoked_only_for_their_user() {
+        run(async {
+            let user_id = register_test_user("devices@example.com").await;
+            let other_user_id = register_test_user("devices-other@example.com").await;
+
+            let device = DeviceInfo {
+                user_agent: Some("Test Browser".to_string()),
+                ip_address: Some("198.51.100.4".to_string()),
+            };
+            let session_token = create_session(user_id.clone(), device).await.unwrap();
+            create_session(user_id.clone(), DeviceInfo::default()).await.unwrap();
+            let other_session_token = create_session(other_user_id, DeviceInfo::default()).await.unwrap();
+
+            let sessions = list_sessions_for_user(user_id.clone()).await.unwrap();
+            assert_eq!(sessions.len(), 2);
+            let session = get_session_by_token(&session_token).await.unwrap();
+            assert_eq!(session.user_agent.as_deref(), Some("Test Browser"));
+            assert_eq!(session.ip_address.as_deref(), Some("198.51.100.4"));
+            assert!(session.last_seen_at.is_some());
+
+            let other_session = get_session_by_token(&other_session_token).await.unwrap();
+            let error = revoke_session_for_user(user_id.clone(), other_session.id).await.unwrap_err();
+            assert!(matches!(error.downcast_ref::<SessionError>(), Some(SessionError::SessionNotFound)));
+            assert!(get_session_by_token(&other_session_token).await.is_ok());
+
+            revoke_session_for_user(user_id.clone(), session.id).await.unwrap();
+            assert!(get_session_by_token(&session_token).await.is_err());
+            assert_eq!(list_sessions_for_user(user_id).await.unwrap().len(), 1);
+        });
+    }
+
+    #[test]
+    fn only_the_hash_of_a_session_token_is_stored() {
+        run(async {
+            let user_id = register_test_user("hashed-sessions@example.com").await;
+            let session_token = create_session(user_id, DeviceInfo::default()).await.unwrap();
+
+            let session = get_session_by_token(&session_token).await.unwrap();
+            assert_ne!(session.session_token_hash, session_token);
+            assert_eq!(session.session_token_hash, hash_session_token(&session_token));
+
+            let stored: Vec<Session> = get_db()
+                .query("SELECT * FROM sessions WHERE session_token_hash = $raw_token")
+                .bind(("raw_token", session_token.clone()))
+                .await
+                .unwrap()
+                .take(0)
+                .unwrap();
+            assert!(stored.is_empty());
+
+            delete_session(&session_token).await.unwrap();
+            assert!(get_session_by_token(&session_token).await.is_err());
+        });
+    }
+}
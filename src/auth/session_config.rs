@@ -0,0 +1,228 @@
+use chrono::Duration;
+use leptos::config::Env;
+use once_cell::sync::OnceCell;
+use std::str::FromStr;
+
+static SESSION_CONFIG: OnceCell<SessionConfig> = OnceCell::new();
+pub const MIN_TOKEN_SECRET_LENGTH: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    /// How long a session stays valid without any activity
+    pub idle_timeout: Duration,
+    /// Sessions with less than this much time left are extended on the next request
+    pub renewal_window: Duration,
+    /// Hard limit on a session's age, counted from its creation, regardless of activity
+    pub max_lifetime: Duration,
+    /// How often the session token is replaced while the session is in use
+    pub rotation_interval: Duration,
+    /// Server side key the tokens handed to users are hashed with before they are stored
+    pub token_secret: String,
+    pub cookie: CookieConfig,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Duration::hours(1),
+            renewal_window: Duration::minutes(15),
+            max_lifetime: Duration::days(30),
+            rotation_interval: Duration::minutes(30),
+            token_secret: String::new(),
+            cookie: CookieConfig::for_env(&Env::PROD),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// Parses `Strict`, `Lax` or `None` regardless of case
+impl FromStr for SameSite {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "strict" => Ok(SameSite::Strict),
+            "lax" => Ok(SameSite::Lax),
+            "none" => Ok(SameSite::None),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How the session cookie is set
+#[derive(Debug, Clone)]
+pub struct CookieConfig {
+    pub name: String,
+    /// Browsers drop `Secure` cookies sent over plain `http://`, other than for `localhost`
+    pub secure: bool,
+    pub same_site: SameSite,
+    /// Unset for a cookie that is only sent to the host that set it
+    pub domain: Option<String>,
+    /// A fixed `Max-Age` for the cookie, which otherwise follows the session's expiry
+    pub max_age: Option<Duration>,
+}
+
+impl CookieConfig {
+    /// The defaults for the Leptos `env`: a `__Host-` prefixed `Secure` cookie in
+    /// production and a plain one in development, which is served over `http://`.
+    pub fn for_env(leptos_env: &Env) -> Self {
+        let (name, secure) = match leptos_env {
+            Env::PROD => ("__Host-session", true),
+            Env::DEV => ("session", false),
+        };
+
+        Self {
+            name: name.to_string(),
+            secure,
+            same_site: SameSite::Lax,
+            domain: None,
+            max_age: None,
+        }
+    }
+
+    /// The `Set-Cookie` value handing out `session_token` for a session with
+    /// `session_max_age_in_seconds` left
+    pub fn session_cookie(&self, session_token: &str, session_max_age_in_seconds: i64) -> String {
+        let max_age_in_seconds = self
+            .max_age
+            .map(|max_age| max_age.num_seconds())
+            .unwrap_or(session_max_age_in_seconds);
+
+        self.build(session_token, max_age_in_seconds)
+    }
+
+    /// The `Set-Cookie` value removing the session cookie from the browser
+    pub fn cleared_session_cookie(&self) -> String {
+        self.build("", 0)
+    }
+
+    /// The `Set-Cookie` value tying an OpenID Connect sign in to the browser that
+    /// started it. It is `Lax` whatever the session cookie is, since it has to
+    /// come along when the provider sends the browser back.
+    pub fn oidc_state_cookie(&self, state: &str, max_age_in_seconds: i64) -> String {
+        self.oidc_state().build(state, max_age_in_seconds)
+    }
+
+    /// The `Set-Cookie` value removing the OpenID Connect state cookie
+    pub fn cleared_oidc_state_cookie(&self) -> String {
+        self.oidc_state().build("", 0)
+    }
+
+    /// The name of the OpenID Connect state cookie, which keeps the prefix of
+    /// the session cookie
+    pub fn oidc_state_cookie_name(&self) -> String {
+        format!("{}-oidc-state", self.name)
+    }
+
+    fn oidc_state(&self) -> Self {
+        Self {
+            name: self.oidc_state_cookie_name(),
+            same_site: SameSite::Lax,
+            max_age: None,
+            ..self.clone()
+        }
+    }
+
+    fn build(&self, value: &str, max_age_in_seconds: i64) -> String {
+        let mut cookie = format!("{}={}; Path=/", self.name, value);
+        if let Some(domain) = &self.domain {
+            cookie.push_str(&format!("; Domain={}", domain));
+        }
+        if self.secure {
+            cookie.push_str("; Secure");
+        }
+        cookie.push_str(&format!(
+            "; HttpOnly; SameSite={}; Max-Age={}",
+            self.same_site.as_str(),
+            max_age_in_seconds.max(0)
+        ));
+
+        cookie
+    }
+}
+
+/// Sets the session config, as loaded with the `AppConfig`
+pub fn init_session_config(config: SessionConfig) {
+    SESSION_CONFIG
+        .set(config)
+        .unwrap_or_else(|_| panic!("Session config already initialized"));
+}
+
+pub fn get_session_config() -> &'static SessionConfig {
+    SESSION_CONFIG.get().expect("Session config not initialized")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn production_cookies_are_host_prefixed_and_secure() {
+        let cookie = CookieConfig::for_env(&Env::PROD);
+
+        assert_eq!(
+            cookie.session_cookie("token", 3600),
+            "__Host-session=token; Path=/; Secure; HttpOnly; SameSite=Lax; Max-Age=3600"
+        );
+        assert_eq!(
+            cookie.cleared_session_cookie(),
+            "__Host-session=; Path=/; Secure; HttpOnly; SameSite=Lax; Max-Age=0"
+        );
+    }
+
+    #[test]
+    fn development_cookies_work_over_plain_http() {
+        let cookie = CookieConfig::for_env(&Env::DEV);
+
+        assert_eq!(
+            cookie.session_cookie("token", 3600),
+            "session=token; Path=/; HttpOnly; SameSite=Lax; Max-Age=3600"
+        );
+        assert_eq!(cookie.cleared_session_cookie(), "session=; Path=/; HttpOnly; SameSite=Lax; Max-Age=0");
+    }
+
+    #[test]
+    fn configured_cookies_use_their_domain_and_max_age() {
+        let cookie = CookieConfig {
+            name: "__Secure-session".to_string(),
+            secure: true,
+            same_site: SameSite::Strict,
+            domain: Some("merzah.example".to_string()),
+            max_age: Some(Duration::days(7)),
+        };
+
+        assert_eq!(
+            cookie.session_cookie("token", 3600),
+            "__Secure-session=token; Path=/; Domain=merzah.example; Secure; HttpOnly; SameSite=Strict; Max-Age=604800"
+        );
+        // A removed cookie expires right away whatever its configured max age
+        assert!(cookie.cleared_session_cookie().ends_with("Max-Age=0"));
+    }
+
+    #[test]
+    fn the_oidc_state_cookie_comes_back_from_providers() {
+        let cookie = CookieConfig { same_site: SameSite::Strict, ..CookieConfig::for_env(&Env::PROD) };
+
+        assert_eq!(
+            cookie.oidc_state_cookie("state", 600),
+            "__Host-session-oidc-state=state; Path=/; Secure; HttpOnly; SameSite=Lax; Max-Age=600"
+        );
+        assert!(cookie.cleared_oidc_state_cookie().starts_with("__Host-session-oidc-state=;"));
+    }
+}
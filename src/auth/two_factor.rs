@@ -0,0 +1,511 @@
+use anyhow::{Context, Result};
+use chrono::{Duration, Utc};
+use rand::{Rng, thread_rng};
+use surrealdb::RecordId;
+use surrealdb::sql::Datetime;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::{
+    auth::{
+        identifiers::list_identifiers,
+        session::{hash_session_token, session_token_matches, validate_session_token},
+        two_factor_config::get_two_factor_config,
+    },
+    database::connection::get_db,
+    errors::two_factor::TwoFactorError,
+    models::{
+        two_factor::{
+            CreateTwoFactor, CreateTwoFactorChallenge, TwoFactor, TwoFactorChallenge,
+            TwoFactorEnrollment, TwoFactorStatus,
+        },
+        user::User,
+    },
+    utils::token_generator::{generate_recovery_code, generate_token},
+};
+
+static CHALLENGE_DURATION_IN_MINUTES: i64 = 5;
+static MAX_CHALLENGE_ATTEMPTS: i64 = 5;
+static RECOVERY_CODE_COUNT: usize = 10;
+static SECRET_LENGTH_IN_BYTES: usize = 20;
+static TOTP_DIGITS: usize = 6;
+static TOTP_STEP_IN_SECONDS: u64 = 30;
+/// Codes from this many steps before or after the current one are accepted too,
+/// for phones whose clocks are a little off
+static TOTP_ALLOWED_DRIFT_IN_STEPS: u64 = 1;
+
+/// Gives the user a new, not yet enabled, TOTP secret to set their authenticator
+/// app up with. Replaces an earlier secret that was never confirmed.
+pub async fn start_enrollment(user: &User) -> Result<TwoFactorEnrollment> {
+    if find_two_factor(&user.id).await?.is_some_and(|two_factor| two_factor.enabled_at.is_some()) {
+        Err(TwoFactorError::AlreadyEnabled)?
+    }
+
+    let mut secret_bytes = vec![0u8; SECRET_LENGTH_IN_BYTES];
+    thread_rng().fill(secret_bytes.as_mut_slice());
+    let Secret::Encoded(secret) = Secret::Raw(secret_bytes).to_encoded() else {
+        unreachable!("to_encoded always returns an encoded secret")
+    };
+
+    let totp = build_totp(&secret, account_name(user).await?)?;
+
+    let surql = r#"
+            BEGIN TRANSACTION;
+
+            DELETE two_factor WHERE user_id = $user_id;
+            CREATE two_factor CONTENT $two_factor;
+
+            COMMIT TRANSACTION;
+        "#;
+
+    get_db()
+        .query(surql)
+        .bind(("user_id", user.id.clone()))
+        .bind(("two_factor", CreateTwoFactor { user_id: user.id.clone(), secret: secret.clone() }))
+        .await
+        .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to store the two-factor secret")?
+        .check()
+        .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))?;
+
+    Ok(TwoFactorEnrollment { provisioning_uri: totp.get_url(), secret })
+}
+
+/// Enables two-factor authentication once the user has entered a first code
+/// from their app, and hands out the recovery codes. These are shown this once,
+/// only their hashes are stored.
+pub async fn confirm_enrollment(user_id: RecordId, code: &str) -> Result<Vec<String>> {
+    let two_factor = find_two_factor(&user_id).await?.ok_or(TwoFactorError::NotEnabled)?;
+
+    if two_factor.enabled_at.is_some() {
+        Err(TwoFactorError::AlreadyEnabled)?
+    }
+
+    accept_totp_code(&two_factor, code).await?;
+
+    let (recovery_codes, recovery_code_hashes) = generate_recovery_codes();
+
+    get_db()
+        .query("UPDATE $two_factor SET enabled_at = time::now(), recovery_code_hashes = $recovery_code_hashes")
+        .bind(("two_factor", two_factor.id))
+        .bind(("recovery_code_hashes", recovery_code_hashes))
+        .await
+        .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to enable two-factor authentication")?
+        .check()
+        .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))?;
+
+    Ok(recovery_codes)
+}
+
+/// Turns two-factor authentication off, after checking a code from the app or
+/// a recovery code.
+pub async fn disable_two_factor(user_id: RecordId, code: &str) -> Result<()> {
+    let two_factor = find_enabled_two_factor(&user_id).await?;
+    accept_code(&two_factor, code).await?;
+
+    get_db()
+        .query("DELETE $two_factor")
+        .bind(("two_factor", two_factor.id))
+        .await
+        .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to disable two-factor authentication")?
+        .check()
+        .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))?;
+
+    Ok(())
+}
+
+/// Replaces every recovery code of the user with new ones, after checking a
+/// code from the app.
+pub async fn regenerate_recovery_codes(user_id: RecordId, code: &str) -> Result<Vec<String>> {
+    let two_factor = find_enabled_two_factor(&user_id).await?;
+    accept_totp_code(&two_factor, code).await?;
+
+    let (recovery_codes, recovery_code_hashes) = generate_recovery_codes();
+
+    get_db()
+        .query("UPDATE $two_factor SET recovery_code_hashes = $recovery_code_hashes")
+        .bind(("two_factor", two_factor.id))
+        .bind(("recovery_code_hashes", recovery_code_hashes))
+        .await
+        .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to replace the recovery codes")?
+        .check()
+        .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))?;
+
+    Ok(recovery_codes)
+}
+
+pub async fn is_two_factor_enabled(user_id: &RecordId) -> Result<bool> {
+    Ok(find_two_factor(user_id)
+        .await?
+        .is_some_and(|two_factor| two_factor.enabled_at.is_some()))
+}
+
+pub async fn two_factor_status(user: &User) -> Result<TwoFactorStatus> {
+    let enabled_two_factor = find_two_factor(&user.id)
+        .await?
+        .filter(|two_factor| two_factor.enabled_at.is_some());
+
+    Ok(TwoFactorStatus {
+        enabled: enabled_two_factor.is_some(),
+        required: get_two_factor_config().is_required_for(user.role),
+        recovery_codes_left: enabled_two_factor
+            .map(|two_factor| two_factor.recovery_code_hashes.len())
+            .unwrap_or(0),
+    })
+}
+
+/// Fails with `TwoFactorError::Required` when the role of the user requires
+/// two-factor authentication and they haven't enabled it yet.
+///
+/// Logging in still works without it, so they can go and set it up.
+pub async fn ensure_two_factor_if_required(user: &User) -> Result<()> {
+    if get_two_factor_config().is_required_for(user.role) && !is_two_factor_enabled(&user.id).await? {
+        Err(TwoFactorError::Required)?
+    }
+
+    Ok(())
+}
+
+/// Starts the second step of a login whose password was right. The returned
+/// token has to come back with a code for the session to be created.
+pub async fn create_challenge(user_id: RecordId) -> Result<String> {
+    let challenge = generate_token();
+    let expires_at = Datetime::from(Utc::now() + Duration::minutes(CHALLENGE_DURATION_IN_MINUTES));
+
+    let surql = r#"
+            BEGIN TRANSACTION;
+
+            DELETE two_factor_challenges WHERE expires_at <= time::now();
+            CREATE two_factor_challenges CONTENT $challenge;
+
+            COMMIT TRANSACTION;
+        "#;
+
+    get_db()
+        .query(surql)
+        .bind((
+            "challenge",
+            CreateTwoFactorChallenge { user_id, token_hash: hash_session_token(&challenge), expires_at },
+        ))
+        .await
+        .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to create the two-factor challenge")?
+        .check()
+        .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))?;
+
+    Ok(challenge)
+}
+
+/// Finishes a login started with `create_challenge` when `code` is a code from
+/// the app or an unused recovery code, returning the user to create the session
+/// for.
+pub async fn complete_challenge(challenge: &str, code: &str) -> Result<RecordId> {
+    validate_session_token(challenge).map_err(|_| TwoFactorError::InvalidChallenge)?;
+
+    let db = get_db();
+    let token_hash = hash_session_token(challenge);
+
+    // Counting the attempt in the same statement that checks the limit keeps
+    // concurrent guesses from all slipping in under it
+    let pending: Option<TwoFactorChallenge> = db
+        .query(
+            "UPDATE two_factor_challenges SET attempts += 1 \
+             WHERE token_hash = $token_hash AND expires_at > time::now() AND attempts < $max_attempts \
+             RETURN AFTER",
+        )
+        .bind(("token_hash", token_hash.clone()))
+        .bind(("max_attempts", MAX_CHALLENGE_ATTEMPTS))
+        .await
+        .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to count the two-factor attempt")?
+        .take(0)
+        .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))?;
+
+    let Some(pending) = pending else {
+        let exhausted: Option<RecordId> = db
+            .query("SELECT VALUE id FROM two_factor_challenges WHERE token_hash = $token_hash AND expires_at > time::now()")
+            .bind(("token_hash", token_hash))
+            .await
+            .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))
+            .with_context(|| "Failed to fetch the two-factor challenge")?
+            .take(0)
+            .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))?;
+
+        if exhausted.is_some() {
+            return Err(TwoFactorError::TooManyAttempts.into());
+        }
+        return Err(TwoFactorError::InvalidChallenge.into());
+    };
+
+    // Two-factor authentication may have been turned off since the password was checked
+    let two_factor = find_enabled_two_factor(&pending.user_id)
+        .await
+        .map_err(|_| TwoFactorError::InvalidChallenge)?;
+    accept_code(&two_factor, code).await?;
+
+    db.query("DELETE $challenge")
+        .bind(("challenge", pending.id))
+        .await
+        .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to delete the two-factor challenge")?
+        .check()
+        .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))?;
+
+    Ok(pending.user_id)
+}
+
+async fn find_two_factor(user_id: &RecordId) -> Result<Option<TwoFactor>> {
+    let two_factor: Option<TwoFactor> = get_db()
+        .query("SELECT * FROM two_factor WHERE user_id = $user_id")
+        .bind(("user_id", user_id.clone()))
+        .await
+        .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the two-factor authentication of the user")?
+        .take(0)
+        .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))?;
+
+    Ok(two_factor)
+}
+
+async fn find_enabled_two_factor(user_id: &RecordId) -> Result<TwoFactor> {
+    let two_factor = find_two_factor(user_id)
+        .await?
+        .filter(|two_factor| two_factor.enabled_at.is_some())
+        .ok_or(TwoFactorError::NotEnabled)?;
+
+    Ok(two_factor)
+}
+
+/// Accepts a code from the app, or else one of the recovery codes
+async fn accept_code(two_factor: &TwoFactor, code: &str) -> Result<()> {
+    let code = code.trim();
+
+    if code.len() == TOTP_DIGITS && code.chars().all(|c| c.is_ascii_digit()) {
+        accept_totp_code(two_factor, code).await
+    } else {
+        use_recovery_code(two_factor, code).await
+    }
+}
+
+/// Accepts a code from the app that is newer than the last accepted one, so a
+/// code seen over someone's shoulder can't be used again.
+async fn accept_totp_code(two_factor: &TwoFactor, code: &str) -> Result<()> {
+    let totp = build_totp(&two_factor.secret, String::new())?;
+    let now = Utc::now().timestamp() as u64;
+    let step = matching_step(&totp, code.trim(), now).ok_or(TwoFactorError::InvalidCode)? as i64;
+
+    let accepted: Option<TwoFactor> = get_db()
+        .query(
+            "UPDATE $two_factor SET last_used_step = $step \
+             WHERE last_used_step = NONE OR last_used_step < $step \
+             RETURN AFTER",
+        )
+        .bind(("two_factor", two_factor.id.clone()))
+        .bind(("step", step))
+        .await
+        .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to record the used two-factor code")?
+        .take(0)
+        .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))?;
+
+    if accepted.is_none() {
+        Err(TwoFactorError::InvalidCode)?
+    }
+
+    Ok(())
+}
+
+/// Uses up the recovery code, which then can't be used again
+async fn use_recovery_code(two_factor: &TwoFactor, code: &str) -> Result<()> {
+    let code = code.to_lowercase();
+    let code_hash = two_factor
+        .recovery_code_hashes
+        .iter()
+        .find(|code_hash| session_token_matches(&code, code_hash))
+        .ok_or(TwoFactorError::InvalidCode)?;
+
+    let used: Option<TwoFactor> = get_db()
+        .query(
+            "UPDATE $two_factor SET recovery_code_hashes -= $code_hash \
+             WHERE recovery_code_hashes CONTAINS $code_hash \
+             RETURN AFTER",
+        )
+        .bind(("two_factor", two_factor.id.clone()))
+        .bind(("code_hash", code_hash.clone()))
+        .await
+        .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to use up the recovery code")?
+        .take(0)
+        .map_err(|e| TwoFactorError::DatabaseError(Box::new(e)))?;
+
+    if used.is_none() {
+        Err(TwoFactorError::InvalidCode)?
+    }
+
+    Ok(())
+}
+
+/// The time step the code was generated in, if it is one of the accepted ones
+fn matching_step(totp: &TOTP, code: &str, now: u64) -> Option<u64> {
+    let current_step = now / TOTP_STEP_IN_SECONDS;
+
+    (current_step.saturating_sub(TOTP_ALLOWED_DRIFT_IN_STEPS)..=current_step + TOTP_ALLOWED_DRIFT_IN_STEPS)
+        .find(|step| totp.check(code, step * TOTP_STEP_IN_SECONDS))
+}
+
+/// Drift is handled by `matching_step`, so the TOTP itself only checks the step
+/// it is asked about
+fn build_totp(secret: &str, account_name: String) -> Result<TOTP> {
+    let secret_bytes = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| TwoFactorError::Provisioning(format!("{:?}", e)))?;
+
+    let totp = TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        0,
+        TOTP_STEP_IN_SECONDS,
+        secret_bytes,
+        Some(get_two_factor_config().issuer.clone()),
+        account_name,
+    )
+    .map_err(|e| TwoFactorError::Provisioning(e.to_string()))?;
+
+    Ok(totp)
+}
+
+/// The name the account shows up with in the authenticator app, the primary
+/// identifier of the user
+async fn account_name(user: &User) -> Result<String> {
+    let account_name = match list_identifiers(user.id.clone()).await?.into_iter().next() {
+        Some(user_identifier) => user_identifier.identifier.value().to_string(),
+        None => user.display_name.clone(),
+    };
+
+    // The issuer and the account name are separated by a colon in the uri
+    Ok(account_name.replace(':', ""))
+}
+
+/// New recovery codes along with the hashes they are stored as
+fn generate_recovery_codes() -> (Vec<String>, Vec<String>) {
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| generate_recovery_code()).collect();
+    let recovery_code_hashes = recovery_codes.iter().map(|code| hash_session_token(code)).collect();
+
+    (recovery_codes, recovery_code_hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user::UserRole;
+    use crate::test_support::{register_test_user, run};
+
+    fn two_factor_error(error: &anyhow::Error) -> Option<&TwoFactorError> {
+        error.downcast_ref::<TwoFactorError>()
+    }
+
+    async fn get_user(user_id: &RecordId) -> User {
+        get_db().select(user_id.clone()).await.unwrap().unwrap()
+    }
+
+    /// The code the authenticator app would show `steps_from_now` steps from now
+    fn code_for(enrollment: &TwoFactorEnrollment, steps_from_now: i64) -> String {
+        let totp = build_totp(&enrollment.secret, String::new()).unwrap();
+        let time = Utc::now().timestamp() + steps_from_now * TOTP_STEP_IN_SECONDS as i64;
+
+        totp.generate(time as u64)
+    }
+
+    #[test]
+    fn enrolling_needs_a_code_and_hands_out_recovery_codes() {
+        run(async {
+            let user_id = register_test_user("two-factor-enroll@example.com").await;
+            let user = get_user(&user_id).await;
+
+            let enrollment = start_enrollment(&user).await.unwrap();
+            assert!(enrollment.provisioning_uri.starts_with("otpauth://totp/"));
+            assert!(enrollment.provisioning_uri.contains("two-factor-enroll%40example.com"));
+            assert!(!is_two_factor_enabled(&user_id).await.unwrap());
+
+            let error = confirm_enrollment(user_id.clone(), "000000").await.unwrap_err();
+            assert!(matches!(two_factor_error(&error), Some(TwoFactorError::InvalidCode)));
+
+            let recovery_codes = confirm_enrollment(user_id.clone(), &code_for(&enrollment, 0)).await.unwrap();
+            assert_eq!(recovery_codes.len(), RECOVERY_CODE_COUNT);
+            assert!(is_two_factor_enabled(&user_id).await.unwrap());
+
+            let error = start_enrollment(&user).await.unwrap_err();
+            assert!(matches!(two_factor_error(&error), Some(TwoFactorError::AlreadyEnabled)));
+        });
+    }
+
+    #[test]
+    fn challenges_take_each_code_once() {
+        run(async {
+            let user_id = register_test_user("two-factor-login@example.com").await;
+            let enrollment = start_enrollment(&get_user(&user_id).await).await.unwrap();
+            let recovery_codes = confirm_enrollment(user_id.clone(), &code_for(&enrollment, -1)).await.unwrap();
+
+            let challenge = create_challenge(user_id.clone()).await.unwrap();
+            assert_eq!(complete_challenge(&challenge, &code_for(&enrollment, 0)).await.unwrap(), user_id);
+
+            // The challenge is used up, and so is the code
+            let error = complete_challenge(&challenge, &code_for(&enrollment, 0)).await.unwrap_err();
+            assert!(matches!(two_factor_error(&error), Some(TwoFactorError::InvalidChallenge)));
+            let challenge = create_challenge(user_id.clone()).await.unwrap();
+            let error = complete_challenge(&challenge, &code_for(&enrollment, 0)).await.unwrap_err();
+            assert!(matches!(two_factor_error(&error), Some(TwoFactorError::InvalidCode)));
+
+            assert_eq!(complete_challenge(&challenge, &recovery_codes[0].to_uppercase()).await.unwrap(), user_id);
+            let challenge = create_challenge(user_id.clone()).await.unwrap();
+            let error = complete_challenge(&challenge, &recovery_codes[0]).await.unwrap_err();
+            assert!(matches!(two_factor_error(&error), Some(TwoFactorError::InvalidCode)));
+
+            let status = two_factor_status(&get_user(&user_id).await).await.unwrap();
+            assert_eq!(status.recovery_codes_left, RECOVERY_CODE_COUNT - 1);
+        });
+    }
+
+    #[test]
+    fn wrong_codes_use_up_the_challenge() {
+        run(async {
+            let user_id = register_test_user("two-factor-guess@example.com").await;
+            let enrollment = start_enrollment(&get_user(&user_id).await).await.unwrap();
+            confirm_enrollment(user_id.clone(), &code_for(&enrollment, 0)).await.unwrap();
+
+            let challenge = create_challenge(user_id).await.unwrap();
+            for _ in 0..MAX_CHALLENGE_ATTEMPTS {
+                let error = complete_challenge(&challenge, "not-a-recovery-code").await.unwrap_err();
+                assert!(matches!(two_factor_error(&error), Some(TwoFactorError::InvalidCode)));
+            }
+
+            let error = complete_challenge(&challenge, &code_for(&enrollment, 1)).await.unwrap_err();
+            assert!(matches!(two_factor_error(&error), Some(TwoFactorError::TooManyAttempts)));
+        });
+    }
+
+    #[test]
+    fn required_roles_need_two_factor_enabled() {
+        run(async {
+            let user_id = register_test_user("two-factor-admin@example.com").await;
+            ensure_two_factor_if_required(&get_user(&user_id).await).await.unwrap();
+
+            get_db()
+                .query("UPDATE $user_id SET role = 'mosque_admin'")
+                .bind(("user_id", user_id.clone()))
+                .await
+                .unwrap();
+            let user = get_user(&user_id).await;
+            assert_eq!(user.role, UserRole::MosqueAdmin);
+
+            let error = ensure_two_factor_if_required(&user).await.unwrap_err();
+            assert!(matches!(two_factor_error(&error), Some(TwoFactorError::Required)));
+
+            let enrollment = start_enrollment(&user).await.unwrap();
+            confirm_enrollment(user_id, &code_for(&enrollment, 0)).await.unwrap();
+            ensure_two_factor_if_required(&user).await.unwrap();
+        });
+    }
+}
@@ -0,0 +1,40 @@
+use once_cell::sync::OnceCell;
+
+use crate::models::user::UserRole;
+
+static TWO_FACTOR_CONFIG: OnceCell<TwoFactorConfig> = OnceCell::new();
+
+#[derive(Debug, Clone)]
+pub struct TwoFactorConfig {
+    /// Shown as the account's issuer in authenticator apps
+    pub issuer: String,
+    /// Users with these roles can't use what their role grants until they have
+    /// two-factor authentication set up
+    pub required_roles: Vec<UserRole>,
+}
+
+impl Default for TwoFactorConfig {
+    fn default() -> Self {
+        Self {
+            issuer: "Merzah".to_string(),
+            required_roles: Vec::new(),
+        }
+    }
+}
+
+impl TwoFactorConfig {
+    pub fn is_required_for(&self, role: UserRole) -> bool {
+        self.required_roles.contains(&role)
+    }
+}
+
+/// Sets the two-factor config, as loaded with the `AppConfig`
+pub fn init_two_factor_config(config: TwoFactorConfig) {
+    TWO_FACTOR_CONFIG
+        .set(config)
+        .unwrap_or_else(|_| panic!("Two-factor config already initialized"));
+}
+
+pub fn get_two_factor_config() -> &'static TwoFactorConfig {
+    TWO_FACTOR_CONFIG.get().expect("Two-factor config not initialized")
+}
@@ -0,0 +1,389 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use reqwest::Url;
+use surrealdb::RecordId;
+use surrealdb::sql::Datetime;
+
+use crate::{
+    auth::{
+        custom_auth::find_user_identifier,
+        session::{hash_session_token, session_token_matches},
+    },
+    database::connection::get_db,
+    errors::verification::VerificationError,
+    models::{
+        user::{Identifier, UserIdentifier},
+        verification::{CreateIdentifierVerification, IdentifierVerification},
+    },
+    notifications::{OutboundMessage, app_base_url, get_delivery_channel},
+    utils::token_generator::{generate_numeric_code, generate_token},
+};
+
+static VERIFICATION_CODE_DURATION_IN_MINUTES: i64 = 30;
+static MAX_VERIFICATION_ATTEMPTS: i64 = 5;
+static MOBILE_VERIFICATION_CODE_DIGITS: u32 = 6;
+/// Sends and wrong attempts are counted over this window, starting at the first send
+static VERIFICATION_WINDOW_IN_MINUTES: i64 = 60;
+static MAX_VERIFICATION_SENDS: i64 = 5;
+static VERIFICATION_RESEND_COOLDOWN_IN_SECONDS: i64 = 60;
+
+/// Sends a fresh verification code to the identifier, replacing the code of any
+/// pending one.
+///
+/// Emails get a link carrying a long token, mobiles get a short numeric code to
+/// type in, which is why wrong attempts are limited. Only the hash of the code
+/// is stored.
+pub async fn send_verification(user_identifier: &UserIdentifier) -> Result<()> {
+    if user_identifier.verified_at.is_some() {
+        Err(VerificationError::AlreadyVerified)?
+    }
+
+    let (code, message) = match &user_identifier.identifier {
+        Identifier::Email(email) => {
+            let code = generate_token();
+            let mut link = Url::parse(&format!("{}/verify", app_base_url()))
+                .with_context(|| "APP_BASE_URL is not a valid url")?;
+            link.query_pairs_mut()
+                .append_pair("email", email)
+                .append_pair("code", &code);
+
+            let message = OutboundMessage {
+                recipient: user_identifier.identifier.clone(),
+                subject: "Verify your email for Merzah".to_string(),
+                body: format!(
+                    "Use the following link to verify your email, it expires in {} minutes:\n{}",
+                    VERIFICATION_CODE_DURATION_IN_MINUTES, link
+                ),
+            };
+
+            (code, message)
+        },
+        // Linked by signing in with the provider, which is verification enough
+        Identifier::Oidc(_) => Err(VerificationError::AlreadyVerified)?,
+        Identifier::Mobile(_) => {
+            let code = generate_numeric_code(MOBILE_VERIFICATION_CODE_DIGITS);
+            let message = OutboundMessage {
+                recipient: user_identifier.identifier.clone(),
+                subject: "Merzah verification code".to_string(),
+                body: format!(
+                    "Your Merzah verification code is {}. It expires in {} minutes.",
+                    code, VERIFICATION_CODE_DURATION_IN_MINUTES
+                ),
+            };
+
+            (code, message)
+        },
+    };
+
+    store_verification_code(user_identifier, &code).await?;
+
+    get_delivery_channel()
+        .deliver(message)
+        .await
+        .map_err(VerificationError::DeliveryError)?;
+
+    Ok(())
+}
+
+/// Stores the hash of a freshly sent code. Sends and wrong attempts add up over
+/// the window that starts with the first send, so resending neither lifts the
+/// attempt limit nor lets anyone have an unlimited number of messages sent.
+async fn store_verification_code(user_identifier: &UserIdentifier, code: &str) -> Result<()> {
+    let db = get_db();
+    let now = Utc::now();
+    let code_hash = hash_session_token(code);
+    let expires_at = Datetime::from(now + Duration::minutes(VERIFICATION_CODE_DURATION_IN_MINUTES));
+    let window_start = Datetime::from(now - Duration::minutes(VERIFICATION_WINDOW_IN_MINUTES));
+
+    let resent: Option<IdentifierVerification> = db
+        .query(
+            "UPDATE identifier_verifications
+            SET code_hash = $code_hash, expires_at = $expires_at, sends += 1, last_sent_at = time::now()
+            WHERE user_identifier = $user_identifier AND created_at > $window_start
+                AND sends < $max_sends AND last_sent_at < $cooldown_start
+            RETURN AFTER",
+        )
+        .bind(("user_identifier", user_identifier.id.clone()))
+        .bind(("code_hash", code_hash.clone()))
+        .bind(("expires_at", expires_at.clone()))
+        .bind(("window_start", window_start.clone()))
+        .bind(("max_sends", MAX_VERIFICATION_SENDS))
+        .bind(("cooldown_start", Datetime::from(now - Duration::seconds(VERIFICATION_RESEND_COOLDOWN_IN_SECONDS))))
+        .await
+        .map_err(|e| VerificationError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to update the pending verification")?
+        .take(0)
+        .map_err(|e| VerificationError::DatabaseError(Box::new(e)))?;
+
+    if resent.is_some() {
+        return Ok(());
+    }
+
+    let pending: Option<IdentifierVerification> = db
+        .query("SELECT * FROM identifier_verifications WHERE user_identifier = $user_identifier AND created_at > $window_start")
+        .bind(("user_identifier", user_identifier.id.clone()))
+        .bind(("window_start", window_start))
+        .await
+        .map_err(|e| VerificationError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the pending verification")?
+        .take(0)
+        .map_err(|e| VerificationError::DatabaseError(Box::new(e)))?;
+
+    if let Some(pending) = pending {
+        if pending.sends >= MAX_VERIFICATION_SENDS {
+            return Err(VerificationError::TooManySends.into());
+        }
+
+        let last_sent_at: DateTime<Utc> = pending.last_sent_at.into();
+        let retry_after = last_sent_at + Duration::seconds(VERIFICATION_RESEND_COOLDOWN_IN_SECONDS) - now;
+        return Err(VerificationError::ResendTooSoon(retry_after.num_seconds().max(1)).into());
+    }
+
+    // The window of the earlier verification is over, it starts anew
+    let surql = r#"
+            BEGIN TRANSACTION;
+
+            DELETE identifier_verifications WHERE user_identifier = $user_identifier;
+            CREATE identifier_verifications CONTENT $verification;
+
+            COMMIT TRANSACTION;
+        "#;
+
+    db.query(surql)
+        .bind(("user_identifier", user_identifier.id.clone()))
+        .bind((
+            "verification",
+            CreateIdentifierVerification { user_identifier: user_identifier.id.clone(), code_hash, expires_at },
+        ))
+        .await
+        .map_err(|e| VerificationError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to create the verification")?
+        .check()
+        .map_err(|e| VerificationError::DatabaseError(Box::new(e)))?;
+
+    Ok(())
+}
+
+/// Marks the identifier as verified when `code` matches its pending verification.
+pub async fn verify_identifier(identifier: &Identifier, code: &str) -> Result<()> {
+    let user_identifier = find_user_identifier(identifier)
+        .await?
+        .ok_or(VerificationError::InvalidCode)?;
+
+    if user_identifier.verified_at.is_some() {
+        Err(VerificationError::AlreadyVerified)?
+    }
+
+    let db = get_db();
+
+    // Counting the attempt in the same statement that checks the limit keeps
+    // concurrent guesses from all slipping in under it
+    let verification: Option<IdentifierVerification> = db
+        .query(
+            "UPDATE identifier_verifications SET attempts += 1 \
+             WHERE user_identifier = $user_identifier AND expires_at > time::now() AND attempts < $max_attempts \
+             RETURN AFTER",
+        )
+        .bind(("user_identifier", user_identifier.id.clone()))
+        .bind(("max_attempts", MAX_VERIFICATION_ATTEMPTS))
+        .await
+        .map_err(|e| VerificationError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to count the verification attempt")?
+        .take(0)
+        .map_err(|e| VerificationError::DatabaseError(Box::new(e)))?;
+
+    let Some(verification) = verification else {
+        let exhausted: Option<RecordId> = db
+            .query("SELECT VALUE id FROM identifier_verifications WHERE user_identifier = $user_identifier AND expires_at > time::now()")
+            .bind(("user_identifier", user_identifier.id.clone()))
+            .await
+            .map_err(|e| VerificationError::DatabaseError(Box::new(e)))
+            .with_context(|| "Failed to fetch the pending verification")?
+            .take(0)
+            .map_err(|e| VerificationError::DatabaseError(Box::new(e)))?;
+
+        if exhausted.is_some() {
+            return Err(VerificationError::TooManyAttempts.into());
+        }
+        return Err(VerificationError::InvalidCode.into());
+    };
+
+    if !session_token_matches(code, &verification.code_hash) {
+        Err(VerificationError::InvalidCode)?
+    }
+
+    let surql = r#"
+            BEGIN TRANSACTION;
+
+            UPDATE $user_identifier SET verified_at = time::now(), updated_at = time::now();
+            DELETE $verification;
+
+            COMMIT TRANSACTION;
+        "#;
+
+    db.query(surql)
+        .bind(("user_identifier", user_identifier.id))
+        .bind(("verification", verification.id))
+        .await
+        .map_err(|e| VerificationError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to mark the identifier as verified")?
+        .check()
+        .map_err(|e| VerificationError::DatabaseError(Box::new(e)))?;
+
+    Ok(())
+}
+
+/// Looks up an identifier of `user_id` so a verification can be (re)sent for it.
+pub async fn find_identifier_of_user(
+    user_id: &RecordId,
+    identifier: &Identifier,
+) -> Result<UserIdentifier> {
+    let user_identifier = find_user_identifier(identifier)
+        .await?
+        .filter(|user_identifier| &user_identifier.user_id == user_id)
+        .ok_or(VerificationError::IdentifierNotFound)?;
+
+    Ok(user_identifier)
+}
+
+/// Whether the user verified an email or mobile. Accounts of OpenID Connect
+/// providers don't count.
+pub async fn has_verified_identifier(user_id: RecordId) -> Result<bool> {
+    let db = get_db();
+
+    // Not `identifier_type IN [...]`, which finds nothing once the query planner
+    // uses the index on user_id, identifier_type and oidc_provider
+    let verified: Vec<RecordId> = db
+        .query("SELECT VALUE id FROM user_identifier WHERE user_id = $user_id AND identifier_type != 'oidc' AND verified_at != NONE")
+        .bind(("user_id", user_id))
+        .await
+        .map_err(|e| VerificationError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the verified identifiers of the user")?
+        .take(0)
+        .map_err(|e| VerificationError::DatabaseError(Box::new(e)))?;
+
+    Ok(!verified.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{messages_to, register_test_user, run};
+
+    /// Sends a verification to `email` and returns the code from the link
+    async fn send_email_verification(email: &str) -> String {
+        let identifier = Identifier::Email(email.to_string());
+        let user_identifier = find_user_identifier(&identifier).await.unwrap().unwrap();
+        send_verification(&user_identifier).await.unwrap();
+
+        let message = messages_to(email).pop().expect("No verification link was sent");
+        let (_, code) = message.split_once("code=").expect("The message has no verification link");
+
+        code.split_whitespace().next().unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn only_the_hash_of_a_code_is_stored_and_it_verifies_once() {
+        run(async {
+            let email = "verify-once@example.com";
+            let user_id = register_test_user(email).await;
+            let code = send_email_verification(email).await;
+
+            let stored: Vec<String> = get_db()
+                .query("SELECT VALUE code_hash FROM identifier_verifications")
+                .await
+                .unwrap()
+                .take(0)
+                .unwrap();
+            assert!(stored.contains(&hash_session_token(&code)));
+            assert!(!stored.contains(&code));
+
+            let identifier = Identifier::Email(email.to_string());
+            assert!(!has_verified_identifier(user_id.clone()).await.unwrap());
+            verify_identifier(&identifier, &code).await.unwrap();
+            assert!(has_verified_identifier(user_id).await.unwrap());
+
+            let error = verify_identifier(&identifier, &code).await.unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<VerificationError>(),
+                Some(VerificationError::AlreadyVerified)
+            ));
+        });
+    }
+
+    #[test]
+    fn wrong_codes_lock_the_verification() {
+        run(async {
+            let email = "verify-locked@example.com";
+            register_test_user(email).await;
+            let code = send_email_verification(email).await;
+            let identifier = Identifier::Email(email.to_string());
+
+            for _ in 0..MAX_VERIFICATION_ATTEMPTS {
+                let error = verify_identifier(&identifier, "not the code").await.unwrap_err();
+                assert!(matches!(
+                    error.downcast_ref::<VerificationError>(),
+                    Some(VerificationError::InvalidCode)
+                ));
+            }
+
+            let error = verify_identifier(&identifier, &code).await.unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<VerificationError>(),
+                Some(VerificationError::TooManyAttempts)
+            ));
+        });
+    }
+
+    /// Moves the last send of the pending verifications of `email` out of the cooldown
+    async fn end_resend_cooldown(email: &str) {
+        let user_identifier = find_user_identifier(&Identifier::Email(email.to_string())).await.unwrap().unwrap();
+
+        get_db()
+            .query("UPDATE identifier_verifications SET last_sent_at = time::now() - 1h WHERE user_identifier = $user_identifier")
+            .bind(("user_identifier", user_identifier.id))
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn resends_are_limited_and_keep_the_wrong_attempts() {
+        run(async {
+            let email = "verify-resend@example.com";
+            register_test_user(email).await;
+            send_email_verification(email).await;
+            let identifier = Identifier::Email(email.to_string());
+            let user_identifier = find_user_identifier(&identifier).await.unwrap().unwrap();
+
+            let error = send_verification(&user_identifier).await.unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<VerificationError>(),
+                Some(VerificationError::ResendTooSoon(_))
+            ));
+
+            for _ in 0..MAX_VERIFICATION_ATTEMPTS {
+                verify_identifier(&identifier, "not the code").await.unwrap_err();
+            }
+
+            end_resend_cooldown(email).await;
+            let code = send_email_verification(email).await;
+            let error = verify_identifier(&identifier, &code).await.unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<VerificationError>(),
+                Some(VerificationError::TooManyAttempts)
+            ));
+
+            for _ in 2..MAX_VERIFICATION_SENDS {
+                end_resend_cooldown(email).await;
+                send_verification(&user_identifier).await.unwrap();
+            }
+
+            end_resend_cooldown(email).await;
+            let error = send_verification(&user_identifier).await.unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<VerificationError>(),
+                Some(VerificationError::TooManySends)
+            ));
+        });
+    }
+}
@@ -0,0 +1,722 @@
+use chrono::Duration;
+use dotenvy::dotenv;
+use leptos::config::Env;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    auth::{
+        oidc_config::{OidcConfig, OidcProviderConfig},
+        password_config::PasswordConfig,
+        session_config::{CookieConfig, MIN_TOKEN_SECRET_LENGTH, SameSite, SessionConfig},
+        two_factor_config::TwoFactorConfig,
+    },
+    errors::config::ConfigError,
+    models::user::UserRole,
+    notifications::{EmailDelivery, NotificationConfig, SmsDelivery, sms::SmsWebhookConfig, smtp::SmtpConfig},
+};
+
+/// Where the config file is looked for when `MERZAH_CONFIG` doesn't point elsewhere
+const DEFAULT_CONFIG_PATH: &str = "merzah.toml";
+
+/// The settings of the app, read from an optional TOML file and the environment
+/// once at startup. Server functions get it from the Leptos context.
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    pub database: DatabaseConfig,
+    pub mosques: MosqueConfig,
+    pub session: SessionConfig,
+    pub passwords: PasswordConfig,
+    pub two_factor: TwoFactorConfig,
+    pub oidc: OidcConfig,
+    pub notifications: NotificationConfig,
+}
+
+#[derive(Debug, Clone)]
+pub struct DatabaseConfig {
+    /// e.g. `ws://127.0.0.1:8000`, a bare `host:port` is connected to over WebSocket
+    pub url: String,
+    pub username: String,
+    pub password: String,
+    pub namespace: String,
+    pub name: String,
+    /// The directory with `schemas/`, `events/` and `migrations/`, looked for
+    /// around the executable when unset
+    pub scripts_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MosqueConfig {
+    /// The Overpass API interpreter mosques are imported from
+    pub overpass_url: String,
+    /// How long the Overpass API may take to answer an import
+    pub overpass_timeout_in_seconds: u32,
+    /// How far from the user mosques are searched for
+    pub search_radius_in_meters: u32,
+}
+
+impl Default for MosqueConfig {
+    fn default() -> Self {
+        Self {
+            overpass_url: "https://overpass-api.de/api/interpreter".to_string(),
+            overpass_timeout_in_seconds: 180,
+            search_radius_in_meters: 10_000,
+        }
+    }
+}
+
+/// The config file, in which every setting is optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    database: DatabaseSection,
+    mosques: MosqueSection,
+    session: SessionSection,
+    passwords: PasswordSection,
+    two_factor: TwoFactorSection,
+    oidc: OidcSection,
+    notifications: NotificationSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct DatabaseSection {
+    url: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    namespace: Option<String>,
+    name: Option<String>,
+    scripts_dir: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct MosqueSection {
+    overpass_url: Option<String>,
+    overpass_timeout_in_seconds: Option<u32>,
+    search_radius_in_meters: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SessionSection {
+    token_secret: Option<String>,
+    idle_timeout_in_minutes: Option<i64>,
+    renewal_window_in_minutes: Option<i64>,
+    max_lifetime_in_minutes: Option<i64>,
+    rotation_interval_in_minutes: Option<i64>,
+    cookie_name: Option<String>,
+    cookie_secure: Option<bool>,
+    cookie_same_site: Option<String>,
+    cookie_domain: Option<String>,
+    cookie_max_age_in_minutes: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PasswordSection {
+    argon2_memory_kib: Option<u32>,
+    argon2_iterations: Option<u32>,
+    argon2_parallelism: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TwoFactorSection {
+    issuer: Option<String>,
+    required_roles: Option<Vec<UserRole>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct OidcSection {
+    providers: BTreeMap<String, OidcProviderSection>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct OidcProviderSection {
+    display_name: Option<String>,
+    issuer_url: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    scopes: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct NotificationSection {
+    app_base_url: Option<String>,
+    email_channel: Option<String>,
+    sms_channel: Option<String>,
+    delivery_file_path: Option<String>,
+    smtp_host: Option<String>,
+    smtp_username: Option<String>,
+    smtp_password: Option<String>,
+    smtp_from: Option<String>,
+    sms_webhook_url: Option<String>,
+    sms_webhook_api_key: Option<String>,
+}
+
+/// Looks settings up in the environment and then in the config file, noting
+/// every problem with them rather than stopping at the first
+struct Settings<E> {
+    env: E,
+    problems: Vec<String>,
+}
+
+impl<E: Fn(&str) -> Option<String>> Settings<E> {
+    fn optional(&self, key: &str, from_file: Option<String>) -> Option<String> {
+        (self.env)(key).or(from_file).filter(|value| !value.trim().is_empty())
+    }
+
+    fn required(&mut self, key: &str, section: &str, from_file: Option<String>) -> String {
+        let value = self.optional(key, from_file);
+        if value.is_none() {
+            self.problems.push(format!("{} must be set, or `{}` in the config file", key, section));
+        }
+        value.unwrap_or_default()
+    }
+
+    /// A setting parsed from the environment or as the file has it, unset when
+    /// it is neither or not `expected`
+    fn checked<T: FromStr>(
+        &mut self,
+        key: &str,
+        section: &str,
+        from_file: Option<T>,
+        expected: &str,
+        is_valid: impl Fn(&T) -> bool,
+    ) -> Option<T> {
+        // Set to something that isn't even a `T` in the environment reads as `Some(None)`
+        let (value, name) = match (self.env)(key) {
+            Some(value) => (Some(value.trim().parse().ok()), key.to_string()),
+            None => (from_file.map(Some), format!("`{}`", section)),
+        };
+
+        match value {
+            None => None,
+            Some(Some(value)) if is_valid(&value) => Some(value),
+            Some(_) => {
+                self.problems.push(format!("{} must be {}", name, expected));
+                None
+            },
+        }
+    }
+
+    fn minutes(&mut self, key: &str, section: &str, from_file: Option<i64>) -> Option<Duration> {
+        self.checked(key, section, from_file, "a positive whole number of minutes", |minutes| *minutes > 0)
+            .map(Duration::minutes)
+    }
+}
+
+impl AppConfig {
+    /// Reads the config file at `MERZAH_CONFIG`, or `merzah.toml` when there is
+    /// one, with the environment taking precedence over it. Some defaults
+    /// follow the Leptos `env`.
+    pub fn load(leptos_env: &Env) -> Result<Self, ConfigError> {
+        dotenv().ok();
+
+        let path = env::var("MERZAH_CONFIG").ok();
+        let path = match path.as_deref() {
+            Some(path) => Some(path),
+            None => Path::new(DEFAULT_CONFIG_PATH).exists().then_some(DEFAULT_CONFIG_PATH),
+        };
+
+        let file = match path {
+            Some(path) => Some(fs::read_to_string(path).map_err(|source| ConfigError::Unreadable {
+                path: path.to_string(),
+                source,
+            })?),
+            None => None,
+        };
+
+        Self::from_sources(file.as_deref(), path.unwrap_or(DEFAULT_CONFIG_PATH), leptos_env, |key| env::var(key).ok())
+    }
+
+    /// Builds the config from the content of a config file and a lookup of
+    /// environment variables, reporting every missing or invalid setting at once
+    fn from_sources(
+        file: Option<&str>,
+        path: &str,
+        leptos_env: &Env,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        let file: ConfigFile = match file {
+            Some(content) => toml::from_str(content).map_err(|source| ConfigError::InvalidFile {
+                path: path.to_string(),
+                source,
+            })?,
+            None => ConfigFile::default(),
+        };
+
+        let mut settings = Settings { env, problems: Vec::new() };
+
+        let database = DatabaseConfig {
+            url: settings.required("SURREAL_URL", "database.url", file.database.url),
+            username: settings.required("SURREAL_USER", "database.username", file.database.username),
+            password: settings.required("SURREAL_PASS", "database.password", file.database.password),
+            namespace: settings.required("SURREAL_NS", "database.namespace", file.database.namespace),
+            name: settings.required("SURREAL_DB", "database.name", file.database.name),
+            scripts_dir: settings.optional("MERZAH_SCRIPTS_DIR", file.database.scripts_dir).map(PathBuf::from),
+        };
+
+        let mosques = mosque_config(&mut settings, file.mosques);
+        let session = session_config(&mut settings, file.session, leptos_env);
+        let passwords = password_config(&mut settings, file.passwords);
+        let two_factor = two_factor_config(&mut settings, file.two_factor);
+        let oidc = oidc_config(&mut settings, file.oidc);
+        let notifications = notification_config(&mut settings, file.notifications, leptos_env);
+
+        if !settings.problems.is_empty() {
+            return Err(ConfigError::Invalid(settings.problems));
+        }
+
+        Ok(Self { database, mosques, session, passwords, two_factor, oidc, notifications })
+    }
+}
+
+fn mosque_config(settings: &mut Settings<impl Fn(&str) -> Option<String>>, file: MosqueSection) -> MosqueConfig {
+    let defaults = MosqueConfig::default();
+
+    let search_radius_in_meters = settings
+        .checked(
+            "MOSQUE_SEARCH_RADIUS_METERS",
+            "mosques.search_radius_in_meters",
+            file.search_radius_in_meters,
+            "a positive whole number of meters",
+            |radius| *radius > 0,
+        )
+        .unwrap_or(defaults.search_radius_in_meters);
+
+    let overpass_timeout_in_seconds = settings
+        .checked(
+            "OVERPASS_TIMEOUT_SECONDS",
+            "mosques.overpass_timeout_in_seconds",
+            file.overpass_timeout_in_seconds,
+            "a positive whole number of seconds",
+            |timeout| *timeout > 0,
+        )
+        .unwrap_or(defaults.overpass_timeout_in_seconds);
+
+    let overpass_url = settings.optional("OVERPASS_URL", file.overpass_url).unwrap_or(defaults.overpass_url);
+    if !(overpass_url.starts_with("http://") || overpass_url.starts_with("https://")) {
+        settings.problems.push("OVERPASS_URL must be an http:// or https:// URL".to_string());
+    }
+
+    MosqueConfig { overpass_url, overpass_timeout_in_seconds, search_radius_in_meters }
+}
+
+/// The session config, with the cookie defaulting to what suits the Leptos `env`
+fn session_config(
+    settings: &mut Settings<impl Fn(&str) -> Option<String>>,
+    file: SessionSection,
+    leptos_env: &Env,
+) -> SessionConfig {
+    let defaults = SessionConfig::default();
+
+    let cookie = cookie_config(settings, &file, leptos_env);
+    let token_secret = settings.required("SESSION_TOKEN_SECRET", "session.token_secret", file.token_secret);
+    if !token_secret.is_empty() && token_secret.len() < MIN_TOKEN_SECRET_LENGTH {
+        settings.problems.push(format!("SESSION_TOKEN_SECRET must be at least {} characters long", MIN_TOKEN_SECRET_LENGTH));
+    }
+
+    let config = SessionConfig {
+        idle_timeout: settings
+            .minutes("SESSION_IDLE_TIMEOUT_MINUTES", "session.idle_timeout_in_minutes", file.idle_timeout_in_minutes)
+            .unwrap_or(defaults.idle_timeout),
+        renewal_window: settings
+            .minutes("SESSION_RENEWAL_WINDOW_MINUTES", "session.renewal_window_in_minutes", file.renewal_window_in_minutes)
+            .unwrap_or(defaults.renewal_window),
+        max_lifetime: settings
+            .minutes("SESSION_MAX_LIFETIME_MINUTES", "session.max_lifetime_in_minutes", file.max_lifetime_in_minutes)
+            .unwrap_or(defaults.max_lifetime),
+        rotation_interval: settings
+            .minutes(
+                "SESSION_ROTATION_INTERVAL_MINUTES",
+                "session.rotation_interval_in_minutes",
+                file.rotation_interval_in_minutes,
+            )
+            .unwrap_or(defaults.rotation_interval),
+        token_secret,
+        cookie,
+    };
+
+    if config.renewal_window > config.idle_timeout {
+        settings.problems.push("The session renewal window must not be longer than the idle timeout".to_string());
+    }
+    if config.idle_timeout > config.max_lifetime {
+        settings.problems.push("The session idle timeout must not be longer than the max lifetime".to_string());
+    }
+
+    config
+}
+
+fn cookie_config(
+    settings: &mut Settings<impl Fn(&str) -> Option<String>>,
+    file: &SessionSection,
+    leptos_env: &Env,
+) -> CookieConfig {
+    let defaults = CookieConfig::for_env(leptos_env);
+
+    let same_site = match settings.optional("SESSION_COOKIE_SAME_SITE", file.cookie_same_site.clone()) {
+        Some(same_site) => same_site.parse().unwrap_or_else(|()| {
+            settings.problems.push("SESSION_COOKIE_SAME_SITE must be Strict, Lax or None".to_string());
+            defaults.same_site
+        }),
+        None => defaults.same_site,
+    };
+
+    let config = CookieConfig {
+        name: settings.optional("SESSION_COOKIE_NAME", file.cookie_name.clone()).unwrap_or(defaults.name),
+        secure: settings
+            .checked("SESSION_COOKIE_SECURE", "session.cookie_secure", file.cookie_secure, "true or false", |_| true)
+            .unwrap_or(defaults.secure),
+        same_site,
+        domain: settings.optional("SESSION_COOKIE_DOMAIN", file.cookie_domain.clone()),
+        max_age: settings.minutes(
+            "SESSION_COOKIE_MAX_AGE_MINUTES",
+            "session.cookie_max_age_in_minutes",
+            file.cookie_max_age_in_minutes,
+        ),
+    };
+
+    // Browsers silently drop cookies breaking these rules, which looks like logins not sticking
+    if config.name.starts_with("__Host-") && (!config.secure || config.domain.is_some()) {
+        settings.problems.push("A __Host- session cookie must be Secure and can't have a domain".to_string());
+    }
+    if (config.name.starts_with("__Secure-") || config.same_site == SameSite::None) && !config.secure {
+        settings.problems.push("This session cookie must be Secure".to_string());
+    }
+
+    config
+}
+
+fn password_config(settings: &mut Settings<impl Fn(&str) -> Option<String>>, file: PasswordSection) -> PasswordConfig {
+    let defaults = PasswordConfig::default();
+
+    let mut number = |key: &str, section: &str, from_file: Option<u32>, default: u32| {
+        settings.checked(key, section, from_file, "a whole number", |_| true).unwrap_or(default)
+    };
+    let config = PasswordConfig {
+        memory_cost: number("ARGON2_MEMORY_KIB", "passwords.argon2_memory_kib", file.argon2_memory_kib, defaults.memory_cost),
+        iterations: number("ARGON2_ITERATIONS", "passwords.argon2_iterations", file.argon2_iterations, defaults.iterations),
+        parallelism: number(
+            "ARGON2_PARALLELISM",
+            "passwords.argon2_parallelism",
+            file.argon2_parallelism,
+            defaults.parallelism,
+        ),
+    };
+
+    if let Err(error) = argon2::Params::new(config.memory_cost, config.iterations, config.parallelism, None) {
+        settings.problems.push(format!("The Argon2 memory, iterations and parallelism are invalid: {}", error));
+    }
+
+    config
+}
+
+fn two_factor_config(
+    settings: &mut Settings<impl Fn(&str) -> Option<String>>,
+    file: TwoFactorSection,
+) -> TwoFactorConfig {
+    let defaults = TwoFactorConfig::default();
+
+    let issuer = settings.optional("TOTP_ISSUER", file.issuer).unwrap_or(defaults.issuer);
+    if issuer.contains(':') {
+        settings.problems.push("TOTP_ISSUER must not contain a colon".to_string());
+    }
+
+    // A comma separated list like `app_admin,mosque_admin`
+    let required_roles = match (settings.env)("TWO_FACTOR_REQUIRED_ROLES") {
+        Some(roles) => roles
+            .split(',')
+            .map(str::trim)
+            .filter(|role| !role.is_empty())
+            .filter_map(|role| {
+                let parsed = serde_json::from_value(serde_json::Value::String(role.to_string())).ok();
+                if parsed.is_none() {
+                    settings.problems.push(format!("TWO_FACTOR_REQUIRED_ROLES has an unknown role: {}", role));
+                }
+                parsed
+            })
+            .collect(),
+        None => file.required_roles.unwrap_or(defaults.required_roles),
+    };
+
+    TwoFactorConfig { issuer, required_roles }
+}
+
+/// The providers listed in `OIDC_PROVIDERS`, e.g. `google,apple`, or else in
+/// the config file. Each is configured through `OIDC_<NAME>_ISSUER_URL`,
+/// `OIDC_<NAME>_CLIENT_ID` and optionally `OIDC_<NAME>_CLIENT_SECRET`,
+/// `OIDC_<NAME>_DISPLAY_NAME` and `OIDC_<NAME>_SCOPES`, or its table in the file.
+fn oidc_config(settings: &mut Settings<impl Fn(&str) -> Option<String>>, mut file: OidcSection) -> OidcConfig {
+    let names: Vec<String> = match (settings.env)("OIDC_PROVIDERS") {
+        Some(names) => names.split(',').map(str::trim).filter(|name| !name.is_empty()).map(str::to_string).collect(),
+        None => file.providers.keys().cloned().collect(),
+    };
+
+    let mut providers = Vec::new();
+    for name in names {
+        if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            settings.problems.push(format!("OpenID Connect provider names may only contain letters, digits and underscores: {}", name));
+            continue;
+        }
+
+        let provider = file.providers.remove(&name).unwrap_or_default();
+        let key = |setting: &str| format!("OIDC_{}_{}", name.to_uppercase(), setting);
+        let section = |setting: &str| format!("oidc.providers.{}.{}", name, setting);
+
+        providers.push(OidcProviderConfig {
+            name: name.to_lowercase(),
+            display_name: settings.optional(&key("DISPLAY_NAME"), provider.display_name).unwrap_or_else(|| name.clone()),
+            issuer_url: settings.required(&key("ISSUER_URL"), &section("issuer_url"), provider.issuer_url),
+            client_id: settings.required(&key("CLIENT_ID"), &section("client_id"), provider.client_id),
+            client_secret: settings.optional(&key("CLIENT_SECRET"), provider.client_secret),
+            scopes: match (settings.env)(&key("SCOPES")) {
+                Some(scopes) => scopes.split_whitespace().map(str::to_string).collect(),
+                None => provider.scopes.unwrap_or_else(|| vec!["email".to_string(), "profile".to_string()]),
+            },
+        });
+    }
+
+    OidcConfig { providers }
+}
+
+/// The email channel is `smtp`, `console` or `file` and the SMS channel
+/// `webhook`, `console` or `file`. Both have to be chosen, and the console is
+/// only allowed in development so codes and reset links never end up in the
+/// logs of a deployment.
+fn notification_config(
+    settings: &mut Settings<impl Fn(&str) -> Option<String>>,
+    file: NotificationSection,
+    leptos_env: &Env,
+) -> NotificationConfig {
+    let console = |settings: &mut Settings<_>, key: &str| {
+        if *leptos_env != Env::DEV {
+            settings.problems.push(format!("{} can only be console in development", key));
+        }
+    };
+    let delivery_file = |settings: &mut Settings<_>| {
+        PathBuf::from(settings.required("DELIVERY_FILE_PATH", "notifications.delivery_file_path", file.delivery_file_path.clone()))
+    };
+
+    let email_channel = settings.required("EMAIL_DELIVERY_CHANNEL", "notifications.email_channel", file.email_channel.clone());
+    let email = match email_channel.as_str() {
+        "smtp" => {
+            let smtp = SmtpConfig {
+                host: settings.required("SMTP_HOST", "notifications.smtp_host", file.smtp_host.clone()),
+                username: settings.required("SMTP_USER", "notifications.smtp_username", file.smtp_username.clone()),
+                password: settings.required("SMTP_PASS", "notifications.smtp_password", file.smtp_password.clone()),
+                from: settings.required("SMTP_FROM", "notifications.smtp_from", file.smtp_from.clone()),
+            };
+            if !smtp.from.is_empty() && smtp.from.parse::<lettre::message::Mailbox>().is_err() {
+                settings.problems.push("SMTP_FROM must be a mailbox like `Merzah <no-reply@merzah.example>`".to_string());
+            }
+            EmailDelivery::Smtp(smtp)
+        },
+        "file" => EmailDelivery::File(delivery_file(settings)),
+        "console" => {
+            console(settings, "EMAIL_DELIVERY_CHANNEL");
+            EmailDelivery::Console
+        },
+        "" => EmailDelivery::Console,
+        _ => {
+            settings.problems.push("EMAIL_DELIVERY_CHANNEL must be smtp, console or file".to_string());
+            EmailDelivery::Console
+        },
+    };
+
+    let sms_channel = settings.required("SMS_DELIVERY_CHANNEL", "notifications.sms_channel", file.sms_channel.clone());
+    let sms = match sms_channel.as_str() {
+        "webhook" => SmsDelivery::Webhook(SmsWebhookConfig {
+            url: settings.required("SMS_WEBHOOK_URL", "notifications.sms_webhook_url", file.sms_webhook_url.clone()),
+            api_key: settings.required(
+                "SMS_WEBHOOK_API_KEY",
+                "notifications.sms_webhook_api_key",
+                file.sms_webhook_api_key.clone(),
+            ),
+        }),
+        "file" => SmsDelivery::File(delivery_file(settings)),
+        "console" => {
+            console(settings, "SMS_DELIVERY_CHANNEL");
+            SmsDelivery::Console
+        },
+        "" => SmsDelivery::Console,
+        _ => {
+            settings.problems.push("SMS_DELIVERY_CHANNEL must be webhook, console or file".to_string());
+            SmsDelivery::Console
+        },
+    };
+
+    let app_base_url = settings
+        .optional("APP_BASE_URL", file.app_base_url)
+        .unwrap_or_else(|| "http://127.0.0.1:3000".to_string());
+    if !(app_base_url.starts_with("http://") || app_base_url.starts_with("https://")) {
+        settings.problems.push("APP_BASE_URL must be an http:// or https:// URL".to_string());
+    }
+
+    NotificationConfig { email, sms, app_base_url: app_base_url.trim_end_matches('/').to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env_of(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> =
+            vars.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        move |key| vars.get(key).cloned()
+    }
+
+    /// Everything that has to be set, leaving the rest to the defaults
+    const REQUIRED_FILE: &str = r#"
+        [database]
+        url = "ws://db:8000"
+        username = "root"
+        password = "secret"
+        namespace = "merzah"
+        name = "merzah"
+
+        [session]
+        token_secret = "a secret that is long enough to hash tokens with"
+
+        [notifications]
+        email_channel = "file"
+        sms_channel = "file"
+        delivery_file_path = "/tmp/merzah-messages.txt"
+    "#;
+
+    #[test]
+    fn the_environment_takes_precedence_over_the_file() {
+        let config = AppConfig::from_sources(
+            Some(REQUIRED_FILE),
+            "merzah.toml",
+            &Env::PROD,
+            env_of(&[
+                ("SURREAL_URL", "127.0.0.1:8000"),
+                ("MOSQUE_SEARCH_RADIUS_METERS", "2500"),
+                ("SESSION_IDLE_TIMEOUT_MINUTES", "30"),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(config.database.url, "127.0.0.1:8000");
+        assert_eq!(config.database.password, "secret");
+        assert_eq!(config.mosques.search_radius_in_meters, 2500);
+        assert_eq!(config.mosques.overpass_url, MosqueConfig::default().overpass_url);
+        assert_eq!(config.session.idle_timeout, Duration::minutes(30));
+        assert_eq!(config.session.cookie.name, "__Host-session");
+        assert!(matches!(config.notifications.email, EmailDelivery::File(_)));
+        assert_eq!(config.notifications.app_base_url, "http://127.0.0.1:3000");
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        let error = AppConfig::from_sources(
+            None,
+            "merzah.toml",
+            &Env::PROD,
+            env_of(&[("SURREAL_URL", "ws://db:8000"), ("MOSQUE_SEARCH_RADIUS_METERS", "far")]),
+        )
+        .unwrap_err();
+
+        let ConfigError::Invalid(problems) = error else {
+            panic!("Expected the settings to be invalid, got {}", error);
+        };
+        assert_eq!(problems.len(), 8);
+        assert!(problems.iter().any(|problem| problem.starts_with("SURREAL_PASS must be set")));
+        assert!(problems.iter().any(|problem| problem.starts_with("MOSQUE_SEARCH_RADIUS_METERS")));
+        assert!(problems.iter().any(|problem| problem.starts_with("SESSION_TOKEN_SECRET must be set")));
+        assert!(problems.iter().any(|problem| problem.starts_with("EMAIL_DELIVERY_CHANNEL must be set")));
+    }
+
+    #[test]
+    fn every_section_can_be_configured_in_the_file() {
+        let file = format!(
+            "{}{}",
+            REQUIRED_FILE.replace("email_channel = \"file\"", "email_channel = \"smtp\""),
+            r#"
+            smtp_host = "smtp.merzah.example"
+            smtp_username = "merzah"
+            smtp_password = "secret"
+            smtp_from = "Merzah <no-reply@merzah.example>"
+
+            [passwords]
+            argon2_iterations = 3
+
+            [two_factor]
+            required_roles = ["app_admin"]
+
+            [oidc.providers.google]
+            display_name = "Google"
+            issuer_url = "https://accounts.google.com"
+            client_id = "merzah"
+            "#
+        );
+
+        let config = AppConfig::from_sources(Some(&file), "merzah.toml", &Env::PROD, env_of(&[])).unwrap();
+
+        assert!(matches!(config.notifications.email, EmailDelivery::Smtp(ref smtp) if smtp.host == "smtp.merzah.example"));
+        assert_eq!(config.passwords.iterations, 3);
+        assert_eq!(config.two_factor.required_roles, vec![UserRole::AppAdmin]);
+        let google = config.oidc.provider("google").unwrap();
+        assert_eq!(google.display_name, "Google");
+        assert_eq!(google.scopes, vec!["email", "profile"]);
+    }
+
+    #[test]
+    fn inconsistent_sessions_and_cookies_are_rejected() {
+        let error = AppConfig::from_sources(
+            Some(REQUIRED_FILE),
+            "merzah.toml",
+            &Env::PROD,
+            env_of(&[
+                ("SESSION_RENEWAL_WINDOW_MINUTES", "120"),
+                ("SESSION_COOKIE_DOMAIN", "merzah.example"),
+                ("SESSION_COOKIE_SAME_SITE", "sometimes"),
+                ("TWO_FACTOR_REQUIRED_ROLES", "app_admin,superuser"),
+            ]),
+        )
+        .unwrap_err();
+
+        let ConfigError::Invalid(problems) = error else {
+            panic!("Expected the settings to be invalid, got {}", error);
+        };
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+    }
+
+    #[test]
+    fn messages_are_only_printed_in_development() {
+        let file = REQUIRED_FILE.replace("\"file\"", "\"console\"");
+
+        let error = AppConfig::from_sources(Some(&file), "merzah.toml", &Env::PROD, env_of(&[])).unwrap_err();
+        assert!(error.to_string().contains("EMAIL_DELIVERY_CHANNEL can only be console in development"));
+
+        let config = AppConfig::from_sources(Some(&file), "merzah.toml", &Env::DEV, env_of(&[])).unwrap();
+        assert!(matches!(config.notifications.sms, SmsDelivery::Console));
+    }
+
+    #[test]
+    fn the_example_config_is_valid_for_development() {
+        let file = include_str!("../merzah.example.toml");
+
+        assert!(AppConfig::from_sources(Some(file), "merzah.example.toml", &Env::DEV, env_of(&[])).is_ok());
+    }
+
+    #[test]
+    fn unknown_settings_in_the_file_are_rejected() {
+        let file = format!("{}\n[mosques]\nsearch_radius = 5000\n", REQUIRED_FILE);
+
+        let error = AppConfig::from_sources(Some(&file), "merzah.toml", &Env::PROD, env_of(&[])).unwrap_err();
+
+        assert!(matches!(error, ConfigError::InvalidFile { .. }));
+    }
+}
@@ -1,42 +1,55 @@
-use dotenvy::dotenv;
 use once_cell::sync::OnceCell;
-use std::env;
-use surrealdb::engine::remote::ws::{Client, Ws};
+use surrealdb::engine::any::{self, Any};
 use surrealdb::opt::auth::Root;
 use surrealdb::Surreal;
 
-static DB: OnceCell<Surreal<Client>> = OnceCell::new();
+use crate::config::DatabaseConfig;
 
-pub async fn init_db() {
-    dotenv().ok();
+static DB: OnceCell<Surreal<Any>> = OnceCell::new();
 
-    let db_url = env::var("SURREAL_URL").expect("SURREAL_URL must be set");
-    let db_user = env::var("SURREAL_USER").expect("SURREAL_USER must be set");
-    let db_pass = env::var("SURREAL_PASS").expect("SURREAL_PASS must be set");
-    let db_name = env::var("SURREAL_DB").expect("SURREAL_DB must be set");
-    let db_ns = env::var("SURREAL_NS").expect("SURREAL_NS must be set");
+pub async fn init_db(config: &DatabaseConfig) {
+    println!("Connecting to: {}", config.url);
 
-    println!("Connecting to: {}", db_url);
+    // `host:port` urls predate the engine being picked from the scheme
+    let db_url = if config.url.contains("://") {
+        config.url.clone()
+    } else {
+        format!("ws://{}", config.url)
+    };
 
-    let db = Surreal::new::<Ws>(&db_url)
+    let db = any::connect(db_url)
         .await
         .expect("Failed to connect to database");
 
     db.signin(Root {
-        username: &db_user,
-        password: &db_pass,
+        username: &config.username,
+        password: &config.password,
     })
     .await
     .expect("Failed to sign in to database");
 
-    db.use_ns(&db_ns)
-        .use_db(&db_name)
+    db.use_ns(&config.namespace)
+        .use_db(&config.name)
         .await
         .expect("Failed to use namespace or database");
     DB.set(db).unwrap();
 }
 
-pub fn get_db() -> &'static Surreal<Client> {
+/// Points `get_db` at an empty in-memory database, for tests
+#[cfg(test)]
+pub async fn init_memory_db() {
+    let db = any::connect("mem://")
+        .await
+        .expect("Failed to start the in-memory database");
+
+    db.use_ns("test")
+        .use_db("test")
+        .await
+        .expect("Failed to use namespace or database");
+    DB.set(db).unwrap_or_else(|_| panic!("Database already initialized"));
+}
+
+pub fn get_db() -> &'static Surreal<Any> {
     DB.get().expect("Database not initialized")
 }
 
@@ -0,0 +1,317 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+use crate::{database::connection::get_db, errors::migration::MigrationError};
+
+/// A `.surql` file, named after the file without its extension
+#[derive(Debug, Clone)]
+pub struct Script {
+    pub name: String,
+    pub content: String,
+    pub checksum: String,
+}
+
+/// What running the migrations did, or would do on a dry run
+#[derive(Debug, Default)]
+pub struct MigrationReport {
+    /// The schema and event definitions, which are applied on every run
+    pub definitions: Vec<String>,
+    /// The migrations that weren't applied before, in the order they ran
+    pub applied: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AppliedScript {
+    script_name: String,
+    checksum: Option<String>,
+}
+
+/// The directory the scripts are in: the configured one, or else the closest
+/// one around the executable that has a `migrations/` directory, which is the
+/// project for a `target/debug/merzah` and the install directory otherwise.
+pub fn find_scripts_root(configured: Option<&Path>) -> Result<PathBuf> {
+    if let Some(root) = configured {
+        return Ok(root.to_path_buf());
+    }
+
+    let executable = env::current_exe().map_err(|source| MigrationError::Unreadable {
+        path: "the directory of the executable".to_string(),
+        source,
+    })?;
+    let root = executable
+        .ancestors()
+        .skip(1)
+        .find(|directory| directory.join("migrations").is_dir())
+        .ok_or_else(|| MigrationError::MissingScripts(executable.parent().unwrap_or(&executable).display().to_string()))?;
+
+    Ok(root.to_path_buf())
+}
+
+/// Brings the database up to date with the scripts under `root`.
+///
+/// The definitions in `schemas/` and `events/` are idempotent and applied every
+/// time. The scripts in `migrations/` are applied once each, in the order of
+/// their names, and recorded in `script_migration` along with their checksum.
+/// Nothing runs when a migration was changed after it was applied, or when a
+/// new one handles transactions itself. A dry run only reports what would be
+/// applied.
+pub async fn run_migrations(root: &Path, dry_run: bool) -> Result<MigrationReport> {
+    // Without it every migration would look applied, most likely because the
+    // scripts are looked for in the wrong place
+    if !root.join("migrations").is_dir() {
+        Err(MigrationError::MissingScripts(root.display().to_string()))?
+    }
+
+    let definitions = [read_scripts(&root.join("schemas"))?, read_scripts(&root.join("events"))?].concat();
+    let migrations = read_scripts(&root.join("migrations"))?;
+
+    let db = get_db();
+
+    // A database that was never migrated has no `script_migration` table yet,
+    // which reads as empty
+    let applied_scripts: Vec<AppliedScript> = db
+        .query("SELECT script_name, checksum FROM script_migration")
+        .await
+        .map_err(|e| MigrationError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the applied migrations")?
+        .take(0)
+        .map_err(|e| MigrationError::DatabaseError(Box::new(e)))?;
+    let applied_checksums: HashMap<String, Option<String>> = applied_scripts
+        .into_iter()
+        .map(|applied| (applied.script_name, applied.checksum))
+        .collect();
+
+    let mut pending = Vec::new();
+    for migration in migrations {
+        match applied_checksums.get(&migration.name) {
+            // Applied before checksums were recorded, so there is nothing to compare with
+            Some(None) => {},
+            Some(Some(checksum)) if *checksum == migration.checksum => {},
+            Some(Some(_)) => Err(MigrationError::ChecksumMismatch(migration.name))?,
+            None if controls_transaction(&migration.content) => Err(MigrationError::OwnTransaction(migration.name))?,
+            None => pending.push(migration),
+        }
+    }
+
+    let report = MigrationReport {
+        definitions: definitions.iter().map(|script| script.name.clone()).collect(),
+        applied: pending.iter().map(|script| script.name.clone()).collect(),
+    };
+
+    if dry_run {
+        return Ok(report);
+    }
+
+    for definition in definitions {
+        let failed = |e| MigrationError::ScriptFailed { name: definition.name.clone(), source: Box::new(e) };
+
+        db.query(definition.content.as_str())
+            .await
+            .map_err(failed)?
+            .check()
+            .map_err(failed)?;
+    }
+
+    // Each migration is recorded in the same transaction it runs in, so a failed
+    // one is tried again on the next run
+    for migration in pending {
+        let surql = format!(
+            "BEGIN TRANSACTION;\n{}\n;CREATE script_migration SET script_name = $script_name, checksum = $checksum;\nCOMMIT TRANSACTION;",
+            migration.content
+        );
+
+        let failed = |e| MigrationError::ScriptFailed { name: migration.name.clone(), source: Box::new(e) };
+
+        db.query(surql)
+            .bind(("script_name", migration.name.clone()))
+            .bind(("checksum", migration.checksum.clone()))
+            .await
+            .map_err(failed)?
+            .check()
+            .map_err(failed)?;
+    }
+
+    Ok(report)
+}
+
+/// The `.surql` files directly in `directory`, ordered by name. A missing
+/// directory has no scripts.
+fn read_scripts(directory: &Path) -> Result<Vec<Script>> {
+    if !directory.exists() {
+        return Ok(Vec::new());
+    }
+
+    let unreadable = |source| MigrationError::Unreadable { path: directory.display().to_string(), source };
+
+    let mut scripts = Vec::new();
+    for entry in fs::read_dir(directory).map_err(unreadable)? {
+        let path = entry.map_err(unreadable)?.path();
+        if path.extension().is_none_or(|extension| extension != "surql") {
+            continue;
+        }
+
+        let content = fs::read_to_string(&path).map_err(unreadable)?;
+        scripts.push(Script {
+            name: path.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+            checksum: checksum(&content),
+            content,
+        });
+    }
+    scripts.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(scripts)
+}
+
+/// Whether the script has a `BEGIN`, `COMMIT` or `CANCEL` statement of its own,
+/// which would end the transaction the migration runs and is recorded in
+fn controls_transaction(content: &str) -> bool {
+    let without_comments: String = content
+        .lines()
+        .map(|line| {
+            let end = ["--", "//", "#"].iter().filter_map(|comment| line.find(comment)).min().unwrap_or(line.len());
+            &line[..end]
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    without_comments.split(';').any(|statement| {
+        let keyword = statement.split_whitespace().next().unwrap_or_default().to_uppercase();
+        matches!(keyword.as_str(), "BEGIN" | "COMMIT" | "CANCEL")
+    })
+}
+
+/// The SHA-256 of the script as hex
+fn checksum(content: &str) -> String {
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::run;
+
+    /// A directory of scripts of its own for the test, as `schemas/`,
+    /// `events/` and `migrations/` would be in the project
+    fn scripts_directory(name: &str, scripts: &[(&str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("merzah-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+
+        for (path, content) in scripts {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+
+        root
+    }
+
+    #[test]
+    fn migrations_are_applied_once_in_order() {
+        run(async {
+            let root = scripts_directory(
+                "migrations-in-order",
+                &[
+                    ("schemas/migration_test.surql", "DEFINE TABLE IF NOT EXISTS migration_test SCHEMALESS;"),
+                    ("migrations/20260101_000002_Second.surql", "UPDATE migration_test SET step = step + 1;"),
+                    ("migrations/20260101_000001_First.surql", "CREATE migration_test:only SET step = 1;"),
+                    ("migrations/notes.md", "Not a migration"),
+                ],
+            );
+
+            let dry_run = run_migrations(&root, true).await.unwrap();
+            assert_eq!(dry_run.definitions, vec!["migration_test"]);
+            assert_eq!(dry_run.applied, vec!["20260101_000001_First", "20260101_000002_Second"]);
+            let steps: Vec<i64> = get_db().query("SELECT VALUE step FROM migration_test").await.unwrap().take(0).unwrap();
+            assert!(steps.is_empty());
+
+            let report = run_migrations(&root, false).await.unwrap();
+            assert_eq!(report.applied, dry_run.applied);
+            let report = run_migrations(&root, false).await.unwrap();
+            assert!(report.applied.is_empty());
+
+            let steps: Vec<i64> = get_db().query("SELECT VALUE step FROM migration_test").await.unwrap().take(0).unwrap();
+            assert_eq!(steps, vec![2]);
+        });
+    }
+
+    #[test]
+    fn changed_migrations_stop_the_run() {
+        run(async {
+            let migration_path = "migrations/20260101_000001_Changed.surql";
+            let root = scripts_directory(
+                "migrations-changed",
+                &[(migration_path, "CREATE migration_changed_test:only SET step = 1;")],
+            );
+            run_migrations(&root, false).await.unwrap();
+
+            fs::write(root.join(migration_path), "CREATE migration_changed_test:only SET step = 2;").unwrap();
+            fs::write(root.join("migrations/20260101_000002_Later.surql"), "CREATE migration_changed_test:later;").unwrap();
+
+            for dry_run in [true, false] {
+                let error = run_migrations(&root, dry_run).await.unwrap_err();
+                assert!(matches!(error.downcast_ref::<MigrationError>(), Some(MigrationError::ChecksumMismatch(name)) if name == "20260101_000001_Changed"));
+            }
+            let later: Vec<serde_json::Value> =
+                get_db().query("SELECT * FROM migration_changed_test:later").await.unwrap().take(0).unwrap();
+            assert!(later.is_empty());
+        });
+    }
+
+    #[test]
+    fn failed_migrations_are_not_recorded() {
+        run(async {
+            let root = scripts_directory(
+                "migrations-failed",
+                &[("migrations/20260101_000001_Broken.surql", "THROW 'broken';")],
+            );
+
+            let error = run_migrations(&root, false).await.unwrap_err();
+            assert!(matches!(error.downcast_ref::<MigrationError>(), Some(MigrationError::ScriptFailed { .. })));
+            assert_eq!(run_migrations(&root, true).await.unwrap().applied, vec!["20260101_000001_Broken"]);
+        });
+    }
+
+    #[test]
+    fn migrations_handling_transactions_themselves_are_rejected() {
+        run(async {
+            let root = scripts_directory(
+                "migrations-transaction",
+                &[
+                    (
+                        "migrations/20260101_000001_Commented.surql",
+                        "-- Not wrapped in BEGIN TRANSACTION; by the script itself\nCREATE migration_transaction_test:commented;",
+                    ),
+                    (
+                        "migrations/20260101_000002_Transaction.surql",
+                        "BEGIN TRANSACTION;\nCREATE migration_transaction_test:own;\nCOMMIT TRANSACTION;",
+                    ),
+                ],
+            );
+
+            for dry_run in [true, false] {
+                let error = run_migrations(&root, dry_run).await.unwrap_err();
+                assert!(matches!(error.downcast_ref::<MigrationError>(), Some(MigrationError::OwnTransaction(name)) if name == "20260101_000002_Transaction"));
+            }
+            let created: Vec<serde_json::Value> =
+                get_db().query("SELECT * FROM migration_transaction_test").await.unwrap().take(0).unwrap();
+            assert!(created.is_empty());
+        });
+    }
+
+    #[test]
+    fn scripts_without_a_migrations_directory_are_not_run() {
+        run(async {
+            let root = scripts_directory("migrations-missing", &[("schemas/missing_test.surql", "DEFINE TABLE missing_test;")]);
+
+            let error = run_migrations(&root, true).await.unwrap_err();
+            assert!(matches!(error.downcast_ref::<MigrationError>(), Some(MigrationError::MissingScripts(_))));
+        });
+    }
+}
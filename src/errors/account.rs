@@ -0,0 +1,13 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum AccountError {
+    #[error("The account is already scheduled for deletion")]
+    DeletionAlreadyRequested,
+
+    #[error("The account is not scheduled for deletion")]
+    DeletionNotRequested,
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] Box<surrealdb::Error>),
+}
@@ -0,0 +1,16 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ApiTokenError {
+    #[error("The API token is invalid, revoked or expired")]
+    InvalidToken,
+
+    #[error("API token not found")]
+    TokenNotFound,
+
+    #[error("The API token doesn't have the scope needed")]
+    MissingScope,
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] Box<surrealdb::Error>),
+}
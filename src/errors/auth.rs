@@ -19,5 +19,11 @@ pub enum AuthError {
 
     #[error("Requested user was not found")]
     UserNotFound,
+
+    #[error("The user is not allowed to perform this action")]
+    Forbidden,
+
+    #[error("Too many failed logins, try again in {0} seconds")]
+    TooManyAttempts(i64),
 }
 
@@ -0,0 +1,13 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Failed to read the config file {path}: {source}")]
+    Unreadable { path: String, source: std::io::Error },
+
+    #[error("The config file {path} is invalid: {source}")]
+    InvalidFile { path: String, source: toml::de::Error },
+
+    #[error("The configuration is invalid:\n  - {}", .0.join("\n  - "))]
+    Invalid(Vec<String>),
+}
@@ -0,0 +1,28 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum IdentifierError {
+    #[error("The identifier is already registered")]
+    AlreadyTaken,
+
+    #[error("The user already has an identifier of this type")]
+    TypeAlreadyLinked,
+
+    #[error("An identifier can only be changed to one of the same type")]
+    TypeMismatch,
+
+    #[error("The identifier was not found")]
+    NotFound,
+
+    #[error("The last identifier of a user can't be removed")]
+    LastIdentifier,
+
+    #[error("Only a verified identifier can be made primary")]
+    Unverified,
+
+    #[error("Only an email or mobile can be made primary")]
+    NotContactable,
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] Box<surrealdb::Error>),
+}
@@ -0,0 +1,22 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum MigrationError {
+    #[error("Failed to read the scripts in {path}: {source}")]
+    Unreadable { path: String, source: std::io::Error },
+
+    #[error("There is no migrations directory in {0}, set MERZAH_SCRIPTS_DIR to the directory that has it")]
+    MissingScripts(String),
+
+    #[error("The migration {0} begins, commits or cancels a transaction, which the runner already wraps it in")]
+    OwnTransaction(String),
+
+    #[error("The migration {0} was changed after it was applied, restore it and add a new migration instead")]
+    ChecksumMismatch(String),
+
+    #[error("The script {name} failed: {source}")]
+    ScriptFailed { name: String, source: Box<surrealdb::Error> },
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] Box<surrealdb::Error>),
+}
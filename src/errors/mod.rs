@@ -2,3 +2,23 @@
 pub mod session;
 #[cfg(feature = "ssr")]
 pub mod auth;
+#[cfg(feature = "ssr")]
+pub mod password_reset;
+#[cfg(feature = "ssr")]
+pub mod verification;
+#[cfg(feature = "ssr")]
+pub mod identifier;
+#[cfg(feature = "ssr")]
+pub mod two_factor;
+#[cfg(feature = "ssr")]
+pub mod oidc;
+#[cfg(feature = "ssr")]
+pub mod api_token;
+#[cfg(feature = "ssr")]
+pub mod account;
+#[cfg(feature = "ssr")]
+pub mod config;
+#[cfg(feature = "ssr")]
+pub mod migration;
+#[cfg(feature = "ssr")]
+pub mod mosque;
@@ -0,0 +1,22 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum MosqueError {
+    #[error("The Overpass API could not be reached: {0}")]
+    OverpassUnreachable(#[source] reqwest::Error),
+
+    #[error("The Overpass API answered with status {status}: {body}")]
+    OverpassFailed { status: u16, body: String },
+
+    #[error("Failed to read {path}: {source}")]
+    UnreadableFile { path: String, source: std::io::Error },
+
+    #[error("{path} is not a valid OSM extract: {reason}")]
+    InvalidFile { path: String, reason: String },
+
+    #[error("The import job was not found")]
+    ImportJobNotFound,
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] Box<surrealdb::Error>),
+}
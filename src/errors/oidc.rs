@@ -0,0 +1,31 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum OidcError {
+    #[error("No OpenID Connect provider is called {0}")]
+    UnknownProvider(String),
+
+    #[error("The sign in is invalid or has expired")]
+    InvalidState,
+
+    #[error("The provider turned the sign in down: {0}")]
+    Rejected(String),
+
+    #[error("Failed to talk to the provider: {0}")]
+    Provider(String),
+
+    #[error("The provider's ID token is invalid: {0}")]
+    InvalidIdToken(String),
+
+    #[error("Another account already uses the email of this provider account")]
+    EmailTaken,
+
+    #[error("This provider account is linked to another user")]
+    AlreadyLinked,
+
+    #[error("An account of this provider is already linked")]
+    ProviderAlreadyLinked,
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] Box<surrealdb::Error>),
+}
@@ -0,0 +1,13 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum PasswordResetError {
+    #[error("The password reset token is invalid, expired or already used")]
+    InvalidToken,
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] Box<surrealdb::Error>),
+
+    #[error("Failed to deliver the password reset message")]
+    DeliveryError(#[source] anyhow::Error),
+}
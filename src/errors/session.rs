@@ -17,4 +17,7 @@ pub enum SessionError {
 
     #[error("User not found for the session")]
     UserNotFound,
+
+    #[error("No valid session was provided with the request")]
+    Unauthenticated,
 }
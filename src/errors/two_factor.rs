@@ -0,0 +1,28 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum TwoFactorError {
+    #[error("The code is invalid")]
+    InvalidCode,
+
+    #[error("The login has expired, log in again")]
+    InvalidChallenge,
+
+    #[error("Too many wrong codes, log in again")]
+    TooManyAttempts,
+
+    #[error("Two-factor authentication is already enabled")]
+    AlreadyEnabled,
+
+    #[error("Two-factor authentication is not enabled")]
+    NotEnabled,
+
+    #[error("Two-factor authentication has to be set up first")]
+    Required,
+
+    #[error("Failed to set up the authenticator: {0}")]
+    Provisioning(String),
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] Box<surrealdb::Error>),
+}
@@ -0,0 +1,31 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum VerificationError {
+    #[error("The verification code is invalid or has expired")]
+    InvalidCode,
+
+    #[error("Too many wrong verification codes, try again later")]
+    TooManyAttempts,
+
+    #[error("A verification code was just sent, try again in {0} seconds")]
+    ResendTooSoon(i64),
+
+    #[error("Too many verification codes were sent, try again later")]
+    TooManySends,
+
+    #[error("The identifier is already verified")]
+    AlreadyVerified,
+
+    #[error("The identifier was not found")]
+    IdentifierNotFound,
+
+    #[error("A verified email or mobile is required")]
+    Unverified,
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] Box<surrealdb::Error>),
+
+    #[error("Failed to deliver the verification message")]
+    DeliveryError(#[source] anyhow::Error),
+}
@@ -2,11 +2,19 @@ pub mod app;
 #[cfg(feature = "ssr")]
 pub mod auth;
 #[cfg(feature = "ssr")]
+pub mod config;
+#[cfg(feature = "ssr")]
 pub mod database;
 pub mod errors;
 pub mod models;
 #[cfg(feature = "ssr")]
+pub mod mosques;
+#[cfg(feature = "ssr")]
+pub mod notifications;
+#[cfg(feature = "ssr")]
 pub mod utils;
+#[cfg(all(test, feature = "ssr"))]
+mod test_support;
 pub mod pages;
 pub mod components;
 
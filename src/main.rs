@@ -8,13 +8,111 @@ async fn main() -> std::io::Result<()> {
     use leptos_actix::{LeptosRoutes, generate_route_list};
     use leptos_meta::MetaTags;
     use merzah::app::*;
+    use merzah::auth::account_deletion::purge_deleted_accounts;
+    use merzah::auth::middleware::{csrf_protection, session_renewal};
+    use merzah::auth::oidc_config::init_oidc_config;
+    use merzah::auth::password_config::init_password_config;
+    use merzah::auth::session_config::init_session_config;
+    use merzah::auth::two_factor_config::init_two_factor_config;
+    use merzah::config::AppConfig;
     use merzah::database::connection::init_db;
-
-    init_db().await;
+    use merzah::database::migrations::{find_scripts_root, run_migrations};
+    use merzah::mosques::import_jobs::resume_import_jobs;
+    use merzah::mosques::offline::import_file;
+    use merzah::notifications::init_notifications;
 
     let conf = get_configuration(None).unwrap();
     let addr = conf.leptos_options.site_addr;
 
+    // Every setting is checked before anything runs, the CLI commands included
+    let app_config = match AppConfig::load(&conf.leptos_options.env) {
+        Ok(app_config) => app_config,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+
+    init_session_config(app_config.session.clone());
+    init_password_config(app_config.passwords.clone());
+    init_two_factor_config(app_config.two_factor.clone());
+    init_oidc_config(app_config.oidc.clone());
+    if let Err(error) = init_notifications(&app_config.notifications) {
+        eprintln!("Failed to set up the notifications: {:#}", error);
+        std::process::exit(1);
+    }
+
+    init_db(&app_config.database).await;
+
+    // `merzah migrate [--dry-run]` only brings the database up to date, and
+    // `merzah import-mosques <file>` also imports the mosques of an OSM extract
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let import_path = match (args.first().map(String::as_str), args.get(1)) {
+        (Some("import-mosques"), Some(path)) => Some(std::path::PathBuf::from(path)),
+        (Some("import-mosques"), None) => {
+            eprintln!("Usage: merzah import-mosques <file.osm.pbf|file.geojson>");
+            std::process::exit(2);
+        },
+        _ => None,
+    };
+    let migrate_only = args.first().is_some_and(|command| command == "migrate");
+    let dry_run = migrate_only && args.iter().any(|arg| arg == "--dry-run");
+
+    let migration_result = match find_scripts_root(app_config.database.scripts_dir.as_deref()) {
+        Ok(scripts_root) => run_migrations(&scripts_root, dry_run).await,
+        Err(error) => Err(error),
+    };
+    match migration_result {
+        Ok(report) => {
+            let verb = if dry_run { "Would apply" } else { "Applied" };
+            println!("{} {} schema and event definitions", verb, report.definitions.len());
+            for migration in &report.applied {
+                println!("{} the migration {}", verb, migration);
+            }
+        },
+        Err(error) => {
+            eprintln!("Failed to migrate the database: {:#}", error);
+            std::process::exit(1);
+        }
+    }
+    if migrate_only {
+        return Ok(());
+    }
+    if let Some(import_path) = import_path {
+        match import_file(&import_path).await {
+            Ok(summary) => println!(
+                "Imported {}: {} created, {} updated, {} unchanged, {} skipped",
+                import_path.display(),
+                summary.created,
+                summary.updated,
+                summary.unchanged,
+                summary.skipped
+            ),
+            Err(error) => {
+                eprintln!("Failed to import the mosques: {:#}", error);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    // Accounts whose grace period is over are deleted in the background
+    rt::spawn(async {
+        let mut interval = rt::time::interval(std::time::Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            if let Err(error) = purge_deleted_accounts().await {
+                tracing::error!(?error, "Failed to purge the deleted accounts");
+            }
+        }
+    });
+
+    // Imports that were cut off by the last shutdown carry on where they stopped
+    match resume_import_jobs(&app_config.mosques).await {
+        Ok(0) => {},
+        Ok(resumed) => println!("Resumed {} mosque import jobs", resumed),
+        Err(error) => tracing::error!(?error, "Failed to resume the mosque import jobs"),
+    }
+
     HttpServer::new(move || {
         // Generate the list of routes in your Leptos App
         let routes = generate_route_list(App);
@@ -30,7 +128,10 @@ async fn main() -> std::io::Result<()> {
             .service(Files::new("/assets", &site_root))
             // serve the favicon from /favicon.ico
             .service(favicon)
-            .leptos_routes(routes, {
+            .leptos_routes_with_context(routes, {
+                let app_config = app_config.clone();
+                move || provide_context(app_config.clone())
+            }, {
                 let leptos_options = leptos_options.clone();
                 move || {
                     view! {
@@ -51,6 +152,8 @@ async fn main() -> std::io::Result<()> {
                 }
             })
             .app_data(web::Data::new(leptos_options.to_owned()))
+            .wrap(middleware::from_fn(session_renewal))
+            .wrap(middleware::from_fn(csrf_protection))
             // .route("/api/{tail:.*}", leptos_actix::handle_server_fns())
         //.wrap(middleware::Compress::default())
     })
@@ -0,0 +1,54 @@
+use garde::Validate;
+use serde::{Deserialize, Serialize};
+
+use crate::models::{
+    api_token::ApiTokenSummary,
+    auth_event::AuthEventSummary,
+    session::DeviceSession,
+    two_factor::TwoFactorStatus,
+    user::{IdentifierSummary, UserProfile},
+};
+
+/// What has to be typed to confirm that the account should be deleted
+pub const ACCOUNT_DELETION_CONFIRMATION: &str = "DELETE";
+
+/// Where the server function exporting the data of the current user is, so it
+/// can be downloaded as a file
+pub const ACCOUNT_EXPORT_URL: &str = "/account/export";
+
+#[derive(Debug, Validate, Deserialize, Serialize, Clone)]
+pub struct DeleteAccountFormData {
+    #[garde(custom(is_deletion_confirmation))]
+    pub confirmation: String,
+}
+
+fn is_deletion_confirmation(value: &str, _: &()) -> garde::Result {
+    if value == ACCOUNT_DELETION_CONFIRMATION {
+        Ok(())
+    } else {
+        Err(garde::Error::new(format!("type {} to confirm", ACCOUNT_DELETION_CONFIRMATION)))
+    }
+}
+
+/// When the account of the current user is deleted, if it is scheduled to be
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AccountDeletionStatus {
+    pub deleted_at: Option<String>,
+}
+
+/// Everything stored about a user, as handed to them when they export their data
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AccountExport {
+    pub exported_at: String,
+    pub profile: UserProfile,
+    pub created_at: String,
+    pub deletion_requested_at: Option<String>,
+    pub identifiers: Vec<IdentifierSummary>,
+    pub sessions: Vec<DeviceSession>,
+    pub api_tokens: Vec<ApiTokenSummary>,
+    pub two_factor: TwoFactorStatus,
+    /// The mosques the user is an admin of
+    pub administered_mosques: Vec<String>,
+    /// The audit log entries about the user or made by them, the newest first
+    pub auth_events: Vec<AuthEventSummary>,
+}
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ApiResponse<T>{
     #[serde(skip_serializing_if = "Option::is_none")]
     pub data: Option<T>,
@@ -0,0 +1,84 @@
+use garde::Validate;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "ssr")]
+use surrealdb::RecordId;
+#[cfg(feature = "ssr")]
+use surrealdb::sql::Datetime;
+
+/// What an API token may be used for
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiTokenScope {
+    /// Acting as the user outside of the admin features, e.g. reading their profile
+    Read,
+    /// The admin features the user's role or mosques give them access to
+    Admin,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateApiToken {
+    pub user_id: RecordId,
+    pub name: String,
+    pub token_hash: String,
+    pub scopes: Vec<ApiTokenScope>,
+    pub expires_at: Option<Datetime>,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub id: RecordId,
+    pub user_id: RecordId,
+    pub name: String,
+    pub token_hash: String,
+    pub scopes: Vec<ApiTokenScope>,
+    pub expires_at: Option<Datetime>,
+    pub created_at: Datetime,
+    /// Only moves forward every few minutes, like `last_seen_at` of sessions
+    pub last_used_at: Option<Datetime>,
+}
+
+#[derive(Debug, Validate, Deserialize, Serialize, Clone)]
+pub struct CreateApiTokenFormData {
+    #[garde(length(min = 1, max = 64))]
+    pub name: String,
+    #[garde(length(min = 1))]
+    pub scopes: Vec<ApiTokenScope>,
+    /// Left out for a token that works until it's revoked
+    #[garde(range(min = 1, max = 3650))]
+    pub expires_in_days: Option<u32>,
+}
+
+/// An API token of the current user as listed on the settings page
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ApiTokenSummary {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<ApiTokenScope>,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub last_used_at: Option<String>,
+}
+
+#[cfg(feature = "ssr")]
+impl From<ApiToken> for ApiTokenSummary {
+    fn from(api_token: ApiToken) -> Self {
+        Self {
+            id: api_token.id.to_string(),
+            name: api_token.name,
+            scopes: api_token.scopes,
+            created_at: api_token.created_at.to_string(),
+            expires_at: api_token.expires_at.map(|expires_at| expires_at.to_string()),
+            last_used_at: api_token.last_used_at.map(|last_used_at| last_used_at.to_string()),
+        }
+    }
+}
+
+/// A newly created API token, the only time the token itself is shown
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CreatedApiToken {
+    pub token: String,
+    pub summary: ApiTokenSummary,
+}
@@ -27,6 +27,14 @@ pub struct LoginFormData {
     pub password: String,
 }
 
+#[derive(Debug, Validate, Deserialize, Serialize, Clone)]
+pub struct ChangePasswordFormData {
+    #[garde(length(min = 1))]
+    pub current_password: String,
+    #[garde(length(min = 8))]
+    pub new_password: String,
+}
+
 #[cfg(feature = "ssr")]
 impl RegistrationFormData {
     pub async fn validate_uniqueness(&self) -> Result<()> {
@@ -35,6 +43,7 @@ impl RegistrationFormData {
         let (field, value) = match &self.identifier {
             Identifier::Email(email) => ("email", email.to_string()),
             Identifier::Mobile(mobile) => ("mobile", mobile.to_string()),
+            Identifier::Oidc(subject) => ("oidc", subject.to_string()),
         };
 
         let query_str = format!("SELECT * FROM user WHERE {} = $value", field);
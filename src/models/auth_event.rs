@@ -0,0 +1,175 @@
+use garde::Validate;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "ssr")]
+use crate::models::user::Identifier;
+#[cfg(feature = "ssr")]
+use surrealdb::RecordId;
+#[cfg(feature = "ssr")]
+use surrealdb::sql::Datetime;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthEventKind {
+    Register,
+    Login,
+    Logout,
+    /// A device was logged out from the devices page
+    SessionRevoked,
+    PasswordReset,
+    PasswordChanged,
+    RoleChanged,
+}
+
+impl AuthEventKind {
+    pub const ALL: [AuthEventKind; 7] = [
+        AuthEventKind::Register,
+        AuthEventKind::Login,
+        AuthEventKind::Logout,
+        AuthEventKind::SessionRevoked,
+        AuthEventKind::PasswordReset,
+        AuthEventKind::PasswordChanged,
+        AuthEventKind::RoleChanged,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            AuthEventKind::Register => "Registration",
+            AuthEventKind::Login => "Login",
+            AuthEventKind::Logout => "Logout",
+            AuthEventKind::SessionRevoked => "Device logged out",
+            AuthEventKind::PasswordReset => "Password reset",
+            AuthEventKind::PasswordChanged => "Password change",
+            AuthEventKind::RoleChanged => "Role change",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthEventOutcome {
+    Success,
+    Failure,
+    /// Waiting for the user to go on, e.g. with the two-factor code of a login
+    Pending,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateAuthEvent {
+    pub kind: AuthEventKind,
+    pub outcome: AuthEventOutcome,
+    pub user_id: Option<RecordId>,
+    pub actor_id: Option<RecordId>,
+    pub identifier: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub reason: Option<String>,
+}
+
+#[cfg(feature = "ssr")]
+impl CreateAuthEvent {
+    /// An event for the current request, the device is filled in when it is recorded
+    pub fn new(kind: AuthEventKind, outcome: AuthEventOutcome) -> Self {
+        Self {
+            kind,
+            outcome,
+            user_id: None,
+            actor_id: None,
+            identifier: None,
+            ip_address: None,
+            user_agent: None,
+            reason: None,
+        }
+    }
+
+    pub fn user(mut self, user_id: RecordId) -> Self {
+        self.user_id = Some(user_id);
+        self
+    }
+
+    pub fn actor(mut self, actor_id: RecordId) -> Self {
+        self.actor_id = Some(actor_id);
+        self
+    }
+
+    pub fn identifier(mut self, identifier: &Identifier) -> Self {
+        self.identifier = Some(identifier.value().to_string());
+        self
+    }
+
+    pub fn reason(mut self, reason: impl Into<String>) -> Self {
+        self.reason = Some(reason.into());
+        self
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthEvent {
+    pub id: RecordId,
+    pub kind: AuthEventKind,
+    pub outcome: AuthEventOutcome,
+    pub user_id: Option<RecordId>,
+    pub actor_id: Option<RecordId>,
+    pub identifier: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub reason: Option<String>,
+    pub created_at: Datetime,
+}
+
+/// What the audit log is narrowed down to, everything left unset matches all events
+#[derive(Debug, Validate, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct AuthEventFilter {
+    #[garde(skip)]
+    pub kind: Option<AuthEventKind>,
+    #[garde(skip)]
+    pub outcome: Option<AuthEventOutcome>,
+    /// The record id of the user, e.g. `users:abc123`
+    #[garde(length(max = 64))]
+    pub user_id: Option<String>,
+    #[garde(length(max = 255))]
+    pub identifier: Option<String>,
+    #[garde(length(max = 64))]
+    pub ip_address: Option<String>,
+    /// Pages of `AUTH_EVENTS_PAGE_SIZE` events, the newest first
+    #[garde(range(max = 10000))]
+    pub page: u32,
+}
+
+/// How many events a page of the audit log has
+pub const AUTH_EVENTS_PAGE_SIZE: u32 = 50;
+
+/// An event of the audit log as shown to app admins
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AuthEventSummary {
+    pub id: String,
+    pub kind: AuthEventKind,
+    pub outcome: AuthEventOutcome,
+    pub user_id: Option<String>,
+    pub actor_id: Option<String>,
+    pub identifier: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub reason: Option<String>,
+    pub created_at: String,
+}
+
+#[cfg(feature = "ssr")]
+impl From<AuthEvent> for AuthEventSummary {
+    fn from(event: AuthEvent) -> Self {
+        Self {
+            id: event.id.to_string(),
+            kind: event.kind,
+            outcome: event.outcome,
+            user_id: event.user_id.map(|user_id| user_id.to_string()),
+            actor_id: event.actor_id.map(|actor_id| actor_id.to_string()),
+            identifier: event.identifier,
+            ip_address: event.ip_address,
+            user_agent: event.user_agent,
+            reason: event.reason,
+            created_at: event.created_at.to_string(),
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::mosque::ImportSummary;
+#[cfg(feature = "ssr")]
+use surrealdb::RecordId;
+#[cfg(feature = "ssr")]
+use surrealdb::sql::Datetime;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportJobStatus {
+    /// Waiting for its first tile to be imported
+    Pending,
+    Running,
+    Completed,
+    /// Stopped at a tile that couldn't be imported, see the error
+    Failed,
+}
+
+impl ImportJobStatus {
+    /// Whether the job won't make any more progress
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Self::Completed | Self::Failed)
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateImportJob {
+    pub requested_by: RecordId,
+    pub south: f64,
+    pub west: f64,
+    pub north: f64,
+    pub east: f64,
+    pub tile_size: f64,
+    pub tiles_total: usize,
+    pub status: ImportJobStatus,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportJob {
+    pub id: RecordId,
+    pub requested_by: RecordId,
+    pub south: f64,
+    pub west: f64,
+    pub north: f64,
+    pub east: f64,
+    pub tile_size: f64,
+    pub tiles_total: usize,
+    pub tiles_done: usize,
+    pub status: ImportJobStatus,
+    pub summary: ImportSummary,
+    pub error: Option<String>,
+    pub created_at: Datetime,
+    pub updated_at: Datetime,
+}
+
+/// The progress of an import as shown on the page it was started from
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ImportJobSummary {
+    pub id: String,
+    pub status: ImportJobStatus,
+    pub tiles_total: usize,
+    pub tiles_done: usize,
+    pub summary: ImportSummary,
+    pub error: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[cfg(feature = "ssr")]
+impl From<ImportJob> for ImportJobSummary {
+    fn from(import_job: ImportJob) -> Self {
+        Self {
+            id: import_job.id.to_string(),
+            status: import_job.status,
+            tiles_total: import_job.tiles_total,
+            tiles_done: import_job.tiles_done,
+            summary: import_job.summary,
+            error: import_job.error,
+            created_at: import_job.created_at.to_string(),
+            updated_at: import_job.updated_at.to_string(),
+        }
+    }
+}
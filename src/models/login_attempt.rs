@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "ssr")]
+use surrealdb::RecordId;
+#[cfg(feature = "ssr")]
+use surrealdb::sql::Datetime;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LoginAttemptKind {
+    Identifier,
+    Ip,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LoginAttempt {
+    pub id: RecordId,
+    pub kind: LoginAttemptKind,
+    /// The email or mobile that was tried, or the IP address the attempts came from
+    pub value: String,
+    pub failures: i64,
+    pub last_failed_at: Datetime,
+    pub locked_until: Option<Datetime>,
+}
+
+/// A locked identifier or IP address as shown to app admins
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LockedLogin {
+    pub kind: LoginAttemptKind,
+    pub value: String,
+    pub failures: i64,
+    pub locked_until: String,
+}
+
+#[cfg(feature = "ssr")]
+impl From<LoginAttempt> for LockedLogin {
+    fn from(attempt: LoginAttempt) -> Self {
+        Self {
+            kind: attempt.kind,
+            value: attempt.value,
+            failures: attempt.failures,
+            locked_until: attempt.locked_until.map(|locked_until| locked_until.to_string()).unwrap_or_default(),
+        }
+    }
+}
@@ -1,7 +1,15 @@
 pub mod auth;
-#[cfg(feature = "ssr")]
 pub mod session;
 pub mod user;
 pub mod api_responses;
 pub mod mosque;
 pub mod form;
+pub mod password_reset;
+pub mod verification;
+pub mod login_attempt;
+pub mod two_factor;
+pub mod oidc;
+pub mod api_token;
+pub mod auth_event;
+pub mod account;
+pub mod import_job;
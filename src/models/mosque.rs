@@ -33,13 +33,63 @@ pub struct MosqueElement {
     pub tags: Option<Tags>,
 }
 
+/// The kinds of OpenStreetMap elements, whose ids are only unique per kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OsmType {
+    Node,
+    Way,
+    Relation,
+}
+
+impl OsmType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Node => "node",
+            Self::Way => "way",
+            Self::Relation => "relation",
+        }
+    }
+}
+
+/// Where the server function exporting mosques is, so the export can be
+/// downloaded as a file
+pub const MOSQUE_EXPORT_URL: &str = "/mosque/export";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MosqueExportFormat {
+    /// A FeatureCollection of points
+    GeoJson,
+    Csv,
+}
+
+impl MosqueExportFormat {
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Self::GeoJson => "mosques.geojson",
+            Self::Csv => "mosques.csv",
+        }
+    }
+}
+
+/// What importing mosques from OpenStreetMap did to the stored ones
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImportSummary {
+    pub created: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    /// Elements that can't be stored, such as ones without coordinates
+    pub skipped: usize,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Center {
     pub lat: f64,
     pub lon: f64,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 pub struct Tags {
     pub name: Option<String>,
     #[serde(rename = "addr:street")]
@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "ssr")]
+use surrealdb::RecordId;
+#[cfg(feature = "ssr")]
+use surrealdb::sql::Datetime;
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateOidcState {
+    pub state_hash: String,
+    pub provider: String,
+    pub nonce: String,
+    pub pkce_verifier: String,
+    pub link_user: Option<RecordId>,
+    pub expires_at: Datetime,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OidcState {
+    pub id: RecordId,
+    pub state_hash: String,
+    pub provider: String,
+    pub nonce: String,
+    pub pkce_verifier: String,
+    pub link_user: Option<RecordId>,
+    pub expires_at: Datetime,
+    pub created_at: Datetime,
+}
+
+/// A provider to show a sign in button for
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct OidcProvider {
+    pub name: String,
+    pub display_name: String,
+}
+
+/// Where the server function starting a sign in with `provider` is, `link`ing
+/// the account to the current user instead of signing in with it when set
+pub fn oidc_start_url(provider: &str, link: bool) -> String {
+    format!("/auth/oidc-start?provider={}&link={}", provider, link)
+}
@@ -0,0 +1,42 @@
+use crate::models::user::Identifier;
+use garde::Validate;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "ssr")]
+use surrealdb::RecordId;
+#[cfg(feature = "ssr")]
+use surrealdb::sql::Datetime;
+
+#[derive(Debug, Validate, Deserialize, Serialize, Clone)]
+pub struct ForgotPasswordFormData {
+    #[garde(dive)]
+    pub identifier: Identifier,
+}
+
+#[derive(Debug, Validate, Deserialize, Serialize, Clone)]
+pub struct ResetPasswordFormData {
+    #[garde(length(min = 1))]
+    pub token: String,
+    #[garde(length(min = 8))]
+    pub password: String,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatePasswordResetToken {
+    pub user_id: RecordId,
+    /// Keyed hash of the token, the raw token is only in the link sent to the user
+    pub token_hash: String,
+    pub expires_at: Datetime,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasswordResetToken {
+    pub id: RecordId,
+    pub user_id: RecordId,
+    pub token_hash: String,
+    pub expires_at: Datetime,
+    pub used_at: Option<Datetime>,
+    pub created_at: Datetime,
+}
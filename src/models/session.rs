@@ -1,27 +1,93 @@
 use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "ssr")]
 use surrealdb::RecordId;
+#[cfg(feature = "ssr")]
 use surrealdb::sql::Datetime;
 
+#[cfg(feature = "ssr")]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateSession {
     pub user_id: RecordId,
-    pub session_token: String,
+    /// Keyed hash of the token, the raw token is only in the user's cookie
+    pub session_token_hash: String,
     pub expires_at: Datetime,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+/// The device a session was created from
+#[cfg(feature = "ssr")]
+#[derive(Debug, Default, Clone)]
+pub struct DeviceInfo {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
 }
 
+#[cfg(feature = "ssr")]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Session {
     pub id: RecordId,
     pub user_id: RecordId,
-    pub session_token: String,
+    pub session_token_hash: String,
+    /// Hash of the token before the last rotation, still accepted until
+    /// `previous_token_valid_until` for requests that were already under way
+    pub previous_session_token_hash: Option<String>,
+    pub previous_token_valid_until: Option<Datetime>,
     pub expires_at: Datetime,
     pub created_at: Datetime,
+    pub token_rotated_at: Option<Datetime>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    /// Only moves forward every few minutes, see `LAST_SEEN_PRECISION_IN_SECONDS`
+    pub last_seen_at: Option<Datetime>,
 }
 
+#[cfg(feature = "ssr")]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UpdateSession {
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub session_token: Option<String>,
+    pub session_token_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_session_token_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_token_valid_until: Option<Datetime>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub expires_at: Option<Datetime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_rotated_at: Option<Datetime>,
+}
+
+/// The token and expiry a session cookie has to be re-issued with after renewal
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+pub struct RenewedSession {
+    pub session_token: String,
+    pub expires_at: Datetime,
+}
+
+/// A session of the current user as listed on the devices page
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DeviceSession {
+    pub id: String,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: String,
+    pub last_seen_at: Option<String>,
+    /// Whether this is the session the list was requested with
+    pub is_current: bool,
+}
+
+#[cfg(feature = "ssr")]
+impl DeviceSession {
+    pub fn from_session(session: Session, current_session_id: &RecordId) -> Self {
+        Self {
+            is_current: &session.id == current_session_id,
+            id: session.id.to_string(),
+            user_agent: session.user_agent,
+            ip_address: session.ip_address,
+            created_at: session.created_at.to_string(),
+            last_seen_at: session.last_seen_at.map(|last_seen_at| last_seen_at.to_string()),
+        }
+    }
 }
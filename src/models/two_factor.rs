@@ -0,0 +1,87 @@
+use garde::Validate;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "ssr")]
+use surrealdb::RecordId;
+#[cfg(feature = "ssr")]
+use surrealdb::sql::Datetime;
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateTwoFactor {
+    pub user_id: RecordId,
+    pub secret: String,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TwoFactor {
+    pub id: RecordId,
+    pub user_id: RecordId,
+    pub secret: String,
+    pub enabled_at: Option<Datetime>,
+    pub last_used_step: Option<i64>,
+    pub recovery_code_hashes: Vec<String>,
+    pub created_at: Datetime,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateTwoFactorChallenge {
+    pub user_id: RecordId,
+    pub token_hash: String,
+    pub expires_at: Datetime,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TwoFactorChallenge {
+    pub id: RecordId,
+    pub user_id: RecordId,
+    pub token_hash: String,
+    pub attempts: i64,
+    pub expires_at: Datetime,
+    pub created_at: Datetime,
+}
+
+/// What a login with the right password leads to
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub enum LoginOutcome {
+    LoggedIn,
+    /// The user has two-factor authentication enabled, the login is finished by
+    /// sending a code along with this challenge
+    TwoFactorRequired { challenge: String },
+}
+
+#[derive(Debug, Validate, Deserialize, Serialize, Clone)]
+pub struct TwoFactorLoginFormData {
+    #[garde(length(min = 40, max = 50))]
+    pub challenge: String,
+    /// A code from the authenticator app or one of the recovery codes
+    #[garde(length(min = 6, max = 32))]
+    pub code: String,
+}
+
+#[derive(Debug, Validate, Deserialize, Serialize, Clone)]
+pub struct TwoFactorCodeFormData {
+    #[garde(length(min = 6, max = 32))]
+    pub code: String,
+}
+
+/// Two-factor authentication of the current user as shown on their settings
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TwoFactorStatus {
+    pub enabled: bool,
+    /// Whether the role of the user requires it
+    pub required: bool,
+    pub recovery_codes_left: usize,
+}
+
+/// What an authenticator app is set up with
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TwoFactorEnrollment {
+    /// The `otpauth://` uri to show as a QR code
+    pub provisioning_uri: String,
+    /// The secret in base32, for typing into the app instead
+    pub secret: String,
+}
@@ -18,8 +18,46 @@ pub struct User {
     pub created_at: Datetime,
     pub display_name: String,
     pub password_hash: String,
-    pub role: String,
+    pub role: UserRole,
     pub updated_at: Datetime,
+    #[serde(default)]
+    pub deletion_requested_at: Option<surrealdb::sql::Datetime>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UserRole {
+    AppAdmin,
+    MosqueAdmin,
+    Educator,
+    Regular,
+}
+
+impl UserRole {
+    /// Whether a user with this role may access something that requires `required`.
+    /// App admins may access everything and every role includes `Regular`.
+    pub fn satisfies(&self, required: UserRole) -> bool {
+        *self == UserRole::AppAdmin || required == UserRole::Regular || *self == required
+    }
+}
+
+/// The subset of a `User` that is safe to send to the client
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct UserProfile {
+    pub id: String,
+    pub display_name: String,
+    pub role: UserRole,
+}
+
+#[cfg(feature = "ssr")]
+impl From<User> for UserProfile {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id.to_string(),
+            display_name: user.display_name,
+            role: user.role,
+        }
+    }
 }
 
 #[cfg(feature = "ssr")]
@@ -28,7 +66,7 @@ pub struct UpdateUser {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub display_name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub role: Option<String>,
+    pub role: Option<UserRole>,
     pub updated_at: Datetime,
 }
 
@@ -40,21 +78,101 @@ pub struct CreateUserIdentifier {
     pub user_id: RecordId,
 }
 
-#[derive(Debug, Validate, Deserialize, Serialize, Clone)]
+#[derive(Debug, Validate, Deserialize, Serialize, Clone, PartialEq, Eq)]
 #[serde(tag = "identifier_type", content = "identifier_value")]
 pub enum Identifier {
     #[serde(rename = "email")]
     Email(#[garde(email)] String),
     #[serde(rename = "mobile")]
     Mobile(#[garde(pattern(r"^[+]?[(]?[0-9]{1,4}[)]?[- .]?[(]?[0-9]{1,4}[)]?[- .]?[0-9]{4,10}$"))] String),
+    /// An account at an OpenID Connect provider, as `<provider>:<subject>`. Only
+    /// ever linked by signing in with the provider, never taken from a form.
+    #[serde(rename = "oidc")]
+    Oidc(#[garde(custom(only_linked_through_provider))] String),
+}
+
+impl Identifier {
+    /// The `identifier_type` this identifier is stored with
+    pub fn identifier_type(&self) -> &'static str {
+        match self {
+            Identifier::Email(_) => "email",
+            Identifier::Mobile(_) => "mobile",
+            Identifier::Oidc(_) => "oidc",
+        }
+    }
+
+    /// The `identifier_value` this identifier is stored with
+    pub fn value(&self) -> &str {
+        match self {
+            Identifier::Email(value) | Identifier::Mobile(value) | Identifier::Oidc(value) => value,
+        }
+    }
+
+    pub fn oidc(provider: &str, subject: &str) -> Self {
+        Identifier::Oidc(format!("{}:{}", provider, subject))
+    }
+
+    /// The provider of an OpenID Connect identifier
+    pub fn oidc_provider(&self) -> Option<&str> {
+        match self {
+            Identifier::Oidc(value) => value.split_once(':').map(|(provider, _)| provider),
+            Identifier::Email(_) | Identifier::Mobile(_) => None,
+        }
+    }
+
+    /// Whether messages can be sent to this identifier
+    pub fn is_contactable(&self) -> bool {
+        !matches!(self, Identifier::Oidc(_))
+    }
+}
+
+fn only_linked_through_provider(_: &str, _: &()) -> garde::Result {
+    Err(garde::Error::new("can only be linked by signing in with the provider"))
 }
 
 #[cfg(feature = "ssr")]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserIdentifier {
+    pub id: RecordId,
     #[serde(flatten)]
     pub identifier: Identifier,
     pub user_id: RecordId,
+    pub verified_at: Option<Datetime>,
+    #[serde(default)]
+    pub is_primary: bool,
     pub created_at: Datetime,
     pub updated_at: Datetime,
 }
+
+/// An identifier of the current user as shown on their settings
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct IdentifierSummary {
+    pub identifier: Identifier,
+    pub is_primary: bool,
+    pub verified: bool,
+}
+
+#[cfg(feature = "ssr")]
+impl From<UserIdentifier> for IdentifierSummary {
+    fn from(user_identifier: UserIdentifier) -> Self {
+        Self {
+            identifier: user_identifier.identifier,
+            is_primary: user_identifier.is_primary,
+            verified: user_identifier.verified_at.is_some(),
+        }
+    }
+}
+
+#[derive(Debug, Validate, Deserialize, Serialize, Clone)]
+pub struct AddIdentifierFormData {
+    #[garde(dive)]
+    pub identifier: Identifier,
+}
+
+#[derive(Debug, Validate, Deserialize, Serialize, Clone)]
+pub struct ChangeIdentifierFormData {
+    #[garde(skip)]
+    pub current: Identifier,
+    #[garde(dive)]
+    pub new_identifier: Identifier,
+}
@@ -0,0 +1,37 @@
+use crate::models::user::Identifier;
+use garde::Validate;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "ssr")]
+use surrealdb::RecordId;
+#[cfg(feature = "ssr")]
+use surrealdb::sql::Datetime;
+
+#[derive(Debug, Validate, Deserialize, Serialize, Clone)]
+pub struct VerifyIdentifierFormData {
+    #[garde(dive)]
+    pub identifier: Identifier,
+    #[garde(length(min = 6, max = 64))]
+    pub code: String,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateIdentifierVerification {
+    pub user_identifier: RecordId,
+    pub code_hash: String,
+    pub expires_at: Datetime,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdentifierVerification {
+    pub id: RecordId,
+    pub user_identifier: RecordId,
+    pub code_hash: String,
+    pub attempts: i64,
+    pub sends: i64,
+    pub last_sent_at: Datetime,
+    pub expires_at: Datetime,
+    pub created_at: Datetime,
+}
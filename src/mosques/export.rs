@@ -0,0 +1,337 @@
+use anyhow::{Context, Result};
+use futures::{Stream, StreamExt, TryStreamExt, stream};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use surrealdb::RecordId;
+
+use crate::{
+    database::connection::get_db,
+    errors::mosque::MosqueError,
+    models::mosque::{MosqueExportFormat, OsmType},
+    mosques::overpass::BoundingBox,
+};
+
+/// How many mosques are fetched at once while the export is written
+const PAGE_SIZE: usize = 500;
+
+const PRAYERS: [&str; 6] = ["fajr", "dhuhr", "asr", "maghrib", "isha", "jummah"];
+
+/// Which mosques are exported
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExportArea {
+    Everywhere,
+    BoundingBox(BoundingBox),
+    /// Matched regardless of case
+    City(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportedMosque {
+    id: RecordId,
+    name: String,
+    street: Option<String>,
+    city: Option<String>,
+    osm_type: Option<OsmType>,
+    osm_id: Option<i64>,
+    /// The longitude and latitude of the location
+    coordinates: [f64; 2],
+    #[serde(default)]
+    details: Option<ExportedDetails>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportedDetails {
+    jamat_times: Option<ExportedPrayerTimes>,
+    adhan_times: Option<ExportedPrayerTimes>,
+}
+
+/// Prayer times as stored, "HH:MM:SS"
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportedPrayerTimes {
+    fajr: String,
+    dhuhr: String,
+    asr: String,
+    maghrib: String,
+    isha: String,
+    jummah: String,
+}
+
+impl ExportedPrayerTimes {
+    fn in_order(&self) -> [&str; 6] {
+        [&self.fajr, &self.dhuhr, &self.asr, &self.maghrib, &self.isha, &self.jummah]
+    }
+}
+
+/// The mosques in the area as a GeoJSON FeatureCollection or CSV, written a
+/// page of mosques at a time so large exports don't have to fit into memory.
+/// With `include_details`, the congregational and call to prayer times are
+/// added for the mosques that have them. The admins of a mosque are never
+/// exported.
+pub fn export_mosques(
+    format: MosqueExportFormat,
+    area: ExportArea,
+    include_details: bool,
+) -> impl Stream<Item = Result<String>> + Send + 'static {
+    // Each page starts after the last mosque of the one before, so mosques added
+    // or removed while the export runs don't shift the pages
+    let pages = stream::try_unfold(Some(None), move |after: Option<Option<RecordId>>| {
+        let area = area.clone();
+        async move {
+            let Some(after) = after else { return Ok(None) };
+
+            let first = after.is_none();
+            let mosques = fetch_page(&area, include_details, after).await?;
+            let next = (mosques.len() == PAGE_SIZE).then(|| mosques.last().map(|mosque| mosque.id.clone()));
+            Ok(Some(((first, mosques), next)))
+        }
+    });
+
+    let rows = pages.map_ok(move |(first, mosques)| {
+        mosques
+            .iter()
+            .enumerate()
+            .map(|(index, mosque)| match format {
+                // Every feature but the first is separated from the one before
+                MosqueExportFormat::GeoJson => {
+                    let separator = if first && index == 0 { "" } else { ",\n" };
+                    format!("{}{}", separator, geojson_feature(mosque, include_details))
+                },
+                MosqueExportFormat::Csv => csv_row(mosque, include_details),
+            })
+            .collect::<String>()
+    });
+
+    let (header, footer) = match format {
+        MosqueExportFormat::GeoJson => ("{\"type\":\"FeatureCollection\",\"features\":[\n".to_string(), "\n]}\n".to_string()),
+        MosqueExportFormat::Csv => (csv_header(include_details), String::new()),
+    };
+
+    stream::once(async move { Ok(header) })
+        .chain(rows)
+        .chain(stream::once(async move { Ok(footer) }))
+}
+
+/// The first `PAGE_SIZE` mosques in the area by id, after the mosque `after`
+async fn fetch_page(area: &ExportArea, include_details: bool, after: Option<RecordId>) -> Result<Vec<ExportedMosque>> {
+    let mut conditions = Vec::new();
+    match area {
+        ExportArea::Everywhere => {},
+        ExportArea::BoundingBox(_) => conditions.push(
+            "location.coordinates[0] >= $west AND location.coordinates[0] <= $east \
+             AND location.coordinates[1] >= $south AND location.coordinates[1] <= $north",
+        ),
+        ExportArea::City(_) => conditions.push("string::lowercase(city ?? '') = string::lowercase($city)"),
+    }
+    if after.is_some() {
+        conditions.push("id > $after");
+    }
+    let condition = if conditions.is_empty() { String::new() } else { format!(" WHERE {}", conditions.join(" AND ")) };
+
+    let details = if include_details {
+        ", (SELECT jamat_times.{fajr, dhuhr, asr, maghrib, isha, jummah} AS jamat_times, \
+            adhan_times.{fajr, dhuhr, asr, maghrib, isha, jummah} AS adhan_times \
+            FROM mosque_details WHERE mosque = $parent.id)[0] AS details"
+    } else {
+        ""
+    };
+    let surql = format!(
+        "SELECT id, name, street, city, osm_type, osm_id, location.coordinates AS coordinates{} \
+         FROM mosques{} ORDER BY id LIMIT $limit",
+        details, condition
+    );
+
+    let mut query = get_db().query(surql).bind(("limit", PAGE_SIZE)).bind(("after", after));
+    match area {
+        ExportArea::Everywhere => {},
+        ExportArea::BoundingBox(bounding_box) => {
+            query = query
+                .bind(("south", bounding_box.south))
+                .bind(("west", bounding_box.west))
+                .bind(("north", bounding_box.north))
+                .bind(("east", bounding_box.east));
+        },
+        ExportArea::City(city) => query = query.bind(("city", city.clone())),
+    }
+
+    let mosques: Vec<ExportedMosque> = query
+        .await
+        .map_err(|e| MosqueError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the mosques to export")?
+        .take(0)
+        .map_err(|e| MosqueError::DatabaseError(Box::new(e)))?;
+
+    Ok(mosques)
+}
+
+/// A point feature of the mosque. Its `@id` names the OSM element it was
+/// imported from, so the export can be imported again.
+fn geojson_feature(mosque: &ExportedMosque, include_details: bool) -> String {
+    let mut properties = json!({
+        "name": mosque.name,
+        "street": mosque.street,
+        "city": mosque.city,
+    });
+    if let (Some(osm_type), Some(osm_id)) = (mosque.osm_type, mosque.osm_id) {
+        properties["@id"] = json!(format!("{}/{}", osm_type.as_str(), osm_id));
+    }
+    if include_details {
+        let details = mosque.details.as_ref();
+        properties["jamat_times"] = json!(details.and_then(|details| details.jamat_times.as_ref()));
+        properties["adhan_times"] = json!(details.and_then(|details| details.adhan_times.as_ref()));
+    }
+
+    json!({
+        "type": "Feature",
+        "id": mosque.id.to_string(),
+        "geometry": { "type": "Point", "coordinates": mosque.coordinates },
+        "properties": properties,
+    })
+    .to_string()
+}
+
+fn csv_header(include_details: bool) -> String {
+    let mut columns: Vec<String> = ["id", "name", "street", "city", "osm_type", "osm_id", "lat", "lon"]
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    if include_details {
+        for times in ["jamat", "adhan"] {
+            columns.extend(PRAYERS.iter().map(|prayer| format!("{}_{}", times, prayer)));
+        }
+    }
+
+    format!("{}\r\n", columns.join(","))
+}
+
+fn csv_row(mosque: &ExportedMosque, include_details: bool) -> String {
+    let [lon, lat] = mosque.coordinates;
+    let mut fields = vec![
+        mosque.id.to_string(),
+        mosque.name.clone(),
+        mosque.street.clone().unwrap_or_default(),
+        mosque.city.clone().unwrap_or_default(),
+        mosque.osm_type.map(|osm_type| osm_type.as_str().to_string()).unwrap_or_default(),
+        mosque.osm_id.map(|osm_id| osm_id.to_string()).unwrap_or_default(),
+        lat.to_string(),
+        lon.to_string(),
+    ];
+    if include_details {
+        let details = mosque.details.as_ref();
+        for times in [details.and_then(|details| details.jamat_times.as_ref()), details.and_then(|details| details.adhan_times.as_ref())] {
+            match times {
+                Some(times) => fields.extend(times.in_order().map(str::to_string)),
+                None => fields.extend(PRAYERS.map(|_| String::new())),
+            }
+        }
+    }
+
+    let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+    format!("{}\r\n", fields.join(","))
+}
+
+/// Quotes the field when it contains a separator, a quote or a line break.
+///
+/// Text a spreadsheet would run as a formula gets a leading `'`, as the names
+/// and addresses come from OSM where anyone can edit them. Numbers, like the
+/// negative coordinates, are left as they are.
+fn csv_field(field: &str) -> String {
+    let field = if field.starts_with(['=', '+', '-', '@', '\t', '\r']) && field.parse::<f64>().is_err() {
+        format!("'{}", field)
+    } else {
+        field.to_string()
+    };
+
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::run;
+    use serde_json::Value;
+
+    async fn export(format: MosqueExportFormat, area: ExportArea, include_details: bool) -> String {
+        export_mosques(format, area, include_details).try_collect::<Vec<String>>().await.unwrap().concat()
+    }
+
+    async fn create_mosques(surql: &'static str) {
+        get_db().query(surql).await.unwrap().check().unwrap();
+    }
+
+    #[test]
+    fn mosques_are_exported_as_geojson() {
+        run(async {
+            create_mosques(
+                r#"
+                CREATE mosques:export_inside SET name = 'Inside', location = (10.5, 60.5), osm_type = 'node', osm_id = 9400000001;
+                CREATE mosques:export_outside SET name = 'Outside', location = (10.7, 60.7);
+                LET $times = (CREATE prayer_times SET fajr = '05:00:00', dhuhr = '13:00:00', asr = '16:30:00',
+                    maghrib = '19:45:00', isha = '21:15:00', jummah = '13:30:00')[0].id;
+                CREATE mosque_details SET mosque = mosques:export_inside, admins = [], jamat_times = $times, adhan_times = $times;
+                "#,
+            )
+            .await;
+
+            let area = ExportArea::BoundingBox(BoundingBox { south: 60.4, west: 10.4, north: 60.6, east: 10.6 });
+            let collection: Value = serde_json::from_str(&export(MosqueExportFormat::GeoJson, area, true).await).unwrap();
+
+            let features = collection["features"].as_array().unwrap();
+            assert_eq!(features.len(), 1);
+            assert_eq!(features[0]["geometry"]["coordinates"], json!([10.5, 60.5]));
+            assert_eq!(features[0]["properties"]["@id"], "node/9400000001");
+            assert_eq!(features[0]["properties"]["jamat_times"]["jummah"], "13:30:00");
+            assert!(features[0]["properties"].get("admins").is_none());
+        });
+    }
+
+    #[test]
+    fn mosques_are_exported_as_csv() {
+        run(async {
+            create_mosques(
+                r#"
+                CREATE mosques:export_csv SET name = 'Export Mosque, "A"', city = 'Exportville', location = (11.5, 61.5),
+                    osm_type = 'way', osm_id = 9400000002;
+                CREATE mosques:export_formula SET name = '=HYPERLINK("http://evil.example")', street = '-2+3',
+                    city = 'Exportville', location = (-11.5, -61.5);
+                CREATE mosques:export_elsewhere SET name = 'Elsewhere', city = 'Elsewhere', location = (11.5, 61.5);
+                "#,
+            )
+            .await;
+
+            let csv = export(MosqueExportFormat::Csv, ExportArea::City("exportville".to_string()), false).await;
+
+            let lines: Vec<&str> = csv.lines().collect();
+            assert_eq!(lines[0], "id,name,street,city,osm_type,osm_id,lat,lon");
+            assert_eq!(lines[1], r#"mosques:export_csv,"Export Mosque, ""A""",,Exportville,way,9400000002,61.5,11.5"#);
+            assert_eq!(
+                lines[2],
+                r#"mosques:export_formula,"'=HYPERLINK(""http://evil.example"")",'-2+3,Exportville,,,-61.5,-11.5"#
+            );
+            assert_eq!(lines.len(), 3);
+        });
+    }
+
+    #[test]
+    fn exports_larger_than_a_page_have_every_mosque_once() {
+        run(async {
+            let surql: String = (0..PAGE_SIZE + 1)
+                .map(|index| format!("CREATE mosques SET name = 'Paged {}', city = 'Pagedville', location = (12.5, 62.5);", index))
+                .collect();
+            get_db().query(surql).await.unwrap().check().unwrap();
+
+            let area = || ExportArea::City("pagedville".to_string());
+            let csv = export(MosqueExportFormat::Csv, area(), false).await;
+            let mut ids: Vec<&str> = csv.lines().skip(1).map(|line| line.split(',').next().unwrap()).collect();
+            ids.sort();
+            ids.dedup();
+            assert_eq!(ids.len(), PAGE_SIZE + 1);
+
+            let collection: Value = serde_json::from_str(&export(MosqueExportFormat::GeoJson, area(), false).await).unwrap();
+            assert_eq!(collection["features"].as_array().unwrap().len(), PAGE_SIZE + 1);
+        });
+    }
+}
@@ -0,0 +1,251 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use surrealdb::RecordId;
+
+use crate::{
+    database::connection::get_db,
+    errors::mosque::MosqueError,
+    models::mosque::{ImportSummary, MosqueElement, OsmType},
+};
+
+/// What mosques without a name in OpenStreetMap are called
+const UNNAMED_MOSQUE: &str = "Unnamed Mosque";
+
+/// Writes the OSM fields of `$mosque` to the mosque being created or updated
+const SET_OSM_FIELDS: &str = "SET osm_type = $mosque.osm_type, osm_id = $mosque.osm_id, name = $mosque.name, \
+    location = <point> $mosque.coordinates, street = $mosque.street, city = $mosque.city, updated_at = time::now()";
+
+/// A mosque the way OpenStreetMap has it
+#[derive(Debug)]
+struct OsmMosque {
+    osm_type: OsmType,
+    osm_id: i64,
+    name: Option<String>,
+    coordinates: [f64; 2],
+    street: Option<String>,
+    city: Option<String>,
+}
+
+/// The fields of a mosque that come from OpenStreetMap
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct OsmFields {
+    osm_type: Option<OsmType>,
+    osm_id: i64,
+    name: String,
+    /// The longitude and latitude of the location
+    coordinates: [f64; 2],
+    street: Option<String>,
+    city: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StoredMosque {
+    id: RecordId,
+    #[serde(flatten)]
+    fields: OsmFields,
+}
+
+impl OsmMosque {
+    /// Ways and relations are located at their center. Elements without
+    /// coordinates can't be stored.
+    fn from_element(element: MosqueElement) -> Option<Self> {
+        let (osm_type, lat, lon) = match element.element_type.as_str() {
+            "node" => (OsmType::Node, element.lat?, element.lon?),
+            "way" => (OsmType::Way, element.center.as_ref()?.lat, element.center.as_ref()?.lon),
+            "relation" => (OsmType::Relation, element.center.as_ref()?.lat, element.center.as_ref()?.lon),
+            _ => return None,
+        };
+        let tags = element.tags.unwrap_or_default();
+
+        Some(Self {
+            osm_type,
+            osm_id: element.id,
+            name: tags.name.filter(|name| !name.trim().is_empty()),
+            coordinates: [lon, lat],
+            street: tags.street,
+            city: tags.city,
+        })
+    }
+
+    /// The fields to store, keeping what's stored where OpenStreetMap has nothing,
+    /// so names and addresses filled in by admins aren't lost
+    fn merged_with(self, stored: Option<&OsmFields>) -> OsmFields {
+        OsmFields {
+            osm_type: Some(self.osm_type),
+            osm_id: self.osm_id,
+            name: self
+                .name
+                .or_else(|| stored.map(|stored| stored.name.clone()))
+                .unwrap_or_else(|| UNNAMED_MOSQUE.to_string()),
+            coordinates: self.coordinates,
+            street: self.street.or_else(|| stored.and_then(|stored| stored.street.clone())),
+            city: self.city.or_else(|| stored.and_then(|stored| stored.city.clone())),
+        }
+    }
+}
+
+/// Stores the mosques among the elements fetched from OpenStreetMap, matching
+/// them to the stored ones on their OSM type and id. Only the fields that come
+/// from OpenStreetMap are written, so importing the same region again leaves
+/// everything else about a mosque, like its details and admins, as it was.
+pub async fn import_mosques(elements: Vec<MosqueElement>) -> Result<ImportSummary> {
+    let mut summary = ImportSummary::default();
+
+    let mut mosques: Vec<OsmMosque> = Vec::new();
+    for element in elements {
+        match OsmMosque::from_element(element) {
+            Some(mosque) => mosques.push(mosque),
+            None => summary.skipped += 1,
+        }
+    }
+
+    let db = get_db();
+
+    let osm_ids: Vec<i64> = mosques.iter().map(|mosque| mosque.osm_id).collect();
+    let mut stored: Vec<StoredMosque> = db
+        .query("SELECT id, osm_type, osm_id, name, location.coordinates AS coordinates, street, city FROM mosques WHERE osm_id IN $osm_ids")
+        .bind(("osm_ids", osm_ids))
+        .await
+        .map_err(|e| MosqueError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the stored mosques")?
+        .take(0)
+        .map_err(|e| MosqueError::DatabaseError(Box::new(e)))?;
+
+    for mosque in mosques {
+        // Mosques imported before the type was stored match elements of any type,
+        // the first one claims them
+        let position = stored
+            .iter()
+            .position(|stored| stored.fields.osm_id == mosque.osm_id && stored.fields.osm_type == Some(mosque.osm_type))
+            .or_else(|| {
+                stored
+                    .iter()
+                    .position(|stored| stored.fields.osm_id == mosque.osm_id && stored.fields.osm_type.is_none())
+            });
+
+        let Some(position) = position else {
+            db.query(format!("CREATE mosques {}", SET_OSM_FIELDS))
+                .bind(("mosque", mosque.merged_with(None)))
+                .await
+                .map_err(|e| MosqueError::DatabaseError(Box::new(e)))
+                .with_context(|| "Failed to create the mosque")?
+                .check()
+                .map_err(|e| MosqueError::DatabaseError(Box::new(e)))?;
+            summary.created += 1;
+            continue;
+        };
+
+        let stored = stored.swap_remove(position);
+        let fields = mosque.merged_with(Some(&stored.fields));
+        if fields == stored.fields {
+            summary.unchanged += 1;
+            continue;
+        }
+
+        db.query(format!("UPDATE $id {}", SET_OSM_FIELDS))
+            .bind(("id", stored.id))
+            .bind(("mosque", fields))
+            .await
+            .map_err(|e| MosqueError::DatabaseError(Box::new(e)))
+            .with_context(|| "Failed to update the mosque")?
+            .check()
+            .map_err(|e| MosqueError::DatabaseError(Box::new(e)))?;
+        summary.updated += 1;
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::mosque::{Center, Tags},
+        test_support::run,
+    };
+
+    fn element(element_type: &str, id: i64, name: Option<&str>, street: Option<&str>) -> MosqueElement {
+        let (lat, lon, center) = match element_type {
+            "node" => (Some(52.52), Some(13.40), None),
+            _ => (None, None, Some(Center { lat: 52.53, lon: 13.41 })),
+        };
+
+        MosqueElement {
+            element_type: element_type.to_string(),
+            id,
+            lat,
+            lon,
+            center,
+            tags: Some(Tags {
+                name: name.map(str::to_string),
+                street: street.map(str::to_string),
+                city: Some("Berlin".to_string()),
+            }),
+        }
+    }
+
+    async fn stored(osm_id: i64) -> Vec<OsmFields> {
+        get_db()
+            .query("SELECT osm_type, osm_id, name, location.coordinates AS coordinates, street, city FROM mosques WHERE osm_id = $osm_id ORDER BY osm_type")
+            .bind(("osm_id", osm_id))
+            .await
+            .unwrap()
+            .take(0)
+            .unwrap()
+    }
+
+    #[test]
+    fn reimporting_updates_the_mosques_that_changed() {
+        run(async {
+            let osm_id = 9_100_000_001;
+            let without_coordinates = MosqueElement { lat: None, ..element("node", 9_100_000_002, None, None) };
+
+            let summary = import_mosques(vec![
+                element("node", osm_id, Some("Old Name"), Some("Hauptstraße 1")),
+                element("way", osm_id, Some("Way Mosque"), None),
+                without_coordinates,
+            ])
+            .await
+            .unwrap();
+            assert_eq!(summary, ImportSummary { created: 2, updated: 0, unchanged: 0, skipped: 1 });
+
+            let summary = import_mosques(vec![
+                element("node", osm_id, Some("New Name"), None),
+                element("way", osm_id, Some("Way Mosque"), None),
+            ])
+            .await
+            .unwrap();
+            assert_eq!(summary, ImportSummary { created: 0, updated: 1, unchanged: 1, skipped: 0 });
+
+            let mosques = stored(osm_id).await;
+            assert_eq!(mosques.len(), 2);
+            assert_eq!(mosques[0].osm_type, Some(OsmType::Node));
+            assert_eq!(mosques[0].name, "New Name");
+            // OpenStreetMap no longer has the street, so the stored one is kept
+            assert_eq!(mosques[0].street.as_deref(), Some("Hauptstraße 1"));
+        });
+    }
+
+    #[test]
+    fn mosques_imported_without_a_type_are_adopted() {
+        run(async {
+            let osm_id = 9_100_000_003;
+            get_db()
+                .query("CREATE type::thing('mosques', $osm_id) SET osm_id = $osm_id, name = 'Curated Name', location = (13.40, 52.52)")
+                .bind(("osm_id", osm_id))
+                .await
+                .unwrap()
+                .check()
+                .unwrap();
+
+            let summary = import_mosques(vec![element("node", osm_id, None, None)]).await.unwrap();
+            assert_eq!(summary, ImportSummary { created: 0, updated: 1, unchanged: 0, skipped: 0 });
+
+            let mosques = stored(osm_id).await;
+            assert_eq!(mosques.len(), 1);
+            assert_eq!(mosques[0].osm_type, Some(OsmType::Node));
+            assert_eq!(mosques[0].name, "Curated Name");
+            assert_eq!(mosques[0].city.as_deref(), Some("Berlin"));
+        });
+    }
+}
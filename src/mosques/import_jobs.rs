@@ -0,0 +1,292 @@
+use actix_web::rt;
+use anyhow::{Context, Result};
+use std::time::Duration;
+use surrealdb::RecordId;
+
+use crate::{
+    config::MosqueConfig,
+    database::connection::get_db,
+    errors::mosque::MosqueError,
+    models::{
+        import_job::{CreateImportJob, ImportJob, ImportJobStatus},
+        mosque::{ImportSummary, MosqueElement},
+    },
+    mosques::{
+        import::import_mosques,
+        overpass::{BoundingBox, OverpassQuery, fetch_elements},
+    },
+};
+
+/// How large the tiles a region is imported in are, in degrees. Small enough
+/// for Overpass to answer within its limits even for dense cities.
+pub const TILE_SIZE_IN_DEGREES: f64 = 0.5;
+
+/// How often a tile is tried again when Overpass is busy or timed out
+const MAX_RETRIES: u32 = 5;
+
+/// How long to wait before trying a tile again the first time, doubling with
+/// every retry
+const RETRY_DELAY: Duration = if cfg!(test) { Duration::from_millis(10) } else { Duration::from_secs(5) };
+
+/// The tiles covering the bounding box, row by row from the south-west. Tiles
+/// share their edges, so a mosque on one is imported twice, which leaves it
+/// unchanged the second time.
+pub fn tiles(bounding_box: &BoundingBox, tile_size: f64) -> Vec<BoundingBox> {
+    let rows = ((bounding_box.north - bounding_box.south) / tile_size).ceil().max(1.0) as usize;
+    let columns = ((bounding_box.east - bounding_box.west) / tile_size).ceil().max(1.0) as usize;
+
+    let mut tiles = Vec::with_capacity(rows * columns);
+    for row in 0..rows {
+        let south = bounding_box.south + row as f64 * tile_size;
+        for column in 0..columns {
+            let west = bounding_box.west + column as f64 * tile_size;
+            tiles.push(BoundingBox {
+                south,
+                west,
+                north: (south + tile_size).min(bounding_box.north),
+                east: (west + tile_size).min(bounding_box.east),
+            });
+        }
+    }
+
+    tiles
+}
+
+/// Stores a pending import of the mosques in the bounding box, which is run
+/// with `run_import_job`
+pub async fn create_import_job(bounding_box: BoundingBox, requested_by: RecordId) -> Result<ImportJob> {
+    let import_job: Option<ImportJob> = get_db()
+        .create("import_jobs")
+        .content(CreateImportJob {
+            requested_by,
+            south: bounding_box.south,
+            west: bounding_box.west,
+            north: bounding_box.north,
+            east: bounding_box.east,
+            tile_size: TILE_SIZE_IN_DEGREES,
+            tiles_total: tiles(&bounding_box, TILE_SIZE_IN_DEGREES).len(),
+            status: ImportJobStatus::Pending,
+        })
+        .await
+        .map_err(|e| MosqueError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to create the import job")?;
+
+    Ok(import_job.ok_or(MosqueError::ImportJobNotFound)?)
+}
+
+pub async fn get_import_job(job_id: RecordId) -> Result<ImportJob> {
+    let import_job: Option<ImportJob> = get_db()
+        .select(job_id)
+        .await
+        .map_err(|e| MosqueError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the import job")?;
+
+    Ok(import_job.ok_or(MosqueError::ImportJobNotFound)?)
+}
+
+/// Imports the tiles of the job that weren't imported yet, recording the
+/// progress after each one. The job fails at the first tile that can't be
+/// imported, finished jobs are left as they are.
+pub async fn run_import_job(job_id: RecordId, config: &MosqueConfig) -> Result<()> {
+    let import_job = get_import_job(job_id.clone()).await?;
+    if import_job.status.is_finished() {
+        return Ok(());
+    }
+
+    set_status(&job_id, ImportJobStatus::Running, None).await?;
+
+    let bounding_box = BoundingBox {
+        south: import_job.south,
+        west: import_job.west,
+        north: import_job.north,
+        east: import_job.east,
+    };
+    let tiles = tiles(&bounding_box, import_job.tile_size);
+
+    for (index, tile) in tiles.into_iter().enumerate().skip(import_job.tiles_done) {
+        let summary = match import_tile(tile, config).await {
+            Ok(summary) => summary,
+            Err(error) => {
+                set_status(&job_id, ImportJobStatus::Failed, Some(format!("{:#}", error))).await?;
+                return Err(error);
+            },
+        };
+
+        get_db()
+            .query(
+                r#"UPDATE $job_id SET
+                    tiles_done = $tiles_done,
+                    summary.created += $summary.created,
+                    summary.updated += $summary.updated,
+                    summary.unchanged += $summary.unchanged,
+                    summary.skipped += $summary.skipped,
+                    updated_at = time::now()"#,
+            )
+            .bind(("job_id", job_id.clone()))
+            .bind(("tiles_done", index + 1))
+            .bind(("summary", summary))
+            .await
+            .map_err(|e| MosqueError::DatabaseError(Box::new(e)))
+            .with_context(|| "Failed to record the progress of the import job")?
+            .check()
+            .map_err(|e| MosqueError::DatabaseError(Box::new(e)))?;
+    }
+
+    set_status(&job_id, ImportJobStatus::Completed, None).await
+}
+
+/// Runs the job in the background, logging why it failed if it does
+pub fn spawn_import_job(job_id: RecordId, config: MosqueConfig) {
+    rt::spawn(async move {
+        if let Err(error) = run_import_job(job_id.clone(), &config).await {
+            tracing::error!(?error, %job_id, "Failed to run the import job");
+        }
+    });
+}
+
+/// Picks up the jobs that were pending or running when the server stopped,
+/// returning how many there were
+pub async fn resume_import_jobs(config: &MosqueConfig) -> Result<usize> {
+    let job_ids: Vec<RecordId> = get_db()
+        .query("SELECT VALUE id FROM import_jobs WHERE status IN ['pending', 'running'] ORDER BY created_at")
+        .await
+        .map_err(|e| MosqueError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to fetch the unfinished import jobs")?
+        .take(0)
+        .map_err(|e| MosqueError::DatabaseError(Box::new(e)))?;
+
+    for job_id in &job_ids {
+        spawn_import_job(job_id.clone(), config.clone());
+    }
+
+    Ok(job_ids.len())
+}
+
+async fn import_tile(tile: BoundingBox, config: &MosqueConfig) -> Result<ImportSummary> {
+    let query = OverpassQuery::mosques(tile)
+        .timeout(Duration::from_secs(config.overpass_timeout_in_seconds.into()));
+    let elements = fetch_with_retries(&config.overpass_url, &query).await?;
+
+    import_mosques(elements).await
+}
+
+/// Fetches the elements, trying again with a growing delay while Overpass is
+/// rate limiting (429) or timed out (504)
+async fn fetch_with_retries(overpass_url: &str, query: &OverpassQuery) -> Result<Vec<MosqueElement>> {
+    let mut retries = 0;
+    loop {
+        match fetch_elements(overpass_url, query).await {
+            Err(error) if retries < MAX_RETRIES && is_retryable(&error) => {
+                rt::time::sleep(RETRY_DELAY * 2u32.pow(retries)).await;
+                retries += 1;
+            },
+            result => return result,
+        }
+    }
+}
+
+fn is_retryable(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<MosqueError>(),
+        Some(MosqueError::OverpassFailed { status: 429 | 504, .. })
+    )
+}
+
+async fn set_status(job_id: &RecordId, status: ImportJobStatus, error: Option<String>) -> Result<()> {
+    get_db()
+        .query("UPDATE $job_id SET status = $status, error = $error, updated_at = time::now()")
+        .bind(("job_id", job_id.clone()))
+        .bind(("status", status))
+        .bind(("error", error))
+        .await
+        .map_err(|e| MosqueError::DatabaseError(Box::new(e)))
+        .with_context(|| "Failed to update the status of the import job")?
+        .check()
+        .map_err(|e| MosqueError::DatabaseError(Box::new(e)))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{overpass_queries, overpass_stand_in, register_test_user, run};
+    use wiremock::MockServer;
+
+    /// Two tiles, the western one first
+    const REGION: BoundingBox = BoundingBox { south: 40.0, west: 10.0, north: 40.5, east: 11.0 };
+
+    const ONE_MOSQUE: &str =
+        r#"{"elements": [{"type": "node", "id": 9200000001, "lat": 40.2, "lon": 10.2, "tags": {"name": "Tile Mosque"}}]}"#;
+    const NO_MOSQUES: &str = r#"{"elements": []}"#;
+
+    fn config(stand_in: &MockServer) -> MosqueConfig {
+        MosqueConfig {
+            overpass_url: format!("{}/api/interpreter", stand_in.uri()),
+            ..MosqueConfig::default()
+        }
+    }
+
+    #[test]
+    fn regions_are_split_into_tiles() {
+        let tiles = tiles(&BoundingBox { south: 0.0, west: 0.0, north: 0.75, east: 1.0 }, 0.5);
+
+        assert_eq!(tiles.len(), 4);
+        assert_eq!(tiles[1], BoundingBox { south: 0.0, west: 0.5, north: 0.5, east: 1.0 });
+        assert_eq!(tiles[3], BoundingBox { south: 0.5, west: 0.5, north: 0.75, east: 1.0 });
+    }
+
+    #[test]
+    fn busy_tiles_are_tried_again() {
+        run(async {
+            let user_id = register_test_user("import-job-retry@example.com").await;
+            let import_job = create_import_job(REGION, user_id).await.unwrap();
+            assert_eq!(import_job.tiles_total, 2);
+
+            let stand_in = overpass_stand_in(vec![
+                (429, "rate limited"),
+                (504, "timed out"),
+                (200, ONE_MOSQUE),
+                (200, NO_MOSQUES),
+            ])
+            .await;
+            run_import_job(import_job.id.clone(), &config(&stand_in)).await.unwrap();
+            assert_eq!(overpass_queries(&stand_in).await.len(), 4);
+
+            let import_job = get_import_job(import_job.id).await.unwrap();
+            assert_eq!(import_job.status, ImportJobStatus::Completed);
+            assert_eq!(import_job.tiles_done, 2);
+            assert_eq!(import_job.summary.created + import_job.summary.unchanged, 1);
+        });
+    }
+
+    #[test]
+    fn interrupted_jobs_resume_at_the_next_tile() {
+        run(async {
+            let user_id = register_test_user("import-job-resume@example.com").await;
+            let import_job = create_import_job(REGION, user_id).await.unwrap();
+            get_db()
+                .query("UPDATE $job_id SET status = 'running', tiles_done = 1")
+                .bind(("job_id", import_job.id.clone()))
+                .await
+                .unwrap()
+                .check()
+                .unwrap();
+
+            let stand_in = overpass_stand_in(vec![(400, "broken query")]).await;
+            let error = run_import_job(import_job.id.clone(), &config(&stand_in)).await.unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<MosqueError>(),
+                Some(MosqueError::OverpassFailed { status: 400, .. })
+            ));
+            let queries = overpass_queries(&stand_in).await;
+            assert_eq!(queries.len(), 1);
+            assert!(queries[0].contains("(40,10.5,40.5,11)"));
+
+            let import_job = get_import_job(import_job.id).await.unwrap();
+            assert_eq!(import_job.status, ImportJobStatus::Failed);
+            assert_eq!(import_job.tiles_done, 1);
+            assert!(import_job.error.unwrap().contains("broken query"));
+        });
+    }
+}
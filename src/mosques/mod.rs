@@ -0,0 +1,6 @@
+pub mod import;
+pub mod overpass;
+pub mod import_jobs;
+pub mod offline;
+pub mod pbf;
+pub mod export;
@@ -0,0 +1,457 @@
+use anyhow::Result;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::{
+    errors::mosque::MosqueError,
+    models::mosque::{Center, ImportSummary, MosqueElement, OsmType, Tags},
+    mosques::{
+        import::import_mosques,
+        overpass::mosque_tag_filters,
+        pbf::{Entity, for_each_entity},
+    },
+};
+
+/// How many mosques are stored at once
+const BATCH_SIZE: usize = 500;
+
+/// The mosques of an extract, as found in the file
+struct Extract {
+    elements: Vec<MosqueElement>,
+    /// Mosques that can't be matched to a stored one, as they have no OSM id
+    skipped: usize,
+}
+
+/// Imports the mosques of an `.osm.pbf` extract or a GeoJSON FeatureCollection,
+/// told apart and stored the same way as the ones fetched from Overpass
+pub async fn import_file(path: &Path) -> Result<ImportSummary> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+    let extract = if file_name.ends_with(".pbf") {
+        read_pbf(path)?
+    } else if file_name.ends_with(".geojson") || file_name.ends_with(".json") {
+        read_geojson(path)?
+    } else {
+        Err(MosqueError::InvalidFile {
+            path: path.display().to_string(),
+            reason: "expected an .osm.pbf or a .geojson file".to_string(),
+        })?
+    };
+
+    let mut summary = ImportSummary { skipped: extract.skipped, ..ImportSummary::default() };
+    let mut elements = extract.elements.into_iter();
+    loop {
+        let batch: Vec<MosqueElement> = elements.by_ref().take(BATCH_SIZE).collect();
+        if batch.is_empty() {
+            break;
+        }
+
+        let imported = import_mosques(batch).await?;
+        summary.created += imported.created;
+        summary.updated += imported.updated;
+        summary.unchanged += imported.unchanged;
+        summary.skipped += imported.skipped;
+    }
+
+    Ok(summary)
+}
+
+fn is_mosque(tags: &HashMap<String, String>) -> bool {
+    mosque_tag_filters().iter().all(|filter| filter.matches(tags))
+}
+
+/// Ways and relations are located at the center of their nodes, like Overpass
+/// does with `out center`. The nodes are found in further passes over the
+/// file, which sorts them before the ways and relations.
+fn read_pbf(path: &Path) -> Result<Extract> {
+    let mut elements = Vec::new();
+    let mut ways = Vec::new();
+    let mut relations = Vec::new();
+
+    for_each_entity(path, |entity| match entity {
+        Entity::Node { id, lat, lon, tags } if is_mosque(&tags) => {
+            elements.push(mosque_element(OsmType::Node, id, Some((lat, lon)), tags))
+        },
+        Entity::Way { id, refs, tags } if is_mosque(&tags) => ways.push((id, refs, tags)),
+        Entity::Relation { id, members, tags } if is_mosque(&tags) => relations.push((id, members, tags)),
+        _ => {},
+    })?;
+
+    let member_way_ids: HashSet<i64> = relations
+        .iter()
+        .flat_map(|(_, members, _)| members.iter())
+        .filter(|(member_type, _)| *member_type == OsmType::Way)
+        .map(|(_, id)| *id)
+        .collect();
+    let mut member_ways: HashMap<i64, Vec<i64>> = HashMap::new();
+    if !member_way_ids.is_empty() {
+        for_each_entity(path, |entity| {
+            if let Entity::Way { id, refs, .. } = entity
+                && member_way_ids.contains(&id)
+            {
+                member_ways.insert(id, refs);
+            }
+        })?;
+    }
+
+    let relation_nodes = |members: &[(OsmType, i64)]| -> Vec<i64> {
+        members
+            .iter()
+            .flat_map(|(member_type, id)| match member_type {
+                OsmType::Node => vec![*id],
+                OsmType::Way => member_ways.get(id).cloned().unwrap_or_default(),
+                OsmType::Relation => Vec::new(),
+            })
+            .collect()
+    };
+
+    let node_ids: HashSet<i64> = ways
+        .iter()
+        .flat_map(|(_, refs, _)| refs.iter().copied())
+        .chain(relations.iter().flat_map(|(_, members, _)| relation_nodes(members)))
+        .collect();
+    let mut coordinates: HashMap<i64, (f64, f64)> = HashMap::new();
+    if !node_ids.is_empty() {
+        for_each_entity(path, |entity| {
+            if let Entity::Node { id, lat, lon, .. } = entity
+                && node_ids.contains(&id)
+            {
+                coordinates.insert(id, (lat, lon));
+            }
+        })?;
+    }
+
+    // Nodes cut off by the bounds of the extract are left out, a way or relation
+    // without any is skipped when it's imported
+    for (id, refs, tags) in ways {
+        let location = center_of(refs.iter().filter_map(|node_id| coordinates.get(node_id).copied()));
+        elements.push(mosque_element(OsmType::Way, id, location, tags));
+    }
+    for (id, members, tags) in relations {
+        let location = center_of(relation_nodes(&members).iter().filter_map(|node_id| coordinates.get(node_id).copied()));
+        elements.push(mosque_element(OsmType::Relation, id, location, tags));
+    }
+
+    Ok(Extract { elements, skipped: 0 })
+}
+
+/// Reads the exports of Overpass turbo and `osmium export`, which keep the
+/// type and id of the OSM element in the id of the feature or its properties
+fn read_geojson(path: &Path) -> Result<Extract> {
+    let invalid = |reason: &str| MosqueError::InvalidFile { path: path.display().to_string(), reason: reason.to_string() };
+
+    let content = fs::read_to_string(path)
+        .map_err(|source| MosqueError::UnreadableFile { path: path.display().to_string(), source })?;
+    let collection: Value = serde_json::from_str(&content).map_err(|error| invalid(&error.to_string()))?;
+    if collection.get("type").and_then(Value::as_str) != Some("FeatureCollection") {
+        Err(invalid("expected a GeoJSON FeatureCollection"))?
+    }
+    let features = collection
+        .get("features")
+        .and_then(Value::as_array)
+        .ok_or_else(|| invalid("the FeatureCollection has no features"))?;
+
+    let mut extract = Extract { elements: Vec::new(), skipped: 0 };
+    let no_properties = Map::new();
+    for feature in features {
+        let properties = feature.get("properties").and_then(Value::as_object).unwrap_or(&no_properties);
+        // Overpass turbo used to nest the tags, other exports have them as properties
+        let tags: HashMap<String, String> = properties
+            .get("tags")
+            .and_then(Value::as_object)
+            .unwrap_or(properties)
+            .iter()
+            .filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+            .collect();
+        if !is_mosque(&tags) {
+            continue;
+        }
+
+        let Some((osm_type, id)) = osm_reference(feature, properties) else {
+            extract.skipped += 1;
+            continue;
+        };
+
+        let mut positions = Vec::new();
+        if let Some(geometry) = feature.get("geometry") {
+            collect_positions(geometry, &mut positions);
+        }
+        extract.elements.push(mosque_element(osm_type, id, center_of(positions), tags));
+    }
+
+    Ok(extract)
+}
+
+/// The type and id of the OSM element the feature was exported from, given as
+/// `node/1`, `n1` or an `@type` and `@id` property
+fn osm_reference(feature: &Value, properties: &Map<String, Value>) -> Option<(OsmType, i64)> {
+    let parse = |value: &Value| {
+        let value = value.as_str()?;
+        let (osm_type, id) = match value.split_once('/') {
+            Some((osm_type, id)) => (osm_type_of(osm_type)?, id),
+            None => {
+                let (osm_type, id) = value.split_at_checked(1)?;
+                let osm_type = match osm_type {
+                    "n" => OsmType::Node,
+                    "w" => OsmType::Way,
+                    "r" => OsmType::Relation,
+                    _ => return None,
+                };
+                (osm_type, id)
+            },
+        };
+        Some((osm_type, id.parse().ok()?))
+    };
+
+    [feature.get("id"), properties.get("@id"), properties.get("id")]
+        .into_iter()
+        .flatten()
+        .find_map(parse)
+        .or_else(|| {
+            let osm_type = osm_type_of(properties.get("@type")?.as_str()?)?;
+            Some((osm_type, properties.get("@id")?.as_i64()?))
+        })
+}
+
+fn osm_type_of(name: &str) -> Option<OsmType> {
+    [OsmType::Node, OsmType::Way, OsmType::Relation]
+        .into_iter()
+        .find(|osm_type| osm_type.as_str() == name)
+}
+
+/// The latitude and longitude of every position in the GeoJSON geometry
+fn collect_positions(geometry: &Value, positions: &mut Vec<(f64, f64)>) {
+    if let Some(geometries) = geometry.get("geometries").and_then(Value::as_array) {
+        geometries.iter().for_each(|geometry| collect_positions(geometry, positions));
+    } else if let Some(coordinates) = geometry.get("coordinates") {
+        collect_coordinates(coordinates, positions);
+    }
+}
+
+fn collect_coordinates(coordinates: &Value, positions: &mut Vec<(f64, f64)>) {
+    let Some(values) = coordinates.as_array() else { return };
+
+    match (values.first().and_then(Value::as_f64), values.get(1).and_then(Value::as_f64)) {
+        (Some(lon), Some(lat)) => positions.push((lat, lon)),
+        _ => values.iter().for_each(|coordinates| collect_coordinates(coordinates, positions)),
+    }
+}
+
+/// The center of the bounding box of the positions, given as latitude and longitude
+fn center_of(positions: impl IntoIterator<Item = (f64, f64)>) -> Option<(f64, f64)> {
+    let mut positions = positions.into_iter();
+    let (lat, lon) = positions.next()?;
+    let (south, west, north, east) = positions.fold((lat, lon, lat, lon), |(south, west, north, east), (lat, lon)| {
+        (south.min(lat), west.min(lon), north.max(lat), east.max(lon))
+    });
+
+    Some(((south + north) / 2.0, (west + east) / 2.0))
+}
+
+/// The element as Overpass would have answered with it, nodes at their
+/// location and everything else at its center
+fn mosque_element(
+    osm_type: OsmType,
+    id: i64,
+    location: Option<(f64, f64)>,
+    mut tags: HashMap<String, String>,
+) -> MosqueElement {
+    let (lat, lon, center) = match (osm_type, location) {
+        (OsmType::Node, Some((lat, lon))) => (Some(lat), Some(lon), None),
+        (_, Some((lat, lon))) => (None, None, Some(Center { lat, lon })),
+        (_, None) => (None, None, None),
+    };
+
+    MosqueElement {
+        element_type: osm_type.as_str().to_string(),
+        id,
+        lat,
+        lon,
+        center,
+        tags: Some(Tags {
+            name: tags.remove("name"),
+            street: tags.remove("addr:street"),
+            city: tags.remove("addr:city"),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::connection::get_db, test_support::run};
+    use flate2::{Compression, write::ZlibEncoder};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    fn extract_path(file_name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("merzah-{}-{}", std::process::id(), file_name))
+    }
+
+    fn varint(mut value: u64, bytes: &mut Vec<u8>) {
+        while value >= 0x80 {
+            bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        bytes.push(value as u8);
+    }
+
+    fn varint_field(number: u64, value: u64, bytes: &mut Vec<u8>) {
+        varint(number << 3, bytes);
+        varint(value, bytes);
+    }
+
+    fn bytes_field(number: u64, value: &[u8], bytes: &mut Vec<u8>) {
+        varint(number << 3 | 2, bytes);
+        varint(value.len() as u64, bytes);
+        bytes.extend_from_slice(value);
+    }
+
+    fn packed(values: &[u64]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        values.iter().for_each(|value| varint(*value, &mut bytes));
+        bytes
+    }
+
+    /// Signed numbers as the differences to the previous one, the way ids,
+    /// coordinates and node references are stored
+    fn packed_deltas(values: &[i64]) -> Vec<u8> {
+        let mut previous = 0;
+        let deltas: Vec<u64> = values
+            .iter()
+            .map(|value| {
+                let delta = value - previous;
+                previous = *value;
+                ((delta << 1) ^ (delta >> 63)) as u64
+            })
+            .collect();
+        packed(&deltas)
+    }
+
+    fn blob(blob_type: &str, blob: &[u8], file: &mut Vec<u8>) {
+        let mut header = Vec::new();
+        bytes_field(1, blob_type.as_bytes(), &mut header);
+        varint_field(3, blob.len() as u64, &mut header);
+
+        file.extend_from_slice(&(header.len() as u32).to_be_bytes());
+        file.extend_from_slice(&header);
+        file.extend_from_slice(blob);
+    }
+
+    /// A mosque node, a mosque way between two plain nodes and a church node, in
+    /// one zlib compressed block after the header
+    fn pbf_extract() -> Vec<u8> {
+        let strings = ["", "amenity", "place_of_worship", "religion", "muslim", "name", "Node Mosque", "Way Mosque", "christian"];
+        let mut string_table = Vec::new();
+        strings.iter().for_each(|string| bytes_field(1, string.as_bytes(), &mut string_table));
+
+        // Coordinates in the default granularity of 100 nanodegrees
+        let mut dense_nodes = Vec::new();
+        bytes_field(1, &packed_deltas(&[9_300_000_001, 9_300_000_002, 9_300_000_003, 9_300_000_004]), &mut dense_nodes);
+        bytes_field(8, &packed_deltas(&[525_000_000, 525_200_000, 525_400_000, 525_000_000]), &mut dense_nodes);
+        bytes_field(9, &packed_deltas(&[134_000_000, 134_000_000, 134_200_000, 134_000_000]), &mut dense_nodes);
+        bytes_field(10, &packed(&[1, 2, 3, 4, 5, 6, 0, 0, 0, 1, 2, 3, 8, 0]), &mut dense_nodes);
+        let mut nodes = Vec::new();
+        bytes_field(2, &dense_nodes, &mut nodes);
+
+        let mut way = Vec::new();
+        varint_field(1, 9_300_000_010, &mut way);
+        bytes_field(2, &packed(&[1, 3, 5]), &mut way);
+        bytes_field(3, &packed(&[2, 4, 7]), &mut way);
+        bytes_field(8, &packed_deltas(&[9_300_000_002, 9_300_000_003]), &mut way);
+        let mut ways = Vec::new();
+        bytes_field(3, &way, &mut ways);
+
+        let mut block = Vec::new();
+        bytes_field(1, &string_table, &mut block);
+        bytes_field(2, &nodes, &mut block);
+        bytes_field(2, &ways, &mut block);
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&block).unwrap();
+        let mut data_blob = Vec::new();
+        varint_field(2, block.len() as u64, &mut data_blob);
+        bytes_field(3, &encoder.finish().unwrap(), &mut data_blob);
+
+        let mut header_block = Vec::new();
+        bytes_field(4, b"OsmSchema-V0.6", &mut header_block);
+        let mut header_blob = Vec::new();
+        bytes_field(1, &header_block, &mut header_blob);
+
+        let mut file = Vec::new();
+        blob("OSMHeader", &header_blob, &mut file);
+        blob("OSMData", &data_blob, &mut file);
+        file
+    }
+
+    #[test]
+    fn pbf_extracts_are_imported() {
+        run(async {
+            let path = extract_path("mosques.osm.pbf");
+            fs::write(&path, pbf_extract()).unwrap();
+
+            let summary = import_file(&path).await.unwrap();
+            assert_eq!(summary, ImportSummary { created: 2, updated: 0, unchanged: 0, skipped: 0 });
+
+            let ways: Vec<(String, [f64; 2])> = get_db()
+                .query("SELECT VALUE [name, location.coordinates] FROM mosques WHERE osm_type = 'way' AND osm_id = 9300000010")
+                .await
+                .unwrap()
+                .take(0)
+                .unwrap();
+            let (name, [lon, lat]) = &ways[0];
+            assert_eq!(name, "Way Mosque");
+            assert!((lat - 52.53).abs() < 1e-9 && (lon - 13.41).abs() < 1e-9);
+
+            let summary = import_file(&path).await.unwrap();
+            assert_eq!(summary.unchanged, 2);
+        });
+    }
+
+    #[test]
+    fn geojson_exports_are_read() {
+        let path = extract_path("mosques.geojson");
+        fs::write(
+            &path,
+            r#"{
+                "type": "FeatureCollection",
+                "features": [
+                    {
+                        "type": "Feature",
+                        "id": "node/1",
+                        "geometry": {"type": "Point", "coordinates": [13.4, 52.5]},
+                        "properties": {"amenity": "place_of_worship", "religion": "muslim", "name": "Point Mosque"}
+                    },
+                    {
+                        "type": "Feature",
+                        "geometry": {"type": "Polygon", "coordinates": [[[13.0, 52.0], [14.0, 52.0], [14.0, 53.0], [13.0, 52.0]]]},
+                        "properties": {"@type": "way", "@id": 2, "amenity": "place_of_worship", "religion": "muslim"}
+                    },
+                    {
+                        "type": "Feature",
+                        "geometry": {"type": "Point", "coordinates": [13.4, 52.5]},
+                        "properties": {"amenity": "place_of_worship", "religion": "muslim"}
+                    },
+                    {
+                        "type": "Feature",
+                        "id": "n3",
+                        "geometry": {"type": "Point", "coordinates": [13.4, 52.5]},
+                        "properties": {"amenity": "place_of_worship", "religion": "christian"}
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let extract = read_geojson(&path).unwrap();
+
+        assert_eq!(extract.skipped, 1);
+        assert_eq!(extract.elements.len(), 2);
+        assert_eq!((extract.elements[0].lat, extract.elements[0].lon), (Some(52.5), Some(13.4)));
+        let way = &extract.elements[1];
+        assert_eq!((way.element_type.as_str(), way.id), ("way", 2));
+        let center = way.center.as_ref().unwrap();
+        assert_eq!((center.lat, center.lon), (52.5, 13.5));
+    }
+}
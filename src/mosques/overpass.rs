@@ -0,0 +1,260 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::{
+    errors::mosque::MosqueError,
+    models::mosque::{MosqueElement, MosquesResponse, OsmType},
+};
+
+/// The area a query is limited to, in degrees
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub south: f64,
+    pub west: f64,
+    pub north: f64,
+    pub east: f64,
+}
+
+impl BoundingBox {
+    /// Whether the corners are coordinates and the south-west one is below and
+    /// left of the north-east one. Overpass doesn't take boxes across the
+    /// antimeridian.
+    pub fn is_valid(&self) -> bool {
+        let latitudes = -90.0..=90.0;
+        let longitudes = -180.0..=180.0;
+
+        latitudes.contains(&self.south)
+            && latitudes.contains(&self.north)
+            && longitudes.contains(&self.west)
+            && longitudes.contains(&self.east)
+            && self.south <= self.north
+            && self.west <= self.east
+    }
+}
+
+/// A condition on the tags of the elements a query finds
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagFilter {
+    /// The tag is set to exactly this value
+    Equals(String, String),
+    /// The tag is set to anything
+    Exists(String),
+}
+
+impl TagFilter {
+    pub fn matches(&self, tags: &HashMap<String, String>) -> bool {
+        match self {
+            Self::Equals(key, value) => tags.get(key) == Some(value),
+            Self::Exists(key) => tags.contains_key(key),
+        }
+    }
+}
+
+/// What tells the places of worship of Muslims apart, which are mapped as all
+/// kinds of elements and often without `building=mosque`
+pub fn mosque_tag_filters() -> Vec<TagFilter> {
+    vec![
+        TagFilter::Equals("amenity".to_string(), "place_of_worship".to_string()),
+        TagFilter::Equals("religion".to_string(), "muslim".to_string()),
+    ]
+}
+
+/// An Overpass QL query for the elements in a bounding box, which is written
+/// out with `to_overpass_ql`
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverpassQuery {
+    bounding_box: BoundingBox,
+    element_types: Vec<OsmType>,
+    tag_filters: Vec<TagFilter>,
+    timeout: Option<Duration>,
+    max_size_in_bytes: Option<u64>,
+}
+
+impl OverpassQuery {
+    /// Nodes, ways and relations in the bounding box, without any tag filters
+    pub fn new(bounding_box: BoundingBox) -> Self {
+        Self {
+            bounding_box,
+            element_types: vec![OsmType::Node, OsmType::Way, OsmType::Relation],
+            tag_filters: Vec::new(),
+            timeout: None,
+            max_size_in_bytes: None,
+        }
+    }
+
+    /// The mosques in the bounding box, as `mosque_tag_filters` tells them apart
+    pub fn mosques(bounding_box: BoundingBox) -> Self {
+        Self {
+            tag_filters: mosque_tag_filters(),
+            ..Self::new(bounding_box)
+        }
+    }
+
+    /// Only finds elements of these types
+    pub fn element_types(mut self, element_types: &[OsmType]) -> Self {
+        self.element_types = element_types.to_vec();
+        self
+    }
+
+    /// Only finds elements with the tag set to `value`
+    pub fn tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tag_filters.push(TagFilter::Equals(key.into(), value.into()));
+        self
+    }
+
+    /// Only finds elements with the tag set to anything
+    pub fn has_tag(mut self, key: impl Into<String>) -> Self {
+        self.tag_filters.push(TagFilter::Exists(key.into()));
+        self
+    }
+
+    /// How long the server may run the query, it uses its own default otherwise
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// How much memory the server may use for the query, it uses its own
+    /// default otherwise
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size_in_bytes = Some(bytes);
+        self
+    }
+
+    /// The query as Overpass QL, asking for JSON with ways and relations
+    /// located at their center
+    pub fn to_overpass_ql(&self) -> String {
+        let mut query = "[out:json]".to_string();
+        if let Some(timeout) = self.timeout {
+            let _ = write!(query, "[timeout:{}]", timeout.as_secs().max(1));
+        }
+        if let Some(max_size_in_bytes) = self.max_size_in_bytes {
+            let _ = write!(query, "[maxsize:{}]", max_size_in_bytes);
+        }
+        query.push_str(";\n(\n");
+
+        let filters: String = self
+            .tag_filters
+            .iter()
+            .map(|filter| match filter {
+                TagFilter::Equals(key, value) => format!("[{}={}]", quoted(key), quoted(value)),
+                TagFilter::Exists(key) => format!("[{}]", quoted(key)),
+            })
+            .collect();
+        let BoundingBox { south, west, north, east } = self.bounding_box;
+
+        for element_type in &self.element_types {
+            let _ = writeln!(query, "  {}{}({},{},{},{});", element_type.as_str(), filters, south, west, north, east);
+        }
+        query.push_str(");\nout center;");
+
+        query
+    }
+}
+
+/// A string literal of Overpass QL
+fn quoted(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Runs the query against the Overpass API interpreter at `overpass_url`. The
+/// request gives up a little after the server would have.
+pub async fn fetch_elements(overpass_url: &str, query: &OverpassQuery) -> Result<Vec<MosqueElement>> {
+    let mut client = reqwest::Client::builder();
+    if let Some(timeout) = query.timeout {
+        client = client.timeout(timeout + Duration::from_secs(10));
+    }
+    let client = client.build().map_err(MosqueError::OverpassUnreachable)?;
+
+    let response = client
+        .post(overpass_url)
+        .body(query.to_overpass_ql())
+        .send()
+        .await
+        .map_err(MosqueError::OverpassUnreachable)
+        .with_context(|| format!("Failed to query the Overpass API at {}", overpass_url))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_else(|_| "Could not read error body".to_string());
+        return Err(MosqueError::OverpassFailed { status: status.as_u16(), body }.into());
+    }
+
+    let response: MosquesResponse = response
+        .json()
+        .await
+        .map_err(MosqueError::OverpassUnreachable)
+        .with_context(|| "Failed to read the elements the Overpass API found")?;
+
+    Ok(response.elements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{overpass_queries, overpass_stand_in, run};
+
+    const BERLIN: BoundingBox = BoundingBox { south: 52.3, west: 13.0, north: 52.7, east: 13.8 };
+
+    #[test]
+    fn queries_are_written_as_overpass_ql() {
+        let query = OverpassQuery::mosques(BERLIN)
+            .element_types(&[OsmType::Node, OsmType::Way])
+            .has_tag("name")
+            .timeout(Duration::from_secs(90))
+            .max_size(512 * 1024 * 1024);
+
+        assert_eq!(
+            query.to_overpass_ql(),
+            r#"[out:json][timeout:90][maxsize:536870912];
+(
+  node["amenity"="place_of_worship"]["religion"="muslim"]["name"](52.3,13,52.7,13.8);
+  way["amenity"="place_of_worship"]["religion"="muslim"]["name"](52.3,13,52.7,13.8);
+);
+out center;"#
+        );
+    }
+
+    #[test]
+    fn tag_values_are_escaped() {
+        let query = OverpassQuery::new(BERLIN).element_types(&[OsmType::Relation]).tag("name", r#"Say "hi"\"#);
+
+        assert!(query.to_overpass_ql().contains(r#"relation["name"="Say \"hi\"\\"](52.3,13,52.7,13.8);"#));
+    }
+
+    #[test]
+    fn bounding_boxes_must_be_ordered_coordinates() {
+        assert!(BERLIN.is_valid());
+        assert!(!BoundingBox { south: 52.7, north: 52.3, ..BERLIN }.is_valid());
+        assert!(!BoundingBox { east: 181.0, ..BERLIN }.is_valid());
+    }
+
+    #[test]
+    fn elements_are_fetched_from_the_configured_endpoint() {
+        run(async {
+            let stand_in = overpass_stand_in(vec![(
+                200,
+                r#"{"elements": [{"type": "relation", "id": 7, "center": {"lat": 52.5, "lon": 13.4}, "tags": {"name": "Mosque"}}]}"#,
+            )])
+            .await;
+            let url = format!("{}/api/interpreter", stand_in.uri());
+            let query = OverpassQuery::mosques(BERLIN).timeout(Duration::from_secs(5));
+
+            let elements = fetch_elements(&url, &query).await.unwrap();
+
+            assert_eq!(elements.len(), 1);
+            assert_eq!(elements[0].element_type, "relation");
+            assert_eq!(overpass_queries(&stand_in).await, vec![query.to_overpass_ql()]);
+
+            let stand_in = overpass_stand_in(vec![(429, "rate limited")]).await;
+            let url = format!("{}/api/interpreter", stand_in.uri());
+            let error = fetch_elements(&url, &query).await.unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<MosqueError>(),
+                Some(MosqueError::OverpassFailed { status: 429, .. })
+            ));
+        });
+    }
+}
@@ -0,0 +1,316 @@
+use anyhow::Result;
+use flate2::read::ZlibDecoder;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::Path;
+
+use crate::{errors::mosque::MosqueError, models::mosque::OsmType};
+
+/// The largest blob header and blob the format allows
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+const MAX_BLOB_SIZE: usize = 32 * 1024 * 1024;
+
+/// An element of an OSM extract with its tags
+#[derive(Debug)]
+pub enum Entity {
+    Node { id: i64, lat: f64, lon: f64, tags: HashMap<String, String> },
+    Way { id: i64, refs: Vec<i64>, tags: HashMap<String, String> },
+    Relation { id: i64, members: Vec<(OsmType, i64)>, tags: HashMap<String, String> },
+}
+
+/// Calls `visit` with every element of the `.osm.pbf` extract at `path`, in the
+/// order of the file. The extract is read a block at a time, so whole countries
+/// don't have to fit into memory.
+pub fn for_each_entity(path: &Path, mut visit: impl FnMut(Entity)) -> Result<()> {
+    let unreadable = |source| MosqueError::UnreadableFile { path: path.display().to_string(), source };
+    let invalid = |reason: String| MosqueError::InvalidFile { path: path.display().to_string(), reason };
+
+    let mut reader = BufReader::new(File::open(path).map_err(unreadable)?);
+    loop {
+        let mut header_size = [0; 4];
+        match reader.read_exact(&mut header_size) {
+            Ok(()) => {},
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(error) => Err(unreadable(error))?,
+        }
+        let header_size = u32::from_be_bytes(header_size) as usize;
+        if header_size > MAX_HEADER_SIZE {
+            Err(invalid(format!("a blob header of {} bytes is too large", header_size)))?
+        }
+        let mut header = vec![0; header_size];
+        reader.read_exact(&mut header).map_err(unreadable)?;
+
+        let mut blob_type = "";
+        let mut blob_size = 0;
+        for (number, value) in fields(&header).map_err(invalid)? {
+            match (number, value) {
+                (1, Value::Bytes(bytes)) => blob_type = std::str::from_utf8(bytes).unwrap_or_default(),
+                (3, Value::Varint(size)) => blob_size = size as usize,
+                _ => {},
+            }
+        }
+        if blob_size > MAX_BLOB_SIZE {
+            Err(invalid(format!("a blob of {} bytes is too large", blob_size)))?
+        }
+        let mut blob = vec![0; blob_size];
+        reader.read_exact(&mut blob).map_err(unreadable)?;
+
+        // The `OSMHeader` blob only describes the extract
+        if blob_type == "OSMData" {
+            let block = decompress(&blob).map_err(invalid)?;
+            read_block(&block, &mut visit).map_err(invalid)?;
+        }
+    }
+}
+
+fn decompress(blob: &[u8]) -> Result<Vec<u8>, String> {
+    let mut raw_size = 0;
+    for (number, value) in fields(blob)? {
+        match (number, value) {
+            (1, Value::Bytes(raw)) => return Ok(raw.to_vec()),
+            (2, Value::Varint(size)) => raw_size = size as usize,
+            (3, Value::Bytes(zlib_data)) => {
+                let mut block = Vec::with_capacity(raw_size.min(MAX_BLOB_SIZE));
+                ZlibDecoder::new(zlib_data)
+                    .read_to_end(&mut block)
+                    .map_err(|error| format!("a block can't be decompressed: {}", error))?;
+                return Ok(block);
+            },
+            (4..=7, Value::Bytes(_)) => return Err("only raw and zlib compressed blocks are supported".to_string()),
+            _ => {},
+        }
+    }
+
+    Err("a blob has no data".to_string())
+}
+
+fn read_block(block: &[u8], visit: &mut impl FnMut(Entity)) -> Result<(), String> {
+    let mut strings = Vec::new();
+    let mut groups = Vec::new();
+    let mut granularity = 100;
+    let mut lat_offset = 0;
+    let mut lon_offset = 0;
+
+    for (number, value) in fields(block)? {
+        match (number, value) {
+            (1, Value::Bytes(string_table)) => {
+                for (number, value) in fields(string_table)? {
+                    if let (1, Value::Bytes(string)) = (number, value) {
+                        strings.push(String::from_utf8_lossy(string).into_owned());
+                    }
+                }
+            },
+            (2, Value::Bytes(group)) => groups.push(group),
+            (17, Value::Varint(value)) => granularity = value as i64,
+            (19, Value::Varint(value)) => lat_offset = value as i64,
+            (20, Value::Varint(value)) => lon_offset = value as i64,
+            _ => {},
+        }
+    }
+
+    // Coordinates are stored in units of `granularity` nanodegrees
+    let lat = |value: i64| (lat_offset + granularity * value) as f64 * 1e-9;
+    let lon = |value: i64| (lon_offset + granularity * value) as f64 * 1e-9;
+
+    for group in groups {
+        for (number, value) in fields(group)? {
+            let Value::Bytes(message) = value else { continue };
+
+            match number {
+                1 => {
+                    let (mut id, mut keys, mut values, mut node_lat, mut node_lon) = (0, Vec::new(), Vec::new(), 0, 0);
+                    for (number, value) in fields(message)? {
+                        match (number, value) {
+                            (1, Value::Varint(value)) => id = zigzag(value),
+                            (2, Value::Bytes(packed)) => keys = varints(packed)?,
+                            (3, Value::Bytes(packed)) => values = varints(packed)?,
+                            (8, Value::Varint(value)) => node_lat = zigzag(value),
+                            (9, Value::Varint(value)) => node_lon = zigzag(value),
+                            _ => {},
+                        }
+                    }
+                    let tags = tags(&keys, &values, &strings)?;
+                    visit(Entity::Node { id, lat: lat(node_lat), lon: lon(node_lon), tags });
+                },
+                2 => read_dense_nodes(message, &strings, &lat, &lon, visit)?,
+                3 => {
+                    let (mut id, mut keys, mut values, mut refs) = (0, Vec::new(), Vec::new(), Vec::new());
+                    for (number, value) in fields(message)? {
+                        match (number, value) {
+                            (1, Value::Varint(value)) => id = value as i64,
+                            (2, Value::Bytes(packed)) => keys = varints(packed)?,
+                            (3, Value::Bytes(packed)) => values = varints(packed)?,
+                            (8, Value::Bytes(packed)) => refs = deltas(packed)?,
+                            _ => {},
+                        }
+                    }
+                    let tags = tags(&keys, &values, &strings)?;
+                    visit(Entity::Way { id, refs, tags });
+                },
+                4 => {
+                    let (mut id, mut keys, mut values, mut member_ids, mut member_types) =
+                        (0, Vec::new(), Vec::new(), Vec::new(), Vec::new());
+                    for (number, value) in fields(message)? {
+                        match (number, value) {
+                            (1, Value::Varint(value)) => id = value as i64,
+                            (2, Value::Bytes(packed)) => keys = varints(packed)?,
+                            (3, Value::Bytes(packed)) => values = varints(packed)?,
+                            (9, Value::Bytes(packed)) => member_ids = deltas(packed)?,
+                            (10, Value::Bytes(packed)) => member_types = varints(packed)?,
+                            _ => {},
+                        }
+                    }
+                    let members = member_types
+                        .iter()
+                        .zip(member_ids)
+                        .filter_map(|(member_type, member_id)| {
+                            let member_type = match member_type {
+                                0 => OsmType::Node,
+                                1 => OsmType::Way,
+                                2 => OsmType::Relation,
+                                _ => return None,
+                            };
+                            Some((member_type, member_id))
+                        })
+                        .collect();
+                    let tags = tags(&keys, &values, &strings)?;
+                    visit(Entity::Relation { id, members, tags });
+                },
+                _ => {},
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Dense nodes store their ids and coordinates as deltas to the previous node,
+/// and the tags of all nodes in one list with a 0 after the tags of each node
+fn read_dense_nodes(
+    message: &[u8],
+    strings: &[String],
+    lat: &impl Fn(i64) -> f64,
+    lon: &impl Fn(i64) -> f64,
+    visit: &mut impl FnMut(Entity),
+) -> Result<(), String> {
+    let (mut ids, mut lats, mut lons, mut keys_values) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (number, value) in fields(message)? {
+        match (number, value) {
+            (1, Value::Bytes(packed)) => ids = deltas(packed)?,
+            (8, Value::Bytes(packed)) => lats = deltas(packed)?,
+            (9, Value::Bytes(packed)) => lons = deltas(packed)?,
+            (10, Value::Bytes(packed)) => keys_values = varints(packed)?,
+            _ => {},
+        }
+    }
+    if lats.len() != ids.len() || lons.len() != ids.len() {
+        return Err("dense nodes have as many ids as coordinates".to_string());
+    }
+
+    let mut keys_values = keys_values.into_iter();
+    for ((id, node_lat), node_lon) in ids.into_iter().zip(lats).zip(lons) {
+        let (mut keys, mut values) = (Vec::new(), Vec::new());
+        while let Some(key) = keys_values.next().filter(|key| *key != 0) {
+            keys.push(key);
+            values.push(keys_values.next().ok_or("the tags of dense nodes end in a key")?);
+        }
+
+        let tags = tags(&keys, &values, strings)?;
+        visit(Entity::Node { id, lat: lat(node_lat), lon: lon(node_lon), tags });
+    }
+
+    Ok(())
+}
+
+/// The tags of an element, given as indexes into the string table of the block
+fn tags(keys: &[u64], values: &[u64], strings: &[String]) -> Result<HashMap<String, String>, String> {
+    let string = |index: &u64| {
+        strings
+            .get(*index as usize)
+            .cloned()
+            .ok_or_else(|| format!("a tag refers to the string {} of {}", index, strings.len()))
+    };
+
+    keys.iter().zip(values).map(|(key, value)| Ok((string(key)?, string(value)?))).collect()
+}
+
+/// A field of a protobuf message, fixed-size fields aren't used by the format
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// The fields of a protobuf message with their numbers, in order
+fn fields(message: &[u8]) -> Result<Vec<(u64, Value<'_>)>, String> {
+    let mut position = 0;
+    let mut fields = Vec::new();
+
+    while position < message.len() {
+        let key = varint(message, &mut position)?;
+
+        let value = match key & 0x7 {
+            0 => Value::Varint(varint(message, &mut position)?),
+            1 => take(message, &mut position, 8).map(|_| Value::Fixed)?,
+            2 => {
+                let size = varint(message, &mut position)? as usize;
+                Value::Bytes(take(message, &mut position, size)?)
+            },
+            5 => take(message, &mut position, 4).map(|_| Value::Fixed)?,
+            wire_type => return Err(format!("the wire type {} is not supported", wire_type)),
+        };
+        fields.push((key >> 3, value));
+    }
+
+    Ok(fields)
+}
+
+fn take<'a>(message: &'a [u8], position: &mut usize, size: usize) -> Result<&'a [u8], String> {
+    let end = position.checked_add(size).filter(|end| *end <= message.len()).ok_or("a field is cut off")?;
+    let bytes = &message[*position..end];
+    *position = end;
+
+    Ok(bytes)
+}
+
+fn varint(bytes: &[u8], position: &mut usize) -> Result<u64, String> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*position).ok_or("a number is cut off")?;
+        *position += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err("a number is too long".to_string())
+}
+
+fn varints(packed: &[u8]) -> Result<Vec<u64>, String> {
+    let mut position = 0;
+    let mut values = Vec::new();
+    while position < packed.len() {
+        values.push(varint(packed, &mut position)?);
+    }
+
+    Ok(values)
+}
+
+/// Signed numbers stored as the difference to the previous one
+fn deltas(packed: &[u8]) -> Result<Vec<i64>, String> {
+    let mut current = 0i64;
+
+    Ok(varints(packed)?
+        .into_iter()
+        .map(|delta| {
+            current = current.wrapping_add(zigzag(delta));
+            current
+        })
+        .collect())
+}
+
+fn zigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
@@ -0,0 +1,23 @@
+use anyhow::Result;
+use futures::future::{BoxFuture, FutureExt};
+use tracing::info;
+
+use crate::notifications::{DeliveryChannel, OutboundMessage};
+
+/// Logs messages instead of sending them, for local development.
+pub struct ConsoleChannel;
+
+impl DeliveryChannel for ConsoleChannel {
+    fn deliver(&self, message: OutboundMessage) -> BoxFuture<'_, Result<()>> {
+        async move {
+            info!(
+                recipient = ?message.recipient,
+                subject = %message.subject,
+                body = %message.body,
+                "Outbound message"
+            );
+            Ok(())
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,50 @@
+use anyhow::{Context, Result, anyhow};
+use futures::future::{BoxFuture, FutureExt};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::notifications::{DeliveryChannel, OutboundMessage};
+
+/// Appends every message to a file, so tests and local setups can read them back.
+pub struct FileChannel {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileChannel {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+impl DeliveryChannel for FileChannel {
+    fn deliver(&self, message: OutboundMessage) -> BoxFuture<'_, Result<()>> {
+        async move {
+            use std::io::Write;
+
+            let _guard = self
+                .lock
+                .lock()
+                .map_err(|_| anyhow!("The message file lock was poisoned"))?;
+
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .with_context(|| format!("Failed to open {}", self.path.display()))?;
+
+            writeln!(
+                file,
+                "To: {:?}\nSubject: {}\n\n{}\n---",
+                message.recipient, message.subject, message.body
+            )
+            .with_context(|| "Failed to write the message")?;
+
+            Ok(())
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,117 @@
+pub mod console;
+pub mod file;
+pub mod sms;
+pub mod smtp;
+
+use anyhow::{Result, anyhow};
+use futures::future::BoxFuture;
+use once_cell::sync::OnceCell;
+use std::path::PathBuf;
+
+use crate::models::user::Identifier;
+use crate::notifications::{
+    console::ConsoleChannel,
+    file::FileChannel,
+    sms::{SmsChannel, SmsWebhookConfig, WebhookSmsProvider},
+    smtp::{SmtpChannel, SmtpConfig},
+};
+
+static DELIVERY_CHANNEL: OnceCell<Box<dyn DeliveryChannel>> = OnceCell::new();
+static APP_BASE_URL: OnceCell<String> = OnceCell::new();
+
+/// How messages are delivered, and where the links in them point to
+#[derive(Debug, Clone)]
+pub struct NotificationConfig {
+    pub email: EmailDelivery,
+    pub sms: SmsDelivery,
+    /// The public URL of the app, without a trailing slash
+    pub app_base_url: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum EmailDelivery {
+    Smtp(SmtpConfig),
+    /// Printed, only allowed in development
+    Console,
+    /// Appended to the file
+    File(PathBuf),
+}
+
+#[derive(Debug, Clone)]
+pub enum SmsDelivery {
+    Webhook(SmsWebhookConfig),
+    /// Printed, only allowed in development
+    Console,
+    /// Appended to the file
+    File(PathBuf),
+}
+
+/// A message addressed to one of a user's identifiers, e.g. a password reset link
+#[derive(Debug, Clone)]
+pub struct OutboundMessage {
+    pub recipient: Identifier,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Something that can get an `OutboundMessage` to its recipient.
+pub trait DeliveryChannel: Send + Sync {
+    fn deliver(&self, message: OutboundMessage) -> BoxFuture<'_, Result<()>>;
+}
+
+/// Sends emails and SMS through their own channels based on the recipient.
+pub struct RoutingChannel {
+    email: Box<dyn DeliveryChannel>,
+    sms: Box<dyn DeliveryChannel>,
+}
+
+impl DeliveryChannel for RoutingChannel {
+    fn deliver(&self, message: OutboundMessage) -> BoxFuture<'_, Result<()>> {
+        match &message.recipient {
+            Identifier::Email(_) => self.email.deliver(message),
+            Identifier::Mobile(_) => self.sms.deliver(message),
+            Identifier::Oidc(_) => Box::pin(async { Err(anyhow!("Messages can't be sent to an OpenID Connect account")) }),
+        }
+    }
+}
+
+/// Sets up the channels emails and SMS are delivered through, as loaded with
+/// the `AppConfig`
+pub fn init_notifications(config: &NotificationConfig) -> Result<()> {
+    let email: Box<dyn DeliveryChannel> = match &config.email {
+        EmailDelivery::Smtp(smtp) => Box::new(SmtpChannel::new(smtp)?),
+        EmailDelivery::Console => Box::new(ConsoleChannel),
+        EmailDelivery::File(path) => Box::new(FileChannel::new(path)),
+    };
+
+    let sms: Box<dyn DeliveryChannel> = match &config.sms {
+        SmsDelivery::Webhook(webhook) => Box::new(SmsChannel::new(WebhookSmsProvider::new(webhook))),
+        SmsDelivery::Console => Box::new(ConsoleChannel),
+        SmsDelivery::File(path) => Box::new(FileChannel::new(path)),
+    };
+
+    DELIVERY_CHANNEL
+        .set(Box::new(RoutingChannel { email, sms }))
+        .unwrap_or_else(|_| panic!("Notifications already initialized"));
+    APP_BASE_URL.set(config.app_base_url.clone()).unwrap();
+
+    Ok(())
+}
+
+/// Delivers through `channel` with links to `base_url`, for tests
+#[cfg(test)]
+pub fn init_test_notifications(channel: Box<dyn DeliveryChannel>, base_url: &str) {
+    DELIVERY_CHANNEL
+        .set(channel)
+        .unwrap_or_else(|_| panic!("Notifications already initialized"));
+    APP_BASE_URL.set(base_url.to_string()).unwrap();
+}
+
+pub fn get_delivery_channel() -> &'static dyn DeliveryChannel {
+    DELIVERY_CHANNEL.get().expect("Notifications not initialized").as_ref()
+}
+
+/// The public URL of the app, used to build links sent in messages
+pub fn app_base_url() -> &'static str {
+    APP_BASE_URL.get().expect("Notifications not initialized")
+}
@@ -0,0 +1,89 @@
+use anyhow::{Context, Result, anyhow};
+use futures::future::{BoxFuture, FutureExt};
+use serde::Serialize;
+
+use crate::models::user::Identifier;
+use crate::notifications::{DeliveryChannel, OutboundMessage};
+
+/// A service that can send a text message to a mobile number.
+pub trait SmsProvider: Send + Sync {
+    fn send_sms<'a>(&'a self, to: &'a str, body: &'a str) -> BoxFuture<'a, Result<()>>;
+}
+
+/// Delivers messages to mobile recipients through an `SmsProvider`.
+pub struct SmsChannel {
+    provider: Box<dyn SmsProvider>,
+}
+
+impl SmsChannel {
+    pub fn new(provider: impl SmsProvider + 'static) -> Self {
+        Self {
+            provider: Box::new(provider),
+        }
+    }
+}
+
+impl DeliveryChannel for SmsChannel {
+    fn deliver(&self, message: OutboundMessage) -> BoxFuture<'_, Result<()>> {
+        async move {
+            let Identifier::Mobile(mobile) = &message.recipient else {
+                return Err(anyhow!("SMS can only be delivered to a mobile number"));
+            };
+
+            self.provider.send_sms(mobile, &message.body).await
+        }
+        .boxed()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct WebhookSmsPayload<'a> {
+    to: &'a str,
+    body: &'a str,
+}
+
+/// The SMS gateway text messages are posted to
+#[derive(Debug, Clone)]
+pub struct SmsWebhookConfig {
+    pub url: String,
+    pub api_key: String,
+}
+
+/// Posts `{ "to", "body" }` as JSON to an SMS gateway, authenticated with a bearer key.
+pub struct WebhookSmsProvider {
+    client: reqwest::Client,
+    url: String,
+    api_key: String,
+}
+
+impl WebhookSmsProvider {
+    pub fn new(config: &SmsWebhookConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: config.url.clone(),
+            api_key: config.api_key.clone(),
+        }
+    }
+}
+
+impl SmsProvider for WebhookSmsProvider {
+    fn send_sms<'a>(&'a self, to: &'a str, body: &'a str) -> BoxFuture<'a, Result<()>> {
+        async move {
+            let response = self
+                .client
+                .post(&self.url)
+                .bearer_auth(&self.api_key)
+                .json(&WebhookSmsPayload { to, body })
+                .send()
+                .await
+                .with_context(|| "Failed to reach the SMS gateway")?;
+
+            if !response.status().is_success() {
+                return Err(anyhow!("The SMS gateway returned status {}", response.status()));
+            }
+
+            Ok(())
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,65 @@
+use anyhow::{Context, Result, anyhow};
+use futures::future::{BoxFuture, FutureExt};
+use lettre::{
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+    message::Mailbox,
+    transport::smtp::authentication::Credentials,
+};
+
+use crate::models::user::Identifier;
+use crate::notifications::{DeliveryChannel, OutboundMessage};
+
+/// The SMTP relay emails are sent through
+#[derive(Debug, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub username: String,
+    pub password: String,
+    /// The sender, e.g. `Merzah <no-reply@merzah.example>`
+    pub from: String,
+}
+
+/// Sends messages to email recipients through an SMTP relay.
+pub struct SmtpChannel {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpChannel {
+    pub fn new(config: &SmtpConfig) -> Result<Self> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)
+            .with_context(|| "Failed to create the SMTP transport")?
+            .credentials(Credentials::new(config.username.clone(), config.password.clone()))
+            .build();
+
+        Ok(Self {
+            transport,
+            from: config.from.parse().with_context(|| "The SMTP sender is not a valid mailbox")?,
+        })
+    }
+}
+
+impl DeliveryChannel for SmtpChannel {
+    fn deliver(&self, message: OutboundMessage) -> BoxFuture<'_, Result<()>> {
+        async move {
+            let Identifier::Email(email) = &message.recipient else {
+                return Err(anyhow!("Email can only be delivered to an email address"));
+            };
+
+            let email = Message::builder()
+                .from(self.from.clone())
+                .to(email.parse().with_context(|| "Invalid recipient email address")?)
+                .subject(message.subject)
+                .body(message.body)
+                .with_context(|| "Failed to build the email")?;
+
+            self.transport
+                .send(email)
+                .await
+                .with_context(|| "Failed to send the email")?;
+
+            Ok(())
+        }
+        .boxed()
+    }
+}
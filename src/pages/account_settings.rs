@@ -0,0 +1,319 @@
+use garde::Validate;
+use leptos::{html, prelude::*, reactive::spawn_local};
+use leptos_router::{components::A, hooks::use_query_map};
+
+use crate::models::{
+    account::{ACCOUNT_DELETION_CONFIRMATION, ACCOUNT_EXPORT_URL, DeleteAccountFormData},
+    api_responses::ApiResponse,
+    auth::ChangePasswordFormData,
+    oidc::oidc_start_url,
+    user::{AddIdentifierFormData, ChangeIdentifierFormData, Identifier, IdentifierSummary},
+};
+use crate::server_functions::account::{
+    scheduled_account_deletion, add_identifier, cancel_account_deletion, change_identifier, change_password,
+    delete_account, list_identifiers, remove_identifier, set_primary_identifier,
+};
+use crate::server_functions::auth::oidc_providers;
+
+fn parse_identifier(email_or_mobile_value: String) -> Identifier {
+    if email_or_mobile_value.contains('@') {
+        Identifier::Email(email_or_mobile_value)
+    } else {
+        Identifier::Mobile(email_or_mobile_value)
+    }
+}
+
+#[component]
+pub fn AccountSettings() -> impl IntoView {
+    // Linking a provider account comes back here with its error if it failed
+    let query = use_query_map();
+    let (error, set_error) = signal(query.read_untracked().get("error").unwrap_or_default());
+    let (success, set_success) = signal("".to_string());
+
+    let identifiers = Resource::new(|| (), |_| list_identifiers());
+    let providers = Resource::new(|| (), |_| oidc_providers());
+    let deletion = Resource::new(|| (), |_| scheduled_account_deletion());
+
+    let new_identifier_input: NodeRef<html::Input> = NodeRef::new();
+    let current_password_input: NodeRef<html::Input> = NodeRef::new();
+    let new_password_input: NodeRef<html::Input> = NodeRef::new();
+    let deletion_confirmation_input: NodeRef<html::Input> = NodeRef::new();
+
+    // Shows the outcome of an action and reloads the identifiers when it worked
+    let show_result = move |result: Result<ApiResponse<String>, ServerFnError>| match result {
+        Ok(response) => {
+            if let Some(err_msg) = response.error {
+                set_error.set(err_msg);
+            } else if let Some(data_msg) = response.data {
+                set_success.set(data_msg);
+                identifiers.refetch();
+                deletion.refetch();
+            }
+        },
+        Err(e) => set_error.set(format!("Error: {}", e)),
+    };
+
+    let clear_messages = move || {
+        set_error.set(String::new());
+        set_success.set(String::new());
+    };
+
+    let on_add = move |ev: leptos::ev::SubmitEvent| {
+        ev.prevent_default();
+        clear_messages();
+
+        let add_form = AddIdentifierFormData {
+            identifier: parse_identifier(
+                new_identifier_input.get().expect("<input> should be mounted").value(),
+            ),
+        };
+
+        if let Err(report) = add_form.validate() {
+            set_error.set(report.to_string());
+            return;
+        }
+
+        spawn_local(async move { show_result(add_identifier(add_form).await) });
+    };
+
+    let on_change_password = move |ev: leptos::ev::SubmitEvent| {
+        ev.prevent_default();
+        clear_messages();
+
+        let change_password_form = ChangePasswordFormData {
+            current_password: current_password_input.get().expect("<input> should be mounted").value(),
+            new_password: new_password_input.get().expect("<input> should be mounted").value(),
+        };
+
+        if let Err(report) = change_password_form.validate() {
+            set_error.set(report.to_string());
+            return;
+        }
+
+        spawn_local(async move { show_result(change_password(change_password_form).await) });
+    };
+
+    let on_delete_account = move |ev: leptos::ev::SubmitEvent| {
+        ev.prevent_default();
+        clear_messages();
+
+        let delete_form = DeleteAccountFormData {
+            confirmation: deletion_confirmation_input.get().expect("<input> should be mounted").value(),
+        };
+
+        if let Err(report) = delete_form.validate() {
+            set_error.set(report.to_string());
+            return;
+        }
+
+        spawn_local(async move { show_result(delete_account(delete_form).await) });
+    };
+
+    let on_cancel_deletion = move |_| {
+        clear_messages();
+        spawn_local(async move { show_result(cancel_account_deletion().await) });
+    };
+
+    let on_change = move |current: Identifier, new_value: String| {
+        clear_messages();
+
+        let change_form = ChangeIdentifierFormData {
+            current,
+            new_identifier: parse_identifier(new_value),
+        };
+
+        if let Err(report) = change_form.validate() {
+            set_error.set(report.to_string());
+            return;
+        }
+
+        spawn_local(async move { show_result(change_identifier(change_form).await) });
+    };
+
+    let on_remove = move |identifier: Identifier| {
+        clear_messages();
+        spawn_local(async move { show_result(remove_identifier(identifier).await) });
+    };
+
+    let on_make_primary = move |identifier: Identifier| {
+        clear_messages();
+        spawn_local(async move { show_result(set_primary_identifier(identifier).await) });
+    };
+
+    let identifier_row = move |summary: IdentifierSummary| {
+        let change_input: NodeRef<html::Input> = NodeRef::new();
+        let value = match summary.identifier.oidc_provider() {
+            Some(provider) => format!("Sign in with {}", provider),
+            None => summary.identifier.value().to_string(),
+        };
+        let is_contactable = summary.identifier.is_contactable();
+        let for_change = summary.identifier.clone();
+        let for_remove = summary.identifier.clone();
+        let for_primary = summary.identifier.clone();
+
+        view! {
+            <li>
+                <span>{value}</span>
+                {summary.is_primary.then(|| view! { <span>" (primary)"</span> })}
+                {if summary.verified {
+                    view! { <span>" Verified"</span> }.into_any()
+                } else {
+                    view! { <span>" Not verified, "<A href = "/verify">"verify it"</A></span> }.into_any()
+                }}
+
+                {is_contactable.then(|| view! {
+                    <div class = "form-group">
+                        <input type = "text" name = "change" placeholder = "New value" node_ref = change_input/>
+                        <button
+                            class = "border-2 cursor-pointer"
+                            type = "button"
+                            on:click = move |_| on_change(
+                                for_change.clone(),
+                                change_input.get().expect("<input> should be mounted").value(),
+                            )>"Change"</button>
+                    </div>
+                })}
+
+                {(!summary.is_primary && is_contactable).then(|| view! {
+                    <button
+                        class = "border-2 cursor-pointer"
+                        type = "button"
+                        on:click = move |_| on_make_primary(for_primary.clone())>"Make Primary"</button>
+                })}
+                <button
+                    class = "border-2 cursor-pointer"
+                    type = "button"
+                    on:click = move |_| on_remove(for_remove.clone())>"Remove"</button>
+            </li>
+        }
+    };
+
+    view! {
+        <h1>"Account Settings"</h1>
+        <A href = "/settings/devices">"See the devices you are logged in on"</A>
+        <A href = "/settings/two-factor">"Two-factor authentication"</A>
+        <A href = "/settings/api-tokens">"API tokens for apps and displays"</A>
+
+        <h2>"Email and Mobile"</h2>
+        <Suspense fallback = || view! { <p>"Loading..."</p> }>
+            {move || identifiers.get().map(|response| match response {
+                Ok(response) => match response.data {
+                    Some(identifiers) => view! {
+                        <ul>{identifiers.into_iter().map(identifier_row).collect_view()}</ul>
+                    }.into_any(),
+                    None => view! { <p>{response.error.unwrap_or_default()}</p> }.into_any(),
+                },
+                Err(e) => view! { <p>{format!("Error: {}", e)}</p> }.into_any(),
+            })}
+        </Suspense>
+
+        <form on:submit = on_add>
+            <div class = "form-group">
+                <label for = "contact">"Add an Email or Mobile"</label>
+                <input
+                    type = "text"
+                    name = "contact"
+                    placeholder = "email@example.com or +91923XXXXX90"
+                    node_ref = new_identifier_input
+                    required
+                />
+            </div>
+
+            <button
+                class = "border-2 cursor-pointer bg-primary"
+                type = "submit">"Add"</button>
+        </form>
+
+        <h2>"Sign In Providers"</h2>
+        <Suspense fallback = || view! { <p>"Loading..."</p> }>
+            {move || providers.get().and_then(|response| response.ok()).and_then(|response| response.data).map(|providers| {
+                providers.into_iter().map(|provider| view! {
+                    // Left to the browser, the provider's page is not a route of the app
+                    <a href = oidc_start_url(&provider.name, true) rel = "external">
+                        <button class = "border-2 cursor-pointer" type = "button">
+                            {format!("Link {}", provider.display_name)}
+                        </button>
+                    </a>
+                }).collect_view()
+            })}
+        </Suspense>
+
+        <h2>"Password"</h2>
+        <form on:submit = on_change_password>
+            <div class = "form-group">
+                <label for = "current_password">"Current Password"</label>
+                <input
+                    type = "password"
+                    name = "current_password"
+                    node_ref = current_password_input
+                    required
+                />
+            </div>
+
+            <div class = "form-group">
+                <label for = "new_password">"New Password"</label>
+                <input
+                    type = "password"
+                    name = "new_password"
+                    node_ref = new_password_input
+                    required
+                />
+            </div>
+
+            <button
+                class = "border-2 cursor-pointer bg-primary"
+                type = "submit">"Change Password"</button>
+        </form>
+
+        <h2>"Your Data"</h2>
+        // A download of the server function response rather than a route of the app
+        <a href = ACCOUNT_EXPORT_URL download = "merzah-account.json" rel = "external">"Download everything stored about you"</a>
+
+        <h2>"Delete Account"</h2>
+        <Suspense fallback = || view! { <p>"Loading..."</p> }>
+            {move || deletion.get().and_then(|response| response.ok()).and_then(|response| response.data).map(|status| {
+                match status.deleted_at {
+                    Some(deleted_at) => view! {
+                        <p>{format!("Your account will be deleted on {}.", deleted_at)}</p>
+                        <button
+                            class = "border-2 cursor-pointer"
+                            type = "button"
+                            on:click = on_cancel_deletion>"Keep My Account"</button>
+                    }.into_any(),
+                    None => view! {
+                        <p>"Your account and everything linked to it will be deleted after a grace period, during which you can still change your mind."</p>
+                        <form on:submit = on_delete_account>
+                            <div class = "form-group">
+                                <label for = "confirmation">{format!("Type {} to confirm", ACCOUNT_DELETION_CONFIRMATION)}</label>
+                                <input
+                                    type = "text"
+                                    name = "confirmation"
+                                    node_ref = deletion_confirmation_input
+                                    required
+                                />
+                            </div>
+
+                            <button
+                                class = "border-2 cursor-pointer"
+                                type = "submit">"Delete Account"</button>
+                        </form>
+                    }.into_any(),
+                }
+            })}
+        </Suspense>
+
+        <Show
+            when = move || !error.get().is_empty()
+            fallback = view! {<p></p>}
+        >
+            <p>{error.get()}</p>
+        </Show>
+
+        <Show
+            when = move || !success.get().is_empty()
+            fallback = view! {<p></p>}
+        >
+            <p>{success.get()}</p>
+        </Show>
+    }
+}
@@ -1,10 +1,43 @@
-use crate::server_functions::mosque::add_mosques_of_region;
+use crate::models::api_responses::ApiResponse;
+use crate::models::import_job::{ImportJobStatus, ImportJobSummary};
+use crate::server_functions::mosque::{add_mosques_of_region, import_job_status};
 use leptos::{html, prelude::*, reactive::spawn_local};
+use std::time::Duration;
+
+/// How often the progress of a running import is fetched
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Fetches the progress of the import until it's finished. Stops early once the
+/// page is left and the signal is gone.
+fn poll_import_job(job_id: String, set_job: WriteSignal<Option<ImportJobSummary>>, set_error: WriteSignal<String>) {
+    set_timeout(
+        move || {
+            spawn_local(async move {
+                match import_job_status(job_id.clone()).await {
+                    Ok(ApiResponse { data: Some(job), .. }) => {
+                        let finished = job.status.is_finished();
+                        if set_job.try_set(Some(job)).is_some() || finished {
+                            return;
+                        }
+                    },
+                    Ok(ApiResponse { error, .. }) => {
+                        set_error.set(format!("Server Error: {}", error.unwrap_or_default()));
+                        return;
+                    },
+                    // Likely a hiccup of the connection, the next poll may get through
+                    Err(_) => {},
+                }
+                poll_import_job(job_id, set_job, set_error);
+            });
+        },
+        POLL_INTERVAL,
+    );
+}
 
 #[component]
 pub fn AddMosquesOfRegion() -> impl IntoView {
     let (error, set_error) = signal("".to_string());
-    let (success, set_success) = signal("".to_string());
+    let (job, set_job) = signal(None::<ImportJobSummary>);
 
     let south_input: NodeRef<html::Input> = NodeRef::new();
     let west_input: NodeRef<html::Input> = NodeRef::new();
@@ -55,18 +88,15 @@ pub fn AddMosquesOfRegion() -> impl IntoView {
                 Ok(response) => {
                     if let Some(err_msg) = response.error {
                         set_error.set(format!("Server Error: {}", err_msg));
-                        set_success.set("".to_string());
-                    } else if let Some(data_msg) = response.data {
-                        set_success.set(format!("Success: {}", data_msg));
-                        set_error.set("".to_string());
+                    } else if let Some(started) = response.data {
+                        poll_import_job(started.id.clone(), set_job, set_error);
+                        set_job.set(Some(started));
                     } else {
                         set_error.set("Received an empty response from server.".to_string());
-                        set_success.set("".to_string());
                     }
                 },
                 Err(e) => {
                     set_error.set(format!("Function Error: {}", e));
-                    set_success.set("".to_string());
                 }
             }
         });
@@ -129,12 +159,33 @@ pub fn AddMosquesOfRegion() -> impl IntoView {
                 <p style="color: red;">{error.get()}</p>
             </Show>
 
-            <Show
-                when=move || !success.get().is_empty()
-                fallback=|| view! { <p></p> }
-            >
-                <p style="color: green;">{success.get()}</p>
-            </Show>
+            {move || job.get().map(|job| {
+                let progress = match job.status {
+                    ImportJobStatus::Pending => "Waiting to start the import.".to_string(),
+                    ImportJobStatus::Running => format!("Importing tile {} of {}.", job.tiles_done + 1, job.tiles_total),
+                    ImportJobStatus::Completed => format!("Imported all {} tiles of the region.", job.tiles_total),
+                    ImportJobStatus::Failed => format!(
+                        "The import stopped after {} of {} tiles: {}",
+                        job.tiles_done,
+                        job.tiles_total,
+                        job.error.unwrap_or_default()
+                    ),
+                };
+                let color = if job.status == ImportJobStatus::Failed { "color: red;" } else { "color: green;" };
+
+                view! {
+                    <section>
+                        <p style=color>{progress}</p>
+                        <progress max=job.tiles_total value=job.tiles_done></progress>
+                        <p>
+                            {format!(
+                                "{} created, {} updated, {} unchanged, {} skipped so far.",
+                                job.summary.created, job.summary.updated, job.summary.unchanged, job.summary.skipped
+                            )}
+                        </p>
+                    </section>
+                }
+            })}
         </main>
     }
 }
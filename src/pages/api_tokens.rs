@@ -0,0 +1,173 @@
+use garde::Validate;
+use leptos::{html, prelude::*, reactive::spawn_local};
+
+use crate::models::api_token::{ApiTokenScope, ApiTokenSummary, CreateApiTokenFormData};
+use crate::server_functions::account::{create_api_token, list_api_tokens, revoke_api_token};
+
+fn scope_label(scope: &ApiTokenScope) -> &'static str {
+    match scope {
+        ApiTokenScope::Read => "read",
+        ApiTokenScope::Admin => "admin",
+    }
+}
+
+#[component]
+pub fn ApiTokens() -> impl IntoView {
+    let (error, set_error) = signal("".to_string());
+    let (success, set_success) = signal("".to_string());
+    // The token just created, which can't be looked at again later
+    let (created_token, set_created_token) = signal(String::new());
+
+    let tokens = Resource::new(|| (), |_| list_api_tokens());
+
+    let name_input: NodeRef<html::Input> = NodeRef::new();
+    let admin_input: NodeRef<html::Input> = NodeRef::new();
+    let expires_in_days_input: NodeRef<html::Input> = NodeRef::new();
+
+    let clear_messages = move || {
+        set_error.set(String::new());
+        set_success.set(String::new());
+        set_created_token.set(String::new());
+    };
+
+    let on_create = move |ev: leptos::ev::SubmitEvent| {
+        ev.prevent_default();
+        clear_messages();
+
+        let mut scopes = vec![ApiTokenScope::Read];
+        if admin_input.get().expect("<input> should be mounted").checked() {
+            scopes.push(ApiTokenScope::Admin);
+        }
+        let expires_in_days_value = expires_in_days_input.get().expect("<input> should be mounted").value();
+        let expires_in_days = if expires_in_days_value.trim().is_empty() {
+            None
+        } else {
+            match expires_in_days_value.trim().parse::<u32>() {
+                Ok(days) => Some(days),
+                Err(_) => {
+                    set_error.set("The expiry has to be a number of days".to_string());
+                    return;
+                },
+            }
+        };
+
+        let create_form = CreateApiTokenFormData {
+            name: name_input.get().expect("<input> should be mounted").value(),
+            scopes,
+            expires_in_days,
+        };
+
+        if let Err(report) = create_form.validate() {
+            set_error.set(report.to_string());
+            return;
+        }
+
+        spawn_local(async move {
+            match create_api_token(create_form).await {
+                Ok(response) => match response.data {
+                    Some(created) => {
+                        set_created_token.set(created.token);
+                        set_success.set("Copy the token now, it won't be shown again".to_string());
+                        tokens.refetch();
+                    },
+                    None => set_error.set(response.error.unwrap_or_default()),
+                },
+                Err(e) => set_error.set(format!("Error: {}", e)),
+            }
+        });
+    };
+
+    let on_revoke = move |token_id: String| {
+        clear_messages();
+
+        spawn_local(async move {
+            match revoke_api_token(token_id).await {
+                Ok(response) => {
+                    if let Some(err_msg) = response.error {
+                        set_error.set(err_msg);
+                    } else if let Some(data_msg) = response.data {
+                        set_success.set(data_msg);
+                        tokens.refetch();
+                    }
+                },
+                Err(e) => set_error.set(format!("Error: {}", e)),
+            }
+        });
+    };
+
+    let token_row = move |token: ApiTokenSummary| {
+        let token_id = token.id.clone();
+        let scopes = token.scopes.iter().map(scope_label).collect::<Vec<_>>().join(", ");
+
+        view! {
+            <li>
+                <p><strong>{token.name}</strong>" ("{scopes}")"</p>
+                <p>"Created: "{token.created_at}</p>
+                <p>"Expires: "{token.expires_at.unwrap_or_else(|| "never".to_string())}</p>
+                <p>"Last used: "{token.last_used_at.unwrap_or_else(|| "never".to_string())}</p>
+                <button
+                    class = "border-2 cursor-pointer"
+                    type = "button"
+                    on:click = move |_| on_revoke(token_id.clone())>"Revoke"</button>
+            </li>
+        }
+    };
+
+    view! {
+        <h1>"API Tokens"</h1>
+        <p>"Tokens let apps and devices like mosque displays act on your behalf, sent as "<code>"Authorization: Bearer <token>"</code>"."</p>
+
+        <Suspense fallback = || view! { <p>"Loading..."</p> }>
+            {move || tokens.get().map(|response| match response {
+                Ok(response) => match response.data {
+                    Some(tokens) => view! {
+                        <ul>{tokens.into_iter().map(token_row).collect_view()}</ul>
+                    }.into_any(),
+                    None => view! { <p>{response.error.unwrap_or_default()}</p> }.into_any(),
+                },
+                Err(e) => view! { <p>{format!("Error: {}", e)}</p> }.into_any(),
+            })}
+        </Suspense>
+
+        <form on:submit = on_create>
+            <div class = "form-group">
+                <label for = "name">"Name"</label>
+                <input type = "text" name = "name" placeholder = "Prayer hall display" node_ref = name_input required/>
+            </div>
+
+            <div class = "form-group">
+                <label for = "admin">
+                    <input type = "checkbox" name = "admin" node_ref = admin_input/>
+                    " Allow the admin features of your role and mosques"
+                </label>
+            </div>
+
+            <div class = "form-group">
+                <label for = "expires_in_days">"Expires after days"</label>
+                <input type = "number" name = "expires_in_days" min = "1" placeholder = "Never" node_ref = expires_in_days_input/>
+            </div>
+
+            <button
+                class = "border-2 cursor-pointer bg-primary"
+                type = "submit">"Create Token"</button>
+        </form>
+
+        <Show when = move || !created_token.get().is_empty()>
+            <p><code>{created_token.get()}</code></p>
+        </Show>
+
+        <Show
+            when = move || !error.get().is_empty()
+            fallback = view! {<p></p>}
+        >
+            <p>{error.get()}</p>
+        </Show>
+
+        <Show
+            when = move || !success.get().is_empty()
+            fallback = view! {<p></p>}
+        >
+            <p>{success.get()}</p>
+        </Show>
+    }
+}
@@ -1,9 +1,15 @@
 use garde::Validate;
 use leptos::{html, prelude::*, reactive::spawn_local};
-use leptos_router::components::A;
+use leptos_router::{components::A, hooks::use_query_map};
 
-use crate::models::{auth::{LoginFormData, RegistrationFormData}, user::Identifier};
-use crate::server_functions::auth::{login, register};
+use crate::models::{
+    api_responses::ApiResponse,
+    auth::{LoginFormData, RegistrationFormData},
+    oidc::oidc_start_url,
+    two_factor::{LoginOutcome, TwoFactorLoginFormData},
+    user::Identifier,
+};
+use crate::server_functions::auth::{login, login_two_factor, oidc_providers, register};
 
 #[component]
 pub fn Register() -> impl IntoView {
@@ -154,13 +160,32 @@ pub fn Register() -> impl IntoView {
 // TO-DO: make the authenticat method in custom_auth.rs file use FETCH clause to retrieve the user's data in a single query only along with the user_details record
 #[component]
 pub fn Login() -> impl IntoView {
-    let (error, set_error) = signal("".to_string());
+    // A sign in through a provider comes back here with its error or challenge
+    let query = use_query_map();
+    let (error, set_error) = signal(query.read_untracked().get("error").unwrap_or_default());
     let (success, set_success) = signal("".to_string());
     let (identifier_error, set_identifier_error) = signal(String::new());
     let (password_error, set_password_error) = signal(String::new());
+    // Set when the password was right and a two-factor code is needed as well
+    let (challenge, set_challenge) = signal(query.read_untracked().get("two_factor").unwrap_or_default());
+
+    let providers = Resource::new(|| (), |_| oidc_providers());
 
     let email_or_mobile_input: NodeRef<html::Input> = NodeRef::new();
     let password_input: NodeRef<html::Input> = NodeRef::new();
+    let code_input: NodeRef<html::Input> = NodeRef::new();
+
+    let show_outcome = move |result: Result<ApiResponse<LoginOutcome>, ServerFnError>| match result {
+        Ok(response) => match (response.data, response.error) {
+            (Some(LoginOutcome::LoggedIn), _) => {
+                set_challenge.set(String::new());
+                set_success.set("Successful".to_string());
+            },
+            (Some(LoginOutcome::TwoFactorRequired { challenge }), _) => set_challenge.set(challenge),
+            (None, error) => set_error.set(error.unwrap_or_default()),
+        },
+        Err(e) => set_error.set(format!("Error: {}", e)),
+    };
 
     let on_submit = move |ev: leptos::ev::SubmitEvent| {
         ev.prevent_default();
@@ -204,13 +229,24 @@ pub fn Login() -> impl IntoView {
             return;
         }
 
-        spawn_local(async move {
-            match login(login_form).await {
-                Ok(_) => set_success.set("Successful".to_string()),
+        spawn_local(async move { show_outcome(login(login_form).await) });
+    };
 
-                Err(e) => set_error.set(format!("Error: {}", e)),
-            }
-        });
+    let on_submit_code = move |ev: leptos::ev::SubmitEvent| {
+        ev.prevent_default();
+        set_error.set(String::new());
+
+        let two_factor_form = TwoFactorLoginFormData {
+            challenge: challenge.get_untracked(),
+            code: code_input.get().expect("<input> should be mounted").value(),
+        };
+
+        if let Err(report) = two_factor_form.validate() {
+            set_error.set(report.to_string());
+            return;
+        }
+
+        spawn_local(async move { show_outcome(login_two_factor(two_factor_form).await) });
     };
 
     view! {
@@ -244,7 +280,30 @@ pub fn Login() -> impl IntoView {
             </section>
 
             <section class = "flex-1 bg-surface-700 h-[85svh]">
-                <form on:submit = on_submit>
+                <Show when = move || !challenge.get().is_empty()>
+                    <form on:submit = on_submit_code>
+                        <h1>"Two-Factor Authentication"</h1>
+                        <h2>"Enter the code from your authenticator app, or one of your recovery codes."</h2>
+
+                        <div class = "form-group">
+                            <label for = "code">"Code"</label>
+                            <input
+                                type = "text"
+                                name = "code"
+                                autocomplete = "one-time-code"
+                                node_ref = code_input
+                                required
+                            />
+                        </div>
+
+                        <button
+                            class = "border-2 bg-primary border-stroke cursor-pointer"
+                            type = "submit">"Verify"
+                        </button>
+                    </form>
+                </Show>
+
+                <form on:submit = on_submit class:hidden = move || !challenge.get().is_empty()>
                     <h1>"Login"</h1>
                     <h2>"Welcome back. please enter your details."</h2>
 
@@ -293,6 +352,19 @@ pub fn Login() -> impl IntoView {
 
                 </form>
 
+                <Suspense fallback = || view! {<p></p>}>
+                    {move || providers.get().and_then(|response| response.ok()).and_then(|response| response.data).map(|providers| {
+                        providers.into_iter().map(|provider| view! {
+                            // Left to the browser, the provider's page is not a route of the app
+                            <a href = oidc_start_url(&provider.name, false) rel = "external">
+                                <button class = "border-2 border-stroke cursor-pointer" type = "button">
+                                    {format!("Continue with {}", provider.display_name)}
+                                </button>
+                            </a>
+                        }).collect_view()
+                    })}
+                </Suspense>
+
                 <Show
                     when = move || !error.get().is_empty()
                     fallback = view! {<p></p>}
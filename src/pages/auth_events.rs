@@ -0,0 +1,159 @@
+use leptos::{html, prelude::*};
+
+use crate::models::auth_event::{
+    AUTH_EVENTS_PAGE_SIZE, AuthEventFilter, AuthEventKind, AuthEventOutcome, AuthEventSummary,
+};
+use crate::server_functions::auth::auth_events;
+
+/// `None` for an empty input, so it doesn't narrow the filter down
+fn non_empty(value: String) -> Option<String> {
+    let value = value.trim().to_string();
+    (!value.is_empty()).then_some(value)
+}
+
+#[component]
+pub fn AuthEvents() -> impl IntoView {
+    let (filter, set_filter) = signal(AuthEventFilter::default());
+
+    let events = Resource::new(move || filter.get(), auth_events);
+
+    let kind_input: NodeRef<html::Select> = NodeRef::new();
+    let outcome_input: NodeRef<html::Select> = NodeRef::new();
+    let user_id_input: NodeRef<html::Input> = NodeRef::new();
+    let identifier_input: NodeRef<html::Input> = NodeRef::new();
+    let ip_address_input: NodeRef<html::Input> = NodeRef::new();
+
+    let on_filter = move |ev: leptos::ev::SubmitEvent| {
+        ev.prevent_default();
+
+        let kind_value = kind_input.get().expect("<select> should be mounted").value();
+        let outcome_value = outcome_input.get().expect("<select> should be mounted").value();
+
+        set_filter.set(AuthEventFilter {
+            kind: AuthEventKind::ALL.into_iter().find(|kind| format!("{:?}", kind) == kind_value),
+            outcome: match outcome_value.as_str() {
+                "success" => Some(AuthEventOutcome::Success),
+                "failure" => Some(AuthEventOutcome::Failure),
+                "pending" => Some(AuthEventOutcome::Pending),
+                _ => None,
+            },
+            user_id: non_empty(user_id_input.get().expect("<input> should be mounted").value()),
+            identifier: non_empty(identifier_input.get().expect("<input> should be mounted").value()),
+            ip_address: non_empty(ip_address_input.get().expect("<input> should be mounted").value()),
+            page: 0,
+        });
+    };
+
+    let event_row = |event: AuthEventSummary| {
+        let outcome = match event.outcome {
+            AuthEventOutcome::Success => "Success",
+            AuthEventOutcome::Failure => "Failure",
+            AuthEventOutcome::Pending => "Pending",
+        };
+
+        view! {
+            <tr>
+                <td>{event.created_at}</td>
+                <td>{event.kind.label()}</td>
+                <td>{outcome}</td>
+                <td>{event.user_id.unwrap_or_default()}</td>
+                <td>{event.actor_id.unwrap_or_default()}</td>
+                <td>{event.identifier.unwrap_or_default()}</td>
+                <td>{event.ip_address.unwrap_or_default()}</td>
+                <td>{event.user_agent.unwrap_or_default()}</td>
+                <td>{event.reason.unwrap_or_default()}</td>
+            </tr>
+        }
+    };
+
+    view! {
+        <h1>"Authentication Events"</h1>
+        <p>"Registrations, logins, logouts, password and role changes, the newest first."</p>
+
+        <form on:submit = on_filter>
+            <div class = "form-group">
+                <label for = "kind">"Event"</label>
+                <select name = "kind" node_ref = kind_input>
+                    <option value = "">"All"</option>
+                    {AuthEventKind::ALL.into_iter().map(|kind| view! {
+                        <option value = format!("{:?}", kind)>{kind.label()}</option>
+                    }).collect_view()}
+                </select>
+            </div>
+
+            <div class = "form-group">
+                <label for = "outcome">"Outcome"</label>
+                <select name = "outcome" node_ref = outcome_input>
+                    <option value = "">"All"</option>
+                    <option value = "success">"Success"</option>
+                    <option value = "failure">"Failure"</option>
+                    <option value = "pending">"Pending"</option>
+                </select>
+            </div>
+
+            <div class = "form-group">
+                <label for = "user_id">"User"</label>
+                <input type = "text" name = "user_id" placeholder = "users:abc123" node_ref = user_id_input/>
+            </div>
+
+            <div class = "form-group">
+                <label for = "identifier">"Email or Mobile"</label>
+                <input type = "text" name = "identifier" node_ref = identifier_input/>
+            </div>
+
+            <div class = "form-group">
+                <label for = "ip_address">"Address"</label>
+                <input type = "text" name = "ip_address" node_ref = ip_address_input/>
+            </div>
+
+            <button
+                class = "border-2 cursor-pointer bg-primary"
+                type = "submit">"Filter"</button>
+        </form>
+
+        <Suspense fallback = || view! { <p>"Loading..."</p> }>
+            {move || events.get().map(|response| match response {
+                Ok(response) => match response.data {
+                    Some(events) if events.is_empty() => view! {
+                        <p>"No events match the filter."</p>
+                    }.into_any(),
+                    Some(events) => {
+                        let is_last_page = events.len() < AUTH_EVENTS_PAGE_SIZE as usize;
+
+                        view! {
+                            <table>
+                                <tr>
+                                    <th>"Time"</th>
+                                    <th>"Event"</th>
+                                    <th>"Outcome"</th>
+                                    <th>"User"</th>
+                                    <th>"By"</th>
+                                    <th>"Email or Mobile"</th>
+                                    <th>"Address"</th>
+                                    <th>"Device"</th>
+                                    <th>"Reason"</th>
+                                </tr>
+                                {events.into_iter().map(event_row).collect_view()}
+                            </table>
+                            <Show when = move || !is_last_page>
+                                <button
+                                    class = "border-2 cursor-pointer"
+                                    type = "button"
+                                    on:click = move |_| set_filter.update(|filter| filter.page += 1)>"Older"</button>
+                            </Show>
+                        }.into_any()
+                    },
+                    None => view! { <p>{response.error.unwrap_or_default()}</p> }.into_any(),
+                },
+                Err(e) => view! { <p>{format!("Error: {}", e)}</p> }.into_any(),
+            })}
+        </Suspense>
+
+        <Show when = move || filter.with(|filter| filter.page > 0)>
+            <button
+                class = "border-2 cursor-pointer"
+                type = "button"
+                on:click = move |_| set_filter.update(|filter| filter.page -= 1)>"Newer"</button>
+        </Show>
+    }
+}
@@ -0,0 +1,84 @@
+use leptos::{prelude::*, reactive::spawn_local};
+
+use crate::models::session::DeviceSession;
+use crate::server_functions::account::{list_sessions, revoke_session};
+
+#[component]
+pub fn Devices() -> impl IntoView {
+    let (error, set_error) = signal("".to_string());
+    let (success, set_success) = signal("".to_string());
+
+    let sessions = Resource::new(|| (), |_| list_sessions());
+
+    let on_revoke = move |session_id: String| {
+        set_error.set(String::new());
+        set_success.set(String::new());
+
+        spawn_local(async move {
+            match revoke_session(session_id).await {
+                Ok(response) => {
+                    if let Some(err_msg) = response.error {
+                        set_error.set(err_msg);
+                    } else if let Some(data_msg) = response.data {
+                        set_success.set(data_msg);
+                        sessions.refetch();
+                    }
+                },
+                Err(e) => set_error.set(format!("Error: {}", e)),
+            }
+        });
+    };
+
+    let session_row = move |session: DeviceSession| {
+        let session_id = session.id.clone();
+
+        view! {
+            <li>
+                <p>
+                    {session.user_agent.unwrap_or_else(|| "Unknown device".to_string())}
+                    {session.is_current.then(|| view! { <strong>" (this device)"</strong> })}
+                </p>
+                <p>"Address: "{session.ip_address.unwrap_or_else(|| "unknown".to_string())}</p>
+                <p>"Logged in: "{session.created_at}</p>
+                <p>"Last seen: "{session.last_seen_at.unwrap_or_else(|| "unknown".to_string())}</p>
+                <button
+                    class = "border-2 cursor-pointer"
+                    type = "button"
+                    on:click = move |_| on_revoke(session_id.clone())>
+                    {if session.is_current { "Log Out" } else { "Log Out Device" }}
+                </button>
+            </li>
+        }
+    };
+
+    view! {
+        <h1>"Devices"</h1>
+        <p>"The devices you are logged in on."</p>
+
+        <Suspense fallback = || view! { <p>"Loading..."</p> }>
+            {move || sessions.get().map(|response| match response {
+                Ok(response) => match response.data {
+                    Some(sessions) => view! {
+                        <ul>{sessions.into_iter().map(session_row).collect_view()}</ul>
+                    }.into_any(),
+                    None => view! { <p>{response.error.unwrap_or_default()}</p> }.into_any(),
+                },
+                Err(e) => view! { <p>{format!("Error: {}", e)}</p> }.into_any(),
+            })}
+        </Suspense>
+
+        <Show
+            when = move || !error.get().is_empty()
+            fallback = view! {<p></p>}
+        >
+            <p>{error.get()}</p>
+        </Show>
+
+        <Show
+            when = move || !success.get().is_empty()
+            fallback = view! {<p></p>}
+        >
+            <p>{success.get()}</p>
+        </Show>
+    }
+}
@@ -0,0 +1,109 @@
+use crate::models::mosque::{MOSQUE_EXPORT_URL, MosqueExportFormat};
+use leptos::prelude::*;
+use reqwest::Url;
+
+/// Where the export is downloaded from, with the empty fields left out so the
+/// server exports everything when nothing is filled in
+fn export_url(format: MosqueExportFormat, fields: &[(&str, String)], include_details: bool) -> String {
+    let format = match format {
+        MosqueExportFormat::GeoJson => "geojson",
+        MosqueExportFormat::Csv => "csv",
+    };
+    let mut params = vec![("format", format.to_string())];
+    params.extend(
+        fields
+            .iter()
+            .filter(|(_, value)| !value.trim().is_empty())
+            .map(|(name, value)| (*name, value.trim().to_string())),
+    );
+    if include_details {
+        params.push(("include_details", "true".to_string()));
+    }
+
+    // Only the query is kept, the origin is needed to build it
+    match Url::parse_with_params(&format!("http://localhost{}", MOSQUE_EXPORT_URL), &params) {
+        Ok(url) => format!("{}?{}", url.path(), url.query().unwrap_or_default()),
+        Err(_) => MOSQUE_EXPORT_URL.to_string(),
+    }
+}
+
+#[component]
+pub fn ExportMosques() -> impl IntoView {
+    let (format, set_format) = signal(MosqueExportFormat::GeoJson);
+    let (south, set_south) = signal(String::new());
+    let (west, set_west) = signal(String::new());
+    let (north, set_north) = signal(String::new());
+    let (east, set_east) = signal(String::new());
+    let (city, set_city) = signal(String::new());
+    let (include_details, set_include_details) = signal(false);
+
+    let href = move || {
+        export_url(
+            format.get(),
+            &[
+                ("south", south.get()),
+                ("west", west.get()),
+                ("north", north.get()),
+                ("east", east.get()),
+                ("city", city.get()),
+            ],
+            include_details.get(),
+        )
+    };
+
+    view! {
+        <main>
+            <h1>"Export Mosques"</h1>
+            <p>"Download the mosques of a region given by its coordinates, of a city, or of the whole directory when both are left empty."</p>
+
+            <div class="form-group">
+                <label for="format">"Format"</label>
+                <select
+                    name="format"
+                    on:change=move |ev| {
+                        let format = match event_target_value(&ev).as_str() {
+                            "csv" => MosqueExportFormat::Csv,
+                            _ => MosqueExportFormat::GeoJson,
+                        };
+                        set_format.set(format);
+                    }
+                >
+                    <option value="geojson">"GeoJSON"</option>
+                    <option value="csv">"CSV"</option>
+                </select>
+            </div>
+            <div class="form-group">
+                <label for="south">"South"</label>
+                <input type="text" name="south" placeholder="-90.0" on:input=move |ev| set_south.set(event_target_value(&ev))/>
+            </div>
+            <div class="form-group">
+                <label for="west">"West"</label>
+                <input type="text" name="west" placeholder="-180.0" on:input=move |ev| set_west.set(event_target_value(&ev))/>
+            </div>
+            <div class="form-group">
+                <label for="north">"North"</label>
+                <input type="text" name="north" placeholder="90.0" on:input=move |ev| set_north.set(event_target_value(&ev))/>
+            </div>
+            <div class="form-group">
+                <label for="east">"East"</label>
+                <input type="text" name="east" placeholder="180.0" on:input=move |ev| set_east.set(event_target_value(&ev))/>
+            </div>
+            <div class="form-group">
+                <label for="city">"City"</label>
+                <input type="text" name="city" on:input=move |ev| set_city.set(event_target_value(&ev))/>
+            </div>
+            <div class="form-group">
+                <label>
+                    <input
+                        type="checkbox"
+                        name="include_details"
+                        on:change=move |ev| set_include_details.set(event_target_checked(&ev))
+                    />
+                    "Include the prayer times"
+                </label>
+            </div>
+
+            <a href=href download=move || format.get().file_name() rel="external">"Download"</a>
+        </main>
+    }
+}
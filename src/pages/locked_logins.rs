@@ -0,0 +1,95 @@
+use leptos::{prelude::*, reactive::spawn_local};
+
+use crate::models::login_attempt::{LockedLogin, LoginAttemptKind};
+use crate::server_functions::auth::{locked_logins, unlock_login};
+
+#[component]
+pub fn LockedLogins() -> impl IntoView {
+    let (error, set_error) = signal("".to_string());
+    let (success, set_success) = signal("".to_string());
+
+    let locked = Resource::new(|| (), |_| locked_logins());
+
+    let on_unlock = move |locked_login: LockedLogin| {
+        set_error.set(String::new());
+        set_success.set(String::new());
+
+        spawn_local(async move {
+            match unlock_login(locked_login.kind, locked_login.value).await {
+                Ok(response) => {
+                    if let Some(err_msg) = response.error {
+                        set_error.set(err_msg);
+                    } else if let Some(data_msg) = response.data {
+                        set_success.set(data_msg);
+                        locked.refetch();
+                    }
+                },
+                Err(e) => set_error.set(format!("Error: {}", e)),
+            }
+        });
+    };
+
+    view! {
+        <h1>"Locked Logins"</h1>
+        <p>"Emails, mobiles and addresses that are locked out after too many failed logins."</p>
+
+        <Suspense fallback = || view! { <p>"Loading..."</p> }>
+            {move || locked.get().map(|response| match response {
+                Ok(response) => match response.data {
+                    Some(locked_logins) if locked_logins.is_empty() => view! {
+                        <p>"Nothing is locked right now."</p>
+                    }.into_any(),
+                    Some(locked_logins) => view! {
+                        <table>
+                            <tr>
+                                <th>"Email, Mobile or Address"</th>
+                                <th>"Failed Logins"</th>
+                                <th>"Locked Until"</th>
+                                <th></th>
+                            </tr>
+                            {locked_logins.into_iter().map(|locked_login| {
+                                let kind = match locked_login.kind {
+                                    LoginAttemptKind::Identifier => "",
+                                    LoginAttemptKind::Ip => " (address)",
+                                };
+                                let value = locked_login.value.clone();
+                                let failures = locked_login.failures;
+                                let locked_until = locked_login.locked_until.clone();
+
+                                view! {
+                                    <tr>
+                                        <td>{value}{kind}</td>
+                                        <td>{failures}</td>
+                                        <td>{locked_until}</td>
+                                        <td>
+                                            <button
+                                                class = "border-2 cursor-pointer"
+                                                type = "button"
+                                                on:click = move |_| on_unlock(locked_login.clone())>"Unlock"</button>
+                                        </td>
+                                    </tr>
+                                }
+                            }).collect_view()}
+                        </table>
+                    }.into_any(),
+                    None => view! { <p>{response.error.unwrap_or_default()}</p> }.into_any(),
+                },
+                Err(e) => view! { <p>{format!("Error: {}", e)}</p> }.into_any(),
+            })}
+        </Suspense>
+
+        <Show
+            when = move || !error.get().is_empty()
+            fallback = view! {<p></p>}
+        >
+            <p>{error.get()}</p>
+        </Show>
+
+        <Show
+            when = move || !success.get().is_empty()
+            fallback = view! {<p></p>}
+        >
+            <p>{success.get()}</p>
+        </Show>
+    }
+}
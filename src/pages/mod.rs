@@ -1,4 +1,13 @@
 pub mod auth;
 pub mod home_screen;
 pub mod add_mosques_of_region;
+pub mod export_mosques;
 pub mod mosque_map;
+pub mod password_reset;
+pub mod verification;
+pub mod locked_logins;
+pub mod account_settings;
+pub mod devices;
+pub mod two_factor;
+pub mod api_tokens;
+pub mod auth_events;
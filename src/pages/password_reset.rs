@@ -0,0 +1,215 @@
+use garde::Validate;
+use leptos::{html, prelude::*, reactive::spawn_local};
+use leptos_router::{components::A, hooks::use_query_map};
+
+use crate::models::{
+    password_reset::{ForgotPasswordFormData, ResetPasswordFormData},
+    user::Identifier,
+};
+use crate::server_functions::auth::{request_password_reset, reset_password};
+
+#[component]
+pub fn ForgotPassword() -> impl IntoView {
+    let (error, set_error) = signal("".to_string());
+    let (success, set_success) = signal("".to_string());
+    let (identifier_error, set_identifier_error) = signal(String::new());
+
+    let email_or_mobile_input: NodeRef<html::Input> = NodeRef::new();
+
+    let on_submit = move |ev: leptos::ev::SubmitEvent| {
+        ev.prevent_default();
+
+        // Clear previous errors
+        set_identifier_error.set(String::new());
+        set_error.set(String::new());
+
+        let email_or_mobile_value = email_or_mobile_input
+            .get()
+            .expect("<input> should be mounted")
+            .value();
+
+        let identifier = if email_or_mobile_value.contains('@') {
+            Identifier::Email(email_or_mobile_value)
+        } else {
+            Identifier::Mobile(email_or_mobile_value)
+        };
+        let forgot_password_form = ForgotPasswordFormData { identifier };
+
+        if let Err(report) = forgot_password_form.validate() {
+            for (_, error) in report.iter() {
+                set_identifier_error.set(error.to_string());
+            }
+            return;
+        }
+
+        spawn_local(async move {
+            match request_password_reset(forgot_password_form).await {
+                Ok(response) => {
+                    if let Some(err_msg) = response.error {
+                        set_error.set(err_msg);
+                    } else if let Some(data_msg) = response.data {
+                        set_success.set(data_msg);
+                    }
+                },
+                Err(e) => set_error.set(format!("Error: {}", e)),
+            }
+        });
+    };
+
+    view! {
+        <form on:submit = on_submit>
+            <h1>"Forgot Password"</h1>
+            <p>"Enter the email or mobile of your account and we will send you a reset link."</p>
+
+            <div class = "form-group">
+                <label for = "contact">"Email or Mobile"</label>
+                <input
+                    type = "text"
+                    name = "contact"
+                    placeholder = "email@example.com or +91923XXXXX90"
+                    node_ref = email_or_mobile_input
+                    required
+                />
+                <Show when = move || !identifier_error.get().is_empty()>
+                    <p>{identifier_error.get()}</p>
+                </Show>
+            </div>
+
+            <button
+                class = "border-2 cursor-pointer bg-primary"
+                type = "submit">"Send Reset Link"</button>
+        </form>
+
+        <Show
+            when = move || !error.get().is_empty()
+            fallback = view! {<p></p>}
+        >
+            <p>{error.get()}</p>
+        </Show>
+
+        <Show
+            when = move || !success.get().is_empty()
+            fallback = view! {<p></p>}
+        >
+            <p>{success.get()}</p>
+        </Show>
+
+        <A href = "/login">"Back to Login"</A>
+    }
+}
+
+#[component]
+pub fn ResetPassword() -> impl IntoView {
+    let query = use_query_map();
+    let token = move || query.read().get("token").unwrap_or_default();
+
+    let (error, set_error) = signal("".to_string());
+    let (success, set_success) = signal("".to_string());
+    let (password_error, set_password_error) = signal(String::new());
+
+    let password_input: NodeRef<html::Input> = NodeRef::new();
+    let confirm_password_input: NodeRef<html::Input> = NodeRef::new();
+
+    let on_submit = move |ev: leptos::ev::SubmitEvent| {
+        ev.prevent_default();
+
+        // Clear previous errors
+        set_password_error.set(String::new());
+        set_error.set(String::new());
+
+        let password_value = password_input
+            .get()
+            .expect("<input> should be mounted")
+            .value();
+        let confirm_password_value = confirm_password_input
+            .get()
+            .expect("<input> should be mounted")
+            .value();
+
+        if password_value != confirm_password_value {
+            set_password_error.set("Passwords do not match".to_string());
+            return;
+        }
+
+        let reset_password_form = ResetPasswordFormData {
+            token: token(),
+            password: password_value,
+        };
+
+        if let Err(report) = reset_password_form.validate() {
+            for (field, error) in report.iter() {
+                if field.to_string().starts_with("token") {
+                    set_error.set("This reset link is invalid.".to_string());
+                } else {
+                    set_password_error.set(error.to_string());
+                }
+            }
+            return;
+        }
+
+        spawn_local(async move {
+            match reset_password(reset_password_form).await {
+                Ok(response) => {
+                    if let Some(err_msg) = response.error {
+                        set_error.set(err_msg);
+                    } else if let Some(data_msg) = response.data {
+                        set_success.set(data_msg);
+                    }
+                },
+                Err(e) => set_error.set(format!("Error: {}", e)),
+            }
+        });
+    };
+
+    view! {
+        <form on:submit = on_submit>
+            <h1>"Reset Password"</h1>
+            <p>"Choose a new password. You will be logged out on all your devices."</p>
+
+            <div class = "form-group">
+                <label for = "password">"New Password"</label>
+                <input
+                    type = "password"
+                    name = "password"
+                    node_ref = password_input
+                    required
+                />
+            </div>
+
+            <div class = "form-group">
+                <label for = "confirm_password">"Confirm New Password"</label>
+                <input
+                    type = "password"
+                    name = "confirm_password"
+                    node_ref = confirm_password_input
+                    required
+                />
+                <Show when = move || !password_error.get().is_empty()>
+                    <p>{password_error.get()}</p>
+                </Show>
+                <Show when = move || password_error.get().is_empty()>
+                    <p>"Password must contain 8 characters"</p>
+                </Show>
+            </div>
+
+            <button
+                class = "border-2 cursor-pointer bg-primary"
+                type = "submit">"Reset Password"</button>
+        </form>
+
+        <Show
+            when = move || !error.get().is_empty()
+            fallback = view! {<p></p>}
+        >
+            <p>{error.get()}</p>
+        </Show>
+
+        <Show
+            when = move || !success.get().is_empty()
+            fallback = view! {<p></p>}
+        >
+            <p>{success.get()}</p>
+            <A href = "/login">"Go to Login"</A>
+        </Show>
+    }
+}
@@ -0,0 +1,174 @@
+use garde::Validate;
+use leptos::{html, prelude::*, reactive::spawn_local};
+
+use crate::models::{
+    api_responses::ApiResponse,
+    two_factor::{TwoFactorCodeFormData, TwoFactorEnrollment},
+};
+use crate::server_functions::account::{
+    confirm_two_factor, disable_two_factor, enroll_two_factor, get_two_factor_status,
+    regenerate_recovery_codes,
+};
+
+#[component]
+pub fn TwoFactorSettings() -> impl IntoView {
+    let (error, set_error) = signal("".to_string());
+    let (success, set_success) = signal("".to_string());
+    let (enrollment, set_enrollment) = signal(None::<TwoFactorEnrollment>);
+    let (recovery_codes, set_recovery_codes) = signal(Vec::<String>::new());
+
+    let status = Resource::new(|| (), |_| get_two_factor_status());
+
+    let code_input: NodeRef<html::Input> = NodeRef::new();
+
+    let clear_messages = move || {
+        set_error.set(String::new());
+        set_success.set(String::new());
+    };
+
+    // The code typed in for whichever action is taken, `None` when it is invalid
+    let take_code = move || {
+        let code_form = TwoFactorCodeFormData {
+            code: code_input.get().expect("<input> should be mounted").value(),
+        };
+
+        match code_form.validate() {
+            Ok(()) => Some(code_form),
+            Err(report) => {
+                set_error.set(report.to_string());
+                None
+            },
+        }
+    };
+
+    // Shows the recovery codes handed out, which can't be looked at again later
+    let show_recovery_codes = move |result: Result<ApiResponse<Vec<String>>, ServerFnError>| match result {
+        Ok(response) => match response.data {
+            Some(codes) => {
+                set_enrollment.set(None);
+                set_recovery_codes.set(codes);
+                set_success.set("Keep these recovery codes somewhere safe, each of them logs you in once".to_string());
+                status.refetch();
+            },
+            None => set_error.set(response.error.unwrap_or_default()),
+        },
+        Err(e) => set_error.set(format!("Error: {}", e)),
+    };
+
+    let on_enroll = move |_| {
+        clear_messages();
+        set_recovery_codes.set(Vec::new());
+
+        spawn_local(async move {
+            match enroll_two_factor().await {
+                Ok(response) => match response.data {
+                    Some(new_enrollment) => set_enrollment.set(Some(new_enrollment)),
+                    None => set_error.set(response.error.unwrap_or_default()),
+                },
+                Err(e) => set_error.set(format!("Error: {}", e)),
+            }
+        });
+    };
+
+    let on_confirm = move |_| {
+        clear_messages();
+        if let Some(code_form) = take_code() {
+            spawn_local(async move { show_recovery_codes(confirm_two_factor(code_form).await) });
+        }
+    };
+
+    let on_regenerate = move |_| {
+        clear_messages();
+        if let Some(code_form) = take_code() {
+            spawn_local(async move { show_recovery_codes(regenerate_recovery_codes(code_form).await) });
+        }
+    };
+
+    let on_disable = move |_| {
+        clear_messages();
+        let Some(code_form) = take_code() else {
+            return;
+        };
+
+        spawn_local(async move {
+            match disable_two_factor(code_form).await {
+                Ok(response) => {
+                    if let Some(err_msg) = response.error {
+                        set_error.set(err_msg);
+                    } else if let Some(data_msg) = response.data {
+                        set_recovery_codes.set(Vec::new());
+                        set_success.set(data_msg);
+                        status.refetch();
+                    }
+                },
+                Err(e) => set_error.set(format!("Error: {}", e)),
+            }
+        });
+    };
+
+    view! {
+        <h1>"Two-Factor Authentication"</h1>
+
+        <Suspense fallback = || view! { <p>"Loading..."</p> }>
+            {move || status.get().map(|response| match response {
+                Ok(response) => match response.data {
+                    Some(status) if status.enabled => view! {
+                        <p>"Two-factor authentication is on."</p>
+                        <p>{format!("{} recovery codes left.", status.recovery_codes_left)}</p>
+                        <button class = "border-2 cursor-pointer" type = "button" on:click = on_regenerate>
+                            "New Recovery Codes"
+                        </button>
+                        <button class = "border-2 cursor-pointer" type = "button" on:click = on_disable>
+                            "Turn Off"
+                        </button>
+                    }.into_any(),
+                    Some(status) => view! {
+                        <p>"Two-factor authentication is off."</p>
+                        {status.required.then(|| view! {
+                            <p>"Your role requires it, set it up to keep using the admin features."</p>
+                        })}
+                        <button class = "border-2 cursor-pointer bg-primary" type = "button" on:click = on_enroll>
+                            "Set Up"
+                        </button>
+                    }.into_any(),
+                    None => view! { <p>{response.error.unwrap_or_default()}</p> }.into_any(),
+                },
+                Err(e) => view! { <p>{format!("Error: {}", e)}</p> }.into_any(),
+            })}
+        </Suspense>
+
+        {move || enrollment.get().map(|enrollment| view! {
+            <p>"Scan this with your authenticator app, or add the key by hand:"</p>
+            <p><a href = enrollment.provisioning_uri.clone()>{enrollment.provisioning_uri.clone()}</a></p>
+            <p>"Key: "<code>{enrollment.secret}</code></p>
+            <button class = "border-2 cursor-pointer bg-primary" type = "button" on:click = on_confirm>
+                "Confirm With Code"
+            </button>
+        })}
+
+        <div class = "form-group">
+            <label for = "code">"Code"</label>
+            <input type = "text" name = "code" autocomplete = "one-time-code" node_ref = code_input/>
+        </div>
+
+        <Show when = move || !recovery_codes.get().is_empty()>
+            <ul>
+                {move || recovery_codes.get().into_iter().map(|code| view! { <li><code>{code}</code></li> }).collect_view()}
+            </ul>
+        </Show>
+
+        <Show
+            when = move || !error.get().is_empty()
+            fallback = view! {<p></p>}
+        >
+            <p>{error.get()}</p>
+        </Show>
+
+        <Show
+            when = move || !success.get().is_empty()
+            fallback = view! {<p></p>}
+        >
+            <p>{success.get()}</p>
+        </Show>
+    }
+}
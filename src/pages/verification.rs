@@ -0,0 +1,131 @@
+use garde::Validate;
+use leptos::{html, prelude::*, reactive::spawn_local};
+use leptos_router::hooks::use_query_map;
+
+use crate::models::{user::Identifier, verification::VerifyIdentifierFormData};
+use crate::server_functions::auth::{send_verification_code, verify_identifier};
+
+#[component]
+pub fn VerifyIdentifier() -> impl IntoView {
+    let query = use_query_map();
+    // Email links carry both the address and the code
+    let email_from_link = move || query.read().get("email").unwrap_or_default();
+    let code_from_link = move || query.read().get("code").unwrap_or_default();
+
+    let (error, set_error) = signal("".to_string());
+    let (success, set_success) = signal("".to_string());
+
+    let email_or_mobile_input: NodeRef<html::Input> = NodeRef::new();
+    let code_input: NodeRef<html::Input> = NodeRef::new();
+
+    let read_identifier = move || {
+        let email_or_mobile_value = email_or_mobile_input
+            .get()
+            .expect("<input> should be mounted")
+            .value();
+
+        if email_or_mobile_value.contains('@') {
+            Identifier::Email(email_or_mobile_value)
+        } else {
+            Identifier::Mobile(email_or_mobile_value)
+        }
+    };
+
+    let on_submit = move |ev: leptos::ev::SubmitEvent| {
+        ev.prevent_default();
+        set_error.set(String::new());
+
+        let verify_form = VerifyIdentifierFormData {
+            identifier: read_identifier(),
+            code: code_input.get().expect("<input> should be mounted").value(),
+        };
+
+        if let Err(report) = verify_form.validate() {
+            set_error.set(report.to_string());
+            return;
+        }
+
+        spawn_local(async move {
+            match verify_identifier(verify_form).await {
+                Ok(response) => {
+                    if let Some(err_msg) = response.error {
+                        set_error.set(err_msg);
+                    } else if let Some(data_msg) = response.data {
+                        set_success.set(data_msg);
+                    }
+                },
+                Err(e) => set_error.set(format!("Error: {}", e)),
+            }
+        });
+    };
+
+    let on_resend = move |_| {
+        set_error.set(String::new());
+        let identifier = read_identifier();
+
+        spawn_local(async move {
+            match send_verification_code(identifier).await {
+                Ok(response) => {
+                    if let Some(err_msg) = response.error {
+                        set_error.set(err_msg);
+                    } else if let Some(data_msg) = response.data {
+                        set_success.set(data_msg);
+                    }
+                },
+                Err(e) => set_error.set(format!("Error: {}", e)),
+            }
+        });
+    };
+
+    view! {
+        <form on:submit = on_submit>
+            <h1>"Verify your Email or Mobile"</h1>
+            <p>"Enter the code we sent you, or open the link from your email."</p>
+
+            <div class = "form-group">
+                <label for = "contact">"Email or Mobile"</label>
+                <input
+                    type = "text"
+                    name = "contact"
+                    placeholder = "email@example.com or +91923XXXXX90"
+                    value = email_from_link
+                    node_ref = email_or_mobile_input
+                    required
+                />
+            </div>
+
+            <div class = "form-group">
+                <label for = "code">"Verification Code"</label>
+                <input
+                    type = "text"
+                    name = "code"
+                    value = code_from_link
+                    node_ref = code_input
+                    required
+                />
+            </div>
+
+            <button
+                class = "border-2 cursor-pointer bg-primary"
+                type = "submit">"Verify"</button>
+            <button
+                class = "border-2 cursor-pointer"
+                type = "button"
+                on:click = on_resend>"Send a new code"</button>
+        </form>
+
+        <Show
+            when = move || !error.get().is_empty()
+            fallback = view! {<p></p>}
+        >
+            <p>{error.get()}</p>
+        </Show>
+
+        <Show
+            when = move || !success.get().is_empty()
+            fallback = view! {<p></p>}
+        >
+            <p>{success.get()}</p>
+        </Show>
+    }
+}
@@ -0,0 +1,678 @@
+#[cfg(feature = "ssr")]
+use actix_web::http::StatusCode;
+#[cfg(feature = "ssr")]
+use actix_web::http::header::{CONTENT_DISPOSITION, HeaderValue, RETRY_AFTER};
+#[cfg(feature = "ssr")]
+use chrono::{Duration, Utc};
+#[cfg(feature = "ssr")]
+use garde::Validate;
+#[cfg(feature = "ssr")]
+use leptos::prelude::expect_context;
+use leptos::prelude::ServerFnError;
+use leptos::*;
+use leptos::server_fn::codec::GetUrl;
+#[cfg(feature = "ssr")]
+use leptos_actix::ResponseOptions;
+#[cfg(feature = "ssr")]
+use tracing::error;
+
+#[cfg(feature = "ssr")]
+use crate::auth::account_deletion;
+#[cfg(feature = "ssr")]
+use crate::auth::api_tokens;
+#[cfg(feature = "ssr")]
+use crate::auth::audit::audit;
+#[cfg(feature = "ssr")]
+use crate::auth::custom_auth;
+#[cfg(feature = "ssr")]
+use crate::auth::data_export;
+#[cfg(feature = "ssr")]
+use crate::auth::guards::guard_error_response;
+#[cfg(feature = "ssr")]
+use crate::auth::identifiers;
+#[cfg(feature = "ssr")]
+use crate::auth::login_throttle::get_client_ip;
+#[cfg(feature = "ssr")]
+use crate::auth::session::{
+    clear_session_cookie, get_session_by_token, get_session_user, get_session_token_from_request,
+    list_sessions_for_user, revoke_session_for_user,
+};
+#[cfg(feature = "ssr")]
+use crate::auth::two_factor;
+#[cfg(feature = "ssr")]
+use crate::errors::account::AccountError;
+#[cfg(feature = "ssr")]
+use crate::errors::api_token::ApiTokenError;
+#[cfg(feature = "ssr")]
+use crate::errors::auth::AuthError;
+#[cfg(feature = "ssr")]
+use crate::errors::identifier::IdentifierError;
+#[cfg(feature = "ssr")]
+use crate::errors::session::SessionError;
+#[cfg(feature = "ssr")]
+use crate::errors::two_factor::TwoFactorError;
+#[cfg(feature = "ssr")]
+use crate::models::auth_event::{AuthEventKind, AuthEventOutcome, CreateAuthEvent};
+#[cfg(feature = "ssr")]
+use crate::models::session::Session;
+#[cfg(feature = "ssr")]
+use crate::models::user::User;
+#[cfg(feature = "ssr")]
+use surrealdb::RecordId;
+use crate::models::account::{AccountDeletionStatus, AccountExport, DeleteAccountFormData};
+use crate::models::api_responses::ApiResponse;
+use crate::models::api_token::{ApiTokenSummary, CreateApiTokenFormData, CreatedApiToken};
+use crate::models::auth::ChangePasswordFormData;
+use crate::models::session::DeviceSession;
+use crate::models::two_factor::{TwoFactorCodeFormData, TwoFactorEnrollment, TwoFactorStatus};
+use crate::models::user::{AddIdentifierFormData, ChangeIdentifierFormData, Identifier, IdentifierSummary};
+
+/// Resolves the current user together with the session the request was made with,
+/// so account settings can't be changed with an API token
+#[cfg(feature = "ssr")]
+async fn get_current_user_and_session() -> anyhow::Result<(User, Session)> {
+    let user = get_session_user().await?;
+    let session_token = get_session_token_from_request().await?;
+    let session = get_session_by_token(&session_token).await?;
+
+    Ok((user, session))
+}
+
+/// Sets the status code matching a failed identifier change and builds the
+/// error response for it
+#[cfg(feature = "ssr")]
+fn identifier_error_response<T>(error: &anyhow::Error) -> ApiResponse<T> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    let (status, message) = match error.downcast_ref::<IdentifierError>() {
+        Some(IdentifierError::AlreadyTaken) => (StatusCode::CONFLICT, "This email or mobile is already registered."),
+        Some(IdentifierError::TypeAlreadyLinked) => (StatusCode::CONFLICT, "Your account already has one of these, change it instead."),
+        Some(IdentifierError::TypeMismatch) => (StatusCode::UNPROCESSABLE_ENTITY, "An email can only be changed to an email and a mobile to a mobile."),
+        Some(IdentifierError::NotFound) => (StatusCode::NOT_FOUND, "This email or mobile is not linked to your account."),
+        Some(IdentifierError::LastIdentifier) => (StatusCode::CONFLICT, "Your account needs at least one email or mobile."),
+        Some(IdentifierError::Unverified) => (StatusCode::CONFLICT, "Please verify this email or mobile first."),
+        Some(IdentifierError::NotContactable) => (StatusCode::UNPROCESSABLE_ENTITY, "Only an email or mobile can be made primary."),
+        Some(IdentifierError::DatabaseError(_)) | None => {
+            error!(?error, "Failed to manage the identifiers.");
+            (StatusCode::INTERNAL_SERVER_ERROR, "An internal error occurred.")
+        },
+    };
+
+    response_option.set_status(status);
+    ApiResponse { data: None, error: Some(message.to_string()) }
+}
+
+/// Sets the status code matching a failed two-factor change and builds the error
+/// response for it
+#[cfg(feature = "ssr")]
+fn two_factor_error_response<T>(error: &anyhow::Error) -> ApiResponse<T> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    let (status, message) = match error.downcast_ref::<TwoFactorError>() {
+        Some(TwoFactorError::InvalidCode) => (StatusCode::FORBIDDEN, "The code is invalid."),
+        Some(TwoFactorError::AlreadyEnabled) => (StatusCode::CONFLICT, "Two-factor authentication is already enabled."),
+        Some(TwoFactorError::NotEnabled) => (StatusCode::CONFLICT, "Two-factor authentication is not set up."),
+        _ => {
+            error!(?error, "Failed to manage the two-factor authentication.");
+            (StatusCode::INTERNAL_SERVER_ERROR, "An internal error occurred.")
+        },
+    };
+
+    response_option.set_status(status);
+    ApiResponse { data: None, error: Some(message.to_string()) }
+}
+
+#[server(prefix = "/account", endpoint = "identifiers")]
+pub async fn list_identifiers() -> Result<ApiResponse<Vec<IdentifierSummary>>, ServerFnError> {
+    let user = match get_session_user().await {
+        Ok(user) => user,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    match identifiers::list_identifiers(user.id).await {
+        Ok(identifiers) => Ok(ApiResponse {
+            data: Some(identifiers.into_iter().map(IdentifierSummary::from).collect()),
+            error: None,
+        }),
+        Err(error) => Ok(identifier_error_response(&error)),
+    }
+}
+
+#[server(prefix = "/account", endpoint = "add-identifier")]
+pub async fn add_identifier(form: AddIdentifierFormData) -> Result<ApiResponse<String>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    if let Err(error) = form.validate() {
+        response_option.set_status(StatusCode::UNPROCESSABLE_ENTITY);
+        return Ok(ApiResponse { data: None, error: Some(error.to_string())});
+    }
+
+    let user = match get_session_user().await {
+        Ok(user) => user,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    if let Err(error) = identifiers::add_identifier(user.id, form.identifier).await {
+        return Ok(identifier_error_response(&error));
+    }
+
+    Ok(ApiResponse {
+        data: Some("Added, we have sent it a verification code".to_string()),
+        error: None,
+    })
+}
+
+#[server(prefix = "/account", endpoint = "change-identifier")]
+pub async fn change_identifier(form: ChangeIdentifierFormData) -> Result<ApiResponse<String>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    if let Err(error) = form.validate() {
+        response_option.set_status(StatusCode::UNPROCESSABLE_ENTITY);
+        return Ok(ApiResponse { data: None, error: Some(error.to_string())});
+    }
+
+    let user = match get_session_user().await {
+        Ok(user) => user,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    if let Err(error) = identifiers::change_identifier(user.id, &form.current, form.new_identifier).await {
+        return Ok(identifier_error_response(&error));
+    }
+
+    Ok(ApiResponse {
+        data: Some("Changed, we have sent a verification code to the new one".to_string()),
+        error: None,
+    })
+}
+
+#[server(prefix = "/account", endpoint = "remove-identifier")]
+pub async fn remove_identifier(identifier: Identifier) -> Result<ApiResponse<String>, ServerFnError> {
+    let user = match get_session_user().await {
+        Ok(user) => user,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    if let Err(error) = identifiers::remove_identifier(user.id, &identifier).await {
+        return Ok(identifier_error_response(&error));
+    }
+
+    Ok(ApiResponse {
+        data: Some("The email or mobile has been removed".to_string()),
+        error: None,
+    })
+}
+
+#[server(prefix = "/account", endpoint = "set-primary-identifier")]
+pub async fn set_primary_identifier(identifier: Identifier) -> Result<ApiResponse<String>, ServerFnError> {
+    let user = match get_session_user().await {
+        Ok(user) => user,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    if let Err(error) = identifiers::set_primary_identifier(user.id, &identifier).await {
+        return Ok(identifier_error_response(&error));
+    }
+
+    Ok(ApiResponse {
+        data: Some("The primary email or mobile has been changed".to_string()),
+        error: None,
+    })
+}
+
+/// Changes the password of the current user, who stays logged in on this device
+/// only.
+#[server(prefix = "/account", endpoint = "change-password")]
+pub async fn change_password(form: ChangePasswordFormData) -> Result<ApiResponse<String>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    if let Err(error) = form.validate() {
+        response_option.set_status(StatusCode::UNPROCESSABLE_ENTITY);
+        return Ok(ApiResponse { data: None, error: Some(error.to_string())});
+    }
+
+    let (user, current_session) = match get_current_user_and_session().await {
+        Ok(current) => current,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    let client_ip = match get_client_ip().await {
+        Ok(client_ip) => client_ip,
+        Err(error) => {
+            error!(?error, "Failed to read the address of the client.");
+            response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+            return Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())});
+        }
+    };
+
+    let change_result = custom_auth::change_password(
+        user.id.clone(),
+        current_session.id,
+        client_ip,
+        &form.current_password,
+        &form.new_password,
+    )
+    .await;
+
+    if let Err(error) = change_result {
+        let failure_event = CreateAuthEvent::new(AuthEventKind::PasswordChanged, AuthEventOutcome::Failure).user(user.id);
+        match error.downcast_ref::<AuthError>() {
+            Some(AuthError::PasswordVerificationError(_)) => {
+                audit(failure_event.reason("wrong current password")).await;
+                response_option.set_status(StatusCode::FORBIDDEN);
+                return Ok(ApiResponse { data: None, error: Some("The current password is incorrect.".to_string())});
+            },
+            Some(AuthError::TooManyAttempts(retry_after_seconds)) => {
+                audit(failure_event.reason("locked out")).await;
+                response_option.set_status(StatusCode::TOO_MANY_REQUESTS);
+                response_option.insert_header(RETRY_AFTER, HeaderValue::from(*retry_after_seconds));
+                return Ok(ApiResponse { data: None, error: Some(format!("Too many wrong passwords, please try again in {} seconds.", retry_after_seconds))});
+            },
+            _ => {},
+        }
+
+        error!(?error, "Failed to change the password.");
+        response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+        return Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())});
+    }
+    audit(CreateAuthEvent::new(AuthEventKind::PasswordChanged, AuthEventOutcome::Success).user(user.id)).await;
+
+    Ok(ApiResponse {
+        data: Some("Your password has been changed, other devices have been logged out".to_string()),
+        error: None,
+    })
+}
+
+#[server(prefix = "/account", endpoint = "sessions")]
+pub async fn list_sessions() -> Result<ApiResponse<Vec<DeviceSession>>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    let (user, current_session) = match get_current_user_and_session().await {
+        Ok(current) => current,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    match list_sessions_for_user(user.id).await {
+        Ok(sessions) => Ok(ApiResponse {
+            data: Some(
+                sessions
+                    .into_iter()
+                    .map(|session| DeviceSession::from_session(session, &current_session.id))
+                    .collect(),
+            ),
+            error: None,
+        }),
+        Err(error) => {
+            error!(?error, "Failed to list the sessions.");
+            response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+            Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())})
+        }
+    }
+}
+
+/// Logs one of the current user's devices out. Revoking the current session also
+/// clears its cookie.
+#[server(prefix = "/account", endpoint = "revoke-session")]
+pub async fn revoke_session(session_id: String) -> Result<ApiResponse<String>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    let (user, current_session) = match get_current_user_and_session().await {
+        Ok(current) => current,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    let Ok(session_id) = session_id.parse::<RecordId>() else {
+        response_option.set_status(StatusCode::NOT_FOUND);
+        return Ok(ApiResponse { data: None, error: Some("This session was not found.".to_string())});
+    };
+    let is_current = session_id == current_session.id;
+
+    if let Err(error) = revoke_session_for_user(user.id.clone(), session_id).await {
+        if let Some(SessionError::SessionNotFound) = error.downcast_ref::<SessionError>() {
+            response_option.set_status(StatusCode::NOT_FOUND);
+            return Ok(ApiResponse { data: None, error: Some("This session was not found.".to_string())});
+        }
+
+        error!(?error, "Failed to revoke the session.");
+        response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+        return Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())});
+    }
+
+    audit(CreateAuthEvent::new(AuthEventKind::SessionRevoked, AuthEventOutcome::Success).user(user.id)).await;
+
+    let cookie_clearing_result = if is_current { clear_session_cookie() } else { Ok(()) };
+    if let Err(error) = cookie_clearing_result {
+        error!(?error);
+        response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+        return Ok(ApiResponse { data: None, error: Some("Failed to clear the session cookie.".to_string())});
+    }
+
+    Ok(ApiResponse {
+        data: Some("The device has been logged out".to_string()),
+        error: None,
+    })
+}
+
+#[server(prefix = "/account", endpoint = "two-factor")]
+pub async fn get_two_factor_status() -> Result<ApiResponse<TwoFactorStatus>, ServerFnError> {
+    let user = match get_session_user().await {
+        Ok(user) => user,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    match two_factor::two_factor_status(&user).await {
+        Ok(status) => Ok(ApiResponse { data: Some(status), error: None }),
+        Err(error) => Ok(two_factor_error_response(&error)),
+    }
+}
+
+/// Hands out a new secret for the authenticator app, which only starts being
+/// asked for once `confirm_two_factor` got a code made with it.
+#[server(prefix = "/account", endpoint = "enroll-two-factor")]
+pub async fn enroll_two_factor() -> Result<ApiResponse<TwoFactorEnrollment>, ServerFnError> {
+    let user = match get_session_user().await {
+        Ok(user) => user,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    match two_factor::start_enrollment(&user).await {
+        Ok(enrollment) => Ok(ApiResponse { data: Some(enrollment), error: None }),
+        Err(error) => Ok(two_factor_error_response(&error)),
+    }
+}
+
+/// Enables two-factor authentication and returns the recovery codes, which are
+/// not shown again.
+#[server(prefix = "/account", endpoint = "confirm-two-factor")]
+pub async fn confirm_two_factor(form: TwoFactorCodeFormData) -> Result<ApiResponse<Vec<String>>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    if let Err(error) = form.validate() {
+        response_option.set_status(StatusCode::UNPROCESSABLE_ENTITY);
+        return Ok(ApiResponse { data: None, error: Some(error.to_string())});
+    }
+
+    let user = match get_session_user().await {
+        Ok(user) => user,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    match two_factor::confirm_enrollment(user.id, &form.code).await {
+        Ok(recovery_codes) => Ok(ApiResponse { data: Some(recovery_codes), error: None }),
+        Err(error) => Ok(two_factor_error_response(&error)),
+    }
+}
+
+#[server(prefix = "/account", endpoint = "regenerate-recovery-codes")]
+pub async fn regenerate_recovery_codes(form: TwoFactorCodeFormData) -> Result<ApiResponse<Vec<String>>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    if let Err(error) = form.validate() {
+        response_option.set_status(StatusCode::UNPROCESSABLE_ENTITY);
+        return Ok(ApiResponse { data: None, error: Some(error.to_string())});
+    }
+
+    let user = match get_session_user().await {
+        Ok(user) => user,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    match two_factor::regenerate_recovery_codes(user.id, &form.code).await {
+        Ok(recovery_codes) => Ok(ApiResponse { data: Some(recovery_codes), error: None }),
+        Err(error) => Ok(two_factor_error_response(&error)),
+    }
+}
+
+#[server(prefix = "/account", endpoint = "disable-two-factor")]
+pub async fn disable_two_factor(form: TwoFactorCodeFormData) -> Result<ApiResponse<String>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    if let Err(error) = form.validate() {
+        response_option.set_status(StatusCode::UNPROCESSABLE_ENTITY);
+        return Ok(ApiResponse { data: None, error: Some(error.to_string())});
+    }
+
+    let user = match get_session_user().await {
+        Ok(user) => user,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    if let Err(error) = two_factor::disable_two_factor(user.id, &form.code).await {
+        return Ok(two_factor_error_response(&error));
+    }
+
+    Ok(ApiResponse {
+        data: Some("Two-factor authentication has been turned off".to_string()),
+        error: None,
+    })
+}
+
+#[server(prefix = "/account", endpoint = "api-tokens")]
+pub async fn list_api_tokens() -> Result<ApiResponse<Vec<ApiTokenSummary>>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    let user = match get_session_user().await {
+        Ok(user) => user,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    match api_tokens::list_api_tokens(user.id).await {
+        Ok(tokens) => Ok(ApiResponse {
+            data: Some(tokens.into_iter().map(ApiTokenSummary::from).collect()),
+            error: None,
+        }),
+        Err(error) => {
+            error!(?error, "Failed to list the API tokens.");
+            response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+            Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())})
+        }
+    }
+}
+
+/// Creates an API token for the current user, which is only returned this once.
+#[server(prefix = "/account", endpoint = "create-api-token")]
+pub async fn create_api_token(form: CreateApiTokenFormData) -> Result<ApiResponse<CreatedApiToken>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    if let Err(error) = form.validate() {
+        response_option.set_status(StatusCode::UNPROCESSABLE_ENTITY);
+        return Ok(ApiResponse { data: None, error: Some(error.to_string())});
+    }
+
+    let user = match get_session_user().await {
+        Ok(user) => user,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    let expires_at = form
+        .expires_in_days
+        .map(|days| Utc::now() + Duration::days(days.into()));
+
+    match api_tokens::create_api_token(user.id, form.name, form.scopes, expires_at).await {
+        Ok((token, api_token)) => Ok(ApiResponse {
+            data: Some(CreatedApiToken { token, summary: ApiTokenSummary::from(api_token) }),
+            error: None,
+        }),
+        Err(error) => {
+            error!(?error, "Failed to create the API token.");
+            response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+            Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())})
+        }
+    }
+}
+
+#[server(prefix = "/account", endpoint = "revoke-api-token")]
+pub async fn revoke_api_token(token_id: String) -> Result<ApiResponse<String>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    let user = match get_session_user().await {
+        Ok(user) => user,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    let Ok(token_id) = token_id.parse::<RecordId>() else {
+        response_option.set_status(StatusCode::NOT_FOUND);
+        return Ok(ApiResponse { data: None, error: Some("This API token was not found.".to_string())});
+    };
+
+    if let Err(error) = api_tokens::revoke_api_token(user.id, token_id).await {
+        if let Some(ApiTokenError::TokenNotFound) = error.downcast_ref::<ApiTokenError>() {
+            response_option.set_status(StatusCode::NOT_FOUND);
+            return Ok(ApiResponse { data: None, error: Some("This API token was not found.".to_string())});
+        }
+
+        error!(?error, "Failed to revoke the API token.");
+        response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+        return Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())});
+    }
+
+    Ok(ApiResponse {
+        data: Some("The API token has been revoked".to_string()),
+        error: None,
+    })
+}
+
+/// Everything stored about the current user, downloaded as a JSON file from
+/// `ACCOUNT_EXPORT_URL`
+#[server(prefix = "/account", endpoint = "export", input = GetUrl)]
+pub async fn export_account() -> Result<ApiResponse<AccountExport>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    let (user, current_session) = match get_current_user_and_session().await {
+        Ok(current) => current,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    match data_export::export_account(&user, &current_session.id).await {
+        Ok(export) => {
+            response_option.insert_header(
+                CONTENT_DISPOSITION,
+                HeaderValue::from_static("attachment; filename=\"merzah-account.json\""),
+            );
+            Ok(ApiResponse { data: Some(export), error: None })
+        },
+        Err(error) => {
+            error!(?error, "Failed to export the account.");
+            response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+            Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())})
+        }
+    }
+}
+
+#[server(prefix = "/account", endpoint = "account-deletion")]
+pub async fn scheduled_account_deletion() -> Result<ApiResponse<AccountDeletionStatus>, ServerFnError> {
+    let user = match get_session_user().await {
+        Ok(user) => user,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    let deleted_at = account_deletion::account_deleted_at(&user);
+
+    Ok(ApiResponse {
+        data: Some(AccountDeletionStatus { deleted_at: deleted_at.map(|deleted_at| deleted_at.to_rfc3339()) }),
+        error: None,
+    })
+}
+
+/// Schedules the account of the current user for deletion, logging out every
+/// other device. It can be cancelled until the grace period is over.
+#[server(prefix = "/account", endpoint = "delete-account")]
+pub async fn delete_account(form: DeleteAccountFormData) -> Result<ApiResponse<String>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    if let Err(error) = form.validate() {
+        response_option.set_status(StatusCode::UNPROCESSABLE_ENTITY);
+        return Ok(ApiResponse { data: None, error: Some(error.to_string())});
+    }
+
+    let (user, current_session) = match get_current_user_and_session().await {
+        Ok(current) => current,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    match account_deletion::request_account_deletion(user.id, current_session.id).await {
+        Ok(deleted_at) => Ok(ApiResponse {
+            data: Some(format!(
+                "Your account will be deleted on {}, until then you can still cancel it here",
+                deleted_at.format("%Y-%m-%d"),
+            )),
+            error: None,
+        }),
+        Err(error) => {
+            if let Some(AccountError::DeletionAlreadyRequested) = error.downcast_ref::<AccountError>() {
+                response_option.set_status(StatusCode::CONFLICT);
+                return Ok(ApiResponse { data: None, error: Some("Your account is already scheduled for deletion.".to_string())});
+            }
+
+            error!(?error, "Failed to schedule the account for deletion.");
+            response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+            Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())})
+        }
+    }
+}
+
+#[server(prefix = "/account", endpoint = "cancel-account-deletion")]
+pub async fn cancel_account_deletion() -> Result<ApiResponse<String>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    let user = match get_session_user().await {
+        Ok(user) => user,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    if let Err(error) = account_deletion::cancel_account_deletion(user.id).await {
+        if let Some(AccountError::DeletionNotRequested) = error.downcast_ref::<AccountError>() {
+            response_option.set_status(StatusCode::CONFLICT);
+            return Ok(ApiResponse { data: None, error: Some("Your account is not scheduled for deletion.".to_string())});
+        }
+
+        error!(?error, "Failed to cancel the deletion of the account.");
+        response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+        return Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())});
+    }
+
+    Ok(ApiResponse {
+        data: Some("Your account will be kept".to_string()),
+        error: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::login_throttle::MAX_FAILED_LOGINS_PER_IDENTIFIER;
+    use crate::auth::session::create_session;
+    use crate::models::session::DeviceInfo;
+    use crate::test_support::{TEST_PASSWORD, handle_request, register_test_user, run, session_cookie};
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn guessing_the_current_password_locks_the_account() {
+        run(async {
+            let user_id = register_test_user("change-password-locked@example.com").await;
+            let session_token = create_session(user_id, DeviceInfo::default()).await.unwrap();
+            let change = |current_password: &str| {
+                let form = ChangePasswordFormData {
+                    current_password: current_password.to_string(),
+                    new_password: "a brand new password".to_string(),
+                };
+                let request = TestRequest::post()
+                    .cookie(session_cookie(&session_token))
+                    .peer_addr("198.51.100.9:4000".parse().unwrap());
+                handle_request(request, || change_password(form))
+            };
+
+            for _ in 0..MAX_FAILED_LOGINS_PER_IDENTIFIER {
+                let (_, response_options) = change("not the password").await;
+                assert_eq!(response_options.0.read().status, Some(StatusCode::FORBIDDEN));
+            }
+
+            // Even the right password is turned away until the lockout ends
+            let (response, response_options) = change(TEST_PASSWORD).await;
+            assert!(response.unwrap().error.is_some());
+            let response_options = response_options.0.read();
+            assert_eq!(response_options.status, Some(StatusCode::TOO_MANY_REQUESTS));
+            assert!(response_options.headers.contains_key(RETRY_AFTER));
+        });
+    }
+}
@@ -1,25 +1,72 @@
 #[cfg(feature = "ssr")]
 use actix_web::http::StatusCode;
 #[cfg(feature = "ssr")]
+use actix_web::http::header::{HeaderValue, RETRY_AFTER};
+#[cfg(feature = "ssr")]
 use garde::Validate;
 #[cfg(feature = "ssr")]
 use leptos::prelude::expect_context;
 use leptos::prelude::ServerFnError;
 use leptos::*;
+use leptos::server_fn::codec::GetUrl;
 #[cfg(feature = "ssr")]
 use leptos_actix::ResponseOptions;
 #[cfg(feature = "ssr")]
 use tracing::error;
 
 #[cfg(feature = "ssr")]
-use crate::auth::custom_auth::authenticate;
+use crate::auth::audit::{self, audit};
+#[cfg(feature = "ssr")]
+use crate::auth::custom_auth::{authenticate, change_user_role};
 #[cfg(feature = "ssr")]
-use crate::auth::session::{create_session, set_session_cookie};
+use crate::auth::session::{
+    clear_session_cookie, create_session, delete_all_sessions_for_user, delete_session,
+    get_current_user, get_device_info, get_session_by_token, get_session_token_from_request, set_session_cookie,
+};
 #[cfg(feature = "ssr")]
 use crate::auth::custom_auth::register_user;
 #[cfg(feature = "ssr")]
+use crate::auth::guards::{guard_error_response, require_role};
+#[cfg(feature = "ssr")]
+use crate::auth::login_throttle::{self, get_client_ip, list_locked_logins};
+#[cfg(feature = "ssr")]
+use crate::auth::oidc::{self, OidcOutcome};
+#[cfg(feature = "ssr")]
+use crate::auth::oidc_config::get_oidc_config;
+#[cfg(feature = "ssr")]
+use crate::auth::password_reset;
+#[cfg(feature = "ssr")]
+use crate::auth::custom_auth::find_user_identifier;
+#[cfg(feature = "ssr")]
+use crate::auth::two_factor::{self, create_challenge, is_two_factor_enabled};
+#[cfg(feature = "ssr")]
+use crate::auth::verification::{self, find_identifier_of_user, send_verification};
+#[cfg(feature = "ssr")]
 use crate::errors::auth::AuthError;
+#[cfg(feature = "ssr")]
+use crate::errors::oidc::OidcError;
+#[cfg(feature = "ssr")]
+use crate::errors::password_reset::PasswordResetError;
+#[cfg(feature = "ssr")]
+use crate::errors::session::SessionError;
+#[cfg(feature = "ssr")]
+use crate::errors::two_factor::TwoFactorError;
+#[cfg(feature = "ssr")]
+use crate::errors::verification::VerificationError;
 use crate::models::auth::LoginFormData;
+use crate::models::auth_event::{AuthEventFilter, AuthEventSummary};
+#[cfg(feature = "ssr")]
+use crate::models::auth_event::{AuthEventKind, AuthEventOutcome, CreateAuthEvent};
+use crate::models::login_attempt::{LockedLogin, LoginAttemptKind};
+use crate::models::oidc::OidcProvider;
+use crate::models::password_reset::{ForgotPasswordFormData, ResetPasswordFormData};
+use crate::models::two_factor::{LoginOutcome, TwoFactorLoginFormData};
+use crate::models::user::Identifier;
+use crate::models::verification::VerifyIdentifierFormData;
+use crate::models::user::UserProfile;
+use crate::models::user::UserRole;
+#[cfg(feature = "ssr")]
+use surrealdb::RecordId;
 use crate::models::{api_responses::ApiResponse, auth::RegistrationFormData};
 
 #[server(prefix = "/auth", endpoint = "register")]
@@ -46,15 +93,38 @@ pub async fn register(form: RegistrationFormData) -> Result<ApiResponse<String>,
         return Ok(ApiResponse { data: None, error: Some(format!("{}", error))});
     } 
 
+    let identifier = form.identifier.clone();
     let registration_result = register_user(form).await;
 
     if let Err(error) = registration_result {
         error!(?error, "Failed to register the user");  
+        audit(CreateAuthEvent::new(AuthEventKind::Register, AuthEventOutcome::Failure)
+            .identifier(&identifier)
+            .reason("internal error")).await;
         return Err(ServerFnError::ServerError("Failed to register the user".to_string()));
     };
+    if let Ok(user_id) = &registration_result {
+        audit(CreateAuthEvent::new(AuthEventKind::Register, AuthEventOutcome::Success)
+            .user(user_id.clone())
+            .identifier(&identifier)).await;
+    }
+
+    // The account is usable without verification, so a failed delivery only gets logged
+    match find_user_identifier(&identifier).await {
+        Ok(Some(user_identifier)) => {
+            if let Err(error) = send_verification(&user_identifier).await {
+                error!(?error, "Failed to send the verification after registration");
+            }
+        },
+        Ok(None) => error!("The registered identifier was not found"),
+        Err(error) => error!(?error, "Failed to fetch the registered identifier"),
+    }
 
     let user_id = registration_result.ok();
-    let session_creation_result = create_session(user_id.unwrap()).await;
+    let session_creation_result = match get_device_info().await {
+        Ok(device) => create_session(user_id.unwrap(), device).await,
+        Err(error) => Err(error),
+    };
     if let Err(error) = session_creation_result {
         error!(?error);
         return Err(ServerFnError::ServerError("Failed to generate session tokens for the registered user".to_string()));
@@ -77,18 +147,42 @@ pub async fn register(form: RegistrationFormData) -> Result<ApiResponse<String>,
 #[server]
 pub async fn login(
     form: LoginFormData,
-) -> Result<ApiResponse<String>, ServerFnError>{
+) -> Result<ApiResponse<LoginOutcome>, ServerFnError>{
     let response_option = expect_context::<ResponseOptions>();
-    let user_id = match authenticate(form).await {
+
+    let client_ip = match get_client_ip().await {
+        Ok(client_ip) => client_ip,
+        Err(error) => {
+            error!(?error, "Failed to read the address of the client.");
+            response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+            return Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())});
+        }
+    };
+
+    let identifier = form.identifier.clone();
+    let login_event = |outcome| CreateAuthEvent::new(AuthEventKind::Login, outcome).identifier(&identifier);
+
+    let user_id = match authenticate(form, client_ip).await {
         Ok(id) => id,
         Err(error) => {
             if let Some(auth_error) = error.downcast_ref::<AuthError>() {
                 match auth_error {
                     AuthError::UserNotFound | AuthError::PasswordVerificationError(_) => {
                         error!("Authentication failed for user.");
+                        let reason = match auth_error {
+                            AuthError::UserNotFound => "unknown identifier",
+                            _ => "wrong password",
+                        };
+                        audit(login_event(AuthEventOutcome::Failure).reason(reason)).await;
                         response_option.set_status(StatusCode::UNAUTHORIZED);
                         return Ok(ApiResponse { data: None, error: Some("Invalid username or password.".to_string())});
                     },
+                    AuthError::TooManyAttempts(retry_after_seconds) => {
+                        audit(login_event(AuthEventOutcome::Failure).reason("locked out")).await;
+                        response_option.set_status(StatusCode::TOO_MANY_REQUESTS);
+                        response_option.insert_header(RETRY_AFTER, HeaderValue::from(*retry_after_seconds));
+                        return Ok(ApiResponse { data: None, error: Some(format!("Too many failed logins, please try again in {} seconds.", retry_after_seconds))});
+                    },
                     AuthError::DatabaseError(_) | AuthError::PasswordHashError(_) => {
                         error!(?error, "Internal server error during authentication.");
                         response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
@@ -108,24 +202,754 @@ pub async fn login(
         }
     };
 
-    let session_creation_result = create_session(user_id).await;
-    if let Err(error) = session_creation_result {
+    // The session is only created once the second step is done too
+    match is_two_factor_enabled(&user_id).await {
+        Ok(true) => {
+            audit(login_event(AuthEventOutcome::Pending).user(user_id.clone()).reason("awaiting the two-factor code")).await;
+            return match create_challenge(user_id).await {
+                Ok(challenge) => Ok(ApiResponse {
+                    data: Some(LoginOutcome::TwoFactorRequired { challenge }),
+                    error: None,
+                }),
+                Err(error) => {
+                    error!(?error, "Failed to create the two-factor challenge.");
+                    response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+                    Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())})
+                }
+            };
+        },
+        Ok(false) => {},
+        Err(error) => {
+            error!(?error, "Failed to check the two-factor authentication of the user.");
+            response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+            return Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())});
+        }
+    }
+
+    if let Err(message) = start_session(user_id.clone()).await {
+        response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+        return Ok(ApiResponse { data: None, error: Some(message.to_string())});
+    }
+    audit(login_event(AuthEventOutcome::Success).user(user_id)).await;
+
+    Ok(ApiResponse {
+        data: Some(LoginOutcome::LoggedIn),
+        error: None,
+    })
+}
+
+/// The second step of a login for users with two-factor authentication, taking
+/// a code from their app or one of their recovery codes.
+#[server(prefix = "/auth", endpoint = "login-two-factor")]
+pub async fn login_two_factor(form: TwoFactorLoginFormData) -> Result<ApiResponse<LoginOutcome>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    if let Err(error) = form.validate() {
+        response_option.set_status(StatusCode::UNPROCESSABLE_ENTITY);
+        return Ok(ApiResponse { data: None, error: Some(error.to_string())});
+    }
+
+    let user_id = match two_factor::complete_challenge(&form.challenge, &form.code).await {
+        Ok(user_id) => user_id,
+        Err(error) => {
+            if let Some(two_factor_error @ (TwoFactorError::InvalidCode | TwoFactorError::TooManyAttempts)) =
+                error.downcast_ref::<TwoFactorError>()
+            {
+                audit(CreateAuthEvent::new(AuthEventKind::Login, AuthEventOutcome::Failure)
+                    .reason(two_factor_error.to_string())).await;
+            }
+
+            return match error.downcast_ref::<TwoFactorError>() {
+                Some(TwoFactorError::InvalidCode) => {
+                    response_option.set_status(StatusCode::UNAUTHORIZED);
+                    Ok(ApiResponse { data: None, error: Some("The code is invalid.".to_string())})
+                },
+                Some(TwoFactorError::InvalidChallenge) => {
+                    response_option.set_status(StatusCode::UNAUTHORIZED);
+                    Ok(ApiResponse { data: None, error: Some("This login has expired, please log in again.".to_string())})
+                },
+                Some(TwoFactorError::TooManyAttempts) => {
+                    response_option.set_status(StatusCode::TOO_MANY_REQUESTS);
+                    Ok(ApiResponse { data: None, error: Some("Too many wrong codes, please log in again.".to_string())})
+                },
+                _ => {
+                    error!(?error, "Failed to complete the two-factor login.");
+                    response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+                    Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())})
+                }
+            };
+        }
+    };
+
+    if let Err(message) = start_session(user_id.clone()).await {
+        response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+        return Ok(ApiResponse { data: None, error: Some(message.to_string())});
+    }
+    audit(CreateAuthEvent::new(AuthEventKind::Login, AuthEventOutcome::Success)
+        .user(user_id)
+        .reason("two-factor code")).await;
+
+    Ok(ApiResponse {
+        data: Some(LoginOutcome::LoggedIn),
+        error: None,
+    })
+}
+
+/// The page at `path` showing `message` as an error
+#[cfg(feature = "ssr")]
+fn error_location(path: &str, message: &str) -> String {
+    let query: String = reqwest::Url::parse_with_params("http://localhost", [("error", message)])
+        .ok()
+        .and_then(|url| url.query().map(str::to_string))
+        .unwrap_or_default();
+
+    format!("{}?{}", path, query)
+}
+
+/// Creates a session for the user on the requesting device and sets its cookie,
+/// failing with the message to show the user
+#[cfg(feature = "ssr")]
+async fn start_session(user_id: RecordId) -> Result<(), &'static str> {
+    let session_creation_result = match get_device_info().await {
+        Ok(device) => create_session(user_id, device).await,
+        Err(error) => Err(error),
+    };
+    let session_token = session_creation_result.map_err(|error| {
+        error!(?error);
+        "Failed to create user session."
+    })?;
+
+    set_session_cookie(&session_token).map_err(|error| {
+        error!(?error);
+        "Failed to set session cookie."
+    })?;
+
+    Ok(())
+}
+
+/// The OpenID Connect providers users can sign in with
+#[server(prefix = "/auth", endpoint = "oidc-providers")]
+pub async fn oidc_providers() -> Result<ApiResponse<Vec<OidcProvider>>, ServerFnError> {
+    Ok(ApiResponse {
+        data: Some(
+            get_oidc_config()
+                .providers
+                .iter()
+                .map(|provider| OidcProvider {
+                    name: provider.name.clone(),
+                    display_name: provider.display_name.clone(),
+                })
+                .collect(),
+        ),
+        error: None,
+    })
+}
+
+/// Sends the browser to the provider to sign in, or to link the account of the
+/// provider to the current user when `link` is set.
+#[server(prefix = "/auth", endpoint = "oidc-start", input = GetUrl)]
+pub async fn start_oidc(provider: String, link: bool) -> Result<ApiResponse<String>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    let link_user = if link {
+        match get_current_user().await {
+            Ok(user) => Some(user.id),
+            Err(error) => return Ok(guard_error_response(&error)),
+        }
+    } else {
+        None
+    };
+
+    let authorization_result = match oidc::begin_authorization(&provider, link_user).await {
+        Ok(authorization) => oidc::set_state_cookie(&authorization.state).map(|()| authorization),
+        Err(error) => Err(error),
+    };
+
+    match authorization_result {
+        Ok(authorization) => {
+            leptos_actix::redirect(&authorization.url);
+            Ok(ApiResponse { data: Some(authorization.url), error: None })
+        },
+        Err(error) => {
+            if let Some(OidcError::UnknownProvider(_)) = error.downcast_ref::<OidcError>() {
+                response_option.set_status(StatusCode::NOT_FOUND);
+                return Ok(ApiResponse { data: None, error: Some("This sign in provider is not available.".to_string())});
+            }
+
+            error!(?error, "Failed to start the OpenID Connect sign in.");
+            response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+            Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())})
+        }
+    }
+}
+
+/// Where providers send the browser back to, which is then sent on to the page
+/// fitting how the sign in went. Only the browser holding the state cookie of
+/// the sign in can complete it.
+#[server(prefix = "/auth", endpoint = "oidc-callback", input = GetUrl)]
+pub async fn oidc_callback(
+    state: String,
+    code: Option<String>,
+    error: Option<String>,
+) -> Result<ApiResponse<String>, ServerFnError> {
+    let browser_state = match oidc::get_state_from_request().await {
+        Ok(browser_state) => browser_state,
+        Err(error) => {
+            error!(?error, "Failed to read the OpenID Connect state cookie.");
+            None
+        }
+    };
+    if let Err(error) = oidc::clear_state_cookie() {
+        error!(?error);
+    }
+
+    let outcome = match (code, error) {
+        (Some(code), None) => oidc::complete_authorization(&state, browser_state.as_deref(), &code).await,
+        (_, rejection) => Err(OidcError::Rejected(rejection.unwrap_or_default()).into()),
+    };
+
+    let location = match outcome {
+        Ok(OidcOutcome::Linked) => "/settings".to_string(),
+        Ok(OidcOutcome::SignedIn(user_id)) => match is_two_factor_enabled(&user_id).await {
+            Ok(true) => match create_challenge(user_id.clone()).await {
+                Ok(challenge) => {
+                    audit(CreateAuthEvent::new(AuthEventKind::Login, AuthEventOutcome::Pending)
+                        .user(user_id)
+                        .reason("OpenID Connect, awaiting the two-factor code")).await;
+                    format!("/login?two_factor={}", challenge)
+                },
+                Err(error) => {
+                    error!(?error, "Failed to create the two-factor challenge.");
+                    error_location("/login", "An internal error occurred.")
+                }
+            },
+            Ok(false) => match start_session(user_id.clone()).await {
+                Ok(()) => {
+                    audit(CreateAuthEvent::new(AuthEventKind::Login, AuthEventOutcome::Success)
+                        .user(user_id)
+                        .reason("OpenID Connect")).await;
+                    "/".to_string()
+                },
+                Err(message) => error_location("/login", message),
+            },
+            Err(error) => {
+                error!(?error, "Failed to check the two-factor authentication of the user.");
+                error_location("/login", "An internal error occurred.")
+            }
+        },
+        Err(error) => match error.downcast_ref::<OidcError>() {
+            Some(oidc_error @ (OidcError::InvalidState | OidcError::EmailTaken)) => {
+                audit(CreateAuthEvent::new(AuthEventKind::Login, AuthEventOutcome::Failure)
+                    .reason(format!("OpenID Connect: {}", oidc_error))).await;
+                match oidc_error {
+                    OidcError::InvalidState => error_location("/login", "This sign in has expired, please try again."),
+                    _ => error_location(
+                        "/login",
+                        "An account with this email already exists, log in to link the provider from the settings.",
+                    ),
+                }
+            },
+            Some(OidcError::Rejected(_)) => error_location("/login", "The sign in was cancelled."),
+            Some(OidcError::AlreadyLinked) => error_location("/settings", "This account is linked to another user."),
+            Some(OidcError::ProviderAlreadyLinked) => {
+                error_location("/settings", "An account of this provider is already linked.")
+            },
+            _ => {
+                error!(?error, "Failed to complete the OpenID Connect sign in.");
+                error_location("/login", "An internal error occurred.")
+            }
+        },
+    };
+
+    leptos_actix::redirect(&location);
+    Ok(ApiResponse { data: Some(location), error: None })
+}
+
+#[server(prefix = "/auth", endpoint = "current-user")]
+pub async fn current_user() -> Result<ApiResponse<UserProfile>, ServerFnError> {
+    match get_current_user().await {
+        Ok(user) => Ok(ApiResponse {
+            data: Some(UserProfile::from(user)),
+            error: None,
+        }),
+        Err(error) => Ok(guard_error_response(&error)),
+    }
+}
+
+/// Ends the current session, or every session of the user when `everywhere` is set.
+#[server(prefix = "/auth", endpoint = "logout")]
+pub async fn logout(everywhere: bool) -> Result<ApiResponse<String>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    // Looked up once, the session decides what is deleted and who the audit log names
+    let current_session = match get_session_token_from_request().await {
+        Ok(session_token) => get_session_by_token(&session_token).await.map(|session| (session_token, session)),
+        Err(error) => Err(error),
+    };
+    let user_id = current_session.as_ref().ok().map(|(_, session)| session.user_id.clone());
+
+    let deletion_result = match current_session {
+        Ok((_, session)) if everywhere => delete_all_sessions_for_user(session.user_id).await,
+        Ok((session_token, _)) => delete_session(&session_token).await,
+        Err(error) => Err(error),
+    };
+
+    if let Err(error) = clear_session_cookie() {
         error!(?error);
         response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
-        return Ok(ApiResponse { data: None, error: Some("Failed to create user session.".to_string())});
+        return Ok(ApiResponse { data: None, error: Some("Failed to clear the session cookie.".to_string())});
     }
 
-    let session_token = session_creation_result.ok().unwrap();
-    let cookie_creation_result = set_session_cookie(&session_token);
+    if let Err(error) = deletion_result {
+        return match error.downcast_ref::<SessionError>() {
+            Some(SessionError::DatabaseError(_)) | None => {
+                error!(?error, "Failed to delete the session during logout.");
+                response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+                Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())})
+            },
+            Some(_) => {
+                response_option.set_status(StatusCode::UNAUTHORIZED);
+                Ok(ApiResponse { data: None, error: Some("You are not logged in.".to_string())})
+            }
+        };
+    }
 
-    if let Err(error) = cookie_creation_result {
+    let mut logout_event = CreateAuthEvent::new(AuthEventKind::Logout, AuthEventOutcome::Success);
+    if let Some(user_id) = user_id {
+        logout_event = logout_event.user(user_id);
+    }
+    if everywhere {
+        logout_event = logout_event.reason("everywhere");
+    }
+    audit(logout_event).await;
+
+    Ok(ApiResponse {
+        data: Some("The user has been logged out successfully".to_string()),
+        error: None,
+    })
+}
+
+#[server(prefix = "/auth", endpoint = "forgot-password")]
+pub async fn request_password_reset(form: ForgotPasswordFormData) -> Result<ApiResponse<String>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    if let Err(error) = form.validate() {
         error!(?error);
+        response_option.set_status(StatusCode::UNPROCESSABLE_ENTITY);
+        return Ok(ApiResponse { data: None, error: Some(error.to_string())});
+    }
+
+    if let Err(error) = password_reset::request_password_reset(form.identifier).await {
+        error!(?error, "Failed to request a password reset.");
         response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
-        return Ok(ApiResponse { data: None, error: Some("Failed to set session cookie.".to_string())});
+        return Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())});
+    }
+
+    // Same answer whether or not the identifier belongs to an account
+    Ok(ApiResponse {
+        data: Some("If an account exists for this email or mobile, a reset link has been sent".to_string()),
+        error: None,
+    })
+}
+
+#[server(prefix = "/auth", endpoint = "reset-password")]
+pub async fn reset_password(form: ResetPasswordFormData) -> Result<ApiResponse<String>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    if let Err(error) = form.validate() {
+        error!(?error);
+        response_option.set_status(StatusCode::UNPROCESSABLE_ENTITY);
+        return Ok(ApiResponse { data: None, error: Some(error.to_string())});
+    }
+
+    match password_reset::reset_password(&form.token, &form.password).await {
+        Ok(user_id) => {
+            audit(CreateAuthEvent::new(AuthEventKind::PasswordReset, AuthEventOutcome::Success).user(user_id)).await;
+        },
+        Err(error) => {
+            if let Some(PasswordResetError::InvalidToken) = error.downcast_ref::<PasswordResetError>() {
+                audit(CreateAuthEvent::new(AuthEventKind::PasswordReset, AuthEventOutcome::Failure)
+                    .reason("invalid token")).await;
+                response_option.set_status(StatusCode::BAD_REQUEST);
+                return Ok(ApiResponse { data: None, error: Some("This reset link is invalid or has expired.".to_string())});
+            }
+
+            error!(?error, "Failed to reset the password.");
+            response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+            return Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())});
+        },
     }
 
     Ok(ApiResponse {
-        data: Some("The user have been logged in successfully".to_string()),
+        data: Some("The password has been reset successfully".to_string()),
         error: None,
     })
 }
+
+#[server(prefix = "/auth", endpoint = "send-verification")]
+pub async fn send_verification_code(identifier: Identifier) -> Result<ApiResponse<String>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    let user = match get_current_user().await {
+        Ok(user) => user,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    let sending_result = match find_identifier_of_user(&user.id, &identifier).await {
+        Ok(user_identifier) => send_verification(&user_identifier).await,
+        Err(error) => Err(error),
+    };
+
+    if let Err(error) = sending_result {
+        return match error.downcast_ref::<VerificationError>() {
+            Some(VerificationError::IdentifierNotFound) => {
+                response_option.set_status(StatusCode::NOT_FOUND);
+                Ok(ApiResponse { data: None, error: Some("This email or mobile is not linked to your account.".to_string())})
+            },
+            Some(VerificationError::AlreadyVerified) => {
+                response_option.set_status(StatusCode::CONFLICT);
+                Ok(ApiResponse { data: None, error: Some("This email or mobile is already verified.".to_string())})
+            },
+            Some(VerificationError::ResendTooSoon(retry_after_seconds)) => {
+                response_option.set_status(StatusCode::TOO_MANY_REQUESTS);
+                response_option.insert_header(RETRY_AFTER, HeaderValue::from(*retry_after_seconds));
+                Ok(ApiResponse { data: None, error: Some(format!("A code was just sent, you can request another one in {} seconds.", retry_after_seconds))})
+            },
+            Some(VerificationError::TooManySends) => {
+                response_option.set_status(StatusCode::TOO_MANY_REQUESTS);
+                Ok(ApiResponse { data: None, error: Some("Too many codes were sent, please try again later.".to_string())})
+            },
+            _ => {
+                error!(?error, "Failed to send the verification.");
+                response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+                Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())})
+            }
+        };
+    }
+
+    Ok(ApiResponse {
+        data: Some("A verification code has been sent".to_string()),
+        error: None,
+    })
+}
+
+#[server(prefix = "/auth", endpoint = "verify")]
+pub async fn verify_identifier(form: VerifyIdentifierFormData) -> Result<ApiResponse<String>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    if let Err(error) = form.validate() {
+        error!(?error);
+        response_option.set_status(StatusCode::UNPROCESSABLE_ENTITY);
+        return Ok(ApiResponse { data: None, error: Some(error.to_string())});
+    }
+
+    if let Err(error) = verification::verify_identifier(&form.identifier, &form.code).await {
+        return match error.downcast_ref::<VerificationError>() {
+            Some(VerificationError::InvalidCode) => {
+                response_option.set_status(StatusCode::BAD_REQUEST);
+                Ok(ApiResponse { data: None, error: Some("The verification code is invalid or has expired.".to_string())})
+            },
+            Some(VerificationError::TooManyAttempts) => {
+                response_option.set_status(StatusCode::TOO_MANY_REQUESTS);
+                Ok(ApiResponse { data: None, error: Some("Too many wrong codes, please try again later.".to_string())})
+            },
+            Some(VerificationError::AlreadyVerified) => {
+                response_option.set_status(StatusCode::CONFLICT);
+                Ok(ApiResponse { data: None, error: Some("This email or mobile is already verified.".to_string())})
+            },
+            _ => {
+                error!(?error, "Failed to verify the identifier.");
+                response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+                Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())})
+            }
+        };
+    }
+
+    Ok(ApiResponse {
+        data: Some("The email or mobile has been verified successfully".to_string()),
+        error: None,
+    })
+}
+
+#[server(prefix = "/auth", endpoint = "locked-logins")]
+pub async fn locked_logins() -> Result<ApiResponse<Vec<LockedLogin>>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    if let Err(error) = require_role(UserRole::AppAdmin).await {
+        return Ok(guard_error_response(&error));
+    }
+
+    match list_locked_logins().await {
+        Ok(locked) => Ok(ApiResponse {
+            data: Some(locked.into_iter().map(LockedLogin::from).collect()),
+            error: None,
+        }),
+        Err(error) => {
+            error!(?error, "Failed to list the locked logins.");
+            response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+            Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())})
+        }
+    }
+}
+
+#[server(prefix = "/auth", endpoint = "unlock-login")]
+pub async fn unlock_login(kind: LoginAttemptKind, value: String) -> Result<ApiResponse<String>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    if let Err(error) = require_role(UserRole::AppAdmin).await {
+        return Ok(guard_error_response(&error));
+    }
+
+    if let Err(error) = login_throttle::unlock_login(kind, &value).await {
+        error!(?error, "Failed to unlock the login.");
+        response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+        return Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())});
+    }
+
+    Ok(ApiResponse {
+        data: Some(format!("{} has been unlocked", value)),
+        error: None,
+    })
+}
+
+/// A page of the authentication audit log, for app admins
+#[server(prefix = "/auth", endpoint = "auth-events")]
+pub async fn auth_events(filter: AuthEventFilter) -> Result<ApiResponse<Vec<AuthEventSummary>>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    if let Err(error) = require_role(UserRole::AppAdmin).await {
+        return Ok(guard_error_response(&error));
+    }
+
+    if let Err(error) = filter.validate() {
+        response_option.set_status(StatusCode::UNPROCESSABLE_ENTITY);
+        return Ok(ApiResponse { data: None, error: Some(error.to_string())});
+    }
+
+    match audit::list_auth_events(&filter).await {
+        Ok(events) => Ok(ApiResponse {
+            data: Some(events.into_iter().map(AuthEventSummary::from).collect()),
+            error: None,
+        }),
+        Err(error) => {
+            error!(?error, "Failed to list the auth events.");
+            response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+            Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())})
+        }
+    }
+}
+
+/// Gives a user another role, for app admins. Admins can't change their own role,
+/// so the app can't be left without one by accident.
+#[server(prefix = "/auth", endpoint = "change-role")]
+pub async fn change_role(user_id: String, role: UserRole) -> Result<ApiResponse<String>, ServerFnError> {
+    let response_option = expect_context::<ResponseOptions>();
+
+    let admin = match require_role(UserRole::AppAdmin).await {
+        Ok(admin) => admin,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    let Ok(user_id) = user_id.parse::<RecordId>() else {
+        response_option.set_status(StatusCode::BAD_REQUEST);
+        return Ok(ApiResponse { data: None, error: Some("Invalid user id.".to_string())});
+    };
+
+    // Any record can be named, only users have a role
+    if user_id.table() != "users" {
+        response_option.set_status(StatusCode::NOT_FOUND);
+        return Ok(ApiResponse { data: None, error: Some("The user doesn't exist.".to_string())});
+    }
+
+    if user_id == admin.id {
+        response_option.set_status(StatusCode::CONFLICT);
+        return Ok(ApiResponse { data: None, error: Some("You can't change your own role.".to_string())});
+    }
+
+    match change_user_role(user_id.clone(), role).await {
+        Ok(previous_role) => {
+            audit(CreateAuthEvent::new(AuthEventKind::RoleChanged, AuthEventOutcome::Success)
+                .user(user_id)
+                .actor(admin.id)
+                .reason(format!("{:?} to {:?}", previous_role, role))).await;
+
+            Ok(ApiResponse { data: Some("The role has been changed successfully".to_string()), error: None })
+        },
+        Err(error) => {
+            if let Some(AuthError::UserNotFound) = error.downcast_ref::<AuthError>() {
+                response_option.set_status(StatusCode::NOT_FOUND);
+                return Ok(ApiResponse { data: None, error: Some("The user doesn't exist.".to_string())});
+            }
+
+            error!(?error, "Failed to change the role of the user.");
+            response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+            Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string())})
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::session::DeviceInfo;
+    use crate::auth::oidc::tests::authorize_at_provider;
+    use crate::auth::session::hash_session_token;
+    use crate::auth::session_config::get_session_config;
+    use crate::database::connection::get_db;
+    use crate::test_support::{TEST_PASSWORD, handle_request, register_test_user, run, session_cookie, user_with_role};
+    use crate::utils::token_generator::generate_token;
+    use actix_web::{cookie::Cookie, http::header::SET_COOKIE, test::TestRequest};
+
+    #[test]
+    fn logging_out_ends_only_the_current_session() {
+        run(async {
+            let user_id = register_test_user("logout@example.com").await;
+            let session_token = create_session(user_id.clone(), DeviceInfo::default()).await.unwrap();
+            let other_session_token = create_session(user_id, DeviceInfo::default()).await.unwrap();
+
+            let (response, response_options) =
+                handle_request(TestRequest::post().cookie(session_cookie(&session_token)), || logout(false)).await;
+
+            assert!(response.unwrap().error.is_none());
+            assert!(response_options.0.read().headers.contains_key(SET_COOKIE));
+            assert!(get_session_by_token(&session_token).await.is_err());
+            assert!(get_session_by_token(&other_session_token).await.is_ok());
+        });
+    }
+
+    #[test]
+    fn logging_out_everywhere_ends_every_session_of_the_user() {
+        run(async {
+            let user_id = register_test_user("logout-everywhere@example.com").await;
+            let other_user_id = register_test_user("logout-everywhere-other@example.com").await;
+            let session_token = create_session(user_id.clone(), DeviceInfo::default()).await.unwrap();
+            let other_session_token = create_session(user_id, DeviceInfo::default()).await.unwrap();
+            let other_users_session_token = create_session(other_user_id, DeviceInfo::default()).await.unwrap();
+
+            let (response, _) =
+                handle_request(TestRequest::post().cookie(session_cookie(&session_token)), || logout(true)).await;
+
+            assert!(response.unwrap().error.is_none());
+            assert!(get_session_by_token(&session_token).await.is_err());
+            assert!(get_session_by_token(&other_session_token).await.is_err());
+            assert!(get_session_by_token(&other_users_session_token).await.is_ok());
+        });
+    }
+
+    #[test]
+    fn error_messages_are_encoded_into_the_location() {
+        assert_eq!(
+            error_location("/login", "Signed in? 50% & more #1"),
+            "/login?error=Signed+in%3F+50%25+%26+more+%231"
+        );
+    }
+
+    #[test]
+    fn oidc_callbacks_without_the_state_cookie_are_turned_away() {
+        run(async {
+            let state = generate_token();
+            get_db()
+                .query(
+                    "CREATE oidc_states SET state_hash = $state_hash, provider = 'mock', nonce = 'nonce', \
+                        pkce_verifier = 'verifier', expires_at = time::now() + 10m",
+                )
+                .bind(("state_hash", hash_session_token(&state)))
+                .await
+                .unwrap()
+                .check()
+                .unwrap();
+
+            let callback = || oidc_callback(state.clone(), Some("code".to_string()), None);
+            let (response, response_options) = handle_request(TestRequest::get(), callback).await;
+
+            let location = response.unwrap().data.unwrap();
+            assert!(location.starts_with("/login?error="), "{}", location);
+            let cookie_name = get_session_config().cookie.oidc_state_cookie_name();
+            let clears_the_state_cookie = response_options
+                .0
+                .read()
+                .headers
+                .get_all(SET_COOKIE)
+                .any(|cookie| cookie.to_str().unwrap().starts_with(&format!("{}=;", cookie_name)));
+            assert!(clears_the_state_cookie);
+
+            // The pending sign in is left for the browser that started it
+            let pending: Option<String> = get_db()
+                .query("SELECT VALUE provider FROM ONLY oidc_states WHERE state_hash = $state_hash LIMIT 1")
+                .bind(("state_hash", hash_session_token(&state)))
+                .await
+                .unwrap()
+                .take(0)
+                .unwrap();
+            assert_eq!(pending.as_deref(), Some("mock"));
+
+            let request = TestRequest::get().cookie(Cookie::new(cookie_name, "another-state"));
+            let (response, _) = handle_request(request, callback).await;
+            assert!(response.unwrap().data.unwrap().starts_with("/login?error="));
+        });
+    }
+
+    #[test]
+    fn oidc_callbacks_start_the_session_and_clear_the_state_cookie() {
+        run(async {
+            let (state, code) = authorize_at_provider(None, "subject-callback", Some("oidc-callback@example.com")).await;
+
+            let cookie = get_session_config().cookie.clone();
+            let request = TestRequest::get().cookie(Cookie::new(cookie.oidc_state_cookie_name(), state.clone()));
+            let (response, response_options) =
+                handle_request(request, || oidc_callback(state.clone(), Some(code), None)).await;
+
+            assert_eq!(response.unwrap().data.as_deref(), Some("/"));
+            let set_cookies: Vec<String> = response_options
+                .0
+                .read()
+                .headers
+                .get_all(SET_COOKIE)
+                .map(|cookie| cookie.to_str().unwrap().to_string())
+                .collect();
+            assert_eq!(set_cookies.len(), 2, "{:?}", set_cookies);
+            assert!(set_cookies.contains(&cookie.cleared_oidc_state_cookie()), "{:?}", set_cookies);
+            let session_token = set_cookies
+                .iter()
+                .find_map(|set_cookie| set_cookie.strip_prefix(&format!("{}=", cookie.name)))
+                .and_then(|rest| rest.split(';').next())
+                .unwrap();
+            assert!(get_session_by_token(session_token).await.is_ok());
+        });
+    }
+
+    #[test]
+    fn logins_awaiting_the_two_factor_code_are_pending() {
+        run(async {
+            let email = "login-pending@example.com";
+            let (user_id, _) = user_with_role(email, UserRole::MosqueAdmin).await;
+            let form = LoginFormData { identifier: Identifier::Email(email.to_string()), password: TEST_PASSWORD.to_string() };
+
+            let (response, _) = handle_request(TestRequest::post().peer_addr("127.0.0.1:4000".parse().unwrap()), || login(form)).await;
+
+            assert!(matches!(response.unwrap().data, Some(LoginOutcome::TwoFactorRequired { .. })));
+            let outcomes: Vec<AuthEventOutcome> = get_db()
+                .query("SELECT VALUE outcome FROM auth_events WHERE user_id = $user_id AND kind = 'login'")
+                .bind(("user_id", user_id))
+                .await
+                .unwrap()
+                .take(0)
+                .unwrap();
+            assert_eq!(outcomes, vec![AuthEventOutcome::Pending]);
+        });
+    }
+
+    #[test]
+    fn roles_are_only_changed_for_users() {
+        run(async {
+            let (_, admin_token) = user_with_role("change-role-admin@example.com", UserRole::AppAdmin).await;
+
+            let request = TestRequest::post().cookie(session_cookie(&admin_token));
+            let (response, response_options) =
+                handle_request(request, || change_role("mosques:change_role".to_string(), UserRole::Educator)).await;
+
+            assert!(response.unwrap().error.is_some());
+            assert_eq!(response_options.0.read().status, Some(StatusCode::NOT_FOUND));
+        });
+    }
+}
@@ -1,87 +1,170 @@
 use leptos::{prelude::ServerFnError, *};
-use crate::models::{api_responses::ApiResponse, mosque::{Mosque, MosquesResponse}};
+use leptos::server_fn::codec::{GetUrl, StreamingText, TextStream};
+use crate::models::{api_responses::ApiResponse, import_job::ImportJobSummary, mosque::{Mosque, MosqueExportFormat}};
 #[cfg(feature = "ssr")]
-use surrealdb::sql::Geometry;
+use actix_web::http::{StatusCode, header::{CONTENT_DISPOSITION, HeaderValue}};
+#[cfg(feature = "ssr")]
+use futures::StreamExt;
+#[cfg(feature = "ssr")]
+use leptos::prelude::expect_context;
+#[cfg(feature = "ssr")]
+use leptos_actix::ResponseOptions;
+#[cfg(feature = "ssr")]
+use tracing::error;
+#[cfg(feature = "ssr")]
+use crate::auth::guards::{guard_error_response, require_role};
+#[cfg(feature = "ssr")]
+use crate::config::AppConfig;
+#[cfg(feature = "ssr")]
+use crate::models::user::UserRole;
+#[cfg(feature = "ssr")]
+use crate::errors::mosque::MosqueError;
+#[cfg(feature = "ssr")]
+use crate::mosques::{export::{self, ExportArea}, import_jobs};
+#[cfg(feature = "ssr")]
+use crate::mosques::overpass::BoundingBox;
 #[cfg(feature = "ssr")]
 use surrealdb::RecordId;
+#[cfg(feature = "ssr")]
+use surrealdb::sql::Geometry;
 
 #[cfg(feature = "ssr")]
 use crate::database::connection::get_db;
 
+/// Starts importing the mosques of the region from OpenStreetMap in the
+/// background, tile by tile. Its progress is polled with `import_job_status`.
 #[server(prefix = "/mosque", endpoint = "fetch-through-region")]
 pub async fn add_mosques_of_region(
     south: f64,
     west: f64,
     north: f64,
     east: f64,
-) -> Result<ApiResponse<String>, ServerFnError> {
-
-    let query = format!(
-        r#"[out:json];
-        (
-            node["amenity"="place_of_worship"]["religion"="muslim"]["building"="mosque"]({},{},{},{});
-            way["amenity"="place_of_worship"]["religion"="muslim"]["building"="mosque"]({},{},{},{});
-        )
-        out center;"#,
-    south, west, north, east,
-    south, west, north, east
-    );
-
-    let client = reqwest::Client::new();
-    let response = client
-        .post("https://overpass-api.de/api/interpreter")
-        .body(query)
-        .send()
-        .await?;
+) -> Result<ApiResponse<ImportJobSummary>, ServerFnError> {
+    let user = match require_role(UserRole::AppAdmin).await {
+        Ok(user) => user,
+        Err(error) => return Ok(guard_error_response(&error)),
+    };
+
+    let response_option = expect_context::<ResponseOptions>();
 
-    if !response.status().is_success() {
-        let status = response.status();
-        let body = response.text().await.unwrap_or_else(|_| "Could not read error body".to_string());
-        return Err(ServerFnError::ServerError(format!("Fetching data returned non 200 status, status: {}, response: {}", status, body)));
+    let bounding_box = BoundingBox { south, west, north, east };
+    if !bounding_box.is_valid() {
+        response_option.set_status(StatusCode::UNPROCESSABLE_ENTITY);
+        return Ok(ApiResponse {
+            data: None,
+            error: Some("The region must be given as south, west, north and east coordinates.".to_string()),
+        });
     }
 
-    let data: MosquesResponse = response.json().await?;
-
-    let mosques: Vec<Mosque> = data.elements
-        .into_iter()
-        .filter_map(|elem| {
-            let (lat, lon) = match elem.element_type.as_str() {
-                "node" => (elem.lat?, elem.lon?),
-                "way" => {
-                    let center = elem.center?;
-                    (center.lat, center.lon)
-                },
-                _ => return None,
-            };
-            let location = Geometry::Point((lon, lat).into());
-            let tags = elem.tags?;
-
-            Some(Mosque {
-                id: RecordId::from(("mosques", elem.id)),
-                name: tags.name.unwrap_or_else(|| "Unnamed Mosque".to_string()),
-                location,
-                street: tags.street,
-                city: tags.city,
-            })
-        }).collect();
+    let import_job = match import_jobs::create_import_job(bounding_box, user.id).await {
+        Ok(import_job) => import_job,
+        Err(error) => {
+            error!(?error, "Failed to create the import job.");
+            response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+            return Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string()) });
+        },
+    };
 
-    let db = get_db();
-    db.create::<Option<Mosque>>("mosques")
-        .content(mosques)
-        .await?;
+    let config = expect_context::<AppConfig>();
+    import_jobs::spawn_import_job(import_job.id.clone(), config.mosques);
 
+    response_option.set_status(StatusCode::ACCEPTED);
     Ok(ApiResponse {
-        data: Some(format!("Added mosques for the region {} {} {} {} successfully", south, west, north, east)),
+        data: Some(import_job.into()),
         error: None,
     })
 }
 
+#[server(prefix = "/mosque", endpoint = "import-job-status", input = GetUrl)]
+pub async fn import_job_status(job_id: String) -> Result<ApiResponse<ImportJobSummary>, ServerFnError> {
+    if let Err(error) = require_role(UserRole::AppAdmin).await {
+        return Ok(guard_error_response(&error));
+    }
+
+    let response_option = expect_context::<ResponseOptions>();
+
+    // Any record can be named, only import jobs are looked up
+    let Some(job_id) = job_id.parse::<RecordId>().ok().filter(|job_id| job_id.table() == "import_jobs") else {
+        response_option.set_status(StatusCode::NOT_FOUND);
+        return Ok(ApiResponse { data: None, error: Some("Import job not found.".to_string()) });
+    };
+
+    match import_jobs::get_import_job(job_id).await {
+        Ok(import_job) => Ok(ApiResponse {
+            data: Some(import_job.into()),
+            error: None,
+        }),
+        Err(error) => {
+            if let Some(MosqueError::ImportJobNotFound) = error.downcast_ref::<MosqueError>() {
+                response_option.set_status(StatusCode::NOT_FOUND);
+                return Ok(ApiResponse { data: None, error: Some("Import job not found.".to_string()) });
+            }
+
+            error!(?error, "Failed to fetch the import job.");
+            response_option.set_status(StatusCode::INTERNAL_SERVER_ERROR);
+            Ok(ApiResponse { data: None, error: Some("An internal error occurred.".to_string()) })
+        },
+    }
+}
+
+/// The mosques in the bounding box, of the city or everywhere, downloaded as a
+/// GeoJSON or CSV file from `MOSQUE_EXPORT_URL`. The file is streamed while
+/// the mosques are read, so whole countries can be exported.
+#[server(prefix = "/mosque", endpoint = "export", input = GetUrl, output = StreamingText)]
+pub async fn export_mosques(
+    format: MosqueExportFormat,
+    south: Option<f64>,
+    west: Option<f64>,
+    north: Option<f64>,
+    east: Option<f64>,
+    city: Option<String>,
+    #[server(default)] include_details: bool,
+) -> Result<TextStream, ServerFnError> {
+    if let Err(error) = require_role(UserRole::Regular).await {
+        let response: ApiResponse<()> = guard_error_response(&error);
+        return Err(ServerFnError::new(response.error.unwrap_or_default()));
+    }
+
+    let response_option = expect_context::<ResponseOptions>();
+
+    let city = city.map(|city| city.trim().to_string()).filter(|city| !city.is_empty());
+    let area = match (south, west, north, east, city) {
+        (None, None, None, None, None) => ExportArea::Everywhere,
+        (None, None, None, None, Some(city)) => ExportArea::City(city),
+        (Some(south), Some(west), Some(north), Some(east), None)
+            if BoundingBox { south, west, north, east }.is_valid() =>
+        {
+            ExportArea::BoundingBox(BoundingBox { south, west, north, east })
+        },
+        _ => {
+            response_option.set_status(StatusCode::UNPROCESSABLE_ENTITY);
+            return Err(ServerFnError::new(
+                "Export either a region given as south, west, north and east coordinates, a city or everything.",
+            ));
+        },
+    };
+
+    let content_disposition = format!("attachment; filename=\"{}\"", format.file_name());
+    if let Ok(content_disposition) = HeaderValue::from_str(&content_disposition) {
+        response_option.insert_header(CONTENT_DISPOSITION, content_disposition);
+    }
+
+    let chunks = export::export_mosques(format, area, include_details).map(|chunk| {
+        chunk.map_err(|error| {
+            error!(?error, "Failed to export the mosques.");
+            ServerFnError::new("An internal error occurred.")
+        })
+    });
+
+    Ok(TextStream::new(chunks))
+}
+
 #[server(prefix = "/mosque", endpoint = "fetch-mosques-from-region")]
 pub async fn fetch_mosques_from_region(lat: f64, lon: f64) -> Result<ApiResponse<Vec<Mosque>>, ServerFnError> {
     let db = get_db();
     let point = Geometry::Point((lon, lat).into());
     
-    let radius_in_meters = 10000;
+    let radius_in_meters = expect_context::<AppConfig>().mosques.search_radius_in_meters;
     let query = r#"
         SELECT * FROM mosques
         WHERE geo::distance(location, $point) < $radius
@@ -99,3 +182,23 @@ pub async fn fetch_mosques_from_region(lat: f64, lon: f64) -> Result<ApiResponse
         error: None,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{handle_request, run, session_cookie, user_with_role};
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn only_import_jobs_have_a_status() {
+        run(async {
+            let (admin_id, admin_token) = user_with_role("import-status-admin@example.com", UserRole::AppAdmin).await;
+
+            let request = TestRequest::get().cookie(session_cookie(&admin_token));
+            let (response, response_options) = handle_request(request, || import_job_status(admin_id.to_string())).await;
+
+            assert!(response.unwrap().data.is_none());
+            assert_eq!(response_options.0.read().status, Some(StatusCode::NOT_FOUND));
+        });
+    }
+}
@@ -0,0 +1,173 @@
+use actix_web::test::TestRequest;
+use leptos::prelude::provide_context;
+use leptos::reactive::{computed::ScopedFuture, owner::Owner};
+use leptos_actix::{Request, ResponseOptions};
+use once_cell::sync::Lazy;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use surrealdb::RecordId;
+use tokio::runtime::Runtime;
+use tokio::sync::OnceCell;
+use wiremock::{Mock, MockServer, ResponseTemplate, matchers::{method, path}};
+
+use crate::{
+    auth::{
+        custom_auth::register_user,
+        session::create_session,
+        password_config::{PasswordConfig, init_password_config},
+        session_config::{SessionConfig, get_session_config, init_session_config},
+        two_factor_config::{TwoFactorConfig, init_two_factor_config},
+    },
+    database::{
+        connection::{get_db, init_memory_db},
+        migrations::run_migrations,
+    },
+    models::{
+        auth::RegistrationFormData,
+        session::DeviceInfo,
+        user::{Identifier, UserRole},
+    },
+    notifications::{file::FileChannel, init_test_notifications},
+};
+
+/// The database runs on this runtime's tasks, so every test shares it rather
+/// than bringing its own
+static RUNTIME: Lazy<Runtime> = Lazy::new(|| Runtime::new().expect("Failed to start the test runtime"));
+static INITIALIZED: OnceCell<()> = OnceCell::const_new();
+
+pub const TEST_PASSWORD: &str = "correct horse battery staple";
+
+/// Where the file channel writes the messages tests send
+pub fn delivery_file_path() -> PathBuf {
+    std::env::temp_dir().join(format!("merzah-test-messages-{}.txt", std::process::id()))
+}
+
+/// Runs a test against the in-memory database, setting up the config, the
+/// schemas and file based notifications the first time
+pub fn run<F: Future>(test: F) -> F::Output {
+    RUNTIME.block_on(async {
+        INITIALIZED
+            .get_or_init(|| async {
+                let delivery_file_path = delivery_file_path();
+                let _ = std::fs::remove_file(&delivery_file_path);
+
+                init_session_config(SessionConfig {
+                    token_secret: "a test secret that is long enough to use".to_string(),
+                    ..SessionConfig::default()
+                });
+                init_password_config(PasswordConfig::default());
+                init_two_factor_config(TwoFactorConfig {
+                    required_roles: vec![UserRole::AppAdmin, UserRole::MosqueAdmin],
+                    ..TwoFactorConfig::default()
+                });
+                init_memory_db().await;
+                apply_schemas().await;
+                init_test_notifications(Box::new(FileChannel::new(delivery_file_path)), "http://127.0.0.1:3000");
+            })
+            .await;
+
+        test.await
+    })
+}
+
+/// Defines every table the way a new database is migrated
+async fn apply_schemas() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+
+    run_migrations(root, false)
+        .await
+        .unwrap_or_else(|error| panic!("Failed to migrate the test database: {:#}", error));
+}
+
+/// Registers a user with `email` as their primary identifier
+pub async fn register_test_user(email: &str) -> surrealdb::RecordId {
+    register_user(RegistrationFormData {
+        name: "Test User".to_string(),
+        identifier: Identifier::Email(email.to_string()),
+        password: TEST_PASSWORD.to_string(),
+    })
+    .await
+    .expect("Failed to register the test user")
+}
+
+/// A verified user with the role and two-factor authentication set up, and
+/// the token of a session of theirs
+pub async fn user_with_role(email: &str, role: UserRole) -> (RecordId, String) {
+    let user_id = register_test_user(email).await;
+    get_db()
+        .query(
+            "UPDATE $user_id SET role = $role;
+            UPDATE user_identifier SET verified_at = time::now() WHERE user_id = $user_id;
+            CREATE two_factor SET user_id = $user_id, secret = 'JBSWY3DPEHPK3PXP', enabled_at = time::now();",
+        )
+        .bind(("user_id", user_id.clone()))
+        .bind(("role", role))
+        .await
+        .unwrap()
+        .check()
+        .unwrap();
+    let session_token = create_session(user_id.clone(), DeviceInfo::default()).await.unwrap();
+
+    (user_id, session_token)
+}
+
+/// Runs `handler` the way a server function handling `request` runs, returning
+/// what it returned and the response options it set
+pub async fn handle_request<F: Future>(request: TestRequest, handler: impl FnOnce() -> F) -> (F::Output, ResponseOptions) {
+    let request = request.to_http_request();
+    let response_options = ResponseOptions::default();
+
+    let output = Owner::new()
+        .with(|| {
+            provide_context(Request::new(&request));
+            provide_context(response_options.clone());
+            ScopedFuture::new(handler())
+        })
+        .await;
+
+    (output, response_options)
+}
+
+/// The session cookie of the request the test sends
+pub fn session_cookie(session_token: &str) -> actix_web::cookie::Cookie<'static> {
+    actix_web::cookie::Cookie::new(get_session_config().cookie.name.clone(), session_token.to_string())
+}
+
+/// The messages sent to `recipient` so far, oldest first
+pub fn messages_to(recipient: &str) -> Vec<String> {
+    let content = std::fs::read_to_string(delivery_file_path()).unwrap_or_default();
+
+    content
+        .split("\n---\n")
+        .filter(|message| message.lines().next().is_some_and(|to| to.contains(recipient)))
+        .map(str::to_string)
+        .collect()
+}
+
+/// An Overpass API answering one request after the other with the status and
+/// body of `responses`. Its interpreter is at `/api/interpreter`.
+pub async fn overpass_stand_in(responses: Vec<(u16, &'static str)>) -> MockServer {
+    let server = MockServer::start().await;
+
+    for (status, body) in responses {
+        Mock::given(method("POST"))
+            .and(path("/api/interpreter"))
+            .respond_with(ResponseTemplate::new(status).set_body_raw(body, "application/json"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+    }
+
+    server
+}
+
+/// The queries the Overpass stand-in was sent, oldest first
+pub async fn overpass_queries(server: &MockServer) -> Vec<String> {
+    server
+        .received_requests()
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .map(|request| String::from_utf8(request.body).unwrap())
+        .collect()
+}
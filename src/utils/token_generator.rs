@@ -7,3 +7,22 @@ pub fn generate_token() -> String {
 
     general_purpose::URL_SAFE_NO_PAD.encode(token_bytes)
 }
+
+/// A zero padded numeric code, short enough to be typed in from an SMS
+pub fn generate_numeric_code(digits: u32) -> String {
+    let code = thread_rng().gen_range(0..10u32.pow(digits));
+
+    format!("{:0width$}", code, width = digits as usize)
+}
+
+/// A recovery code like `k7m2p-x9qrt`, leaving out characters that are easily
+/// mistaken for each other when written down
+pub fn generate_recovery_code() -> String {
+    const ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+    let mut rng = thread_rng();
+    let mut half = || -> String {
+        (0..5).map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char).collect()
+    };
+
+    format!("{}-{}", half(), half())
+}